@@ -2,7 +2,7 @@ use std::net::Ipv4Addr;
 
 use crate::{packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpMessage}};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct AllocationDraft {
     ip_addr: Ipv4Addr,
     options: DhcpOptions,
@@ -24,7 +24,12 @@ impl AllocationDraft {
     }
 }
 
-pub trait Allocator {
-    fn allocate(&mut self, request: DhcpMessage) -> Option<AllocationDraft>;
-    fn seal_allocation(&mut self, draft: AllocationDraft) -> Result<(), ()>;
+/// An `Allocator` hands out addresses to clients.
+///
+/// Allocators are shared between the tasks of the server,
+/// hence they must be `Send + Sync` and rely on interior
+/// locking rather than on `&mut self`.
+pub trait Allocator: Send + Sync {
+    fn allocate(&self, request: DhcpMessage) -> Option<AllocationDraft>;
+    fn seal_allocation(&self, draft: AllocationDraft) -> Result<(), ()>;
 }
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use crate::packet::dhcp_packet::DhcpMessage;
+
+use super::{allocator::{Allocator, AllocationDraft}, static_alloc::static_allocator::StaticAllocator, dynamic_alloc::dynamic_allocator::DynamicAllocator};
+
+/// `AllocatorChain` combines several [`Allocator`]s, consulted
+/// in turn until one of them hands out an address.
+///
+/// The usual chain consults the [`StaticAllocator`] first, so that
+/// reserved clients always get their reservation, then the
+/// [`DynamicAllocator`].
+///
+/// Both allocators must be given the same subnets : reservations
+/// are statically allocated in the shared [`Ipv4Subnet`](crate::leases::ip_subnet::Ipv4Subnet),
+/// so that dynamic allocations never hand out a reserved address, and a
+/// reservation is refused if its address is already dynamically allocated.
+///
+/// # Examples:
+///
+/// ```
+/// let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+/// let static_allocator = Arc::new(StaticAllocator::new());
+/// let dynamic_allocator = Arc::new(DynamicAllocator::new());
+/// static_allocator.register_subnet(subnet.clone()).unwrap();
+/// dynamic_allocator.register_subnet(subnet).unwrap();
+/// let chain = AllocatorChain::with_static_and_dynamic(static_allocator, dynamic_allocator);
+/// let draft = chain.allocate(dhcp_msg);
+/// ```
+pub struct AllocatorChain {
+
+    allocators: Vec<Arc<dyn Allocator>>,
+
+}
+
+impl Allocator for AllocatorChain {
+
+    /// Returns the [`AllocationDraft`] of the first
+    /// allocator that managed to reserve an address.
+    fn allocate(
+        &self,
+        msg: DhcpMessage
+    ) -> Option<AllocationDraft> {
+        self.allocators
+            .iter()
+            .find_map(|allocator| allocator.allocate(msg.clone()))
+    }
+
+    /// Seals the draft with the first allocator
+    /// that recognizes the drafted address.
+    fn seal_allocation(
+        &self,
+        draft: AllocationDraft
+    ) -> Result<(), ()> {
+        match self.allocators
+            .iter()
+            .any(|allocator| allocator.seal_allocation(draft.clone()).is_ok()) {
+            true => Ok(()),
+            false => Err(())
+        }
+    }
+}
+
+impl AllocatorChain {
+
+    pub fn new(
+        allocators: Vec<Arc<dyn Allocator>>
+    ) -> Self {
+        Self { allocators }
+    }
+
+    /// Builds the usual chain, consulting the [`StaticAllocator`]
+    /// before the [`DynamicAllocator`].
+    pub fn with_static_and_dynamic(
+        static_allocator: Arc<StaticAllocator>,
+        dynamic_allocator: Arc<DynamicAllocator>
+    ) -> Self {
+        Self::new(vec![static_allocator as Arc<dyn Allocator>, dynamic_allocator])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Mutex};
+
+    use crate::{leases::{ip_subnet::Ipv4Subnet, bootp::BootpSettings}, packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet}, netutils::hw_addr::HardwareAddress, allocators::static_alloc::static_allocation::{StaticAllocation, ReservationKey}};
+
+    use super::*;
+
+    const MAC: [u8; 6] = [0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08];
+
+    fn build_chain(subnet: Ipv4Subnet) -> (AllocatorChain, Arc<StaticAllocator>, Arc<Mutex<Ipv4Subnet>>) {
+        let subnet = Arc::new(Mutex::new(subnet));
+        let static_allocator = Arc::new(StaticAllocator::new());
+        let dynamic_allocator = Arc::new(DynamicAllocator::new());
+        static_allocator.register_subnet(subnet.clone()).unwrap();
+        dynamic_allocator.register_subnet(subnet.clone()).unwrap();
+        dynamic_allocator.bind_interface(String::from("eth0"), Ipv4Addr::new(192, 168, 0, 254), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+        let chain = AllocatorChain::with_static_and_dynamic(static_allocator.clone(), dynamic_allocator);
+        (chain, static_allocator, subnet)
+    }
+
+    fn discover(chaddr: &[u8]) -> DhcpMessage {
+        let mut buf = vec![0u8; 240];
+        buf[1] = 1;
+        buf[2] = chaddr.len() as u8;
+        buf[28..28 + chaddr.len()].copy_from_slice(chaddr);
+        let mut packet = DhcpV4Packet::from_raw_bytes(buf.as_slice());
+        packet.interface = Some(String::from("eth0"));
+        DhcpMessage::DhcpDiscover(packet)
+    }
+
+    fn reserve(static_allocator: &StaticAllocator, chaddr: &[u8], ip_addr: Ipv4Addr) -> Result<(), ()> {
+        let key = ReservationKey::HwAddr(HardwareAddress::from_slice(chaddr).unwrap());
+        static_allocator.register_static_allocation(StaticAllocation::new(key, ip_addr, DhcpOptions::new()))
+    }
+
+    #[test]
+    fn test_static_before_dynamic() {
+        let (chain, static_allocator, _) = build_chain(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24));
+        reserve(&static_allocator, &MAC, Ipv4Addr::new(192, 168, 0, 1)).unwrap();
+
+        let draft = chain.allocate(discover(&MAC)).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 1));
+        assert!(chain.seal_allocation(draft).is_ok());
+
+        // Unknown clients are dynamically allocated, around the reservation
+        let draft = chain.allocate(discover(&[0x01; 6])).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 2));
+        assert!(chain.seal_allocation(draft).is_ok());
+    }
+
+    #[test]
+    fn test_later_reservation_never_collides() {
+        let (chain, static_allocator, subnet) = build_chain(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24));
+
+        let draft = chain.allocate(discover(&[0x01; 6])).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 1));
+
+        // Dynamically allocated address cannot be reserved
+        assert!(reserve(&static_allocator, &MAC, Ipv4Addr::new(192, 168, 0, 1)).is_err());
+
+        // Reserved addresses are skipped by later dynamic allocations,
+        // even once released
+        reserve(&static_allocator, &MAC, Ipv4Addr::new(192, 168, 0, 2)).unwrap();
+        subnet.lock().unwrap().free(Ipv4Addr::new(192, 168, 0, 1)).unwrap();
+        reserve(&static_allocator, &[0x02; 6], Ipv4Addr::new(192, 168, 0, 1)).unwrap();
+
+        let draft = chain.allocate(discover(&[0x03; 6])).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 3));
+    }
+
+    #[test]
+    fn test_only_static_subnet() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_only_static(true);
+        let (chain, static_allocator, _) = build_chain(subnet);
+        reserve(&static_allocator, &MAC, Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+
+        assert!(chain.allocate(discover(&MAC)).unwrap().ip_addr() == Ipv4Addr::new(192, 168, 0, 10));
+        assert!(chain.allocate(discover(&[0x01; 6])).is_none());
+    }
+
+    fn bootrequest(chaddr: &[u8]) -> DhcpMessage {
+        match discover(chaddr) {
+            DhcpMessage::DhcpDiscover(packet) => DhcpMessage::BootpRequest(packet),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_bootp_clients() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+        let (chain, static_allocator, subnet) = build_chain(subnet);
+        reserve(&static_allocator, &MAC, Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+
+        // BOOTP is disabled by default
+        assert!(chain.allocate(bootrequest(&MAC)).is_none());
+        assert!(chain.allocate(bootrequest(&[0x01; 6])).is_none());
+
+        // Reserved clients only
+        subnet.lock().unwrap().set_bootp(Some(BootpSettings::static_only())).unwrap();
+        let draft = chain.allocate(bootrequest(&MAC)).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 10));
+        assert!(draft.options().lease_time().is_none());
+        assert!(chain.allocate(bootrequest(&[0x01; 6])).is_none());
+
+        // Unknown clients are given an address of the dynamic-bootp range,
+        // never one of the DHCP pool
+        let bootp = BootpSettings::with_dynamic_range(Ipv4Addr::new(192, 168, 0, 200), Ipv4Addr::new(192, 168, 0, 219));
+        subnet.lock().unwrap().set_bootp(Some(bootp)).unwrap();
+        let draft = chain.allocate(bootrequest(&[0x01; 6])).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 200));
+        assert!(draft.options().message_type().is_none());
+        assert!(chain.seal_allocation(draft).is_ok());
+        assert!(chain.allocate(discover(&[0x02; 6])).unwrap().ip_addr() == Ipv4Addr::new(192, 168, 0, 100));
+    }
+}
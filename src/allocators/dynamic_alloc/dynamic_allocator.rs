@@ -1,13 +1,23 @@
-use std::{net::Ipv4Addr, cell::RefCell, rc::Rc};
+use std::{net::Ipv4Addr, sync::{Arc, Mutex, RwLock}};
 
-use log::trace;
+use log::{trace, warn};
 
-use crate::{leases::ip_subnet::Ipv4Subnet, allocators::{allocator::{Allocator, AllocationDraft}, subnet_map::SubnetV4Map}, packet::dhcp_packet::{DhcpMessage, DhcpV4Packet} };
+use crate::{cfg::main_cfg::DhcpCfg, leases::ip_subnet::Ipv4Subnet, netutils::conflict_probe::ConflictProbe, allocators::{allocator::{Allocator, AllocationDraft}, subnet_map::{SubnetV4Map, CidrSubnet}, shared_network::SharedNetwork, subnet_registry::SubnetRegistry}, packet::{dhcp_packet::{DhcpMessage, DhcpV4Packet}, dhcp_options::DhcpOptions} };
 
 
-struct DynamicAllocator {
+// Allocations are given up after that many addresses found in use
+const MAX_PROBED_ALLOCATIONS: usize = 8;
+
+/// `DynamicAllocator` allocates addresses from the pools of
+/// its registered subnets.
+///
+/// It can be shared between threads : the subnet map is behind
+/// a [`RwLock`] that is only written when registering subnets,
+/// and each subnet has its own lock, so that concurrent allocations
+/// only contend when they target the same subnet.
+pub struct DynamicAllocator {
     
-    subnet_map: SubnetV4Map, 
+    subnet_map: RwLock<SubnetV4Map>, 
         
 }
 
@@ -20,8 +30,19 @@ impl Allocator for DynamicAllocator {
     /// associated to the message is field, it first tries
     /// to allocate that IP. 
     ///
-    /// In case of failure, it then tries to allocate a
-    /// random [`Ipv4Addr`] in the client's subnet.
+    /// In case of failure, it then allocates an [`Ipv4Addr`]
+    /// in the client's subnet, chosen by the [`SelectionKind`](crate::leases::selection::SelectionKind)
+    /// of that subnet for the client identifier (option 61, or
+    /// the hardware address of the client). If that subnet
+    /// is part of a [`SharedNetwork`], the other member subnets
+    /// are tried in turn until one has a free address.
+    ///
+    /// Subnets flagged as `only_static`, or restricted to client
+    /// classes the client is not a member of, are skipped.
+    ///
+    /// The lease time requested by the client is clamped
+    /// to the [`LeaseTimes`] of the subnet, and the renewal
+    /// and rebinding times are filled in the draft options.
     ///
     /// Returns an [`AllocationDraft`] if it successfully
     /// managed to reserve an address.
@@ -29,41 +50,72 @@ impl Allocator for DynamicAllocator {
     /// # Examples:
     ///
     /// ```
-    /// let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-    /// let mut allocator = DynamicAllocator::new();
-    /// allocator.register_subnet(subnet.clone());
+    /// let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+    /// let allocator = DynamicAllocator::new();
+    /// allocator.register_subnet(subnet.clone()).unwrap();
     /// let draft = allocator.allocate(dhcp_msg);
     /// ```
 
     fn allocate(
-        & mut self,
+        &self,
         msg: DhcpMessage
     ) -> Option<AllocationDraft> {
         let request = match msg {
             DhcpMessage::DhcpDiscover(packet) => packet,
             DhcpMessage::DhcpRequest(packet) => packet,
+            DhcpMessage::BootpRequest(packet) => { return self.allocate_bootp(&packet); },
             _ => { return None; },
         };
 
-        let subnet = self.get_client_subnet(&request)?;
-        let mut subnet = subnet.borrow_mut();
-        let options = subnet.options().clone();
+        let subnets: Vec<Arc<Mutex<Ipv4Subnet>>> = self.get_client_subnets(&request)
+            .into_iter()
+            .filter(|subnet| {
+                let subnet = subnet.lock().unwrap();
+                !subnet.only_static() & subnet.allows(&request.classes)
+            })
+            .collect();
 
         if let Some(req_ip) = request.options.requested_ip() {
-            if subnet.is_free(req_ip) {
-                subnet.force_allocate(req_ip).ok()?;
-                return Some(AllocationDraft::new(req_ip, options));
-            } 
+            for subnet in subnets.iter() {
+                let mut subnet = subnet.lock().unwrap();
+                // Addresses outside of the dynamic pool are reserved
+                if subnet.take(req_ip).is_ok() {
+                    let options = Self::lease_options(&subnet, &request);
+                    return Some(AllocationDraft::new(req_ip, options));
+                } 
+            }
         }
 
-        let ip_addr = subnet.allocate().ok()?;
-        drop(subnet);
-        Some(AllocationDraft::new(ip_addr, options))
+        let client_id = Self::client_id(&request);
+        for subnet in subnets.iter() {
+            let mut subnet = subnet.lock().unwrap();
+            if let Ok(ip_addr) = subnet.allocate_for(&client_id) {
+                let options = Self::lease_options(&subnet, &request);
+                return Some(AllocationDraft::new(ip_addr, options));
+            }
+            trace!("Subnet {} is full, trying next one.", subnet.network());
+        }
+
+        None
     }
 
 
-    fn seal_allocation(&mut self, _draft: AllocationDraft) -> Result<(), ()> {
-        todo!()
+    /// Addresses are taken from their subnet as soon as they
+    /// are drafted : sealing only checks that the drafted address
+    /// belongs to the dynamic pool (or dynamic-bootp range) of a
+    /// registered subnet and is still held.
+    fn seal_allocation(&self, draft: AllocationDraft) -> Result<(), ()> {
+        let subnet = self.subnet_map
+            .read()
+            .unwrap()
+            .get_matching_subnet(draft.ip_addr())
+            .ok_or(())?;
+        let subnet = subnet.lock().unwrap();
+        let in_range = subnet.in_pool(draft.ip_addr()) | subnet.in_bootp_range(draft.ip_addr());
+        match in_range & !subnet.is_free(draft.ip_addr()) {
+            true => Ok(()),
+            false => Err(())
+        }
     }
 }
 
@@ -72,53 +124,231 @@ impl DynamicAllocator {
     pub fn new()
         -> Self {
         Self { 
-            subnet_map: SubnetV4Map::new(), 
+            subnet_map: RwLock::new(SubnetV4Map::new()), 
         }
     }
 
-    fn get_client_subnet(
-        & mut self,
+    /// Allocates an [`Ipv4Addr`] like [`Allocator::allocate`], then
+    /// probes it before it gets offered.
+    ///
+    /// Addresses found in use by another host are declined, and
+    /// allocation is retried, up to `MAX_PROBED_ALLOCATIONS` times.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let probe = IcmpProbe::new(Duration::from_millis(500));
+    /// let draft = allocator.allocate_probed(dhcp_msg, &probe).await;
+    /// ```
+    pub async fn allocate_probed(
+        &self,
+        msg: DhcpMessage,
+        probe: &dyn ConflictProbe
+    ) -> Option<AllocationDraft> {
+        for _ in 0..MAX_PROBED_ALLOCATIONS {
+            let draft = self.allocate(msg.clone())?;
+            if !probe.probe(draft.ip_addr()).await {
+                return Some(draft);
+            };
+            warn!("Address {} is already in use, declining it.", draft.ip_addr());
+            self.decline(draft.ip_addr()).ok()?;
+        }
+        None
+    }
+
+    /// Marks an allocated [`Ipv4Addr`] as declined in its
+    /// subnet, so that it is not handed out anymore.
+    pub fn decline(
+        &self,
+        ip: Ipv4Addr
+    ) -> Result<(), ()> {
+        let subnet = self.subnet_map
+            .read()
+            .unwrap()
+            .get_matching_subnet(ip)
+            .ok_or(())?;
+        let mut subnet = subnet.lock().unwrap();
+        subnet.decline(ip)
+    }
+
+    /// Allocates an [`Ipv4Addr`] of the dynamic-bootp range of
+    /// the client's subnets (see [`Ipv4Subnet::allocate_bootp`])
+    /// to an unknown BOOTP client.
+    ///
+    /// BOOTP leases are infinite : the draft options hold
+    /// no lease time, nor any other DHCP-only option.
+    fn allocate_bootp(
+        &self,
+        request: &DhcpV4Packet
+    ) -> Option<AllocationDraft> {
+        for subnet in self.get_client_subnets(request).iter() {
+            let mut subnet = subnet.lock().unwrap();
+            if !subnet.allows(&request.classes) {
+                continue;
+            };
+            if let Ok(ip_addr) = subnet.allocate_bootp() {
+                return Some(AllocationDraft::new(ip_addr, subnet.options().to_bootp()));
+            };
+        }
+        None
+    }
+
+    /// Identifies the client for the selection strategies :
+    /// its client identifier if any, its hardware address otherwise.
+    fn client_id(
+        request: &DhcpV4Packet
+    ) -> Vec<u8> {
+        match request.options.client_identifier() {
+            Some(client_id) => client_id.clone(),
+            None => request.chadd.raw[..(request.hlen as usize).min(16)].to_vec()
+        }
+    }
+
+    /// Builds the options of a lease granted in the given
+    /// subnet, according to its [`LeaseTimes`]. Rapid commit
+    /// is set when the DHCPDISCOVER must be acknowledged.
+    fn lease_options(
+        subnet: &Ipv4Subnet,
+        request: &DhcpV4Packet
+    ) -> DhcpOptions {
+        let mut options = subnet.options().clone();
+        subnet.lease_times().apply(request.options.lease_time(), &mut options);
+        options.set_rapid_commit(subnet.accepts_rapid_commit(&request.options));
+        options
+    }
+
+    /// Returns the subnets the client can be given an
+    /// address in (see [`SubnetV4Map::get_client_subnets`]).
+    fn get_client_subnets(
+        &self,
         packet: &DhcpV4Packet
-    ) -> Option<Rc<RefCell<Ipv4Subnet>>> {
-
-        let bootp_relay_ip = packet.giaddr;
-
-        // might require to be more specific and allocate an ip
-        // on the exact same subnet the dhcp server is in
-        if bootp_relay_ip == Ipv4Addr::new(0, 0, 0, 0) {
-            if let Some(req_ip) = packet.options.requested_ip() {
-                return self.subnet_map
-                    .get_matching_subnet(req_ip)
-                    .or_else(|| {
-                        trace!("DHCP Message received from an unknown subnet.");
-                        None
-                });                   
+    ) -> Vec<Arc<Mutex<Ipv4Subnet>>> {
+        self.subnet_map
+            .read()
+            .unwrap()
+            .get_client_subnets(packet)
+    }
+
+    /// Registers a new [`Ipv4Subnet`] to allocate from.
+    ///
+    /// Returns the already registered [`CidrSubnet`]
+    /// overlapping the given subnet, if any.
+    pub fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .insert_subnet(subnet) 
+    }
+
+    /// Ties a listening interface to its directly connected subnet,
+    /// given the address and mask of the interface.
+    ///
+    /// Returns the [`CidrSubnet`] of the interface if that
+    /// subnet is not registered.
+    pub fn bind_interface(
+        &self,
+        name: String,
+        ip: Ipv4Addr,
+        mask: Ipv4Addr
+    ) -> Result<(), CidrSubnet> {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .bind_interface(name, ip, mask)
+    }
+
+    /// Ties every interface the server listens on (see
+    /// [`DhcpCfg::interfaces`]) to its directly connected
+    /// subnet. Interfaces without an IPv4 address are skipped.
+    ///
+    /// Returns the [`CidrSubnet`] of the first interface
+    /// whose subnet is not registered.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let cfg = load_main_cfg("tests/main.yml").unwrap();
+    /// allocator.register_subnet(subnet).unwrap();
+    /// allocator.bind_interfaces(&cfg).unwrap();
+    /// ```
+    pub fn bind_interfaces(
+        &self,
+        cfg: &DhcpCfg
+    ) -> Result<(), CidrSubnet> {
+        for interface in cfg.interfaces() {
+            match (interface.ipv4(), interface.mask()) {
+                (Some(ip), Some(mask)) => self.bind_interface(interface.name().to_string(), ip, mask)?,
+                _ => warn!("Interface {} has no IPv4 address, its clients must be relayed.", interface.name())
             };
-            return None;
-        };
+        }
+        Ok(())
+    }
 
+    /// Registers a [`SharedNetwork`] grouping already
+    /// registered subnets.
+    ///
+    /// Returns the first member [`CidrSubnet`] that is
+    /// unknown or already part of another shared network.
+    pub fn register_shared_network(
+        &self,
+        shared_network: SharedNetwork
+    ) -> Result<(), CidrSubnet> {
         self.subnet_map
-            .get_matching_subnet(bootp_relay_ip)
-            .or_else(|| {
-                trace!("DHCP Message received from an unknown subnet.");
-                None
-            })
+            .write()
+            .unwrap()
+            .insert_shared_network(shared_network)
+    }
+
+}
 
-    }  
+impl SubnetRegistry for DynamicAllocator {
 
-    pub fn register_subnet(
-        &mut self,
-        subnet: Rc<RefCell<Ipv4Subnet>>
-    ) {
-        self.subnet_map.insert_subnet(subnet) 
+    fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        DynamicAllocator::register_subnet(self, subnet)
+    }
+
+    fn unregister_subnet(
+        &self,
+        subnet: CidrSubnet
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .remove_subnet(subnet)
+    }
+
+    fn register_shared_network(
+        &self,
+        shared_network: SharedNetwork
+    ) -> Result<(), CidrSubnet> {
+        DynamicAllocator::register_shared_network(self, shared_network)
     }
 
+    fn unregister_shared_network(
+        &self,
+        name: &str
+    ) {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .remove_shared_network(name);
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use fp_core::core::packet::PacketType;
 
+    use crate::leases::lease_time::LeaseTimes;
+
     use super::*;
     const DHCP_PACKET: [u8; 304]  = [
          0x01, 0x01, 0x06, 0x00, 0x5d, 0x14, 0xd3, 0x27, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 
@@ -145,38 +375,283 @@ mod tests {
     #[test]
     fn test_simple_allocation() {
    
-        let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-        let mut allocator = DynamicAllocator::new();
-        allocator.register_subnet(subnet.clone());
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(subnet.clone()).unwrap();
         let packet = DhcpV4Packet::from_raw_bytes(&DHCP_PACKET);
         let dhcp_msg = DhcpMessage::DhcpDiscover(packet.clone());
 
         assert!(packet.options.requested_ip().unwrap() == Ipv4Addr::new(192, 168, 0, 17));
 
         let draft = allocator.allocate(dhcp_msg).unwrap();
-        let sub = subnet.borrow();
+        let sub = subnet.lock().unwrap();
         assert!(!sub.is_free(Ipv4Addr::new(192, 168, 0, 17)));
         assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 17));
          
     }
+
+    #[test]
+    fn test_allocation_lease_times() {
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        subnet.lock().unwrap().set_lease_times(LeaseTimes::new(3600, 600, 7200));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(subnet.clone()).unwrap();
+        let mut packet = DhcpV4Packet::from_raw_bytes(&DHCP_PACKET);
+
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).unwrap();
+        assert!(draft.options().lease_time() == Some(3600));
+        assert!(draft.options().renewal_time() == Some(1800));
+        assert!(draft.options().rebinding_time() == Some(3150));
+
+        packet.options.set_lease_time(Some(86400));
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet)).unwrap();
+        assert!(draft.options().lease_time() == Some(7200));
+        assert!(draft.options().renewal_time() == Some(3600));
+        assert!(draft.options().rebinding_time() == Some(6300));
+    }
     #[test]
     fn test_double_allocation() {
-        let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-        let allocator = Rc::new(RefCell::new(DynamicAllocator::new()));
-        let mut allocator_mut = allocator.borrow_mut();
-        allocator_mut.register_subnet(subnet.clone());
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(subnet.clone()).unwrap();
         let packet = DhcpV4Packet::from_raw_bytes(DHCP_PACKET.as_slice());
         let dhcp_msg = DhcpMessage::DhcpDiscover(packet.clone());
 
         assert!(packet.options.requested_ip().unwrap() == Ipv4Addr::new(192, 168, 0, 17));
 
-        allocator_mut.allocate(dhcp_msg.clone()).unwrap();
-        let draft2 = allocator_mut.allocate(dhcp_msg);
-        let sub = subnet.borrow();
+        allocator.allocate(dhcp_msg.clone()).unwrap();
+        let draft2 = allocator.allocate(dhcp_msg);
+        let sub = subnet.lock().unwrap();
         assert!(!sub.is_free(Ipv4Addr::new(192, 168, 0, 17)));
         assert!(!draft2.is_none());
         assert!(draft2.unwrap().ip_addr() != Ipv4Addr::new(192, 168, 0, 17))
  
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_allocation() {
+        // 1022 usable addresses, 1024 allocation attempts
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 22)));
+        let allocator = Arc::new(DynamicAllocator::new());
+        allocator.register_subnet(subnet.clone()).unwrap();
+        let packet = DhcpV4Packet::from_raw_bytes(DHCP_PACKET.as_slice());
+
+        let mut handles = Vec::new();
+        for _ in 0..16 {
+            let allocator = allocator.clone();
+            let packet = packet.clone();
+            handles.push(tokio::spawn(async move {
+                let mut allocated = Vec::new();
+                for _ in 0..64 {
+                    if let Some(draft) = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())) {
+                        allocated.push(draft.ip_addr());
+                    }
+                    tokio::task::yield_now().await;
+                }
+                allocated
+            }));
+        }
+
+        let mut allocated = Vec::new();
+        for handle in handles {
+            allocated.append(&mut handle.await.unwrap());
+        }
+        let unique: HashSet<Ipv4Addr> = allocated.iter().copied().collect();
+        let sub = subnet.lock().unwrap();
+        assert!(allocated.len() == 1022);
+        assert!(unique.len() == allocated.len());
+        assert!(!unique.contains(&sub.network()));
+        assert!(!unique.contains(&sub.broadcast()));
+        assert!(unique.iter().all(|ip| sub.contains(*ip) && !sub.is_free(*ip)));
+    }
+
+    #[test]
+    fn test_shared_network_fallover() {
+        // Primary subnet only has 6 usable addresses
+        let primary = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 29)));
+        let secondary = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24)));
+        secondary.lock().unwrap().set_lease_times(LeaseTimes::new(3600, 600, 7200));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(primary.clone()).unwrap();
+        allocator.register_subnet(secondary.clone()).unwrap();
+        allocator.register_shared_network(SharedNetwork::new(
+            String::from("vlan10"),
+            vec![
+                CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 29),
+                CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 0, 0)), 24),
+            ]
+        )).unwrap();
+
+        // Relayed from the primary subnet, without requested ip
+        let mut packet = DhcpV4Packet::from_raw_bytes(DHCP_PACKET.as_slice());
+        packet.giaddr = Ipv4Addr::new(192, 168, 0, 1);
+        packet.options.set_requested_ip(None);
+
+        for _ in 0..6 {
+            let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).unwrap();
+            assert!(primary.lock().unwrap().contains(draft.ip_addr()));
+        }
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(10, 0, 0, 1));
+        // Options come from the subnet the address was taken in
+        assert!(draft.options().lease_time() == Some(3600));
+
+        // Relay on the secondary subnet selects the group too
+        packet.giaddr = Ipv4Addr::new(10, 0, 0, 254);
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet)).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(10, 0, 0, 2));
+    }
+
+    #[test]
+    fn test_full_subnet_without_shared_network() {
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 30)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(subnet).unwrap();
+        let mut packet = DhcpV4Packet::from_raw_bytes(DHCP_PACKET.as_slice());
+        packet.giaddr = Ipv4Addr::new(192, 168, 0, 1);
+        packet.options.set_requested_ip(None);
+
+        assert!(allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).is_some());
+        assert!(allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).is_some());
+        assert!(allocator.allocate(DhcpMessage::DhcpDiscover(packet)).is_none());
+    }
+
+    #[test]
+    fn test_local_client_allocation() {
+        let eth0 = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let eth1 = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(eth0).unwrap();
+        allocator.register_subnet(eth1).unwrap();
+        allocator.bind_interface(String::from("eth0"), Ipv4Addr::new(192, 168, 0, 254), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+        allocator.bind_interface(String::from("eth1"), Ipv4Addr::new(10, 0, 0, 254), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+
+        // Fresh client, neither relayed nor requesting an ip
+        let mut packet = DhcpV4Packet::from_raw_bytes(DHCP_PACKET.as_slice());
+        packet.options.set_requested_ip(None);
+        assert!(allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).is_none());
+
+        let mut packet = DhcpV4Packet::from_interface(DHCP_PACKET.as_slice(), "eth0");
+        packet.options.set_requested_ip(None);
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 1));
+
+        packet.interface = Some(String::from("eth1"));
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(10, 0, 0, 1));
+
+        // Relay address still wins over the receiving interface
+        packet.giaddr = Ipv4Addr::new(192, 168, 0, 254);
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet)).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 2));
+    }
+
+    #[test]
+    fn test_bind_configured_interfaces() {
+        // tests/main.yml listens on lo0 only
+        let cfg = crate::cfg::main_cfg::load_main_cfg("tests/main.yml").unwrap();
+        let allocator = DynamicAllocator::new();
+        assert!(allocator.bind_interfaces(&cfg) == Err(CidrSubnet::new(u32::from(Ipv4Addr::new(127, 0, 0, 0)), 8)));
+
+        let loopback = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(127, 0, 0, 0), 8)));
+        allocator.register_subnet(loopback).unwrap();
+        allocator.bind_interfaces(&cfg).unwrap();
+
+        let mut packet = DhcpV4Packet::from_interface(DHCP_PACKET.as_slice(), "lo0");
+        packet.options.set_requested_ip(None);
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet)).unwrap();
+        assert!(draft.ip_addr().is_loopback());
+    }
+
+    #[test]
+    fn test_allocator_is_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DynamicAllocator>();
+        assert_send_sync::<Arc<dyn Allocator>>();
+    }
+
+    /// Reports the addresses of `in_use` as taken,
+    /// and records every probed address.
+    struct StubProbe {
+        in_use: HashSet<Ipv4Addr>,
+        probed: Mutex<Vec<Ipv4Addr>>,
+    }
+
+    impl ConflictProbe for StubProbe {
+        fn probe(&self, ip: Ipv4Addr) -> std::pin::Pin<Box<dyn std::future::Future<Output = bool> + Send + '_>> {
+            self.probed.lock().unwrap().push(ip);
+            let in_use = self.in_use.contains(&ip);
+            Box::pin(async move { in_use })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probed_allocation() {
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(subnet.clone()).unwrap();
+        let packet = DhcpV4Packet::from_raw_bytes(&DHCP_PACKET);
+        let probe = StubProbe {
+            in_use: HashSet::from([Ipv4Addr::new(192, 168, 0, 17), Ipv4Addr::new(192, 168, 0, 1)]),
+            probed: Mutex::new(Vec::new()),
+        };
+
+        // Requested address then first pool address are in use
+        let draft = allocator.allocate_probed(DhcpMessage::DhcpDiscover(packet), &probe).await.unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(192, 168, 0, 2));
+        assert!(*probe.probed.lock().unwrap() == vec![
+            Ipv4Addr::new(192, 168, 0, 17),
+            Ipv4Addr::new(192, 168, 0, 1),
+            Ipv4Addr::new(192, 168, 0, 2)
+        ]);
+
+        let sub = subnet.lock().unwrap();
+        assert!(sub.is_declined(Ipv4Addr::new(192, 168, 0, 17)));
+        assert!(sub.is_declined(Ipv4Addr::new(192, 168, 0, 1)));
+        assert!(!sub.is_free(Ipv4Addr::new(192, 168, 0, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_probed_allocation_gives_up() {
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(subnet.clone()).unwrap();
+        let packet = DhcpV4Packet::from_raw_bytes(&DHCP_PACKET);
+        let probe = StubProbe {
+            in_use: (1..=254).map(|i| Ipv4Addr::new(192, 168, 0, i)).collect(),
+            probed: Mutex::new(Vec::new()),
+        };
+
+        assert!(allocator.allocate_probed(DhcpMessage::DhcpDiscover(packet), &probe).await.is_none());
+        assert!(probe.probed.lock().unwrap().len() == MAX_PROBED_ALLOCATIONS);
+    }
+
+    #[test]
+    fn test_class_restricted_pools() {
+        let phones = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24)));
+        phones.lock().unwrap().set_client_classes(vec![String::from("voip")]);
+        let others = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(10, 0, 1, 0), 24)));
+        let allocator = DynamicAllocator::new();
+        allocator.register_subnet(phones).unwrap();
+        allocator.register_subnet(others).unwrap();
+        allocator.register_shared_network(SharedNetwork::new(
+            String::from("vlan10"),
+            vec![
+                CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 0, 0)), 24),
+                CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 1, 0)), 24),
+            ]
+        )).unwrap();
+
+        // Clients outside of the voip class skip the phones pool
+        let mut packet = DhcpV4Packet::from_raw_bytes(DHCP_PACKET.as_slice());
+        packet.giaddr = Ipv4Addr::new(10, 0, 0, 254);
+        packet.options.set_requested_ip(None);
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(10, 0, 1, 1));
+
+        packet.classes = vec![String::from("voip")];
+        let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet)).unwrap();
+        assert!(draft.ip_addr() == Ipv4Addr::new(10, 0, 0, 1));
+    }
+
 }
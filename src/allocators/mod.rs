@@ -2,3 +2,6 @@ pub mod static_alloc;
 pub mod subnet_map;
 pub mod dynamic_alloc;
 pub mod allocator;
+pub mod shared_network;
+pub mod allocator_chain;
+pub mod subnet_registry;
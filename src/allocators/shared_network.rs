@@ -0,0 +1,56 @@
+use super::subnet_map::CidrSubnet;
+
+/// A `SharedNetwork` groups several subnets living
+/// on the same physical link (e.g. a VLAN carrying a
+/// primary and a secondary subnet).
+///
+/// A client relayed from any member subnet may be given
+/// an address in any other member subnet. Members are
+/// tried in the order they were declared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedNetwork {
+    name: String,
+    subnets: Vec<CidrSubnet>,
+}
+
+impl SharedNetwork {
+
+    /// Creates a new `SharedNetwork`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let shared = SharedNetwork::new(
+    ///     String::from("vlan10"),
+    ///     vec![
+    ///         CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24),
+    ///         CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 0, 0)), 24),
+    ///     ]
+    /// );
+    /// ```
+    pub fn new(
+        name: String,
+        subnets: Vec<CidrSubnet>
+    ) -> Self {
+        Self { name, subnets }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn subnets(&self) -> &[CidrSubnet] {
+        &self.subnets
+    }
+
+    /// Drops a member subnet, returns false if it
+    /// was not part of this `SharedNetwork`.
+    pub fn remove_subnet(
+        &mut self,
+        subnet: CidrSubnet
+    ) -> bool {
+        let len = self.subnets.len();
+        self.subnets.retain(|member| *member != subnet);
+        len != self.subnets.len()
+    }
+}
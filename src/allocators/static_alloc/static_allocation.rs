@@ -1,18 +1,172 @@
 use std::net::Ipv4Addr;
-use crate::{packet::dhcp_options::DhcpOptions, netutils::hw_addr::HardwareAddress};
 
+use serde::{Serialize, Deserialize};
+
+use crate::{packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet}, netutils::{hw_addr::HardwareAddress, client_id::ClientId}};
+
+/// Identifies the client a [`StaticAllocation`]
+/// is reserved for.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ReservationKey {
+    /// Client identifier (option 61) sent by the client
+    ClientId(ClientId),
+    /// Hardware address of the client
+    HwAddr(HardwareAddress),
+    /// Hostname (option 12) sent by the client
+    Hostname(String),
+    /// Agent Circuit ID inserted by the relay (option 82),
+    /// usually identifying a switch port
+    CircuitId(Vec<u8>),
+    /// Agent Remote ID inserted by the relay (option 82)
+    RemoteId(Vec<u8>),
+    /// Vendor class identifier (option 60) sent by the client
+    VendorClass(String),
+}
+
+/// The kinds of [`ReservationKey`], used to configure
+/// in which order reservations are looked up.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationKind {
+    ClientId,
+    HwAddr,
+    Hostname,
+    CircuitId,
+    RemoteId,
+    VendorClass,
+}
+
+impl ReservationKind {
+
+    /// Returns the default match order, from the most
+    /// to the least specific identifier of a client.
+    pub fn default_order() -> Vec<ReservationKind> {
+        vec![
+            ReservationKind::ClientId,
+            ReservationKind::HwAddr,
+            ReservationKind::CircuitId,
+            ReservationKind::RemoteId,
+            ReservationKind::Hostname,
+            ReservationKind::VendorClass,
+        ]
+    }
+}
+
+impl ReservationKey {
+
+    pub fn kind(&self) -> ReservationKind {
+        match self {
+            ReservationKey::ClientId(_) => ReservationKind::ClientId,
+            ReservationKey::HwAddr(_) => ReservationKind::HwAddr,
+            ReservationKey::Hostname(_) => ReservationKind::Hostname,
+            ReservationKey::CircuitId(_) => ReservationKind::CircuitId,
+            ReservationKey::RemoteId(_) => ReservationKind::RemoteId,
+            ReservationKey::VendorClass(_) => ReservationKind::VendorClass,
+        }
+    }
+
+    /// Returns the keys a reservation for the given packet
+    /// may be registered with, following the given match order.
+    ///
+    /// Hardware addresses are looked up from the most to
+    /// the least specific :
+    /// - the hardware address embedded in a type-prefixed client identifier
+    /// - the hardware address of the packet (chaddr)
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let candidates = ReservationKey::candidates(&packet, &ReservationKind::default_order());
+    /// ```
+    pub fn candidates(
+        packet: &DhcpV4Packet,
+        order: &[ReservationKind]
+    ) -> Vec<ReservationKey> {
+        let mut candidates = Vec::new();
+        let client_id = packet.options
+            .client_identifier()
+            .and_then(|cid| ClientId::parse(cid));
+        let relay_agent_info = packet.options.relay_agent_info();
+
+        for kind in order {
+            match kind {
+                ReservationKind::ClientId => {
+                    if let Some(client_id) = &client_id {
+                        candidates.push(ReservationKey::ClientId(client_id.clone()));
+                    };
+                }
+                ReservationKind::HwAddr => {
+                    if let Some(hw_addr) = client_id.as_ref().and_then(|cid| cid.hardware_address()) {
+                        candidates.push(ReservationKey::HwAddr(hw_addr));
+                    };
+                    let hlen = (packet.hlen as usize).min(16);
+                    if hlen > 0 {
+                        if let Some(chaddr) = HardwareAddress::from_slice(&packet.chadd.raw[..hlen]) {
+                            candidates.push(ReservationKey::HwAddr(chaddr));
+                        };
+                    };
+                }
+                ReservationKind::Hostname => {
+                    if let Some(hostname) = packet.options.hostname() {
+                        candidates.push(ReservationKey::Hostname(hostname.clone()));
+                    };
+                }
+                ReservationKind::CircuitId => {
+                    if let Some(circuit_id) = relay_agent_info.and_then(|info| info.circuit_id()) {
+                        candidates.push(ReservationKey::CircuitId(circuit_id.clone()));
+                    };
+                }
+                ReservationKind::RemoteId => {
+                    if let Some(remote_id) = relay_agent_info.and_then(|info| info.remote_id()) {
+                        candidates.push(ReservationKey::RemoteId(remote_id.clone()));
+                    };
+                }
+                ReservationKind::VendorClass => {
+                    if let Some(vendor_class) = packet.options.vendor_class() {
+                        candidates.push(ReservationKey::VendorClass(vendor_class.clone()));
+                    };
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Where a [`StaticAllocation`] applies.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ReservationScope {
+    /// Applies to the client on whichever subnet it appears,
+    /// as long as the reserved address belongs to that subnet
+    Global,
+    /// Only applies to the client when it appears
+    /// on the subnet of the reserved address
+    Subnet,
+}
 
 pub struct StaticAllocation {
-    cid: HardwareAddress,
+    key: ReservationKey,
     ip_addr: Ipv4Addr,
+    scope: ReservationScope,
     options: DhcpOptions
 }
 
 impl StaticAllocation {
-    pub fn new(cid: HardwareAddress, ip_addr: Ipv4Addr, options: DhcpOptions) -> Self { Self { cid, ip_addr, options } }
+    pub fn new(key: ReservationKey, ip_addr: Ipv4Addr, options: DhcpOptions) -> Self { Self { key, ip_addr, scope: ReservationScope::Subnet, options } }
+
+    /// Creates a [`ReservationScope::Global`] `StaticAllocation`.
+    pub fn new_global(key: ReservationKey, ip_addr: Ipv4Addr, options: DhcpOptions) -> Self { Self { key, ip_addr, scope: ReservationScope::Global, options } }
+
+    pub fn key(&self) -> &ReservationKey {
+        &self.key
+    }
+
+    pub fn ip_addr(&self) -> Ipv4Addr {
+        self.ip_addr
+    }
 
-    pub fn cid(&self) -> HardwareAddress {
-        self.cid
+    pub fn scope(&self) -> ReservationScope {
+        self.scope
     }
 
     pub fn options(&self) -> &DhcpOptions {
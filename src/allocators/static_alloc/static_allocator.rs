@@ -1,61 +1,126 @@
-use std::{collections::HashMap, net::Ipv4Addr, cell::RefCell, rc::Rc};
-
-
-
-use crate::{leases::ip_subnet::Ipv4Subnet, netutils::hw_addr::HardwareAddress, packet::{dhcp_packet::DhcpMessage}, allocators::{allocator::{Allocator, AllocationDraft}, subnet_map::{SubnetV4Map, CidrSubnet}}};
-
-use super::static_allocation::StaticAllocation;
-
-struct StaticAllocator {
+use std::{collections::HashMap, net::Ipv4Addr, sync::{Arc, Mutex, RwLock}};
+
+use log::warn;
+
+use crate::{leases::ip_subnet::Ipv4Subnet, packet::{dhcp_packet::DhcpMessage}, allocators::{allocator::{Allocator, AllocationDraft}, subnet_map::{SubnetV4Map, CidrSubnet}, subnet_registry::SubnetRegistry}};
+
+use super::static_allocation::{StaticAllocation, ReservationKey, ReservationKind, ReservationScope};
+
+/// `StaticAllocator` hands out the addresses that were
+/// reserved for known clients.
+///
+/// Like the [`DynamicAllocator`](crate::allocators::dynamic_alloc::dynamic_allocator::DynamicAllocator),
+/// it can be shared between threads : both the subnet map and the
+/// registry of reservations are behind a [`RwLock`].
+///
+/// Reservations are keyed by a [`ReservationKey`] : the client
+/// identifier, the hardware address, the hostname, the vendor
+/// class of the client, or the circuit / remote ID inserted by
+/// the relay agent. Keys are tried following the configurable
+/// match order, the first matching reservation wins.
+///
+/// A reservation is either scoped to the subnet of its address,
+/// or global (see [`ReservationScope`]). For a given key, the
+/// reservation of the client's subnet wins over the global one.
+/// Reserved addresses may lie outside of the dynamic pool of
+/// their subnet.
+pub struct StaticAllocator {
     
-    subnet_map: SubnetV4Map,
-    registry: HashMap<HardwareAddress, StaticAllocation>,
+    subnet_map: RwLock<SubnetV4Map>,
+    // Reservations indexed by key and subnet, global ones having no subnet
+    registry: RwLock<HashMap<(ReservationKey, Option<CidrSubnet>), StaticAllocation>>,
+    match_order: RwLock<Vec<ReservationKind>>,
 
 }
 
 impl Allocator for StaticAllocator {
     fn allocate(
-        &mut self, 
+        &self, 
         msg: DhcpMessage
     ) -> Option<AllocationDraft> 
     {
 
-        let request = match msg {
-            DhcpMessage::DhcpDiscover(packet) => packet,
-            DhcpMessage::DhcpRequest(packet) => packet,
+        let (request, bootp) = match msg {
+            DhcpMessage::DhcpDiscover(packet) => (packet, false),
+            DhcpMessage::DhcpRequest(packet) => (packet, false),
+            DhcpMessage::BootpRequest(packet) => (packet, true),
             _ => { return None; },
         };
 
-        // The following lines are an absurdity. Client identifier is by no mean of fixed length,
-        // nor always correspond to a so called HardwareAddress. 
-        //
-        // TO CHANGE ASAP
-        if let Some(cid) = request.options.client_identifier() {
-            let cidc = cid.clone();
-            let client_id: &[u8; 16] = cidc[..16].try_into().unwrap();
-            let record = self.registry.get(&HardwareAddress::new(*client_id))?; 
-
-            let ip_addr = record
-                .options()
-                .requested_ip();
-
-            if let Some(ip_addr) = ip_addr {
-                let ip_addr = u32::from(ip_addr); 
-                return Some(AllocationDraft::new(Ipv4Addr::from(ip_addr), record.options().clone()))
-            } else {
-                return None
-            }
+        // Clients that cannot be located may match the
+        // reservations of any subnet
+        let (client_subnets, scopes) = {
+            let subnet_map = self.subnet_map.read().unwrap();
+            let client_subnets: Vec<CidrSubnet> = subnet_map
+                .get_client_subnets(&request)
+                .iter()
+                .map(|subnet| {
+                    let subnet = subnet.lock().unwrap();
+                    CidrSubnet::new(u32::from(subnet.network()), subnet.prefix())
+                })
+                .collect();
+            let scopes = match client_subnets.is_empty() {
+                true => subnet_map.iter().map(|(cidr, _)| *cidr).collect(),
+                false => client_subnets.clone()
+            };
+            (client_subnets, scopes)
+        };
+
+        // Reservations are looked up following the match order
+        let match_order = self.match_order.read().unwrap().clone();
+        let registry = self.registry.read().unwrap();
+        let record = ReservationKey::candidates(&request, &match_order)
+            .into_iter()
+            .find_map(|key| {
+                scopes.iter()
+                    .find_map(|cidr| registry.get(&(key.clone(), Some(*cidr))))
+                    .or_else(|| registry.get(&(key, None)).filter(|record| {
+                        client_subnets.is_empty() | client_subnets.iter().any(|cidr| cidr.contains(record.ip_addr()))
+                    }))
+            })?;
+
+        let ip_addr = record.ip_addr();
+
+        let mut options = record.options().clone();
+        let subnet = self.subnet_map
+            .read()
+            .unwrap()
+            .get_matching_subnet(ip_addr);
+
+        // BOOTP clients are only answered on subnets accepting
+        // them, and are given an infinite lease
+        if bootp {
+            let accepted = subnet.map_or(false, |subnet| subnet.lock().unwrap().bootp().is_some());
+            return match accepted {
+                true => Some(AllocationDraft::new(ip_addr, options.to_bootp())),
+                false => None
+            };
         };
 
-        None
+        if let Some(subnet) = subnet {
+            let subnet = subnet.lock().unwrap();
+            subnet.lease_times().apply(request.options.lease_time(), &mut options);
+            options.set_rapid_commit(subnet.accepts_rapid_commit(&request.options));
+        }
+        Some(AllocationDraft::new(ip_addr, options))
         
     }
 
+    /// Reserved addresses are statically allocated upon
+    /// registration : sealing only checks that the drafted
+    /// address is still reserved.
     fn seal_allocation(
-        &mut self, 
-        _draft: AllocationDraft
+        &self, 
+        draft: AllocationDraft
     ) -> Result<(), ()> {
-        Ok(())
+        match self.registry
+            .read()
+            .unwrap()
+            .values()
+            .any(|record| record.ip_addr() == draft.ip_addr()) {
+            true => Ok(()),
+            false => Err(())
+        }
     }
 }
 
@@ -64,128 +129,240 @@ impl StaticAllocator {
     pub fn new()
         -> Self {
         Self { 
-            subnet_map: SubnetV4Map::new(),
-            registry: HashMap::new(),
+            subnet_map: RwLock::new(SubnetV4Map::new()),
+            registry: RwLock::new(HashMap::new()),
+            match_order: RwLock::new(ReservationKind::default_order()),
         }
     }
 
-    pub fn register_subnet(
-        &mut self,
-        subnet: Rc<RefCell<Ipv4Subnet>>
+    /// Sets the order in which the identifiers of a client
+    /// are matched against the reservations.
+    ///
+    /// Kinds of key absent from `match_order` are never matched.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let static_allocator = StaticAllocator::new();
+    /// static_allocator.set_match_order(vec![ReservationKind::CircuitId, ReservationKind::HwAddr]);
+    /// ```
+    pub fn set_match_order(
+        &self,
+        match_order: Vec<ReservationKind>
     ) {
-        self.subnet_map.insert_subnet(subnet) 
+        *self.match_order.write().unwrap() = match_order;
+    }
+
+    /// Registers a new [`Ipv4Subnet`] to allocate from.
+    ///
+    /// Addresses of the already registered global reservations
+    /// that belong to this subnet are statically allocated, as
+    /// well as the ones of the reservations left by a previously
+    /// unregistered subnet, which are now scoped to this one.
+    ///
+    /// Returns the already registered [`CidrSubnet`]
+    /// overlapping the given subnet, if any.
+    pub fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        let mut registry = self.registry.write().unwrap();
+        let mut subnet_map = self.subnet_map.write().unwrap();
+        subnet_map.insert_subnet(subnet.clone())?;
+
+        let mut subnet = subnet.lock().unwrap();
+        let cidr = CidrSubnet::new(u32::from(subnet.network()), subnet.prefix());
+        let orphans: Vec<(ReservationKey, Option<CidrSubnet>)> = registry.iter()
+            .filter(|((_, scope), record)| {
+                scope.map_or(false, |scope| subnet_map.get_subnet(scope).is_none()) && subnet.contains(record.ip_addr())
+            })
+            .map(|(index, _)| index.clone())
+            .collect();
+        for (key, scope) in orphans {
+            if registry.contains_key(&(key.clone(), Some(cidr))) {
+                warn!("Reservation of {} in {} conflicts with another reservation.", registry[&(key.clone(), scope)].ip_addr(), cidr);
+                continue;
+            };
+            let record = registry.remove(&(key.clone(), scope)).unwrap();
+            registry.insert((key, Some(cidr)), record);
+        }
+
+        for record in registry.iter().filter(|((_, scope), _)| scope.map_or(true, |scope| scope == cidr)).map(|(_, record)| record) {
+            if subnet.contains(record.ip_addr()) && subnet.force_allocate(record.ip_addr()).is_err() {
+                warn!("Reservation of {} conflicts with another static allocation.", record.ip_addr());
+            };
+        }
+        Ok(())
     }
 
+    /// Registers a new [`StaticAllocation`].
+    ///
+    /// Subnet scoped reservations require the subnet of their
+    /// address to be registered, global ones do not.
+    ///
+    /// Returns an error if the client already has a reservation
+    /// of the same scope, or if the address is already allocated.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let key = ReservationKey::Hostname(String::from("lab-box-1"));
+    /// static_allocator.register_static_allocation(
+    ///     StaticAllocation::new_global(key, Ipv4Addr::new(192, 168, 0, 3), DhcpOptions::new())
+    /// ).unwrap();
+    /// ```
     pub fn register_static_allocation(
-        &mut self,
+        &self,
         alloc: StaticAllocation
     ) -> Result<(), ()> {
-        let subnet_mask = alloc.options()
-            .subnet_mask()
-            .ok_or(())?;
-        let requested_ip = alloc.options()
-            .requested_ip()
-            .ok_or(())?;
-
-        let requested_ip = u32::from(requested_ip); 
-        let subnet_mask = u32::from(subnet_mask);
-
-        let prefix = subnet_mask.count_ones();
-        let network_ip = requested_ip & subnet_mask;
-        let cidr = CidrSubnet::new(network_ip, prefix as u8);
+        let ip_addr = alloc.ip_addr();
+        let (cidr, subnet) = {
+            let subnet_map = self.subnet_map.read().unwrap();
+            (subnet_map.get_matching_cidr(ip_addr), subnet_map.get_matching_subnet(ip_addr))
+        };
 
-        let subnet = self.subnet_map
-                .get_subnet(cidr)
-                .ok_or(())?;
+        let scope = match alloc.scope() {
+            ReservationScope::Subnet => Some(cidr.ok_or(())?),
+            ReservationScope::Global => None,
+        };
 
-        let mut subnet = subnet.borrow_mut();
+        // Registry is locked first so that concurrent registrations
+        // for the same client cannot both succeed
+        let mut registry = self.registry.write().unwrap();
+        let index = (alloc.key().clone(), scope);
+        if registry.contains_key(&index) {
+            return Err(());
+        };
 
-        subnet.force_allocate(Ipv4Addr::from(requested_ip))?;
-        self.registry.insert(alloc.cid(), alloc);
+        if let Some(subnet) = subnet {
+            subnet.lock().unwrap().force_allocate(ip_addr)?;
+        };
+        registry.insert(index, alloc);
         Ok(())
     }
 
+    /// Removes the reservation of the given client in the given
+    /// subnet, or its global reservation if `subnet` is [`None`].
     pub fn remove_static_allocation(
-        &mut self,
-        alloc: HardwareAddress
+        &self,
+        key: ReservationKey,
+        subnet: Option<CidrSubnet>
     ) -> Result<(), ()> {
         
-        let alloc = self.registry.get(&alloc).unwrap();
+        let mut registry = self.registry.write().unwrap();
+        let index = (key, subnet);
+        let alloc = registry.get(&index).ok_or(())?;
 
-        let ip_addr = alloc.options().requested_ip().ok_or(())?;
+        let ip_addr = alloc.ip_addr();
 
         let subnet = self.subnet_map
-                .get_matching_subnet(ip_addr)
-                .ok_or(())?;
-
-        let mut subnet = subnet.borrow_mut();
-        
+                .read()
+                .unwrap()
+                .get_matching_subnet(ip_addr);
 
-        let ip_addr = u32::from(ip_addr);
-        subnet.free_static_alloc(Ipv4Addr::from(ip_addr))?;
-        self.registry.remove(&alloc.cid());
+        if let Some(subnet) = subnet {
+            subnet.lock().unwrap().free_static_alloc(ip_addr)?;
+        };
+        registry.remove(&index);
         Ok(())
     }
 
 }
 
+impl SubnetRegistry for StaticAllocator {
+
+    fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        StaticAllocator::register_subnet(self, subnet)
+    }
+
+    /// Unregisters a subnet, and frees the addresses
+    /// it reserved. Reservations scoped to the subnet are
+    /// kept until another subnet containing them is registered.
+    fn unregister_subnet(
+        &self,
+        cidr: CidrSubnet
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        let registry = self.registry.read().unwrap();
+        let subnet = self.subnet_map
+            .write()
+            .unwrap()
+            .remove_subnet(cidr)?;
+
+        {
+            let mut subnet = subnet.lock().unwrap();
+            for record in registry.iter().filter(|((_, scope), _)| scope.map_or(true, |scope| scope == cidr)).map(|(_, record)| record) {
+                if subnet.contains(record.ip_addr()) {
+                    let _ = subnet.free_static_alloc(record.ip_addr());
+                };
+            }
+        }
+        Some(subnet)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use fp_core::core::packet::PacketType;
 
-    use crate::packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet};
+    use crate::{packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet, relay_agent_info::RelayAgentInfo}, netutils::{hw_addr::HardwareAddress, client_id::ClientId}};
 
     use super::*;
 
 
     #[test]
     fn test_static_alloc_creation() {
-        let mut static_allocator = StaticAllocator::new();
-        let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-        static_allocator.register_subnet(subnet.clone());
+        let static_allocator = StaticAllocator::new();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet.clone()).unwrap();
         let mut options = DhcpOptions::new();
         options.set_requested_ip(Some(Ipv4Addr::new(192, 168, 0, 3)));
         options.set_subnet_mask(Some(Ipv4Addr::new(255, 255, 255, 0)));
         static_allocator.register_static_allocation(
             StaticAllocation::new(
-                HardwareAddress::broadcast(), 
+                ReservationKey::HwAddr(HardwareAddress::broadcast()), 
                 Ipv4Addr::new(192, 168, 0, 3),
                 options
         )).unwrap();
 
-        assert!(!subnet.borrow().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        assert!(!subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
     }
 
     #[test]
     fn test_static_alloc_removal() {
-        let mut static_allocator = StaticAllocator::new();
-        let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-        static_allocator.register_subnet(subnet.clone());
+        let static_allocator = StaticAllocator::new();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet.clone()).unwrap();
         let mut options = DhcpOptions::new();
         options.set_requested_ip(Some(Ipv4Addr::new(192, 168, 0, 3)));
         options.set_subnet_mask(Some(Ipv4Addr::new(255, 255, 255, 0)));
         static_allocator.register_static_allocation(
             StaticAllocation::new(
-                HardwareAddress::broadcast(), 
+                ReservationKey::HwAddr(HardwareAddress::broadcast()), 
                 Ipv4Addr::new(192, 168, 0, 3),
                 options
         )).unwrap();
 
-        static_allocator.remove_static_allocation(HardwareAddress::broadcast()).unwrap();
-        assert!(subnet.borrow().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        static_allocator.remove_static_allocation(
+            ReservationKey::HwAddr(HardwareAddress::broadcast()),
+            Some(CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24))
+        ).unwrap();
+        assert!(subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
     }
 
     #[test]
     fn test_static_allocate() {
-        let mut static_allocator = StaticAllocator::new();
-        let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-        static_allocator.register_subnet(subnet);
+        let static_allocator = StaticAllocator::new();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet).unwrap();
         let mut options = DhcpOptions::new();
         options.set_requested_ip(Some(Ipv4Addr::new(192, 168, 0, 3)));
         options.set_subnet_mask(Some(Ipv4Addr::new(255, 255, 255, 0)));
         static_allocator.register_static_allocation(
             StaticAllocation::new(
-                HardwareAddress::broadcast(), 
+                ReservationKey::ClientId(ClientId::parse(&[0xf,0xf,0xf,0xf,0xf,0xf,0,0,0,0,0,0,0,0,0,0]).unwrap()), 
                 Ipv4Addr::new(192, 168, 0, 3),
                 options
         )).unwrap();
@@ -201,16 +378,16 @@ mod tests {
 
     #[test]
     fn test_static_allocate_options() {
-        let mut static_allocator = StaticAllocator::new();
-        let subnet = Rc::new(RefCell::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
-        static_allocator.register_subnet(subnet);
+        let static_allocator = StaticAllocator::new();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet).unwrap();
         let mut options = DhcpOptions::new();
         options.set_requested_ip(Some(Ipv4Addr::new(192, 168, 0, 3)));
         options.set_subnet_mask(Some(Ipv4Addr::new(255, 255, 255, 0)));
         options.set_log_server(Some(vec![Ipv4Addr::new(10, 1, 1, 3)]));
         static_allocator.register_static_allocation(
             StaticAllocation::new(
-                HardwareAddress::broadcast(), 
+                ReservationKey::ClientId(ClientId::parse(&[0xf,0xf,0xf,0xf,0xf,0xf,0,0,0,0,0,0,0,0,0,0]).unwrap()), 
                 Ipv4Addr::new(192, 168, 0, 3),
                 options
         )).unwrap();
@@ -226,4 +403,279 @@ mod tests {
         assert!(*log_server.get(0).unwrap() == Ipv4Addr::new(10, 1, 1, 3));
     }
 
+    const MAC: [u8; 6] = [0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08];
+
+    fn allocator_with_subnet() -> StaticAllocator {
+        let static_allocator = StaticAllocator::new();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet).unwrap();
+        static_allocator
+    }
+
+    fn reserve(static_allocator: &StaticAllocator, key: ReservationKey, ip_addr: Ipv4Addr) {
+        let mut options = DhcpOptions::new();
+        options.set_requested_ip(Some(ip_addr));
+        options.set_subnet_mask(Some(Ipv4Addr::new(255, 255, 255, 0)));
+        static_allocator.register_static_allocation(StaticAllocation::new(key, ip_addr, options)).unwrap();
+    }
+
+    fn build_packet(client_id: Option<&[u8]>, chaddr: &[u8]) -> DhcpV4Packet {
+        let mut buf = vec![0u8; 240];
+        buf[1] = 1;
+        buf[2] = chaddr.len() as u8;
+        buf[28..28 + chaddr.len()].copy_from_slice(chaddr);
+        if let Some(client_id) = client_id {
+            buf.push(61);
+            buf.push(client_id.len() as u8);
+            buf.extend_from_slice(client_id);
+        }
+        DhcpV4Packet::from_raw_bytes(buf.as_slice())
+    }
+
+    fn allocated_ip(static_allocator: &StaticAllocator, packet: DhcpV4Packet) -> Option<Ipv4Addr> {
+        static_allocator
+            .allocate(DhcpMessage::DhcpDiscover(packet))
+            .map(|draft| draft.ip_addr())
+    }
+
+    #[test]
+    fn test_static_allocate_by_embedded_mac() {
+        let static_allocator = allocator_with_subnet();
+        reserve(&static_allocator, ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+
+        // Common 7 bytes identifier : hardware type followed by the MAC
+        let mut client_id = vec![0x01];
+        client_id.extend_from_slice(&MAC);
+        let packet = build_packet(Some(&client_id[..]), &[0; 6]);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+    }
+
+    #[test]
+    fn test_static_allocate_by_duid() {
+        let static_allocator = allocator_with_subnet();
+        let client_id = [0xff, 0x0c, 0x0d, 0x0e, 0x0f, 0x00, 0x03, 0x00, 0x01, 0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08];
+        reserve(&static_allocator, ReservationKey::ClientId(ClientId::parse(&client_id).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+
+        let packet = build_packet(Some(&client_id[..]), &MAC);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        // Same DUID with another IAID is another client
+        let mut other_client_id = client_id;
+        other_client_id[4] = 0x10;
+        let packet = build_packet(Some(&other_client_id[..]), &[0; 6]);
+        assert!(allocated_ip(&static_allocator, packet).is_none());
+    }
+
+    #[test]
+    fn test_static_allocate_by_opaque_id() {
+        let static_allocator = allocator_with_subnet();
+        let client_id = b"\x00printer-3rd-floor";
+        reserve(&static_allocator, ReservationKey::ClientId(ClientId::parse(client_id).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+
+        let packet = build_packet(Some(&client_id[..]), &MAC);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+    }
+
+    #[test]
+    fn test_static_allocate_by_chaddr() {
+        let static_allocator = allocator_with_subnet();
+        reserve(&static_allocator, ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+
+        // No client identifier at all
+        let packet = build_packet(None, &MAC);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        // Unknown client identifier, falls back to chaddr
+        let packet = build_packet(Some(&b"\x00unknown"[..]), &MAC);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+    }
+
+    #[test]
+    fn test_static_allocate_fallback_order() {
+        let static_allocator = allocator_with_subnet();
+        let mut client_id = vec![0x01];
+        client_id.extend_from_slice(&MAC);
+        reserve(&static_allocator, ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap()), Ipv4Addr::new(192, 168, 0, 4));
+        reserve(&static_allocator, ReservationKey::ClientId(ClientId::parse(&client_id).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+
+        // Client identifier wins over the hardware address
+        let packet = build_packet(Some(&client_id[..]), &MAC);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        // Registering the same key twice is refused
+        let mut options = DhcpOptions::new();
+        options.set_requested_ip(Some(Ipv4Addr::new(192, 168, 0, 5)));
+        options.set_subnet_mask(Some(Ipv4Addr::new(255, 255, 255, 0)));
+        let duplicate = StaticAllocation::new(ReservationKey::ClientId(ClientId::parse(&client_id).unwrap()), Ipv4Addr::new(192, 168, 0, 5), options);
+        assert!(static_allocator.register_static_allocation(duplicate).is_err());
+    }
+
+    #[test]
+    fn test_static_allocate_malformed_client_id() {
+        let static_allocator = allocator_with_subnet();
+        reserve(&static_allocator, ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+
+        for client_id in [vec![], vec![0x01], vec![0xff, 0x01], vec![0x01; 40]] {
+            let packet = build_packet(Some(&client_id[..]), &[0; 6]);
+            assert!(allocated_ip(&static_allocator, packet).is_none());
+        }
+        // Oversized hlen is clamped to the size of chaddr
+        let mut packet = build_packet(None, &MAC);
+        packet.hlen = 255;
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+    }
+
+    fn build_relayed_packet(hostname: &str, circuit_id: &[u8], remote_id: &[u8]) -> DhcpV4Packet {
+        let mut packet = build_packet(None, &MAC);
+        packet.options.set_hostname(Some(hostname.to_string()));
+        packet.options.set_relay_agent_info(Some(RelayAgentInfo::new(
+            Some(circuit_id.to_vec()),
+            Some(remote_id.to_vec())
+        )));
+        packet
+    }
+
+    #[test]
+    fn test_static_allocate_by_relay_agent_info() {
+        let static_allocator = allocator_with_subnet();
+        reserve(&static_allocator, ReservationKey::CircuitId(b"Gi1/0/12".to_vec()), Ipv4Addr::new(192, 168, 0, 3));
+        reserve(&static_allocator, ReservationKey::RemoteId(b"switch-2".to_vec()), Ipv4Addr::new(192, 168, 0, 4));
+
+        let packet = build_relayed_packet("lab-1", b"Gi1/0/12", b"switch-1");
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        let packet = build_relayed_packet("lab-1", b"Gi1/0/13", b"switch-2");
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 4)));
+    }
+
+    #[test]
+    fn test_static_allocate_by_hostname_and_vendor_class() {
+        let static_allocator = allocator_with_subnet();
+        reserve(&static_allocator, ReservationKey::Hostname("lab-box-1".to_string()), Ipv4Addr::new(192, 168, 0, 3));
+        reserve(&static_allocator, ReservationKey::VendorClass("PXEClient".to_string()), Ipv4Addr::new(192, 168, 0, 4));
+
+        let mut packet = build_packet(None, &MAC);
+        packet.options.set_hostname(Some("lab-box-1".to_string()));
+        packet.options.set_vendor_class(Some("PXEClient".to_string()));
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        let mut packet = build_packet(None, &MAC);
+        packet.options.set_vendor_class(Some("PXEClient".to_string()));
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 4)));
+    }
+
+    #[test]
+    fn test_static_allocate_match_order() {
+        let static_allocator = allocator_with_subnet();
+        reserve(&static_allocator, ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap()), Ipv4Addr::new(192, 168, 0, 3));
+        reserve(&static_allocator, ReservationKey::CircuitId(b"Gi1/0/12".to_vec()), Ipv4Addr::new(192, 168, 0, 4));
+        reserve(&static_allocator, ReservationKey::Hostname("lab-box-1".to_string()), Ipv4Addr::new(192, 168, 0, 5));
+
+        // Hardware address comes first by default
+        let packet = build_relayed_packet("lab-box-1", b"Gi1/0/12", b"switch-1");
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        static_allocator.set_match_order(vec![ReservationKind::CircuitId, ReservationKind::HwAddr]);
+        let packet = build_relayed_packet("lab-box-1", b"Gi1/0/12", b"switch-1");
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 4)));
+
+        // Kinds missing from the match order are ignored
+        static_allocator.set_match_order(vec![ReservationKind::ClientId]);
+        let packet = build_relayed_packet("lab-box-1", b"Gi1/0/12", b"switch-1");
+        assert!(allocated_ip(&static_allocator, packet).is_none());
+    }
+
+    fn relayed_from(mut packet: DhcpV4Packet, giaddr: Ipv4Addr) -> DhcpV4Packet {
+        packet.giaddr = giaddr;
+        packet
+    }
+
+    #[test]
+    fn test_out_of_pool_reservation() {
+        let static_allocator = StaticAllocator::new();
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+        let subnet = Arc::new(Mutex::new(subnet));
+        static_allocator.register_subnet(subnet.clone()).unwrap();
+
+        // Reservation address is taken from the allocation, not from its options
+        let key = ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap());
+        static_allocator.register_static_allocation(
+            StaticAllocation::new(key, Ipv4Addr::new(192, 168, 0, 3), DhcpOptions::new())
+        ).unwrap();
+
+        assert!(!subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        let packet = build_packet(None, &MAC);
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        // Subnet scoped reservations need their subnet
+        let key = ReservationKey::Hostname("lab-box-1".to_string());
+        let alloc = StaticAllocation::new(key, Ipv4Addr::new(10, 0, 0, 3), DhcpOptions::new());
+        assert!(static_allocator.register_static_allocation(alloc).is_err());
+    }
+
+    #[test]
+    fn test_global_reservation() {
+        let static_allocator = StaticAllocator::new();
+        let key = ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap());
+
+        // Global reservations may be registered before their subnet
+        static_allocator.register_static_allocation(
+            StaticAllocation::new_global(key.clone(), Ipv4Addr::new(192, 168, 0, 3), DhcpOptions::new())
+        ).unwrap();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet.clone()).unwrap();
+        static_allocator.register_subnet(Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24)))).unwrap();
+        assert!(!subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+
+        let packet = relayed_from(build_packet(None, &MAC), Ipv4Addr::new(192, 168, 0, 1));
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+
+        // Reserved address does not belong to the subnet of the client
+        let packet = relayed_from(build_packet(None, &MAC), Ipv4Addr::new(10, 0, 0, 1));
+        assert!(allocated_ip(&static_allocator, packet).is_none());
+
+        // Subnet scoped reservation wins on its own subnet
+        static_allocator.register_static_allocation(
+            StaticAllocation::new(key.clone(), Ipv4Addr::new(10, 0, 0, 3), DhcpOptions::new())
+        ).unwrap();
+        let packet = relayed_from(build_packet(None, &MAC), Ipv4Addr::new(10, 0, 0, 1));
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(10, 0, 0, 3)));
+
+        static_allocator.remove_static_allocation(key, None).unwrap();
+        assert!(subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        let packet = relayed_from(build_packet(None, &MAC), Ipv4Addr::new(192, 168, 0, 1));
+        assert!(allocated_ip(&static_allocator, packet).is_none());
+    }
+
+    #[test]
+    fn test_resized_subnet_keeps_reservations() {
+        let static_allocator = StaticAllocator::new();
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        static_allocator.register_subnet(subnet.clone()).unwrap();
+        let key = ReservationKey::HwAddr(HardwareAddress::from_slice(&MAC).unwrap());
+        static_allocator.register_static_allocation(
+            StaticAllocation::new(key.clone(), Ipv4Addr::new(192, 168, 0, 3), DhcpOptions::new())
+        ).unwrap();
+        static_allocator.register_static_allocation(
+            StaticAllocation::new_global(ReservationKey::Hostname("lab-box-1".to_string()), Ipv4Addr::new(192, 168, 0, 5), DhcpOptions::new())
+        ).unwrap();
+
+        let cidr = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24);
+        assert!(static_allocator.unregister_subnet(cidr).is_some());
+        assert!(subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        assert!(subnet.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 5)));
+        let packet = relayed_from(build_packet(None, &MAC), Ipv4Addr::new(192, 168, 0, 1));
+        assert!(allocated_ip(&static_allocator, packet).is_none());
+
+        // The reservation follows its address into the resized subnet
+        let resized = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 23)));
+        static_allocator.register_subnet(resized.clone()).unwrap();
+        assert!(!resized.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        assert!(!resized.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 5)));
+        let packet = relayed_from(build_packet(None, &MAC), Ipv4Addr::new(192, 168, 1, 1));
+        assert!(allocated_ip(&static_allocator, packet) == Some(Ipv4Addr::new(192, 168, 0, 3)));
+        assert!(static_allocator.remove_static_allocation(key, Some(CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 23))).is_ok());
+    }
+
 } 
@@ -1,6 +1,10 @@
-use std::{collections::BTreeMap, net::Ipv4Addr, cmp::Ordering, cell::RefCell, rc::Rc};
+use std::{collections::{BTreeMap, HashMap}, fmt, hash::{Hash, Hasher}, net::Ipv4Addr, sync::{Arc, Mutex}};
 
-use crate::leases::ip_subnet::Ipv4Subnet;   
+use log::trace;
+
+use crate::{leases::ip_subnet::Ipv4Subnet, packet::dhcp_packet::DhcpV4Packet};
+
+use super::shared_network::SharedNetwork;
 
 /// Custom representation of a subnet defined
 /// by its CIDR notation (network address + CIDR prefix)
@@ -11,7 +15,7 @@ use crate::leases::ip_subnet::Ipv4Subnet;
 ///
 /// A subnet will be greater than an other subnet if its
 /// network address is bigger.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct CidrSubnet {
     network_addr: u32,
     prefix: u8
@@ -19,30 +23,71 @@ pub struct CidrSubnet {
 
 impl CidrSubnet {
 
+    /// Creates a new `CidrSubnet`. Host bits of the given
+    /// network address are cleared, and the prefix is
+    /// capped to 32.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let cidr = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 12)), 24);
+    /// assert!(cidr.network() == Ipv4Addr::new(192, 168, 0, 0));
+    /// ```
     pub fn new(
         network_addr: u32,
         prefix: u8
     ) -> Self { 
+        let prefix = prefix.min(32);
         Self { 
-            network_addr, 
+            network_addr: network_addr & !Self::host_mask(prefix), 
             prefix } 
         }
 
+    fn host_mask(
+        prefix: u8
+    ) -> u32 {
+        u32::MAX.checked_shr(prefix as u32).unwrap_or(0)
+    }
+
+    pub fn network(
+        &self
+    ) -> Ipv4Addr {
+        Ipv4Addr::from(self.network_addr)
+    }
+
+    pub fn prefix(
+        &self
+    ) -> u8 {
+        self.prefix
+    }
+
     pub fn contains(&self,
         ip: Ipv4Addr
     ) -> bool {
         (self.network_addr <= u32::from(ip)) && (u32::from(self.broadcast()) >= u32::from(ip))
     }
 
+    /// Returns true if both subnets share at least one address
+    pub fn overlaps(
+        &self,
+        other: &CidrSubnet
+    ) -> bool {
+        self.contains(other.network()) | other.contains(self.network())
+    }
+
     pub fn broadcast(
         &self
     ) -> Ipv4Addr {
-        let wildcard_bits_count = 32 - self.prefix;
-        let network_bytes = self.network_addr;
-
-        let broadcast_bytes = network_bytes | ((2 << (wildcard_bits_count - 1)) - 1);
+        Ipv4Addr::from(self.network_addr | Self::host_mask(self.prefix))
+    }
+}
 
-        Ipv4Addr::from(broadcast_bytes)
+impl fmt::Display for CidrSubnet {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        write!(f, "{}/{}", self.network(), self.prefix)
     }
 }
 
@@ -56,6 +101,16 @@ impl PartialEq for CidrSubnet {
 
 impl Eq for CidrSubnet {}
 
+impl Hash for CidrSubnet {
+    fn hash<H: Hasher>(
+        &self,
+        state: &mut H
+    ) {
+        self.network_addr.hash(state);
+        self.prefix.hash(state);
+    }
+}
+
 impl PartialOrd for CidrSubnet {
     fn partial_cmp(
         &self,
@@ -74,9 +129,28 @@ impl Ord for CidrSubnet {
     }
 }
 
+/// `SubnetV4Map` indexes the known [`Ipv4Subnet`]s by their
+/// CIDR notation.
+///
+/// Each subnet is shared behind its own lock, so that allocations
+/// happening in different subnets never wait for each other.
+///
+/// Subnets held by a `SubnetV4Map` never overlap : inserting a
+/// subnet that shares addresses with a registered one fails.
+/// Therefore an [`Ipv4Addr`] belongs to at most one subnet, which is
+/// both the only and the longest prefix match.
+///
+/// Registered subnets can be grouped in [`SharedNetwork`]s, in
+/// which case an [`Ipv4Addr`] matching any member selects the
+/// whole group (see [`SubnetV4Map::get_matching_subnets`]).
 pub struct SubnetV4Map {
 
-    subnets: BTreeMap<CidrSubnet, Rc<RefCell<Ipv4Subnet>>>,
+    subnets: BTreeMap<CidrSubnet, Arc<Mutex<Ipv4Subnet>>>,
+    shared_networks: BTreeMap<String, SharedNetwork>,
+    // Name of the shared network each grouped subnet belongs to
+    memberships: BTreeMap<CidrSubnet, String>,
+    // Subnet directly connected to each listening interface
+    interfaces: HashMap<String, CidrSubnet>,
 
 }
 
@@ -84,50 +158,374 @@ impl SubnetV4Map {
 
     pub fn new()
         -> Self {
-        SubnetV4Map { subnets: BTreeMap::new() }
+        SubnetV4Map { 
+            subnets: BTreeMap::new(),
+            shared_networks: BTreeMap::new(),
+            memberships: BTreeMap::new(),
+            interfaces: HashMap::new(),
+        }
     }
 
+    /// Registers a new [`Ipv4Subnet`].
+    ///
+    /// Returns the already registered [`CidrSubnet`] if
+    /// it overlaps the given subnet, which is then not inserted.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut map = SubnetV4Map::new();
+    /// let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// let overlapping = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 128), 25);
+    /// assert!(map.insert_subnet(Arc::new(Mutex::new(subnet))).is_ok());
+    /// assert!(map.insert_subnet(Arc::new(Mutex::new(overlapping))).is_err());
+    /// ```
     pub fn insert_subnet(
         &mut self,
-        subnet: Rc<RefCell<Ipv4Subnet>>
-    ) {
-        self.subnets.insert(CidrSubnet::new(u32::from(subnet.borrow().network()), subnet.borrow().prefix()), subnet.clone());
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        let cidr = {
+            let subnet = subnet.lock().unwrap();
+            CidrSubnet::new(u32::from(subnet.network()), subnet.prefix())
+        };
+        if let Some(overlapping) = self.find_overlapping(&cidr) {
+            return Err(overlapping);
+        };
+        self.subnets.insert(cidr, subnet);
+        Ok(())
     }
 
-    pub fn get_subnet(&self, subnet: CidrSubnet) -> Option<&Rc<RefCell<Ipv4Subnet>>>{
-        self.subnets.get(&subnet)
+    /// Returns a registered [`CidrSubnet`] overlapping
+    /// the given one, if any.
+    fn find_overlapping(
+        &self,
+        cidr: &CidrSubnet
+    ) -> Option<CidrSubnet> {
+        // Registered subnets never overlap, hence only the closest
+        // subnets on each side of the network address must be checked
+        let before = self.subnets.range(..=*cidr).next_back();
+        let after = self.subnets.range(*cidr..).next();
+        before.into_iter()
+            .chain(after)
+            .map(|(registered, _)| *registered)
+            .find(|registered| registered.overlaps(cidr))
     }
 
+    /// Unregisters the [`Ipv4Subnet`] matching exactly
+    /// the given [`CidrSubnet`], and returns it.
+    pub fn remove_subnet(
+        &mut self,
+        subnet: CidrSubnet
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        // Keys are ordered by network address only, so the
+        // prefix must be checked before removing anything
+        let (registered, _) = self.subnets.get_key_value(&subnet)?;
+        if *registered != subnet {
+            return None;
+        };
+        if let Some(name) = self.memberships.remove(&subnet) {
+            if let Some(shared_network) = self.shared_networks.get_mut(&name) {
+                shared_network.remove_subnet(subnet);
+            };
+        };
+        self.subnets.remove(&subnet)
+    }
+
+    /// Registers a [`SharedNetwork`], replacing any
+    /// shared network with the same name.
+    ///
+    /// Every member must be a registered subnet that does
+    /// not belong to another shared network, otherwise the
+    /// first offending [`CidrSubnet`] is returned.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut map = SubnetV4Map::new();
+    /// let primary = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// let secondary = Ipv4Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24);
+    /// map.insert_subnet(Arc::new(Mutex::new(primary))).unwrap();
+    /// map.insert_subnet(Arc::new(Mutex::new(secondary))).unwrap();
+    /// map.insert_shared_network(SharedNetwork::new(
+    ///     String::from("vlan10"),
+    ///     vec![
+    ///         CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24),
+    ///         CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 0, 0)), 24),
+    ///     ]
+    /// )).unwrap();
+    /// assert!(map.get_matching_subnets(Ipv4Addr::new(10, 0, 0, 1)).len() == 2);
+    /// ```
+    pub fn insert_shared_network(
+        &mut self,
+        shared_network: SharedNetwork
+    ) -> Result<(), CidrSubnet> {
+        for member in shared_network.subnets() {
+            if self.get_subnet(*member).is_none() {
+                return Err(*member);
+            };
+            if let Some(name) = self.memberships.get(member) {
+                if name != shared_network.name() {
+                    return Err(*member);
+                };
+            };
+        };
+
+        self.remove_shared_network(shared_network.name());
+        for member in shared_network.subnets() {
+            self.memberships.insert(*member, shared_network.name().to_string());
+        };
+        self.shared_networks.insert(shared_network.name().to_string(), shared_network);
+        Ok(())
+    }
+
+    /// Unregisters a [`SharedNetwork`] given its name. Its
+    /// member subnets are kept as standalone subnets.
+    pub fn remove_shared_network(
+        &mut self,
+        name: &str
+    ) -> Option<SharedNetwork> {
+        let shared_network = self.shared_networks.remove(name)?;
+        for member in shared_network.subnets() {
+            self.memberships.remove(member);
+        };
+        Some(shared_network)
+    }
+
+    /// Returns the [`SharedNetwork`] a registered subnet belongs to
+    pub fn get_shared_network(
+        &self,
+        subnet: CidrSubnet
+    ) -> Option<&SharedNetwork> {
+        let name = self.memberships.get(&subnet)?;
+        self.shared_networks.get(name)
+    }
+
+    pub fn get_subnet(&self, subnet: CidrSubnet) -> Option<Arc<Mutex<Ipv4Subnet>>>{
+        self.subnets
+            .get_key_value(&subnet)
+            .filter(|(registered, _)| **registered == subnet)
+            .map(|(_, subnet)| subnet.clone())
+    }
+
+    /// Returns the [`Ipv4Subnet`] the given [`Ipv4Addr`]
+    /// belongs to, or `None` if it is outside of every
+    /// registered subnet.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut map = SubnetV4Map::new();
+    /// map.insert_subnet(Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)))).unwrap();
+    /// assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 0, 5)).is_some());
+    /// assert!(map.get_matching_subnet(Ipv4Addr::new(10, 0, 0, 1)).is_none());
+    /// ```
     pub fn get_matching_subnet(
         &self,
         ip: Ipv4Addr
-    ) -> Option<Rc<RefCell<Ipv4Subnet>>> {
-        let available_subnets: Vec<&CidrSubnet> = self.subnets.keys().collect();
-        let subnet = available_subnets.binary_search_by(|elem| {
-            if elem.contains(ip) { 
-                return Ordering::Equal; 
-            }
-            else if ip > elem.broadcast() {
-                return Ordering::Less
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        let cidr = self.get_matching_cidr(ip)?;
+        self.subnets.get(&cidr).cloned()
+    }
+
+    /// Returns the [`CidrSubnet`] of the registered subnet
+    /// the given [`Ipv4Addr`] belongs to.
+    pub fn get_matching_cidr(
+        &self,
+        ip: Ipv4Addr
+    ) -> Option<CidrSubnet> {
+        // The only candidate is the subnet with the greatest
+        // network address lower or equal to the ip
+        let probe = CidrSubnet::new(u32::from(ip), 32);
+        let (cidr, _) = self.subnets.range(..=probe).next_back()?;
+        match cidr.contains(ip) {
+            true => Some(*cidr),
+            false => None
+        }
+    }
+
+    /// Returns every [`Ipv4Subnet`] a client located at the
+    /// given [`Ipv4Addr`] can be given an address in.
+    ///
+    /// If the matching subnet is part of a [`SharedNetwork`],
+    /// all its members are returned in declaration order.
+    /// Otherwise, only the matching subnet is returned.
+    pub fn get_matching_subnets(
+        &self,
+        ip: Ipv4Addr
+    ) -> Vec<Arc<Mutex<Ipv4Subnet>>> {
+        let cidr = match self.get_matching_cidr(ip) {
+            Some(cidr) => cidr,
+            None => return Vec::new()
+        };
+        match self.get_shared_network(cidr) {
+            Some(shared_network) => shared_network.subnets()
+                .iter()
+                .filter_map(|member| self.subnets.get(member).cloned())
+                .collect(),
+            None => self.subnets.get(&cidr).cloned().into_iter().collect()
+        }
+    }
+
+    /// Ties a listening interface to the subnet it is directly
+    /// connected to, given the address and mask of the interface.
+    /// Clients that are not relayed and reach the server through
+    /// that interface will be given an address in that subnet.
+    ///
+    /// Returns the [`CidrSubnet`] of the interface if no such
+    /// subnet is registered.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut map = SubnetV4Map::new();
+    /// map.insert_subnet(Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)))).unwrap();
+    /// map.bind_interface(String::from("eth0"), Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+    /// assert!(map.get_interface_subnets("eth0").len() == 1);
+    /// ```
+    pub fn bind_interface(
+        &mut self,
+        name: String,
+        ip: Ipv4Addr,
+        mask: Ipv4Addr
+    ) -> Result<(), CidrSubnet> {
+        let cidr = CidrSubnet::new(u32::from(ip), u32::from(mask).count_ones() as u8);
+        if self.get_subnet(cidr).is_none() {
+            return Err(cidr);
+        };
+        self.interfaces.insert(name, cidr);
+        Ok(())
+    }
+
+    /// Returns every [`Ipv4Subnet`] a client reaching the server
+    /// through the given interface can be given an address in, including
+    /// the other members of a [`SharedNetwork`].
+    pub fn get_interface_subnets(
+        &self,
+        name: &str
+    ) -> Vec<Arc<Mutex<Ipv4Subnet>>> {
+        match self.interfaces.get(name) {
+            Some(cidr) => self.get_matching_subnets(cidr.network()),
+            None => Vec::new()
+        }
+    }
+
+    /// Returns the subnets the client can be given an
+    /// address in, the whole [`SharedNetwork`] if the
+    /// client's subnet belongs to one.
+    ///
+    /// Relayed clients are located by the relay address. Directly
+    /// connected clients are located by the interface the packet
+    /// was received on, or by their requested ip as a last resort.
+    pub fn get_client_subnets(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> Vec<Arc<Mutex<Ipv4Subnet>>> {
+
+        let bootp_relay_ip = packet.giaddr;
+
+        let client_ip = if bootp_relay_ip == Ipv4Addr::new(0, 0, 0, 0) {
+            if let Some(interface) = &packet.interface {
+                let subnets = self.get_interface_subnets(interface);
+                if !subnets.is_empty() {
+                    return subnets;
+                };
+                trace!("DHCP Message received on an unbound interface {}.", interface);
+            };
+            match packet.options.requested_ip() {
+                Some(req_ip) => req_ip,
+                None => return Vec::new()
             }
-            Ordering::Greater
-            
-        });
-        let subnet = available_subnets.get(subnet.unwrap())?;
-        let subnet = *(*subnet);
+        } else {
+            bootp_relay_ip
+        };
+
+        let subnets = self.get_matching_subnets(client_ip);
+        if subnets.is_empty() {
+            trace!("DHCP Message received from an unknown subnet.");
+        };
+        subnets
 
-        Some(self.subnets.get(&subnet)?.clone())
+    }
+
+    /// Iterates over the registered subnets, ordered
+    /// by network address.
+    pub fn iter(
+        &self
+    ) -> impl Iterator<Item = (&CidrSubnet, &Arc<Mutex<Ipv4Subnet>>)> {
+        self.subnets.iter()
+    }
+
+    pub fn len(
+        &self
+    ) -> usize {
+        self.subnets.len()
+    }
+
+    pub fn is_empty(
+        &self
+    ) -> bool {
+        self.subnets.is_empty()
     }
 
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{net::Ipv4Addr, cell::RefCell, rc::Rc};
+    use std::{net::Ipv4Addr, sync::{Arc, Mutex}};
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
 
     use crate::leases::ip_subnet::Ipv4Subnet;
 
-    use super::SubnetV4Map;
+    use crate::allocators::shared_network::SharedNetwork;
+
+    use super::{CidrSubnet, SubnetV4Map};
+
+    /// Naive reference implementation, checking every
+    /// subnet one after the other.
+    struct LinearMap {
+        subnets: Vec<CidrSubnet>
+    }
+
+    impl LinearMap {
+        fn insert(&mut self, cidr: CidrSubnet) -> bool {
+            if self.subnets.iter().any(|registered| registered.overlaps(&cidr)) {
+                return false;
+            }
+            self.subnets.push(cidr);
+            true
+        }
+
+        fn remove(&mut self, cidr: CidrSubnet) -> bool {
+            let len = self.subnets.len();
+            self.subnets.retain(|registered| *registered != cidr);
+            len != self.subnets.len()
+        }
+
+        fn lookup(&self, ip: Ipv4Addr) -> Option<CidrSubnet> {
+            self.subnets.iter()
+                .filter(|registered| registered.contains(ip))
+                .max_by_key(|registered| registered.prefix())
+                .copied()
+        }
+    }
+
+    fn random_cidr(rng: &mut StdRng) -> CidrSubnet {
+        // Keep subnets in a narrow range so that overlaps are frequent
+        let network = u32::from(Ipv4Addr::new(10, 0, 0, 0)) | rng.gen_range(0..(1 << 16));
+        CidrSubnet::new(network, rng.gen_range(14..=32))
+    }
+
+    fn to_subnet(cidr: CidrSubnet) -> Arc<Mutex<Ipv4Subnet>> {
+        Arc::new(Mutex::new(Ipv4Subnet::new(cidr.network(), cidr.prefix())))
+    }
+
+    fn matching_cidr(map: &SubnetV4Map, ip: Ipv4Addr) -> Option<CidrSubnet> {
+        map.get_matching_subnet(ip).map(|subnet| {
+            let subnet = subnet.lock().unwrap();
+            CidrSubnet::new(u32::from(subnet.network()), subnet.prefix())
+        })
+    }
 
     #[test]
     fn test_get_matching_subnet() {
@@ -135,13 +533,157 @@ mod tests {
         let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
         let subnet2 = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 1, 0), 24);
         let subnet3 = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 3, 0), 24);
-        map.insert_subnet(Rc::new(RefCell::new(subnet)));
-        map.insert_subnet(Rc::new(RefCell::new(subnet2)));
-        map.insert_subnet(Rc::new(RefCell::new(subnet3)));
+        map.insert_subnet(Arc::new(Mutex::new(subnet))).unwrap();
+        map.insert_subnet(Arc::new(Mutex::new(subnet2))).unwrap();
+        map.insert_subnet(Arc::new(Mutex::new(subnet3))).unwrap();
+
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 0, 5)).unwrap().lock().unwrap().network() == Ipv4Addr::new(192, 168, 0, 0));
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 1, 5)).unwrap().lock().unwrap().network() == Ipv4Addr::new(192, 168, 1, 0));
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 3, 5)).unwrap().lock().unwrap().network() == Ipv4Addr::new(192, 168, 3, 0));
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 2, 5)).is_none());
+        assert!(map.get_matching_subnet(Ipv4Addr::new(10, 0, 0, 1)).is_none());
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 4, 0)).is_none());
+    }
 
-        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 0, 5)).unwrap().borrow().network() == Ipv4Addr::new(192, 168, 0, 0));
-        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 1, 5)).unwrap().borrow().network() == Ipv4Addr::new(192, 168, 1, 0));
-        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 3, 5)).unwrap().borrow().network() == Ipv4Addr::new(192, 168, 3, 0));
+    #[test]
+    fn test_overlapping_insertion() {
+        let mut map = SubnetV4Map::new();
+        map.insert_subnet(Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)))).unwrap();
+
+        let inner = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 128), 25);
+        let outer = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 16);
+        let same_network = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 28);
+        let existing = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24);
+        assert!(map.insert_subnet(Arc::new(Mutex::new(inner))) == Err(existing));
+        assert!(map.insert_subnet(Arc::new(Mutex::new(outer))) == Err(existing));
+        assert!(map.insert_subnet(Arc::new(Mutex::new(same_network))) == Err(existing));
+        assert!(map.len() == 1);
+
+        let adjacent = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 1, 0), 24);
+        assert!(map.insert_subnet(Arc::new(Mutex::new(adjacent))).is_ok());
+        assert!(map.len() == 2);
+    }
+
+    #[test]
+    fn test_subnet_removal() {
+        let mut map = SubnetV4Map::new();
+        map.insert_subnet(Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)))).unwrap();
+
+        // Prefix must match too
+        assert!(map.remove_subnet(CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 25)).is_none());
+        assert!(map.remove_subnet(CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24)).is_some());
+        assert!(map.is_empty());
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 0, 5)).is_none());
+    }
+
+    #[test]
+    fn test_shared_network() {
+        let mut map = SubnetV4Map::new();
+        let primary = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24);
+        let secondary = CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 0, 0)), 24);
+        let standalone = CidrSubnet::new(u32::from(Ipv4Addr::new(172, 16, 0, 0)), 16);
+        let unknown = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 1, 0)), 24);
+        map.insert_subnet(to_subnet(primary)).unwrap();
+        map.insert_subnet(to_subnet(secondary)).unwrap();
+        map.insert_subnet(to_subnet(standalone)).unwrap();
+
+        // Members must be registered
+        let invalid = SharedNetwork::new(String::from("vlan10"), vec![primary, unknown]);
+        assert!(map.insert_shared_network(invalid) == Err(unknown));
+        map.insert_shared_network(SharedNetwork::new(String::from("vlan10"), vec![primary, secondary])).unwrap();
+
+        // Members cannot belong to two shared networks
+        let conflicting = SharedNetwork::new(String::from("vlan20"), vec![standalone, secondary]);
+        assert!(map.insert_shared_network(conflicting) == Err(secondary));
+
+        // Any member selects the whole group, in declaration order
+        for ip in [Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(10, 0, 0, 1)] {
+            let networks: Vec<Ipv4Addr> = map.get_matching_subnets(ip)
+                .iter()
+                .map(|subnet| subnet.lock().unwrap().network())
+                .collect();
+            assert!(networks == vec![primary.network(), secondary.network()]);
+        }
+        assert!(map.get_matching_subnets(Ipv4Addr::new(172, 16, 3, 1)).len() == 1);
+        assert!(map.get_matching_subnets(Ipv4Addr::new(192, 168, 1, 1)).is_empty());
+
+        // Removing a member shrinks the group
+        map.remove_subnet(secondary).unwrap();
+        assert!(map.get_shared_network(primary).unwrap().subnets() == [primary]);
+        assert!(map.get_matching_subnets(Ipv4Addr::new(192, 168, 0, 1)).len() == 1);
+
+        assert!(map.remove_shared_network("vlan10").is_some());
+        assert!(map.get_shared_network(primary).is_none());
+    }
+
+    #[test]
+    fn test_interface_binding() {
+        let mut map = SubnetV4Map::new();
+        let eth0 = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24);
+        let eth1 = CidrSubnet::new(u32::from(Ipv4Addr::new(10, 0, 0, 0)), 16);
+        map.insert_subnet(to_subnet(eth0)).unwrap();
+        map.insert_subnet(to_subnet(eth1)).unwrap();
+
+        map.bind_interface(String::from("eth0"), Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+        map.bind_interface(String::from("eth1"), Ipv4Addr::new(10, 0, 3, 1), Ipv4Addr::new(255, 255, 0, 0)).unwrap();
+        // Mask does not match the configured subnet
+        assert!(map.bind_interface(String::from("eth2"), Ipv4Addr::new(10, 0, 3, 1), Ipv4Addr::new(255, 255, 255, 0)).is_err());
+
+        assert!(map.get_interface_subnets("eth0")[0].lock().unwrap().network() == eth0.network());
+        assert!(map.get_interface_subnets("eth1")[0].lock().unwrap().network() == eth1.network());
+        assert!(map.get_interface_subnets("eth2").is_empty());
+    }
+
+    #[test]
+    fn test_prefix_edge_cases() {
+        let host = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 7)), 32);
+        assert!(host.broadcast() == Ipv4Addr::new(192, 168, 0, 7));
+        assert!(host.contains(Ipv4Addr::new(192, 168, 0, 7)));
+        assert!(!host.contains(Ipv4Addr::new(192, 168, 0, 8)));
+
+        let everything = CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 7)), 0);
+        assert!(everything.network() == Ipv4Addr::UNSPECIFIED);
+        assert!(everything.broadcast() == Ipv4Addr::BROADCAST);
+        assert!(everything.overlaps(&host));
+
+        let mut map = SubnetV4Map::new();
+        map.insert_subnet(to_subnet(host)).unwrap();
+        assert!(map.insert_subnet(to_subnet(everything)).is_err());
+        assert!(matching_cidr(&map, Ipv4Addr::new(192, 168, 0, 7)) == Some(host));
+        assert!(map.get_matching_subnet(Ipv4Addr::new(192, 168, 0, 6)).is_none());
+        assert!(map.get_matching_subnet(Ipv4Addr::UNSPECIFIED).is_none());
+        assert!(map.get_matching_subnet(Ipv4Addr::BROADCAST).is_none());
+    }
+
+    #[test]
+    fn test_map_against_linear_implementation() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..20 {
+            let mut map = SubnetV4Map::new();
+            let mut linear = LinearMap { subnets: Vec::new() };
+
+            for _ in 0..200 {
+                let cidr = random_cidr(&mut rng);
+                if rng.gen_bool(0.2) {
+                    assert!(map.remove_subnet(cidr).is_some() == linear.remove(cidr));
+                } else {
+                    assert!(map.insert_subnet(to_subnet(cidr)).is_ok() == linear.insert(cidr));
+                }
+            }
+            assert!(map.len() == linear.subnets.len());
+            let mut sorted = linear.subnets.clone();
+            sorted.sort();
+            assert!(map.iter().map(|(cidr, _)| *cidr).eq(sorted));
+
+            for _ in 0..2000 {
+                let ip = Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + rng.gen_range(0..(1 << 17)));
+                assert!(matching_cidr(&map, ip) == linear.lookup(ip));
+            }
+            for (cidr, _) in map.iter() {
+                assert!(matching_cidr(&map, cidr.network()) == Some(*cidr));
+                assert!(matching_cidr(&map, cidr.broadcast()) == Some(*cidr));
+            }
+        }
     }
 
     #[bench]
@@ -151,7 +693,7 @@ mod tests {
             for j in 0..255 {
                 for i in 0..255 {
                     let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, j, i, 0), 24);
-                    map.insert_subnet(Rc::new(RefCell::new(subnet)));
+                    map.insert_subnet(Arc::new(Mutex::new(subnet))).unwrap();
                 }
             }
 
@@ -165,14 +707,14 @@ mod tests {
             for j in 0..=255 {
                 for i in 0..=255 {
                     let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, j, i, 0), 24);
-                    map.insert_subnet(Rc::new(RefCell::new(subnet)));
+                    map.insert_subnet(Arc::new(Mutex::new(subnet))).unwrap();
                 }
             };
 
             for i in 0..=255 {
                 let first_byte: u8 = rand::random();
                 let last_byte: u8 = rand::random();
-                assert!(map.get_matching_subnet(Ipv4Addr::new(192, i, first_byte, last_byte)).unwrap().borrow().network() == Ipv4Addr::new(192, i, first_byte, 0));
+                assert!(map.get_matching_subnet(Ipv4Addr::new(192, i, first_byte, last_byte)).unwrap().lock().unwrap().network() == Ipv4Addr::new(192, i, first_byte, 0));
                 
             }
         })
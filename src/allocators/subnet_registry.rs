@@ -0,0 +1,39 @@
+use std::sync::{Arc, Mutex};
+
+use crate::leases::ip_subnet::Ipv4Subnet;
+
+use super::{shared_network::SharedNetwork, subnet_map::CidrSubnet};
+
+/// A `SubnetRegistry` holds its own view of the served
+/// [`Ipv4Subnet`]s, which is kept up to date when the
+/// configuration is reloaded.
+///
+/// Like [`Allocator`](super::allocator::Allocator)s, registries
+/// are shared between the tasks of the server and rely on
+/// interior locking.
+pub trait SubnetRegistry: Send + Sync {
+
+    /// Registers a new [`Ipv4Subnet`].
+    ///
+    /// Returns the already registered [`CidrSubnet`]
+    /// overlapping the given subnet, if any.
+    fn register_subnet(&self, subnet: Arc<Mutex<Ipv4Subnet>>) -> Result<(), CidrSubnet>;
+
+    /// Unregisters the [`Ipv4Subnet`] matching exactly
+    /// the given [`CidrSubnet`], and returns it.
+    fn unregister_subnet(&self, subnet: CidrSubnet) -> Option<Arc<Mutex<Ipv4Subnet>>>;
+
+    /// Called once a registered [`Ipv4Subnet`] was
+    /// reconfigured in place.
+    fn subnet_reconfigured(&self, _subnet: &Arc<Mutex<Ipv4Subnet>>) {}
+
+    /// Registers a [`SharedNetwork`], replacing any shared
+    /// network with the same name. Registries ignoring
+    /// shared networks accept any of them.
+    fn register_shared_network(&self, _shared_network: SharedNetwork) -> Result<(), CidrSubnet> {
+        Ok(())
+    }
+
+    /// Unregisters a [`SharedNetwork`] given its name.
+    fn unregister_shared_network(&self, _name: &str) {}
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned while loading a configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CfgError {
+    /// The file could not be read
+    Io(String),
+    /// The file is not a valid YAML configuration
+    Parse(String),
+    /// The configuration is well formed, but inconsistent
+    Invalid(String)
+}
+
+impl fmt::Display for CfgError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfgError::Io(err) => write!(f, "Failed to read config file: {}", err),
+            CfgError::Parse(err) => write!(f, "Failed to parse config file: {}", err),
+            CfgError::Invalid(err) => write!(f, "Invalid configuration: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for CfgError {}
+
+impl From<io::Error> for CfgError {
+    fn from(err : io::Error) -> Self {
+        CfgError::Io(err.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for CfgError {
+    fn from(err : serde_yaml::Error) -> Self {
+        CfgError::Parse(err.to_string())
+    }
+}
@@ -1,12 +1,148 @@
 use std::{fs, net::{Ipv4Addr, IpAddr}};
 
+use chrono::Duration;
+
 use pnet::{datalink::NetworkInterface, util::MacAddr};
 use serde::{Serialize, Deserialize, Serializer, Deserializer, de};
 
+use crate::{netutils::conflict_probe::IcmpProbe, ddns::updater::DdnsSettings, leases::hostname::HostnamePolicy, leasequery::responder::LeaseQuerySettings, failover::peer::FailoverSettings};
+
+use super::error::CfgError;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DhcpCfg {
     #[serde(rename = "network")]
-    network_cfg: NetworkCfg
+    network_cfg: NetworkCfg,
+    #[serde(rename = "transactions", default)]
+    transaction_cfg: TransactionCfg,
+    /// Additional interfaces to listen on
+    #[serde(default)]
+    interfaces: Vec<NetworkCfg>,
+    #[serde(default)]
+    ping_check: PingCheckCfg,
+    /// Dynamic DNS updates, disabled when unset
+    #[serde(default)]
+    ddns: Option<DdnsSettings>,
+    /// Sanitization of the names sent by clients
+    #[serde(default)]
+    hostname_policy: HostnamePolicy,
+    /// Leasequery and bulk leasequery, disabled when unset
+    #[serde(default)]
+    leasequery: Option<LeaseQuerySettings>,
+    /// Failover partner, none when unset
+    #[serde(default)]
+    failover: Option<FailoverSettings>
+}
+
+impl DhcpCfg {
+
+    pub fn network_cfg(&self) -> &NetworkCfg {
+        &self.network_cfg
+    }
+
+    /// Returns every interface the server listens on,
+    /// starting with the main one.
+    ///
+    /// # Examples: 
+    ///
+    /// ```
+    /// let cfg = load_main_cfg("tests/main.yml").unwrap();
+    /// assert!(cfg.interfaces().count() == 1)
+    /// ```
+    pub fn interfaces(&self) -> impl Iterator<Item = &NetworkCfg> {
+        std::iter::once(&self.network_cfg).chain(self.interfaces.iter())
+    }
+
+    pub fn transaction_cfg(&self) -> &TransactionCfg {
+        &self.transaction_cfg
+    }
+
+    pub fn ping_check(&self) -> &PingCheckCfg {
+        &self.ping_check
+    }
+
+    pub fn ddns(&self) -> Option<&DdnsSettings> {
+        self.ddns.as_ref()
+    }
+
+    pub fn hostname_policy(&self) -> &HostnamePolicy {
+        &self.hostname_policy
+    }
+
+    pub fn leasequery(&self) -> Option<&LeaseQuerySettings> {
+        self.leasequery.as_ref()
+    }
+
+    pub fn failover(&self) -> Option<&FailoverSettings> {
+        self.failover.as_ref()
+    }
+
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PingCheckCfg {
+    /// Whether addresses are probed before being offered
+    enabled: bool,
+    /// Time (in milliseconds) to wait for an echo reply
+    timeout: u64,
+}
+
+impl Default for PingCheckCfg {
+    fn default() -> Self {
+        Self { enabled: false, timeout: 500 }
+    }
+}
+
+impl PingCheckCfg {
+
+    /// Returns the [`IcmpProbe`] to run before offering
+    /// an address, if ping checks are enabled.
+    ///
+    /// # Examples: 
+    ///
+    /// ```
+    /// let cfg = load_main_cfg("tests/main.yml").unwrap();
+    /// assert!(cfg.ping_check().probe().unwrap().timeout() == std::time::Duration::from_millis(200))
+    /// ```
+    pub fn probe(&self) -> Option<IcmpProbe> {
+        match self.enabled {
+            true => Some(IcmpProbe::new(std::time::Duration::from_millis(self.timeout))),
+            false => None
+        }
+    }
+
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct TransactionCfg {
+    /// Time (in seconds) during which an offered
+    /// lease is held for the client
+    offer_timeout: u32,
+}
+
+impl Default for TransactionCfg {
+    fn default() -> Self {
+        Self { offer_timeout: 30 }
+    }
+}
+
+impl TransactionCfg {
+
+    /// Returns the time during which an offered
+    /// lease is held before the transaction is aborted.
+    ///
+    /// # Examples: 
+    ///
+    /// ```
+    /// let cfg = load_main_cfg("tests/main.yml").unwrap();
+    /// assert!(cfg.transaction_cfg().offer_timeout() == Duration::seconds(10))
+    /// ```
+    pub fn offer_timeout(&self) -> Duration {
+        Duration::seconds(self.offer_timeout as i64)
+    }
+
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -18,6 +154,12 @@ pub struct NetworkCfg {
 
 impl NetworkCfg {
 
+    /// Returns the name of the network interface
+    /// defined in the config.
+    pub fn name(&self) -> &str {
+        &self.interface.name
+    }
+
     /// Returns the [`MacAddr`] corresponding to
     /// the network interface defined in the config.
     ///
@@ -95,29 +237,20 @@ where
 {
     let if_name: &str = de::Deserialize::deserialize(de)?;
 
-    let interface = pnet::datalink::interfaces()
+    pnet::datalink::interfaces()
         .into_iter()
-        .filter(|iface| iface.name == if_name)
-        .next()
-        .or_else(|| {
-            panic!("Fatal: failed to load main config file \n
-                Encountered the following error: \n
-                Failed to bind network interface {}", if_name);
-        })
-        .unwrap();   
-
-    Ok(interface) 
+        .find(|iface| iface.name == if_name)
+        .ok_or_else(|| de::Error::custom(format!("failed to bind network interface {}", if_name)))
 }
 
-pub fn load_main_cfg(path: &str) -> Result<DhcpCfg, std::io::Error> {
+/// Loads the main configuration file.
+///
+/// Returns an error if the file cannot be read or parsed,
+/// or names a network interface that does not exist.
+pub fn load_main_cfg(path: &str) -> Result<DhcpCfg, CfgError> {
 
-    let cfg = fs::read_to_string(path)
-        .expect("Fatal: failed to load main config file");
-    serde_yaml::from_str(&cfg).map_err(|err| {
-        panic!("Fatal: failed to load main config file \n 
-                Encountered the following error while trying to parse
-                YAML file: {}", err);
-    })
+    let cfg = fs::read_to_string(path)?;
+    Ok(serde_yaml::from_str(&cfg)?)
 
 }
 
@@ -132,6 +265,79 @@ mod tests {
         assert!(cfg.network_cfg.interface.name == "lo0");
     }
 
+    #[test]
+    fn test_load_transaction_cfg() {
+        let cfg = load_main_cfg("tests/main.yml").unwrap();
+        assert!(cfg.transaction_cfg().offer_timeout() == Duration::seconds(10));
+        assert!(TransactionCfg::default().offer_timeout() == Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_load_ping_check() {
+        let cfg = load_main_cfg("tests/main.yml").unwrap();
+        assert!(cfg.ping_check().probe().unwrap().timeout() == std::time::Duration::from_millis(200));
+        assert!(PingCheckCfg::default().probe().is_none());
+    }
+
+    #[test]
+    fn test_load_ddns() {
+        let cfg = load_main_cfg("tests/main.yml").unwrap();
+        let ddns = cfg.ddns().unwrap();
+        assert!(ddns.server == "127.0.0.1:53".parse().unwrap());
+        assert!(ddns.forward_zone == "example.com");
+        assert!(ddns.reverse_zone.as_deref() == Some("168.192.in-addr.arpa"));
+        assert!(ddns.ttl == 300);
+        assert!(ddns.key.as_ref().unwrap().name == "dhcp-key");
+        assert!(crate::ddns::updater::DdnsUpdater::new(ddns.clone()).is_ok());
+    }
+
+    #[test]
+    fn test_load_hostname_policy() {
+        let cfg = load_main_cfg("tests/main.yml").unwrap();
+        let policy = cfg.hostname_policy();
+        assert!(policy.domain_suffix.as_deref() == Some("example.com"));
+        assert!(policy.generated_prefix.as_deref() == Some("dhcp-"));
+        assert!(policy.replacement == "-");
+        assert!(policy.lowercase);
+
+        let cfg: DhcpCfg = serde_yaml::from_str("network:\n  interface: lo0\n").unwrap();
+        assert!(*cfg.hostname_policy() == HostnamePolicy::default());
+    }
+
+    #[test]
+    fn test_load_leasequery() {
+        let cfg = load_main_cfg("tests/main.yml").unwrap();
+        let leasequery = cfg.leasequery().unwrap();
+        assert!(leasequery.bulk_address == "127.0.0.1:6767".parse().unwrap());
+        assert!(leasequery.allowed == vec![Ipv4Addr::new(192, 168, 0, 254)]);
+        assert!(leasequery.timeout() == std::time::Duration::from_secs(30));
+
+        let cfg: DhcpCfg = serde_yaml::from_str("network:\n  interface: lo0\n").unwrap();
+        assert!(cfg.leasequery().is_none());
+    }
+
+    #[test]
+    fn test_load_failover() {
+        let cfg = load_main_cfg("tests/main.yml").unwrap();
+        let failover = cfg.failover().unwrap();
+        assert!(failover.role == crate::failover::load_balance::FailoverRole::Primary);
+        assert!(failover.address == "0.0.0.0:647".parse().unwrap());
+        assert!(failover.partner == "192.168.0.2:647".parse().unwrap());
+        assert!(failover.mclt() == Duration::hours(1));
+        assert!(failover.split == 128);
+        assert!(failover.auto_partner_down == Some(600000));
+
+        let cfg: DhcpCfg = serde_yaml::from_str("network:\n  interface: lo0\n").unwrap();
+        assert!(cfg.failover().is_none());
+    }
+
+    #[test]
+    fn test_load_interfaces() {
+        let cfg: DhcpCfg = serde_yaml::from_str("network:\n  interface: lo0\ninterfaces:\n  - interface: lo0\n").unwrap();
+        let names: Vec<&str> = cfg.interfaces().map(|iface| iface.name()).collect();
+        assert!(names == vec!["lo0", "lo0"]);
+    }
+
     #[test]
     fn test_load_iface_ipv4() {
         let cfg = load_main_cfg("tests/main.yml").unwrap();
@@ -139,5 +345,14 @@ mod tests {
         assert!(cfg.network_cfg.mask().unwrap() == Ipv4Addr::new(255, 0, 0, 0))
     }
 
+    #[test]
+    fn test_load_errors() {
+        assert!(matches!(load_main_cfg("tests/missing.yml"), Err(CfgError::Io(_))));
+
+        // Unknown interfaces are reported instead of aborting
+        let error = serde_yaml::from_str::<DhcpCfg>("network:\n  interface: missing0\n").unwrap_err();
+        assert!(error.to_string().contains("failed to bind network interface missing0"));
+    }
+
 
 }
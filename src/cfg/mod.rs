@@ -1,2 +1,4 @@
 pub mod main_cfg;
-pub mod subnets_cfg;
\ No newline at end of file
+pub mod subnets_cfg;
+pub mod error;
+pub mod reload;
\ No newline at end of file
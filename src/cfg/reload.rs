@@ -0,0 +1,356 @@
+//! Reloads the subnets configuration of a running server,
+//! upon SIGHUP or when its file is modified, without
+//! dropping the leases of the subnets it still declares.
+
+use std::{collections::HashMap, fs, io, net::Ipv4Addr, sync::{Arc, Mutex, Weak}, thread::{self, JoinHandle}, time::{Duration, SystemTime}};
+
+use log::{error, info, warn};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{allocators::{subnet_map::CidrSubnet, subnet_registry::SubnetRegistry}, leases::ip_subnet::Ipv4Subnet};
+
+use super::{error::CfgError, subnets_cfg::{load_subnet_cfg, SubnetCfg}};
+
+/// Differences between the running subnets
+/// and a newly applied configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub added: Vec<CidrSubnet>,
+    pub removed: Vec<CidrSubnet>,
+    /// Running subnets replaced by an overlapping one,
+    /// as (running, configured) pairs
+    pub resized: Vec<(CidrSubnet, CidrSubnet)>,
+    /// Subnets whose CIDR did not change, reconfigured in
+    /// place : their leases and allocation state are kept
+    pub unchanged: Vec<CidrSubnet>,
+    /// Leased addresses out of every configured pool and
+    /// dynamic-bootp range, in ascending order
+    pub out_of_range: Vec<Ipv4Addr>,
+}
+
+// Subnets and shared networks of the last applied configuration
+#[derive(Default)]
+struct Running {
+    subnets: HashMap<CidrSubnet, Arc<Mutex<Ipv4Subnet>>>,
+    shared_networks: Vec<String>,
+}
+
+/// `ConfigReloader` applies the subnets configuration to the
+/// [`SubnetRegistry`]s of the server (allocators, leasequery
+/// responder, failover peer...).
+///
+/// A configuration is fully validated before anything is
+/// changed : an invalid one is rejected, and the running
+/// configuration stays in place. Subnets still configured
+/// with the same CIDR are reconfigured in place, keeping their
+/// leases, while resized subnets take over the leases of the
+/// subnets they replace.
+///
+/// # Examples:
+///
+/// ```
+/// let reloader = Arc::new(ConfigReloader::new("subnets.yml", vec![dynamic_allocator, static_allocator]));
+/// reloader.reload().unwrap();
+/// reloader.watch(Duration::from_secs(5));
+/// tokio::spawn(reloader.clone().reload_on_hangup());
+/// ```
+pub struct ConfigReloader {
+    path: String,
+    registries: Vec<Arc<dyn SubnetRegistry>>,
+    running: Mutex<Running>,
+}
+
+impl ConfigReloader {
+
+    /// Creates a `ConfigReloader` for the given subnets
+    /// configuration file. Nothing is registered until
+    /// the first reload.
+    pub fn new(
+        path: &str,
+        registries: Vec<Arc<dyn SubnetRegistry>>
+    ) -> Self {
+        Self { path: path.to_string(), registries, running: Mutex::new(Running::default()) }
+    }
+
+    /// Returns the running subnets
+    pub fn subnets(&self) -> Vec<Arc<Mutex<Ipv4Subnet>>> {
+        self.running.lock().unwrap().subnets.values().cloned().collect()
+    }
+
+    /// Loads the configuration file again and applies it.
+    pub fn reload(&self) -> Result<ReloadReport, CfgError> {
+        self.apply(&load_subnet_cfg(&self.path)?)
+    }
+
+    /// Applies a configuration, once validated (see
+    /// [`SubnetCfg::validate`]).
+    ///
+    /// Subnets that are no longer configured are unregistered
+    /// first, so that the subnets replacing them can take over
+    /// their leases. Running subnets configured with the same
+    /// CIDR keep their leases, and are updated in place.
+    pub fn apply(
+        &self,
+        cfg: &SubnetCfg
+    ) -> Result<ReloadReport, CfgError> {
+        cfg.validate()?;
+        let shared_networks = cfg.shared_networks()
+            .map_err(|network| CfgError::Invalid(format!("shared network member {} is not a configured subnet", network)))?;
+        let configured: Vec<(CidrSubnet, Ipv4Subnet)> = cfg.subnets.iter()
+            .map(|subnet_cfg| {
+                let subnet = subnet_cfg.subnet();
+                (CidrSubnet::new(u32::from(subnet.network()), subnet.prefix()), subnet)
+            })
+            .collect();
+
+        let mut running = self.running.lock().unwrap();
+        let mut report = ReloadReport::default();
+
+        let mut retired: Vec<CidrSubnet> = running.subnets.keys()
+            .filter(|cidr| !configured.iter().any(|(configured, _)| configured == *cidr))
+            .copied()
+            .collect();
+        retired.sort();
+        let retired: Vec<(CidrSubnet, Arc<Mutex<Ipv4Subnet>>)> = retired.into_iter()
+            .filter_map(|cidr| running.subnets.remove(&cidr).map(|subnet| (cidr, subnet)))
+            .collect();
+        for (cidr, _) in retired.iter() {
+            for registry in self.registries.iter() {
+                registry.unregister_subnet(*cidr);
+            }
+        }
+
+        for (cidr, mut subnet) in configured.iter().cloned() {
+            if let Some(running_subnet) = running.subnets.get(&cidr) {
+                let mut out_of_range = {
+                    let mut running_subnet = running_subnet.lock().unwrap();
+                    let out_of_range = subnet.absorb(&running_subnet);
+                    *running_subnet = subnet;
+                    out_of_range
+                };
+                for registry in self.registries.iter() {
+                    registry.subnet_reconfigured(running_subnet);
+                }
+                // Registries may have let go of some addresses
+                let running_subnet = running_subnet.lock().unwrap();
+                out_of_range.retain(|address| !running_subnet.is_free(*address));
+                report.out_of_range.extend(out_of_range);
+                report.unchanged.push(cidr);
+                continue;
+            };
+
+            let replaced: Vec<&(CidrSubnet, Arc<Mutex<Ipv4Subnet>>)> = retired.iter()
+                .filter(|(retired, _)| retired.overlaps(&cidr))
+                .collect();
+            if replaced.is_empty() {
+                report.added.push(cidr);
+            };
+            for (old, previous) in replaced {
+                subnet.absorb(&previous.lock().unwrap());
+                report.resized.push((*old, cidr));
+            }
+            let subnet = Arc::new(Mutex::new(subnet));
+            for registry in self.registries.iter() {
+                if let Err(overlapping) = registry.register_subnet(subnet.clone()) {
+                    warn!("Reloaded subnet {} overlaps the registered subnet {}.", cidr, overlapping);
+                };
+            }
+            running.subnets.insert(cidr, subnet);
+        }
+
+        // Leases of the replaced subnets are out of range
+        // unless some configured subnet hands them out
+        for (cidr, previous) in retired.iter() {
+            if !report.resized.iter().any(|(retired, _)| retired == cidr) {
+                report.removed.push(*cidr);
+            };
+            let in_range = |address: Ipv4Addr| configured.iter()
+                .any(|(_, subnet)| subnet.in_pool(address) | subnet.in_bootp_range(address));
+            report.out_of_range.extend(previous.lock().unwrap().leased().into_iter().filter(|address| !in_range(*address)));
+        }
+        report.out_of_range.sort();
+        report.out_of_range.dedup();
+
+        // Subnets may move from a shared network to another
+        for name in running.shared_networks.iter() {
+            for registry in self.registries.iter() {
+                registry.unregister_shared_network(name);
+            }
+        }
+        for shared_network in shared_networks.iter() {
+            for registry in self.registries.iter() {
+                if let Err(member) = registry.register_shared_network(shared_network.clone()) {
+                    warn!("Shared network {} could not be registered with {}.", shared_network.name(), member);
+                };
+            }
+        }
+        running.shared_networks = shared_networks.iter().map(|shared_network| shared_network.name().to_string()).collect();
+        Ok(report)
+    }
+
+    /// Reloads the configuration whenever its file is
+    /// modified, checking it every `interval`. Stops once
+    /// the `ConfigReloader` is dropped.
+    pub fn watch(
+        self: &Arc<Self>,
+        interval: Duration
+    ) -> JoinHandle<()> {
+        let reloader: Weak<Self> = Arc::downgrade(self);
+        let mut last_modified = self.modified();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let reloader = match reloader.upgrade() {
+                Some(reloader) => reloader,
+                None => return
+            };
+            let modified = reloader.modified();
+            if modified != last_modified {
+                last_modified = modified;
+                reloader.reload_logged();
+            };
+        })
+    }
+
+    /// Reloads the configuration on every SIGHUP received.
+    pub async fn reload_on_hangup(
+        self: Arc<Self>
+    ) -> io::Result<()> {
+        let mut hangups = signal(SignalKind::hangup())?;
+        while hangups.recv().await.is_some() {
+            self.reload_logged();
+        }
+        Ok(())
+    }
+
+    // Modification time and size of the configuration file
+    fn modified(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    fn reload_logged(&self) {
+        match self.reload() {
+            Ok(report) => {
+                info!("Configuration reloaded : {} subnets added, {} removed, {} resized.", report.added.len(), report.removed.len(), report.resized.len());
+                for address in report.out_of_range {
+                    warn!("Lease of {} is out of the reloaded subnets.", address);
+                }
+            },
+            Err(e) => error!("Configuration not reloaded, the running one is kept : {}", e)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocators::{dynamic_alloc::dynamic_allocator::DynamicAllocator, shared_network::SharedNetwork};
+
+    use super::*;
+
+    fn cidr(network: [u8; 4], prefix: u8) -> CidrSubnet {
+        CidrSubnet::new(u32::from(Ipv4Addr::from(network)), prefix)
+    }
+
+    fn subnet_yaml(network: &str, prefix: u8, settings: &str) -> String {
+        format!("  - - network_addr: {}\n      prefix: {}\n      options: {{}}\n{}    - allocations: []\n", network, prefix, settings)
+    }
+
+    fn cfg(subnets: &[String], shared_networks: &str) -> SubnetCfg {
+        let cfg = format!("defaults: {{}}\nsubnets:\n{}shared_networks: [{}]\n", subnets.concat(), shared_networks);
+        serde_yaml::from_str(&cfg).unwrap()
+    }
+
+    fn reloader() -> (ConfigReloader, Arc<DynamicAllocator>) {
+        let dynamic_allocator = Arc::new(DynamicAllocator::new());
+        let reloader = ConfigReloader::new("tests/subnets.yml", vec![dynamic_allocator.clone()]);
+        (reloader, dynamic_allocator)
+    }
+
+    fn subnet(reloader: &ConfigReloader, cidr: CidrSubnet) -> Arc<Mutex<Ipv4Subnet>> {
+        reloader.running.lock().unwrap().subnets[&cidr].clone()
+    }
+
+    #[test]
+    fn test_reload_keeps_leases() {
+        let (reloader, dynamic_allocator) = reloader();
+        let pool = "      pool_start: 192.168.0.10\n      pool_end: 192.168.0.99\n";
+        let report = reloader.apply(&cfg(&[subnet_yaml("192.168.0.0", 24, pool), subnet_yaml("10.0.0.0", 24, ""), subnet_yaml("172.16.0.0", 24, "")], "")).unwrap();
+        assert!(report.added == vec![cidr([192, 168, 0, 0], 24), cidr([10, 0, 0, 0], 24), cidr([172, 16, 0, 0], 24)]);
+
+        let lan = subnet(&reloader, cidr([192, 168, 0, 0], 24));
+        for host in [10, 11, 50] {
+            lan.lock().unwrap().take(Ipv4Addr::new(192, 168, 0, host)).unwrap();
+        }
+        subnet(&reloader, cidr([10, 0, 0, 0], 24)).lock().unwrap().take(Ipv4Addr::new(10, 0, 0, 200)).unwrap();
+        subnet(&reloader, cidr([172, 16, 0, 0], 24)).lock().unwrap().take(Ipv4Addr::new(172, 16, 0, 7)).unwrap();
+
+        // Shrunk pool, grown and removed subnets, new subnet
+        let pool = "      pool_start: 192.168.0.11\n      pool_end: 192.168.0.99\n      rapid_commit: true\n";
+        let report = reloader.apply(&cfg(&[subnet_yaml("192.168.0.0", 24, pool), subnet_yaml("10.0.0.0", 23, ""), subnet_yaml("192.168.5.0", 24, "")], "")).unwrap();
+        assert!(report.unchanged == vec![cidr([192, 168, 0, 0], 24)]);
+        assert!(report.resized == vec![(cidr([10, 0, 0, 0], 24), cidr([10, 0, 0, 0], 23))]);
+        assert!(report.removed == vec![cidr([172, 16, 0, 0], 24)]);
+        assert!(report.added == vec![cidr([192, 168, 5, 0], 24)]);
+        assert!(report.out_of_range == vec![Ipv4Addr::new(172, 16, 0, 7), Ipv4Addr::new(192, 168, 0, 10)]);
+
+        // Same subnet, reconfigured with its leases
+        assert!(Arc::ptr_eq(&lan, &subnet(&reloader, cidr([192, 168, 0, 0], 24))));
+        assert!(lan.lock().unwrap().rapid_commit());
+        assert!(!lan.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 50)));
+        assert!(lan.lock().unwrap().allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 12));
+        let resized = subnet(&reloader, cidr([10, 0, 0, 0], 23));
+        assert!(!resized.lock().unwrap().is_free(Ipv4Addr::new(10, 0, 0, 200)));
+
+        // Registries follow the running subnets
+        assert!(dynamic_allocator.unregister_subnet(cidr([172, 16, 0, 0], 24)).is_none());
+        assert!(dynamic_allocator.unregister_subnet(cidr([10, 0, 0, 0], 24)).is_none());
+        assert!(Arc::ptr_eq(&dynamic_allocator.unregister_subnet(cidr([10, 0, 0, 0], 23)).unwrap(), &resized));
+        assert!(dynamic_allocator.unregister_subnet(cidr([192, 168, 5, 0], 24)).is_some());
+    }
+
+    #[test]
+    fn test_invalid_configuration_is_rejected() {
+        let (reloader, dynamic_allocator) = reloader();
+        reloader.apply(&cfg(&[subnet_yaml("192.168.0.0", 24, ""), subnet_yaml("10.0.0.0", 24, "")], "{name: lan, subnets: [192.168.0.0, 10.0.0.0]}")).unwrap();
+        let lan = subnet(&reloader, cidr([192, 168, 0, 0], 24));
+        lan.lock().unwrap().take(Ipv4Addr::new(192, 168, 0, 5)).unwrap();
+
+        let overlapping = cfg(&[subnet_yaml("192.168.0.0", 24, ""), subnet_yaml("192.168.0.0", 23, "")], "");
+        assert!(matches!(reloader.apply(&overlapping), Err(CfgError::Invalid(_))));
+        assert!(reloader.subnets().len() == 2);
+        assert!(!lan.lock().unwrap().is_free(Ipv4Addr::new(192, 168, 0, 5)));
+
+        // Dropped shared networks are unregistered
+        let report = reloader.apply(&cfg(&[subnet_yaml("192.168.0.0", 24, ""), subnet_yaml("10.0.0.0", 24, "")], "")).unwrap();
+        assert!(report.unchanged.len() == 2);
+        assert!(report.out_of_range.is_empty());
+        assert!(dynamic_allocator.register_shared_network(SharedNetwork::new(String::from("wifi"), vec![cidr([192, 168, 0, 0], 24)])).is_ok());
+    }
+
+    #[test]
+    fn test_reload_from_file() {
+        let path = std::env::temp_dir().join(format!("subnets-reload-{}.yml", std::process::id()));
+        let path = path.to_str().unwrap();
+        let write = |subnets: &[String]| fs::write(path, format!("defaults: {{}}\nsubnets:\n{}", subnets.concat())).unwrap();
+        write(&[subnet_yaml("192.168.0.0", 24, "")]);
+
+        let reloader = Arc::new(ConfigReloader::new(path, Vec::new()));
+        assert!(reloader.reload().unwrap().added.len() == 1);
+        reloader.watch(Duration::from_millis(10));
+
+        // Broken files are ignored, the next valid one is applied
+        fs::write(path, "subnets: [").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(matches!(reloader.reload(), Err(CfgError::Parse(_))));
+        assert!(reloader.subnets().len() == 1);
+
+        write(&[subnet_yaml("192.168.0.0", 24, ""), subnet_yaml("10.0.0.0", 24, "")]);
+        let mut attempts = 0;
+        while (reloader.subnets().len() != 2) & (attempts < 200) {
+            thread::sleep(Duration::from_millis(10));
+            attempts += 1;
+        }
+        assert!(reloader.subnets().len() == 2);
+        fs::remove_file(path).unwrap();
+    }
+}
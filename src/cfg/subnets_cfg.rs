@@ -1,48 +1,235 @@
-use std::{fs, net::Ipv4Addr};
+use std::{collections::HashSet, fs, net::Ipv4Addr, sync::{Arc, Mutex}};
 
 use log::error;
 use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
 
 
-use crate::{leases::ip_subnet::Ipv4Subnet, packet::dhcp_options::DhcpOptions, netutils::hw_addr::HardwareAddress};
+use crate::{leases::ip_subnet::Ipv4Subnet, packet::dhcp_options::DhcpOptions, netutils::{hw_addr::HardwareAddress, client_id::ClientId}, classes::{client_class::ClientClass, classifier::Classifier}, allocators::{shared_network::SharedNetwork, subnet_map::{CidrSubnet, SubnetV4Map}, static_alloc::static_allocation::{ReservationKey, ReservationKind}}};
+
+use super::error::CfgError;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StaticAllocs{ 
-    #[serde(skip)]
+    /// Only reserved addresses are handed out in the subnet
+    #[serde(default)]
     pub only_static: bool, 
     pub allocations: Vec<AllocCfg> 
 }
 
+/// A reservation, keyed by exactly one of the client
+/// identifiers below.
+///
+/// Hardware addresses and client identifiers are written
+/// as colon separated hexadecimal bytes, other identifiers
+/// as plain strings.
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AllocCfg {
     
     pub ip_addr: Ipv4Addr, 
-    pub hw_addr: String,
+    pub hw_addr: Option<String>,
+    pub client_id: Option<String>,
+    pub hostname: Option<String>,
+    pub circuit_id: Option<String>,
+    pub remote_id: Option<String>,
+    pub vendor_class: Option<String>,
     #[serde(skip)]
     pub options: DhcpOptions
 }
 
+fn _parse_hex(value: &str) -> Option<Vec<u8>> {
+    value.split(':')
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect()
+}
+
+impl AllocCfg {
+
+    /// Returns the [`ReservationKey`] of the reservation.
+    ///
+    /// Returns [`None`] if no identifier, several identifiers,
+    /// or a malformed identifier were configured.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+    /// let key = cfg.subnets[0].1.allocations[0].key().unwrap();
+    /// assert!(key.kind() == ReservationKind::HwAddr);
+    /// ```
+    pub fn key(&self) -> Option<ReservationKey> {
+        let mut keys = Vec::new();
+        if let Some(hw_addr) = &self.hw_addr {
+            keys.push(HardwareAddress::from_slice(&_parse_hex(hw_addr)?).map(ReservationKey::HwAddr));
+        }
+        if let Some(client_id) = &self.client_id {
+            keys.push(ClientId::parse(&_parse_hex(client_id)?).map(ReservationKey::ClientId));
+        }
+        if let Some(hostname) = &self.hostname {
+            keys.push(Some(ReservationKey::Hostname(hostname.clone())));
+        }
+        if let Some(circuit_id) = &self.circuit_id {
+            keys.push(Some(ReservationKey::CircuitId(circuit_id.as_bytes().to_vec())));
+        }
+        if let Some(remote_id) = &self.remote_id {
+            keys.push(Some(ReservationKey::RemoteId(remote_id.as_bytes().to_vec())));
+        }
+        if let Some(vendor_class) = &self.vendor_class {
+            keys.push(Some(ReservationKey::VendorClass(vendor_class.clone())));
+        }
+
+        if keys.len() != 1 {
+            return None;
+        }
+        keys.pop().flatten()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Ipv4SubnetCfg(Ipv4Subnet, StaticAllocs);
 
+impl Ipv4SubnetCfg {
+
+    /// Returns the configured [`Ipv4Subnet`], flagged as
+    /// `only_static` according to its allocations section.
+    pub fn subnet(&self) -> Ipv4Subnet {
+        let mut subnet = self.0.clone();
+        subnet.set_only_static(self.1.only_static);
+        subnet
+    }
+
+    pub fn static_allocs(&self) -> &StaticAllocs {
+        &self.1
+    }
+}
+
+/// Subnets sharing the same physical link, referenced
+/// by their network address.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SharedNetworkCfg {
+    pub name: String,
+    pub subnets: Vec<Ipv4Addr>
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubnetCfg {
     #[serde(rename = "defaults")]
     pub default_options: DhcpOptions,
-    pub subnets: Vec<Ipv4SubnetCfg>
+    pub subnets: Vec<Ipv4SubnetCfg>,
+    #[serde(default)]
+    pub shared_networks: Vec<SharedNetworkCfg>,
+    /// Global reservations, applying on whichever
+    /// subnet the client appears
+    #[serde(default)]
+    pub global_allocations: Vec<AllocCfg>,
+    /// Order in which the identifiers of a client are
+    /// matched against the reservations
+    #[serde(default = "ReservationKind::default_order")]
+    pub match_order: Vec<ReservationKind>,
+    /// Client classes, matched in declaration order
+    #[serde(default)]
+    pub classes: Vec<ClientClass>
 }
 
+impl SubnetCfg {
 
-pub fn load_subnet_cfg(path: &str) -> Result<SubnetCfg, std::io::Error> {
+    /// Resolves the configured shared networks into
+    /// [`SharedNetwork`]s, using the prefix of the
+    /// configured subnets.
+    ///
+    /// Returns the first member network address that
+    /// does not match any configured subnet.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+    /// let shared_networks = cfg.shared_networks().unwrap();
+    /// assert!(shared_networks[0].name() == "vlan10");
+    /// ```
+    pub fn shared_networks(&self) -> Result<Vec<SharedNetwork>, Ipv4Addr> {
+        self.shared_networks.iter().map(|shared_network| {
+            let members = shared_network.subnets.iter().map(|network| {
+                self.subnets.iter()
+                    .find(|subnet| subnet.0.network() == *network)
+                    .map(|subnet| CidrSubnet::new(u32::from(subnet.0.network()), subnet.0.prefix()))
+                    .ok_or(*network)
+            }).collect::<Result<Vec<CidrSubnet>, Ipv4Addr>>()?;
+            Ok(SharedNetwork::new(shared_network.name.clone(), members))
+        }).collect()
+    }
 
-    let cfg = fs::read_to_string(path)
-        .expect("Fatal: failed to load subnets config file");
-    serde_yaml::from_str(&cfg).map_err(|err| {
-        let error = format!("Fatal: failed to load subnets config file \n 
-                Encountered the following error while trying to parse
-                YAML file: {}", err);
-        panic!("{}", error);
-    }) 
+    /// Checks that the configured subnets can all be served
+    /// together : network addresses match their prefix, pools
+    /// and dynamic-bootp ranges lie within their subnet without
+    /// overlapping each other, subnets do not overlap,
+    /// reservations belong to their subnet, shared networks
+    /// group distinct configured subnets, and classes only test
+    /// the membership to classes declared before them.
+    ///
+    /// Returns an error describing the first inconsistency.
+    pub fn validate(&self) -> Result<(), CfgError> {
+        let mut subnet_map = SubnetV4Map::new();
+        for subnet_cfg in self.subnets.iter() {
+            let subnet = &subnet_cfg.0;
+            let cidr = CidrSubnet::new(u32::from(subnet.network()), subnet.prefix());
+            if !(1..=30).contains(&subnet.prefix()) {
+                return Err(CfgError::Invalid(format!("{} has no host addresses to lease", cidr)));
+            };
+            if cidr.network() != subnet.network() {
+                return Err(CfgError::Invalid(format!("{}/{} is not a network address", subnet.network(), subnet.prefix())));
+            };
+
+            let mut ranges = Ipv4Subnet::new(subnet.network(), subnet.prefix());
+            ranges.set_pool(subnet.pool_start(), subnet.pool_end())
+                .map_err(|_| CfgError::Invalid(format!("pool of {} is out of the subnet", cidr)))?;
+            ranges.set_bootp(subnet.bootp().copied())
+                .map_err(|_| CfgError::Invalid(format!("dynamic-bootp range of {} is out of the subnet or overlaps its pool", cidr)))?;
+
+            if let Some(alloc) = subnet_cfg.1.allocations.iter().find(|alloc| !ranges.contains(alloc.ip_addr)) {
+                return Err(CfgError::Invalid(format!("reservation of {} is out of {}", alloc.ip_addr, cidr)));
+            };
+            subnet_map.insert_subnet(Arc::new(Mutex::new(ranges)))
+                .map_err(|overlapping| CfgError::Invalid(format!("{} overlaps {}", cidr, overlapping)))?;
+        }
+
+        let shared_networks = self.shared_networks()
+            .map_err(|network| CfgError::Invalid(format!("shared network member {} is not a configured subnet", network)))?;
+        let mut names = HashSet::new();
+        for shared_network in shared_networks {
+            if !names.insert(shared_network.name().to_string()) {
+                return Err(CfgError::Invalid(format!("shared network {} is declared twice", shared_network.name())));
+            };
+            subnet_map.insert_shared_network(shared_network)
+                .map_err(|member| CfgError::Invalid(format!("{} belongs to several shared networks", member)))?;
+        }
+
+        Classifier::new(self.classes.clone())
+            .map_err(|err| CfgError::Invalid(err.to_string()))?;
+        Ok(())
+    }
+
+}
+
+
+/// Loads the subnets configuration file, and
+/// validates it (see [`SubnetCfg::validate`]).
+///
+/// # Examples:
+///
+/// ```
+/// match load_subnet_cfg("tests/subnets.yml") {
+///     Ok(cfg) => reloader.apply(&cfg),
+///     Err(err) => error!("{}", err)
+/// }
+/// ```
+pub fn load_subnet_cfg(path: &str) -> Result<SubnetCfg, CfgError> {
+
+    let cfg = fs::read_to_string(path)?;
+    let cfg: SubnetCfg = serde_yaml::from_str(&cfg)?;
+    cfg.validate()?;
+    Ok(cfg)
 }
 
 pub fn save_subnet_cfg(path: &str, cfg: SubnetCfg) {
@@ -67,17 +254,160 @@ pub fn save_subnet_cfg(path: &str, cfg: SubnetCfg) {
 mod tests {
     use std::net::Ipv4Addr;
 
-    use super::load_subnet_cfg;
+    use crate::{allocators::static_alloc::static_allocation::{ReservationKey, ReservationKind}, leases::{selection::SelectionKind, boot::ClientArch}};
+
+    use super::{load_subnet_cfg, CfgError, SubnetCfg};
 
     #[test]
     fn test_load_subnet_cfg() {
         let subnets = load_subnet_cfg("tests/subnets.yml");
         let subnet = subnets.unwrap()
             .subnets
-            .pop().unwrap();
+            .remove(0);
 
         assert!(subnet.0.network() == Ipv4Addr::new(192, 168, 0, 0));
         assert!(subnet.0.prefix() == 24);
+        assert!(subnet.0.lease_times().default_lease_time == 3600);
+        assert!(subnet.0.lease_times().min_lease_time == 600);
+        assert!(subnet.0.lease_times().max_lease_time == 7200);
+    }
+
+    #[test]
+    fn test_load_shared_networks() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        let shared_networks = cfg.shared_networks().unwrap();
+
+        assert!(shared_networks.len() == 1);
+        assert!(shared_networks[0].name() == "vlan10");
+        assert!(shared_networks[0].subnets().len() == 2);
+        assert!(shared_networks[0].subnets()[1].network() == Ipv4Addr::new(10, 0, 0, 0));
+        assert!(shared_networks[0].subnets()[1].prefix() == 24);
+    }
+
+    #[test]
+    fn test_load_reservations() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        let keys: Vec<Option<ReservationKey>> = cfg.subnets[0].1.allocations
+            .iter()
+            .map(|alloc| alloc.key())
+            .collect();
+
+        assert!(keys[0].as_ref().unwrap().kind() == ReservationKind::HwAddr);
+        assert!(keys[1] == Some(ReservationKey::CircuitId(b"Gi1/0/12".to_vec())));
+        assert!(keys[2] == Some(ReservationKey::Hostname("lab-box-1".to_string())));
+        // Several identifiers for a single reservation are refused
+        assert!(keys[3].is_none());
+        assert!(cfg.match_order == vec![ReservationKind::CircuitId, ReservationKind::HwAddr, ReservationKind::Hostname]);
+    }
+
+    #[test]
+    fn test_load_selection() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        assert!(cfg.subnets[0].0.selection() == SelectionKind::Sequential);
+        assert!(cfg.subnets[1].0.selection() == SelectionKind::ClientHash);
+    }
+
+    #[test]
+    fn test_load_pool_and_global_reservations() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        let subnet = &cfg.subnets[0].0;
+        assert!(subnet.pool_start() == Ipv4Addr::new(192, 168, 0, 100));
+        assert!(subnet.pool_end() == Ipv4Addr::new(192, 168, 0, 199));
+        assert!(!subnet.in_pool(cfg.subnets[0].1.allocations[0].ip_addr));
+
+        assert!(!cfg.subnets[0].subnet().only_static());
+        assert!(cfg.subnets[1].subnet().only_static());
+
+        assert!(cfg.global_allocations.len() == 1);
+        assert!(cfg.global_allocations[0].key() == Some(ReservationKey::RemoteId(b"switch-1".to_vec())));
+    }
+
+    #[test]
+    fn test_load_boot() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        let boot = cfg.subnets[0].0.boot().unwrap();
+        assert!(boot.next_server == Some(Ipv4Addr::new(192, 168, 0, 10)));
+        assert!(boot.filename.as_deref() == Some("pxelinux.0"));
+        assert!(boot.arch_filenames[&ClientArch::EfiArm64] == "grub/grubaa64.efi");
+        assert!(cfg.subnets[1].0.boot().is_none());
+
+        let class_boot = cfg.classes[0].boot().unwrap();
+        assert!(class_boot.ipxe_filename.as_deref() == Some("http://boot.example.com/boot.ipxe"));
+        assert!(class_boot.filename.is_none());
+    }
+
+    #[test]
+    fn test_load_bootp() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        let bootp = cfg.subnets[0].0.bootp().unwrap();
+        assert!(bootp.dynamic_range() == Some((Ipv4Addr::new(192, 168, 0, 200), Ipv4Addr::new(192, 168, 0, 219))));
+        assert!(cfg.subnets[1].0.bootp().is_none());
+    }
+
+    #[test]
+    fn test_load_classes() {
+        let cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        assert!(cfg.classes.len() == 3);
+        assert!(cfg.classes[0].name() == "pxe");
+        assert!(cfg.classes[0].criteria().vendor_class.as_deref() == Some("PXEClient"));
+        assert!(cfg.classes[0].options().domain_name().unwrap() == "pxe.example.com");
+        assert!(cfg.classes[1].criteria().hostname.as_ref().unwrap().is_match("printer-12"));
+        assert!(cfg.subnets[1].0.client_classes() == ["printers"]);
+        assert!(cfg.classes[2].criteria().expr.as_ref().unwrap().source() == "member(\"pxe\") and pkt.giaddr in 10.0.0.0/8");
+
+        // Malformed patterns are reported at load time
+        let error = serde_yaml::from_str::<SubnetCfg>(
+            "defaults: {}\nsubnets: []\nclasses:\n  - name: \"broken\"\n    match:\n      hostname: \"printer-(\"\n"
+        ).unwrap_err();
+        assert!(error.to_string().contains("invalid hostname pattern"));
+
+        let error = serde_yaml::from_str::<SubnetCfg>(
+            "defaults: {}\nsubnets: []\nclasses:\n  - name: \"broken\"\n    match:\n      expr: \"pkt.giaddr in 10.0.0.1\"\n"
+        ).unwrap_err();
+        assert!(error.to_string().contains("\"in\" expects subnet on its right, found address"));
+    }
+
+    fn subnet_yaml(network: &str, prefix: u8, settings: &str, allocations: &str) -> String {
+        format!("  - - network_addr: {}\n      prefix: {}\n      options: {{}}\n{}    - allocations: [{}]\n", network, prefix, settings, allocations)
+    }
+
+    fn validate(subnets: &[String], shared_networks: &str) -> Result<(), CfgError> {
+        let cfg = format!("defaults: {{}}\nsubnets:\n{}shared_networks: [{}]\n", subnets.concat(), shared_networks);
+        serde_yaml::from_str::<SubnetCfg>(&cfg).unwrap().validate()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(load_subnet_cfg("tests/subnets.yml").unwrap().validate().is_ok());
+        assert!(matches!(load_subnet_cfg("tests/missing.yml"), Err(CfgError::Io(_))));
+
+        let valid = [subnet_yaml("192.168.0.0", 24, "", ""), subnet_yaml("192.168.1.0", 24, "", "")];
+        assert!(validate(&valid, "{name: lan, subnets: [192.168.0.0, 192.168.1.0]}").is_ok());
+
+        let overlapping = [subnet_yaml("192.168.0.0", 23, "", ""), subnet_yaml("192.168.1.0", 24, "", "")];
+        assert!(validate(&overlapping, "") == Err(CfgError::Invalid(String::from("192.168.1.0/24 overlaps 192.168.0.0/23"))));
+        assert!(validate(&[subnet_yaml("192.168.0.12", 24, "", "")], "").is_err());
+        assert!(validate(&[subnet_yaml("192.168.0.0", 32, "", "")], "").is_err());
+        let pool = "      pool_start: 192.168.0.100\n      pool_end: 192.168.1.10\n";
+        assert!(validate(&[subnet_yaml("192.168.0.0", 24, pool, "")], "").is_err());
+        let bootp = "      bootp: {dynamic_start: 192.168.0.250, dynamic_end: 192.168.0.255}\n";
+        assert!(validate(&[subnet_yaml("192.168.0.0", 24, bootp, "")], "").is_err());
+        let bootp = "      pool_start: 192.168.0.100\n      pool_end: 192.168.0.199\n      bootp: {dynamic_start: 192.168.0.150, dynamic_end: 192.168.0.219}\n";
+        assert!(validate(&[subnet_yaml("192.168.0.0", 24, bootp, "")], "").is_err());
+        let bootp = "      bootp: {dynamic_start: 192.168.0.200, dynamic_end: 192.168.0.219}\n";
+        assert!(validate(&[subnet_yaml("192.168.0.0", 24, bootp, "")], "").is_err());
+        let reservation = "{hostname: lab-box-1, ip_addr: 10.0.0.3}";
+        assert!(validate(&[subnet_yaml("192.168.0.0", 24, "", reservation)], "").is_err());
+
+        // Shared networks group distinct configured subnets
+        assert!(validate(&valid, "{name: lan, subnets: [192.168.0.0, 10.0.0.0]}").is_err());
+        assert!(validate(&valid, "{name: lan, subnets: [192.168.0.0]}, {name: wifi, subnets: [192.168.0.0]}").is_err());
+        assert!(validate(&valid, "{name: lan, subnets: [192.168.0.0]}, {name: lan, subnets: [192.168.1.0]}").is_err());
+
+        // Classes are matched in declaration order
+        let mut cfg = load_subnet_cfg("tests/subnets.yml").unwrap();
+        cfg.classes.reverse();
+        assert!(matches!(cfg.validate(), Err(CfgError::Invalid(message)) if message.contains("\"pxe\", which is not declared before it")));
     }
 
 }
@@ -0,0 +1,278 @@
+use std::{collections::HashSet, fmt};
+
+use crate::{packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet}, leases::boot::BootSettings};
+
+use super::client_class::ClientClass;
+
+/// Inconsistency found between the declared [`ClientClass`]es.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClassifierError {
+    /// A class is declared twice
+    DuplicateClass(String),
+    /// A class expression tests the membership to a class
+    /// which is not declared before it
+    UnknownMember { class: String, member: String },
+}
+
+impl fmt::Display for ClassifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassifierError::DuplicateClass(class) => write!(f, "class \"{}\" is declared twice", class),
+            ClassifierError::UnknownMember { class, member } => {
+                write!(f, "class \"{}\" tests the membership to \"{}\", which is not declared before it", class, member)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClassifierError {}
+
+/// `Classifier` matches incoming packets against the
+/// configured [`ClientClass`]es.
+///
+/// Matched class names are attached to the packet
+/// (see [`DhcpV4Packet::classes`]), so that allocators can
+/// restrict subnets to some classes, and the options of
+/// the classes can be layered on top of the lease options.
+///
+/// # Examples:
+///
+/// ```
+/// let classifier = Classifier::new(cfg.classes).unwrap();
+/// classifier.classify(&mut packet);
+/// assert!(packet.classes.contains(&String::from("voip")));
+/// ```
+pub struct Classifier {
+
+    classes: Vec<ClientClass>,
+
+}
+
+impl Classifier {
+
+    /// Creates a `Classifier` from classes listed in
+    /// declaration order.
+    ///
+    /// Returns an error if a class is declared twice, or if
+    /// an expression tests the membership to a class that is
+    /// not declared before its own, as it would never match.
+    pub fn new(
+        classes: Vec<ClientClass>
+    ) -> Result<Self, ClassifierError> {
+        let mut names = HashSet::new();
+        for class in classes.iter() {
+            let members = class.criteria().expr.as_ref().map(|expr| expr.members()).unwrap_or_default();
+            if let Some(member) = members.into_iter().find(|member| !names.contains(member)) {
+                return Err(ClassifierError::UnknownMember { class: class.name().to_string(), member: member.to_string() });
+            };
+            if !names.insert(class.name()) {
+                return Err(ClassifierError::DuplicateClass(class.name().to_string()));
+            };
+        }
+        Ok(Self { classes })
+    }
+
+    /// Attaches the names of every class the
+    /// packet matches, in declaration order.
+    ///
+    /// Classes are matched one after the other, so that
+    /// expressions can test the membership of the packet
+    /// to the classes declared before.
+    pub fn classify(
+        &self,
+        packet: &mut DhcpV4Packet
+    ) {
+        packet.classes.clear();
+        for class in self.classes.iter() {
+            if class.matches(packet) {
+                packet.classes.push(class.name().to_string());
+            };
+        }
+    }
+
+    /// Layers the options of the classes attached to the
+    /// packet on top of the given options. Classes declared
+    /// first take precedence.
+    pub fn apply_options(
+        &self,
+        packet: &DhcpV4Packet,
+        options: &mut DhcpOptions
+    ) {
+        self.classes
+            .iter()
+            .rev()
+            .filter(|class| packet.classes.iter().any(|name| name == class.name()))
+            .for_each(|class| options.overlay(class.options()));
+    }
+
+    /// Returns the network boot settings of the first
+    /// declared class of the packet that has some.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let boot = classifier.boot(&packet).or(subnet.boot());
+    /// if let Some(boot) = boot {
+    ///     boot.apply(&packet, &mut reply);
+    /// }
+    /// ```
+    pub fn boot(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> Option<&BootSettings> {
+        self.classes
+            .iter()
+            .filter(|class| packet.classes.iter().any(|name| name == class.name()))
+            .find_map(|class| class.boot())
+    }
+
+    pub fn classes(&self) -> &[ClientClass] {
+        &self.classes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use fp_core::core::packet::PacketType;
+
+    use crate::{classes::{client_class::{ClassMatch, HostnamePattern, HwAddrPrefix}, expression::Expression}, packet::relay_agent_info::RelayAgentInfo};
+
+    use super::*;
+
+    fn packet() -> DhcpV4Packet {
+        let mut buf = vec![0u8; 240];
+        buf[1] = 1;
+        buf[2] = 6;
+        buf[28..34].copy_from_slice(&[0x00, 0x04, 0xf2, 0x35, 0x76, 0x08]);
+        DhcpV4Packet::from_raw_bytes(buf.as_slice())
+    }
+
+    fn class(name: &str, criteria: ClassMatch) -> ClientClass {
+        let mut options = DhcpOptions::new();
+        options.set_domain_name(Some(format!("{}.example.com", name)));
+        ClientClass::new(name.to_string(), criteria, options)
+    }
+
+    fn classifier() -> Classifier {
+        Classifier::new(vec![
+            class("pxe", ClassMatch { vendor_class: Some(String::from("PXEClient")), ..Default::default() }),
+            class("ipxe", ClassMatch { user_class: Some(String::from("iPXE")), ..Default::default() }),
+            class("voip", ClassMatch { chaddr_prefix: Some(HwAddrPrefix::new(vec![0x00, 0x04, 0xf2])), ..Default::default() }),
+            class("access", ClassMatch {
+                circuit_id: Some(String::from("Gi1/0/12")),
+                remote_id: Some(String::from("switch-1")),
+                ..Default::default()
+            }),
+            class("printers", ClassMatch { hostname: Some(HostnamePattern::new("^printer-[0-9]+$").unwrap()), ..Default::default() }),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_classify() {
+        let classifier = classifier();
+
+        let mut pxe = packet();
+        pxe.options.set_vendor_class(Some(String::from("PXEClient:Arch:00000:UNDI:002001")));
+        pxe.options.set_user_class(Some(b"iPXE".to_vec()));
+        classifier.classify(&mut pxe);
+        assert!(pxe.classes == vec!["pxe", "ipxe", "voip"]);
+
+        let mut printer = packet();
+        printer.chadd.raw[0] = 0x10;
+        printer.options.set_hostname(Some(String::from("printer-12")));
+        printer.options.set_relay_agent_info(Some(RelayAgentInfo::new(Some(b"Gi1/0/12".to_vec()), None)));
+        classifier.classify(&mut printer);
+        assert!(printer.classes == vec!["printers"]);
+
+        // Every criterion of a class must match
+        printer.options.set_relay_agent_info(Some(RelayAgentInfo::new(Some(b"Gi1/0/12".to_vec()), Some(b"switch-1".to_vec()))));
+        printer.options.set_hostname(Some(String::from("printer-12b")));
+        classifier.classify(&mut printer);
+        assert!(printer.classes == vec!["access"]);
+    }
+
+    #[test]
+    fn test_member_of_previous_class() {
+        let mut classes = classifier().classes().to_vec();
+        classes.push(class("remote-pxe", ClassMatch {
+            expr: Some(Expression::parse("member(\"pxe\") and pkt.giaddr in 10.0.0.0/8").unwrap()),
+            ..Default::default()
+        }));
+        let classifier = Classifier::new(classes).unwrap();
+
+        let mut pxe = packet();
+        pxe.options.set_vendor_class(Some(String::from("PXEClient")));
+        classifier.classify(&mut pxe);
+        assert!(!pxe.classes.contains(&String::from("remote-pxe")));
+
+        pxe.giaddr = Ipv4Addr::new(10, 1, 0, 1);
+        classifier.classify(&mut pxe);
+        assert!(pxe.classes == vec!["pxe", "voip", "remote-pxe"]);
+    }
+
+    #[test]
+    fn test_class_options() {
+        let classifier = classifier();
+        let mut pxe = packet();
+        pxe.options.set_vendor_class(Some(String::from("PXEClient")));
+        classifier.classify(&mut pxe);
+
+        let mut options = DhcpOptions::new();
+        options.set_domain_name(Some(String::from("example.com")));
+        options.set_hostname(Some(String::from("client")));
+        classifier.apply_options(&pxe, &mut options);
+
+        // First declared class wins
+        assert!(options.domain_name().unwrap() == "pxe.example.com");
+        assert!(options.hostname().unwrap() == "client");
+    }
+
+    #[test]
+    fn test_class_boot() {
+        let mut classes = classifier().classes().to_vec();
+        let boot = BootSettings { filename: Some(String::from("pxelinux.0")), ..Default::default() };
+        classes[0].set_boot(Some(boot.clone()));
+        classes[2].set_boot(Some(BootSettings::default()));
+        let classifier = Classifier::new(classes).unwrap();
+
+        let mut pxe = packet();
+        pxe.options.set_vendor_class(Some(String::from("PXEClient")));
+        classifier.classify(&mut pxe);
+        assert!(classifier.boot(&pxe) == Some(&boot));
+
+        let mut phone = packet();
+        classifier.classify(&mut phone);
+        assert!(classifier.boot(&phone) == Some(&BootSettings::default()));
+        phone.chadd.raw[0] = 0x10;
+        classifier.classify(&mut phone);
+        assert!(classifier.boot(&phone).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_class() {
+        let classes = vec![class("pxe", ClassMatch::default()), class("pxe", ClassMatch::default())];
+        assert!(Classifier::new(classes).err() == Some(ClassifierError::DuplicateClass(String::from("pxe"))));
+    }
+
+    #[test]
+    fn test_unknown_member() {
+        let remote_pxe = class("remote-pxe", ClassMatch {
+            expr: Some(Expression::parse("member(\"pxe\") and pkt.giaddr in 10.0.0.0/8").unwrap()),
+            ..Default::default()
+        });
+        let unknown = ClassifierError::UnknownMember { class: String::from("remote-pxe"), member: String::from("pxe") };
+
+        // Classes are matched in declaration order
+        let classes = vec![remote_pxe.clone(), class("pxe", ClassMatch::default())];
+        assert!(Classifier::new(classes).err() == Some(unknown.clone()));
+        assert!(Classifier::new(vec![remote_pxe.clone()]).err() == Some(unknown));
+
+        // Nor can a class test its own membership
+        let self_member = class("loop", ClassMatch { expr: Some(Expression::parse("member(\"loop\")").unwrap()), ..Default::default() });
+        assert!(Classifier::new(vec![self_member]).is_err());
+
+        assert!(Classifier::new(vec![class("pxe", ClassMatch::default()), remote_pxe]).is_ok());
+    }
+}
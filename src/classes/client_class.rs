@@ -0,0 +1,240 @@
+use std::fmt;
+
+use regex::Regex;
+use serde::{Serialize, Deserialize, Serializer, Deserializer, de};
+use serde_with::skip_serializing_none;
+
+use crate::{packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet}, leases::boot::BootSettings};
+
+use super::expression::Expression;
+
+/// Regular expression matched against the hostname
+/// of a client, compiled when the configuration is loaded.
+#[derive(Clone, Debug)]
+pub struct HostnamePattern(Regex);
+
+impl HostnamePattern {
+
+    pub fn new(
+        pattern: &str
+    ) -> Result<Self, regex::Error> {
+        Ok(Self(Regex::new(pattern)?))
+    }
+
+    pub fn is_match(
+        &self,
+        hostname: &str
+    ) -> bool {
+        self.0.is_match(hostname)
+    }
+}
+
+impl PartialEq for HostnamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for HostnamePattern {}
+
+impl Serialize for HostnamePattern {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        s.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for HostnamePattern {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de> {
+        let pattern = String::deserialize(de)?;
+        HostnamePattern::new(&pattern).map_err(|err| {
+            de::Error::custom(format!("invalid hostname pattern \"{}\" : {}", pattern, err))
+        })
+    }
+}
+
+/// Hardware address prefix, written as colon separated
+/// hexadecimal bytes (e.g. `00:04:f2`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HwAddrPrefix(Vec<u8>);
+
+impl HwAddrPrefix {
+
+    pub fn new(
+        prefix: Vec<u8>
+    ) -> Self {
+        Self(prefix)
+    }
+
+    pub fn is_prefix_of(
+        &self,
+        hw_addr: &[u8]
+    ) -> bool {
+        hw_addr.starts_with(&self.0)
+    }
+}
+
+impl fmt::Display for HwAddrPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.0.iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(f, "{}", bytes.join(":"))
+    }
+}
+
+impl Serialize for HwAddrPrefix {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        s.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for HwAddrPrefix {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de> {
+        let prefix = String::deserialize(de)?;
+        prefix.split(':')
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map(HwAddrPrefix)
+            .map_err(|_| de::Error::custom(format!("invalid hardware address prefix \"{}\"", prefix)))
+    }
+}
+
+/// Criteria a client must meet to belong to a [`ClientClass`].
+///
+/// Every criterion that is set must match, a `ClassMatch`
+/// without any criterion matches every client.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassMatch {
+    /// Prefix of the vendor class identifier (option 60)
+    pub vendor_class: Option<String>,
+    /// One of the user classes (option 77)
+    pub user_class: Option<String>,
+    /// Prefix of the client hardware address (chaddr)
+    pub chaddr_prefix: Option<HwAddrPrefix>,
+    /// Agent Circuit ID inserted by the relay (option 82)
+    pub circuit_id: Option<String>,
+    /// Agent Remote ID inserted by the relay (option 82)
+    pub remote_id: Option<String>,
+    /// Regular expression matching the hostname (option 12)
+    pub hostname: Option<HostnamePattern>,
+    /// Free form [`Expression`] over the packet
+    pub expr: Option<Expression>,
+}
+
+impl ClassMatch {
+
+    /// Returns true if the given packet meets every criterion.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let criteria = ClassMatch { vendor_class: Some(String::from("PXEClient")), ..Default::default() };
+    /// assert!(criteria.matches(&packet));
+    /// ```
+    pub fn matches(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> bool {
+        let options = &packet.options;
+        let relay_agent_info = options.relay_agent_info();
+
+        let vendor_class = self.vendor_class.as_ref().map_or(true, |prefix| {
+            options.vendor_class().map_or(false, |vendor_class| vendor_class.starts_with(prefix.as_str()))
+        });
+        let user_class = self.user_class.as_ref().map_or(true, |class| {
+            options.user_classes().iter().any(|user_class| user_class == class.as_bytes())
+        });
+        let chaddr_prefix = self.chaddr_prefix.as_ref().map_or(true, |prefix| {
+            prefix.is_prefix_of(&packet.chadd.raw[..(packet.hlen as usize).min(16)])
+        });
+        let circuit_id = self.circuit_id.as_ref().map_or(true, |circuit_id| {
+            relay_agent_info
+                .and_then(|info| info.circuit_id())
+                .map_or(false, |id| id == circuit_id.as_bytes())
+        });
+        let remote_id = self.remote_id.as_ref().map_or(true, |remote_id| {
+            relay_agent_info
+                .and_then(|info| info.remote_id())
+                .map_or(false, |id| id == remote_id.as_bytes())
+        });
+        let hostname = self.hostname.as_ref().map_or(true, |pattern| {
+            options.hostname().map_or(false, |hostname| pattern.is_match(hostname))
+        });
+        let expr = self.expr.as_ref().map_or(true, |expr| expr.evaluate(packet));
+
+        vendor_class && user_class && chaddr_prefix && circuit_id && remote_id && hostname && expr
+    }
+}
+
+/// A `ClientClass` groups clients sharing some properties
+/// (PXE clients, VoIP phones, printers...) so that they can be
+/// given their own pools and options.
+///
+/// # Examples:
+///
+/// ```yaml
+/// classes:
+///   - name: "voip"
+///     match:
+///       chaddr_prefix: "00:04:f2"
+///     options:
+///       domain_name: "voip.example.com"
+///   - name: "remote-pxe"
+///     match:
+///       expr: 'option[60].text starts_with "PXEClient" and pkt.giaddr in 10.0.0.0/8'
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientClass {
+    name: String,
+    #[serde(rename = "match", default)]
+    criteria: ClassMatch,
+    /// Options given to the members of the class
+    #[serde(default)]
+    options: DhcpOptions,
+    /// Network boot settings of the members of the class,
+    /// taking precedence over those of the subnet
+    #[serde(default)]
+    boot: Option<BootSettings>,
+}
+
+impl ClientClass {
+
+    pub fn new(
+        name: String,
+        criteria: ClassMatch,
+        options: DhcpOptions
+    ) -> Self {
+        Self { name, criteria, options, boot: None }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn criteria(&self) -> &ClassMatch {
+        &self.criteria
+    }
+
+    pub fn options(&self) -> &DhcpOptions {
+        &self.options
+    }
+
+    pub fn boot(&self) -> Option<&BootSettings> {
+        self.boot.as_ref()
+    }
+
+    pub fn set_boot(&mut self, boot: Option<BootSettings>) {
+        self.boot = boot;
+    }
+
+    pub fn matches(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> bool {
+        self.criteria.matches(packet)
+    }
+}
@@ -0,0 +1,800 @@
+//! Boolean expressions over a DHCP packet, used to decide
+//! class membership from the configuration files.
+//!
+//! Expressions are parsed and type checked when the configuration
+//! is loaded, then evaluated against every incoming packet. They
+//! cannot loop nor call out of the evaluator, so a misbehaving
+//! expression cannot harm the server.
+//!
+//! ```text
+//! expr     := and ("or" and)*
+//! and      := not ("and" not)*
+//! not      := "not" not | compare
+//! compare  := operand (("==" | "!=" | "starts_with" | "ends_with" | "contains" | "in") operand)?
+//! operand  := "(" expr ")" | literal | accessor
+//! literal  := "text" | 0xhex | integer | ipv4 | ipv4/prefix | "true" | "false"
+//! accessor := "option" "[" code "]" "." ("text" | "hex" | "exists")
+//!           | "relay" "." ("circuit_id" | "remote_id")
+//!           | "pkt" "." ("giaddr" | "ciaddr" | "yiaddr" | "siaddr" | "chaddr"
+//!                      | "htype" | "hlen" | "hops" | "msgtype" | "interface")
+//!           | "member" "(" "class" ")"
+//! ```
+
+use std::{fmt, net::Ipv4Addr};
+
+use serde::{Serialize, Deserialize, Serializer, Deserializer, de};
+
+use crate::{allocators::subnet_map::CidrSubnet, packet::dhcp_packet::DhcpV4Packet};
+
+// Nesting of parentheses and "not" operators, bounded
+// so that parsing cannot overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// Type of the value of an expression.
+///
+/// Option contents, hardware addresses and string literals
+/// are all strings of bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExprType {
+    Bool,
+    String,
+    Int,
+    Address,
+    Subnet,
+}
+
+impl fmt::Display for ExprType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExprType::Bool => "boolean",
+            ExprType::String => "string",
+            ExprType::Int => "integer",
+            ExprType::Address => "address",
+            ExprType::Subnet => "subnet",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Error found while parsing or type checking an expression,
+/// at the given column (starting at 1) of the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExprError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl ExprError {
+    fn new(position: usize, message: String) -> Self {
+        Self { column: position + 1, message }
+    }
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Text(Vec<u8>),
+    Int(u32),
+    Address(Ipv4Addr),
+    Subnet(CidrSubnet),
+    Eq,
+    Ne,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "\"{}\"", ident),
+            Token::Text(_) => write!(f, "string literal"),
+            Token::Int(int) => write!(f, "{}", int),
+            Token::Address(ip) => write!(f, "{}", ip),
+            Token::Subnet(subnet) => write!(f, "{}", subnet),
+            Token::Eq => write!(f, "\"==\""),
+            Token::Ne => write!(f, "\"!=\""),
+            Token::LParen => write!(f, "\"(\""),
+            Token::RParen => write!(f, "\")\""),
+            Token::LBracket => write!(f, "\"[\""),
+            Token::RBracket => write!(f, "\"]\""),
+            Token::Dot => write!(f, "\".\""),
+            Token::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+fn _parse_literal(word: &str) -> Option<Token> {
+    if let Some(hex) = word.strip_prefix("0x") {
+        if hex.is_empty() || (hex.len() % 2 != 0) {
+            return None;
+        };
+        return (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .map(Token::Text);
+    };
+    if let Some((ip, prefix)) = word.split_once('/') {
+        let ip: Ipv4Addr = ip.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+        return Some(Token::Subnet(CidrSubnet::new(u32::from(ip), prefix)));
+    };
+    if word.contains('.') {
+        return word.parse().ok().map(Token::Address);
+    };
+    word.parse().ok().map(Token::Int)
+}
+
+/// Splits the source into tokens, paired with their position
+fn _tokenize(source: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let token = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => { i += 1; Token::LParen }
+            ')' => { i += 1; Token::RParen }
+            '[' => { i += 1; Token::LBracket }
+            ']' => { i += 1; Token::RBracket }
+            '.' => { i += 1; Token::Dot }
+            '=' | '!' => {
+                if chars.get(i + 1) != Some(&'=') {
+                    return Err(ExprError::new(start, format!("unexpected \"{}\", expected \"{}=\"", chars[i], chars[i])));
+                };
+                i += 2;
+                match chars[start] {
+                    '=' => Token::Eq,
+                    _ => Token::Ne
+                }
+            }
+            '"' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(ExprError::new(start, String::from("unterminated string literal"))),
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(c) => {
+                            text.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                Token::Text(text.into_bytes())
+            }
+            c if c.is_ascii_digit() => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || (chars[i] == '.') || (chars[i] == '/')) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                _parse_literal(&word)
+                    .ok_or(ExprError::new(start, format!("invalid literal \"{}\"", word)))?
+            }
+            c if c.is_ascii_alphabetic() || (c == '_') => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || (chars[i] == '_')) {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            }
+            c => return Err(ExprError::new(start, format!("unexpected character \"{}\"", c)))
+        };
+        tokens.push((start, token));
+    }
+    tokens.push((chars.len(), Token::End));
+    Ok(tokens)
+}
+
+/// Packet fields readable with `pkt.<field>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PacketField {
+    Giaddr,
+    Ciaddr,
+    Yiaddr,
+    Siaddr,
+    Chaddr,
+    Htype,
+    Hlen,
+    Hops,
+    MsgType,
+    Interface,
+}
+
+impl PacketField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "giaddr" => Some(PacketField::Giaddr),
+            "ciaddr" => Some(PacketField::Ciaddr),
+            "yiaddr" => Some(PacketField::Yiaddr),
+            "siaddr" => Some(PacketField::Siaddr),
+            "chaddr" => Some(PacketField::Chaddr),
+            "htype" => Some(PacketField::Htype),
+            "hlen" => Some(PacketField::Hlen),
+            "hops" => Some(PacketField::Hops),
+            "msgtype" => Some(PacketField::MsgType),
+            "interface" => Some(PacketField::Interface),
+            _ => None
+        }
+    }
+
+    fn expr_type(&self) -> ExprType {
+        match self {
+            PacketField::Giaddr | PacketField::Ciaddr | PacketField::Yiaddr | PacketField::Siaddr => ExprType::Address,
+            PacketField::Chaddr | PacketField::Interface => ExprType::String,
+            PacketField::Htype | PacketField::Hlen | PacketField::Hops | PacketField::MsgType => ExprType::Int,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Eq,
+    Ne,
+    StartsWith,
+    EndsWith,
+    Contains,
+    In,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn parse(token: &Token) -> Option<Self> {
+        match token {
+            Token::Eq => Some(BinaryOp::Eq),
+            Token::Ne => Some(BinaryOp::Ne),
+            Token::Ident(ident) => match ident.as_str() {
+                "starts_with" => Some(BinaryOp::StartsWith),
+                "ends_with" => Some(BinaryOp::EndsWith),
+                "contains" => Some(BinaryOp::Contains),
+                "in" => Some(BinaryOp::In),
+                _ => None
+            },
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::StartsWith => "starts_with",
+            BinaryOp::EndsWith => "ends_with",
+            BinaryOp::Contains => "contains",
+            BinaryOp::In => "in",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        };
+        write!(f, "\"{}\"", name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Bool(bool),
+    Text(Vec<u8>),
+    Int(u32),
+    Address(Ipv4Addr),
+    Subnet(CidrSubnet),
+    OptionValue(u8),
+    OptionExists(u8),
+    CircuitId,
+    RemoteId,
+    Packet(PacketField),
+    Member(String),
+    Not(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    // Operands of a chain of "and" or "or", kept flat so
+    // that long chains do not make the tree any deeper
+    Chain(BinaryOp, Vec<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Bool(bool),
+    Bytes(Vec<u8>),
+    Int(u32),
+    Address(Ipv4Addr),
+    Subnet(CidrSubnet),
+}
+
+// Nodes are paired with their type, and with the position
+// of their first token for error reporting
+type Typed = (usize, Node, ExprType);
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    current: usize,
+    depth: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current].1
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.current].0
+    }
+
+    fn advance(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.current].clone();
+        if self.current < self.tokens.len() - 1 {
+            self.current += 1;
+        };
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> ExprError {
+        ExprError::new(self.position(), format!("unexpected {}, expected {}", self.peek(), expected))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ExprError> {
+        match *self.peek() == token {
+            true => {
+                self.advance();
+                Ok(())
+            }
+            false => Err(self.unexpected(&token.to_string()))
+        }
+    }
+
+    fn expect_ident(&mut self, expected: &str) -> Result<String, ExprError> {
+        match self.advance() {
+            (_, Token::Ident(ident)) => Ok(ident),
+            (position, token) => Err(ExprError::new(position, format!("unexpected {}, expected {}", token, expected)))
+        }
+    }
+
+    fn nest(&mut self, position: usize) -> Result<(), ExprError> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(ExprError::new(position, format!("expression is nested more than {} levels deep", MAX_DEPTH))),
+            false => Ok(())
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(ident) if ident == keyword)
+    }
+
+    fn parse_or(&mut self) -> Result<Typed, ExprError> {
+        self.parse_chain(BinaryOp::Or, "or", Self::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Typed, ExprError> {
+        self.parse_chain(BinaryOp::And, "and", Self::parse_not)
+    }
+
+    fn parse_chain(
+        &mut self,
+        op: BinaryOp,
+        keyword: &str,
+        parse_operand: fn(&mut Self) -> Result<Typed, ExprError>
+    ) -> Result<Typed, ExprError> {
+        let (position, lhs, lhs_type) = parse_operand(self)?;
+        let mut operands = vec![lhs];
+        while self.is_keyword(keyword) {
+            self.advance();
+            let (rhs_position, rhs, rhs_type) = parse_operand(self)?;
+            if lhs_type != ExprType::Bool {
+                return Err(ExprError::new(position, format!("{} expects {} on its left, found {}", op, ExprType::Bool, lhs_type)));
+            };
+            if rhs_type != ExprType::Bool {
+                return Err(ExprError::new(rhs_position, format!("{} expects {} on its right, found {}", op, ExprType::Bool, rhs_type)));
+            };
+            operands.push(rhs);
+        }
+        match operands.len() {
+            1 => Ok((position, operands.remove(0), lhs_type)),
+            _ => Ok((position, Node::Chain(op, operands), ExprType::Bool))
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<Typed, ExprError> {
+        if !self.is_keyword("not") {
+            return self.parse_compare();
+        };
+        let (position, _) = self.advance();
+        self.nest(position)?;
+        let (operand_position, operand, operand_type) = self.parse_not()?;
+        self.depth -= 1;
+        if operand_type != ExprType::Bool {
+            return Err(ExprError::new(operand_position, format!("\"not\" expects a boolean, found {}", operand_type)));
+        };
+        Ok((position, Node::Not(Box::new(operand)), ExprType::Bool))
+    }
+
+    fn parse_compare(&mut self) -> Result<Typed, ExprError> {
+        let lhs = self.parse_operand()?;
+        match BinaryOp::parse(self.peek()) {
+            Some(op) => {
+                self.advance();
+                let rhs = self.parse_operand()?;
+                Self::check_binary(op, lhs, rhs)
+            }
+            None => Ok(lhs)
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Typed, ExprError> {
+        let (position, token) = self.advance();
+        let (node, expr_type) = match token {
+            Token::LParen => {
+                self.nest(position)?;
+                let (_, node, expr_type) = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                self.depth -= 1;
+                (node, expr_type)
+            }
+            Token::Text(text) => (Node::Text(text), ExprType::String),
+            Token::Int(int) => (Node::Int(int), ExprType::Int),
+            Token::Address(ip) => (Node::Address(ip), ExprType::Address),
+            Token::Subnet(subnet) => (Node::Subnet(subnet), ExprType::Subnet),
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => (Node::Bool(true), ExprType::Bool),
+                "false" => (Node::Bool(false), ExprType::Bool),
+                "option" => self.parse_option()?,
+                "relay" => {
+                    self.expect(Token::Dot)?;
+                    let field_position = self.position();
+                    match self.expect_ident("relay agent sub-option")?.as_str() {
+                        "circuit_id" => (Node::CircuitId, ExprType::String),
+                        "remote_id" => (Node::RemoteId, ExprType::String),
+                        field => return Err(ExprError::new(field_position, format!("unknown relay agent sub-option \"{}\"", field)))
+                    }
+                }
+                "pkt" => {
+                    self.expect(Token::Dot)?;
+                    let field_position = self.position();
+                    let name = self.expect_ident("packet field")?;
+                    let field = PacketField::parse(&name)
+                        .ok_or(ExprError::new(field_position, format!("unknown packet field \"{}\"", name)))?;
+                    (Node::Packet(field), field.expr_type())
+                }
+                "member" => {
+                    self.expect(Token::LParen)?;
+                    let class = match self.advance() {
+                        (_, Token::Text(class)) => String::from_utf8(class)
+                            .map_err(|_| ExprError::new(position, String::from("class name is not valid UTF-8")))?,
+                        (position, token) => return Err(ExprError::new(position, format!("unexpected {}, expected class name", token)))
+                    };
+                    self.expect(Token::RParen)?;
+                    (Node::Member(class), ExprType::Bool)
+                }
+                _ => return Err(ExprError::new(position, format!("unknown identifier \"{}\"", ident)))
+            },
+            token => return Err(ExprError::new(position, format!("unexpected {}, expected a value", token)))
+        };
+        Ok((position, node, expr_type))
+    }
+
+    fn parse_option(&mut self) -> Result<(Node, ExprType), ExprError> {
+        self.expect(Token::LBracket)?;
+        let code = match self.advance() {
+            (_, Token::Int(code)) if (1..=254).contains(&code) => code as u8,
+            (position, token) => return Err(ExprError::new(position, format!("unexpected {}, expected an option code between 1 and 254", token)))
+        };
+        self.expect(Token::RBracket)?;
+        self.expect(Token::Dot)?;
+        let position = self.position();
+        match self.expect_ident("\"text\", \"hex\" or \"exists\"")?.as_str() {
+            "text" | "hex" => Ok((Node::OptionValue(code), ExprType::String)),
+            "exists" => Ok((Node::OptionExists(code), ExprType::Bool)),
+            representation => Err(ExprError::new(position, format!("unknown option representation \"{}\", expected \"text\", \"hex\" or \"exists\"", representation)))
+        }
+    }
+
+    fn check_binary(
+        op: BinaryOp,
+        (position, lhs, lhs_type): Typed,
+        (rhs_position, rhs, rhs_type): Typed
+    ) -> Result<Typed, ExprError> {
+        let (expected_lhs, expected_rhs) = match op {
+            BinaryOp::And | BinaryOp::Or => (ExprType::Bool, ExprType::Bool),
+            BinaryOp::StartsWith | BinaryOp::EndsWith | BinaryOp::Contains => (ExprType::String, ExprType::String),
+            BinaryOp::In => (ExprType::Address, ExprType::Subnet),
+            BinaryOp::Eq | BinaryOp::Ne => (lhs_type, lhs_type),
+        };
+        if lhs_type != expected_lhs {
+            return Err(ExprError::new(position, format!("{} expects {} on its left, found {}", op, expected_lhs, lhs_type)));
+        };
+        if rhs_type != expected_rhs {
+            return Err(ExprError::new(rhs_position, format!("{} expects {} on its right, found {}", op, expected_rhs, rhs_type)));
+        };
+        Ok((position, Node::Binary(op, Box::new(lhs), Box::new(rhs)), ExprType::Bool))
+    }
+}
+
+fn _members<'a>(node: &'a Node, members: &mut Vec<&'a str>) {
+    match node {
+        Node::Member(class) => members.push(class),
+        Node::Not(operand) => _members(operand, members),
+        Node::Binary(_, lhs, rhs) => {
+            _members(lhs, members);
+            _members(rhs, members);
+        }
+        Node::Chain(_, operands) => {
+            for operand in operands {
+                _members(operand, members);
+            }
+        }
+        _ => ()
+    }
+}
+
+fn _evaluate(node: &Node, packet: &DhcpV4Packet) -> Value {
+    match node {
+        Node::Bool(value) => Value::Bool(*value),
+        Node::Text(text) => Value::Bytes(text.clone()),
+        Node::Int(int) => Value::Int(*int),
+        Node::Address(ip) => Value::Address(*ip),
+        Node::Subnet(subnet) => Value::Subnet(*subnet),
+        // Missing options read as empty strings
+        Node::OptionValue(code) => Value::Bytes(packet.options.option_bytes(*code).unwrap_or_default()),
+        Node::OptionExists(code) => Value::Bool(packet.options.option_bytes(*code).is_some()),
+        Node::CircuitId => Value::Bytes(
+            packet.options.relay_agent_info().and_then(|info| info.circuit_id()).cloned().unwrap_or_default()
+        ),
+        Node::RemoteId => Value::Bytes(
+            packet.options.relay_agent_info().and_then(|info| info.remote_id()).cloned().unwrap_or_default()
+        ),
+        Node::Packet(field) => match field {
+            PacketField::Giaddr => Value::Address(packet.giaddr),
+            PacketField::Ciaddr => Value::Address(packet.ciaddr),
+            PacketField::Yiaddr => Value::Address(packet.yiaddr),
+            PacketField::Siaddr => Value::Address(packet.siaddr),
+            PacketField::Chaddr => Value::Bytes(packet.chadd.raw[..(packet.hlen as usize).min(16)].to_vec()),
+            PacketField::Htype => Value::Int(packet.htype as u32),
+            PacketField::Hlen => Value::Int(packet.hlen as u32),
+            PacketField::Hops => Value::Int(packet.hops as u32),
+            PacketField::MsgType => Value::Int(packet.options.message_type().unwrap_or(0) as u32),
+            PacketField::Interface => Value::Bytes(packet.interface.clone().unwrap_or_default().into_bytes()),
+        },
+        Node::Member(class) => Value::Bool(packet.classes.contains(class)),
+        Node::Not(operand) => Value::Bool(_evaluate(operand, packet) == Value::Bool(false)),
+        Node::Binary(op, lhs, rhs) => {
+            let result = match (op, _evaluate(lhs, packet), _evaluate(rhs, packet)) {
+                (BinaryOp::Eq, lhs, rhs) => lhs == rhs,
+                (BinaryOp::Ne, lhs, rhs) => lhs != rhs,
+                (BinaryOp::StartsWith, Value::Bytes(lhs), Value::Bytes(rhs)) => lhs.starts_with(&rhs),
+                (BinaryOp::EndsWith, Value::Bytes(lhs), Value::Bytes(rhs)) => lhs.ends_with(&rhs),
+                (BinaryOp::Contains, Value::Bytes(lhs), Value::Bytes(rhs)) => {
+                    rhs.is_empty() || lhs.windows(rhs.len()).any(|window| window == rhs.as_slice())
+                }
+                (BinaryOp::In, Value::Address(ip), Value::Subnet(subnet)) => subnet.contains(ip),
+                // Ruled out by the type checker
+                _ => false
+            };
+            Value::Bool(result)
+        }
+        // Short-circuit boolean operators
+        Node::Chain(op, operands) => {
+            let mut values = operands.iter().map(|operand| _evaluate(operand, packet) == Value::Bool(true));
+            Value::Bool(match op {
+                BinaryOp::And => values.all(|value| value),
+                _ => values.any(|value| value)
+            })
+        }
+    }
+}
+
+/// A type checked boolean `Expression` over a [`DhcpV4Packet`].
+///
+/// # Examples:
+///
+/// ```
+/// let expr = Expression::parse("option[60].text starts_with \"PXEClient\" and pkt.giaddr in 10.0.0.0/8").unwrap();
+/// assert!(expr.evaluate(&packet));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+
+    /// Parses and type checks the given source.
+    ///
+    /// Returns the first [`ExprError`] found, including
+    /// expressions that do not evaluate to a boolean.
+    pub fn parse(
+        source: &str
+    ) -> Result<Self, ExprError> {
+        let mut parser = Parser { tokens: _tokenize(source)?, current: 0, depth: 0 };
+        let (position, root, expr_type) = parser.parse_or()?;
+        if *parser.peek() != Token::End {
+            return Err(parser.unexpected("\"and\", \"or\" or end of expression"));
+        };
+        if expr_type != ExprType::Bool {
+            return Err(ExprError::new(position, format!("expression must be a boolean, found {}", expr_type)));
+        };
+        Ok(Self { source: source.to_string(), root })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the names of the classes whose membership
+    /// is tested with `member("class")`.
+    pub fn members(&self) -> Vec<&str> {
+        let mut members = Vec::new();
+        _members(&self.root, &mut members);
+        members
+    }
+
+    /// Returns true if the packet satisfies the expression.
+    pub fn evaluate(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> bool {
+        _evaluate(&self.root, packet) == Value::Bool(true)
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Expression {}
+
+impl Serialize for Expression {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        s.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de> {
+        let source = String::deserialize(de)?;
+        Expression::parse(&source).map_err(|err| {
+            de::Error::custom(format!("invalid expression \"{}\" : {}", source, err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp_core::core::packet::PacketType;
+
+    use crate::packet::relay_agent_info::RelayAgentInfo;
+
+    use super::*;
+
+    fn packet() -> DhcpV4Packet {
+        let mut buf = vec![0u8; 240];
+        buf[1] = 1;
+        buf[2] = 6;
+        buf[28..34].copy_from_slice(&[0x00, 0x04, 0xf2, 0x35, 0x76, 0x08]);
+        let mut packet = DhcpV4Packet::from_raw_bytes(buf.as_slice());
+        packet.giaddr = Ipv4Addr::new(10, 1, 2, 254);
+        packet.options.set_message_type(Some(1));
+        packet.options.set_vendor_class(Some(String::from("PXEClient:Arch:00007")));
+        packet.options.set_relay_agent_info(Some(RelayAgentInfo::new(Some(b"Gi1/0/12".to_vec()), None)));
+        packet
+    }
+
+    fn eval(source: &str) -> bool {
+        Expression::parse(source).unwrap().evaluate(&packet())
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert!(eval("option[60].text starts_with \"PXEClient\" and pkt.giaddr in 10.0.0.0/8"));
+        assert!(!eval("option[60].text starts_with \"PXEClient\" and pkt.giaddr in 192.168.0.0/16"));
+        assert!(eval("option[60].text ends_with \"00007\" or false"));
+        assert!(eval("option[60].exists and not option[12].exists"));
+        assert!(eval("option[12].text == \"\""));
+        assert!(eval("option[53].hex == 0x01 and pkt.msgtype == 1"));
+        assert!(eval("pkt.chaddr starts_with 0x0004f2 and pkt.hlen == 6"));
+        assert!(eval("relay.circuit_id == \"Gi1/0/12\" and relay.remote_id == \"\""));
+        assert!(eval("pkt.giaddr == 10.1.2.254 and pkt.ciaddr != 10.1.2.254"));
+        assert!(eval("not (pkt.hops != 0 or option[60].text contains \"UNDI\")"));
+        assert!(eval("option[60].text contains \"Arch\""));
+    }
+
+    #[test]
+    fn test_member() {
+        let expr = Expression::parse("member(\"voip\") and not member(\"printers\")").unwrap();
+        let mut packet = packet();
+        assert!(!expr.evaluate(&packet));
+        packet.classes = vec![String::from("voip")];
+        assert!(expr.evaluate(&packet));
+        assert!(expr.members() == vec!["voip", "printers"]);
+    }
+
+    #[test]
+    fn test_precedence() {
+        // "and" binds tighter than "or", "not" tighter than "and"
+        assert!(eval("true or false and false"));
+        assert!(!eval("(true or false) and false"));
+        assert!(eval("not false and true"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| Expression::parse(source).unwrap_err();
+
+        assert!(error("option[60].text starts_with 1") == ExprError {
+            column: 29,
+            message: String::from("\"starts_with\" expects string on its right, found integer")
+        });
+        assert!(error("pkt.giaddr in 10.0.0.0").message == "\"in\" expects subnet on its right, found address");
+        assert!(error("pkt.giaddr == 10.0.0.0/8").message == "\"==\" expects address on its right, found subnet");
+        assert!(error("option[60].text").message == "expression must be a boolean, found string");
+        assert!(error("not pkt.hops").message == "\"not\" expects a boolean, found integer");
+        assert!(error("pkt.macaddr == 0x00").message == "unknown packet field \"macaddr\"");
+        assert!(error("option[300].exists").column == 8);
+        assert!(error("option[60].txt == \"\"").message.starts_with("unknown option representation \"txt\""));
+        assert!(error("option[60].exists and").message == "unexpected end of expression, expected a value");
+        assert!(error("(true").message == "unexpected end of expression, expected \")\"");
+        assert!(error("true true").message == "unexpected \"true\", expected \"and\", \"or\" or end of expression");
+        assert!(error("pkt.hlen = 6").column == 10);
+        assert!(error("option[60].text == \"PXE").message == "unterminated string literal");
+        assert!(error("pkt.giaddr in 10.0.0.0/33").message == "invalid literal \"10.0.0.0/33\"");
+        assert!(error("pkt.chaddr == 0x0").message == "invalid literal \"0x0\"");
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}true{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expression::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Expression::parse(&format!("{}true", "not ".repeat(MAX_DEPTH))).is_ok());
+
+        let error = Expression::parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+        assert!(error == ExprError { column: MAX_DEPTH + 1, message: format!("expression is nested more than {} levels deep", MAX_DEPTH) });
+        assert!(Expression::parse(&format!("{}true", "not ".repeat(100000))).is_err());
+        assert!(Expression::parse(&nested(100000)).is_err());
+
+        // Sibling groups do not add up
+        let siblings = vec![nested(MAX_DEPTH); 4].join(" and ");
+        assert!(Expression::parse(&siblings).is_ok());
+
+        // Nor do long chains of "and" and "or"
+        let chain = vec!["true"; 100000].join(" and ");
+        assert!(eval(&chain));
+        assert!(!eval(&format!("{} and false", chain)));
+        let chain = vec!["false"; 100000].join(" or ");
+        assert!(!eval(&chain));
+        assert!(eval(&format!("{} or true", chain)));
+        let chain = vec!["member(\"voip\") and true"; 50000].join(" or ");
+        assert!(Expression::parse(&chain).unwrap().members().len() == 50000);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let expr: Expression = serde_yaml::from_str("\"pkt.giaddr in 10.0.0.0/8\"").unwrap();
+        assert!(expr.source() == "pkt.giaddr in 10.0.0.0/8");
+        let error = serde_yaml::from_str::<Expression>("\"pkt.giaddr in\"").unwrap_err();
+        assert!(error.to_string().contains("invalid expression \"pkt.giaddr in\" : column 14"));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod client_class;
+pub mod classifier;
+pub mod expression;
@@ -0,0 +1,104 @@
+//! Implements the [`Clock`] abstraction, used
+//! by every component that depends on the current
+//! time (leases, transactions, reclaimer).
+//!
+//! Injecting a [`MockClock`] instead of the
+//! [`SystemClock`] allows tests to move time
+//! forward instantly and deterministically.
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// `Clock` is a source of the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// `SystemClock` is the [`Clock`] backed by
+/// the system time, used outside of tests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// `MockClock` is a [`Clock`] whose time only
+/// changes when asked to.
+///
+/// Clones of a `MockClock` share the same time,
+/// so that a test can keep a handle on the clock
+/// given to the tested component.
+///
+/// # Examples:
+///
+/// ```
+/// let clock = MockClock::new(Utc::now());
+/// let start = clock.now();
+/// clock.advance(Duration::seconds(30));
+/// assert!(clock.now() - start == Duration::seconds(30));
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+
+    pub fn new(
+        now: DateTime<Utc>
+    ) -> Self {
+        Self { now: Arc::new(Mutex::new(now)) }
+    }
+
+    /// Moves the time of this `MockClock` forward
+    /// by the given [`Duration`].
+    pub fn advance(
+        &self,
+        duration: Duration
+    ) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+
+    /// Sets the time of this `MockClock`.
+    pub fn set(
+        &self,
+        now: DateTime<Utc>
+    ) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_advance() {
+        let clock = MockClock::new(Utc::now());
+        let start = clock.now();
+        assert!(clock.now() == start);
+        clock.advance(Duration::seconds(30));
+        assert!(clock.now() - start == Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_mock_clock_shared() {
+        let clock = MockClock::new(Utc::now());
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        let start = shared.now();
+        clock.advance(Duration::hours(1));
+        assert!(shared.now() - start == Duration::hours(1));
+    }
+
+}
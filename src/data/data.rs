@@ -1,5 +1,5 @@
 use std::net::Ipv4Addr;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use fp_core::utils::data::Storable;
 use derive_data::Storable;
 use crate::{transactions::transaction::Transaction, leases::lease::LeaseV4};
@@ -57,12 +57,37 @@ impl LeaseData {
     pub fn from(lease : LeaseV4) -> LeaseData{
         let expiration_time = lease.end();
         let address = lease.addr();
-        LeaseData { expiration_time, address , uid : 0, hostname : "unknown".to_string()}
+        LeaseData { expiration_time, address , uid : 0, hostname : lease.hostname().to_string()}
+    }
+
+    /// Creates a [`LeaseData`] that is not tied to any [`LeaseV4`],
+    /// such as the leases replicated by a failover partner.
+    pub fn new(address : Ipv4Addr, hostname : String, expiration_time : DateTime<Utc>) -> LeaseData {
+        LeaseData { expiration_time, address, uid : 0, hostname }
     }
 
     pub fn address(&self) -> Ipv4Addr {
         self.address
     }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn expiration(&self) -> DateTime<Utc> {
+        self.expiration_time
+    }
+
+    /// Renews the lease so that it expires after the given
+    /// [`Duration`], starting from `now`.
+    pub fn renew(&mut self, now: DateTime<Utc>, duration: Duration) {
+        self.expiration_time = now + duration;
+    }
+
+    /// Returns true if the lease expired before `now`
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiration_time <= now
+    }
 }
 
 impl FromRow for Data{
@@ -0,0 +1,130 @@
+//! DHCP Information records (RFC 4701), binding a name to the
+//! client that owns it, for conflict resolution (RFC 4703).
+
+use sha2::{Digest, Sha256};
+
+use crate::packet::dhcp_packet::DhcpV4Packet;
+
+use super::{error::DdnsError, message::encode_name};
+
+const DIGEST_SHA256: u8 = 1;
+// Client identifiers of RFC 4361 : type, IAID then DUID
+const NODE_SPECIFIC_TYPE: u8 = 255;
+const IAID_SIZE: usize = 4;
+
+/// Identity of a client, as hashed in its DHCID record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DhcidIdentifier {
+    /// Hardware type and address (identifier type 0x0000)
+    HwAddr { htype: u8, chaddr: Vec<u8> },
+    /// Client identifier option (identifier type 0x0001)
+    ClientId(Vec<u8>),
+    /// DUID of a node-specific client identifier (identifier type 0x0002)
+    Duid(Vec<u8>),
+}
+
+impl DhcidIdentifier {
+
+    /// Returns the identifier of the client of a packet : its
+    /// client identifier if any, its hardware address otherwise.
+    pub fn from_packet(
+        packet: &DhcpV4Packet
+    ) -> Self {
+        match packet.options.client_identifier() {
+            Some(client_id) if (client_id.len() > 1 + IAID_SIZE) & (client_id.first() == Some(&NODE_SPECIFIC_TYPE)) => {
+                DhcidIdentifier::Duid(client_id[1 + IAID_SIZE..].to_vec())
+            },
+            Some(client_id) if !client_id.is_empty() => DhcidIdentifier::ClientId(client_id.clone()),
+            _ => {
+                let hlen = (packet.hlen as usize).min(packet.chadd.raw.len());
+                DhcidIdentifier::HwAddr { htype: packet.htype, chaddr: packet.chadd.raw[..hlen].to_vec() }
+            }
+        }
+    }
+
+    fn type_code(
+        &self
+    ) -> u16 {
+        match self {
+            DhcidIdentifier::HwAddr { .. } => 0,
+            DhcidIdentifier::ClientId(_) => 1,
+            DhcidIdentifier::Duid(_) => 2
+        }
+    }
+
+    /// Returns the DHCID RDATA of the client for the given name :
+    /// the identifier type, the digest type, and the SHA-256 digest
+    /// of the identifier followed by the name.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let identifier = DhcidIdentifier::HwAddr { htype: 1, chaddr: vec![1, 2, 3, 4, 5, 6] };
+    /// let rdata = identifier.rdata("client.example.com").unwrap();
+    /// assert!(rdata[..3] == [0, 0, 1]);
+    /// ```
+    pub fn rdata(
+        &self,
+        fqdn: &str
+    ) -> Result<Vec<u8>, DdnsError> {
+        let mut digest = Sha256::new();
+        match self {
+            DhcidIdentifier::HwAddr { htype, chaddr } => {
+                digest.update([*htype]);
+                digest.update(chaddr);
+            },
+            DhcidIdentifier::ClientId(identifier) | DhcidIdentifier::Duid(identifier) => {
+                digest.update(identifier);
+            }
+        };
+        // Names are hashed in canonical (lowercase) wire format
+        digest.update(encode_name(&fqdn.to_ascii_lowercase())?);
+
+        let mut rdata = self.type_code().to_be_bytes().to_vec();
+        rdata.push(DIGEST_SHA256);
+        rdata.extend_from_slice(&digest.finalize());
+        Ok(rdata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    use fp_core::core::packet::PacketType;
+
+    use super::*;
+
+    // Examples of RFC 4701 section 3.6
+    #[test]
+    fn test_rfc_examples() {
+        let identifier = DhcidIdentifier::HwAddr { htype: 1, chaddr: vec![1, 2, 3, 4, 5, 6] };
+        let expected = STANDARD.decode("AAABxLmlskllE0MVjd57zHcWmEH3pCQ6VytcKD//7es/deY=").unwrap();
+        assert!(identifier.rdata("client.example.com").unwrap() == expected);
+
+        let identifier = DhcidIdentifier::ClientId(vec![0x01, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c]);
+        let expected = STANDARD.decode("AAEBOSD+XR3Os/0LozeXVqcNc7FwCfQdWL3b/NaiUDlW2No=").unwrap();
+        assert!(identifier.rdata("chi.example.com").unwrap() == expected);
+        // Names are case insensitive
+        assert!(identifier.rdata("CHI.Example.com").unwrap() == expected);
+
+        let identifier = DhcidIdentifier::Duid(vec![0x00, 0x01, 0x00, 0x06, 0x41, 0x2d, 0xf1, 0x66, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        let expected = STANDARD.decode("AAIBY2/AuCccgoJbsaxcQc9TUapptP69lOjxfNuVAA2kjEA=").unwrap();
+        assert!(identifier.rdata("chi6.example.com").unwrap() == expected);
+    }
+
+    #[test]
+    fn test_identifier_from_packet() {
+        let mut buf = vec![0u8; 240];
+        buf[1] = 1;
+        buf[2] = 6;
+        buf[28..34].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        let mut packet = DhcpV4Packet::from_raw_bytes(buf.as_slice());
+        assert!(DhcidIdentifier::from_packet(&packet) == DhcidIdentifier::HwAddr { htype: 1, chaddr: vec![1, 2, 3, 4, 5, 6] });
+
+        packet.options.set_client_identifier(Some(vec![1, 2, 3, 4, 5, 6, 7]));
+        assert!(DhcidIdentifier::from_packet(&packet) == DhcidIdentifier::ClientId(vec![1, 2, 3, 4, 5, 6, 7]));
+
+        packet.options.set_client_identifier(Some(vec![255, 0, 0, 0, 1, 0, 1, 0, 6]));
+        assert!(DhcidIdentifier::from_packet(&packet) == DhcidIdentifier::Duid(vec![0, 1, 0, 6]));
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+use std::io;
+
+use super::message::Rcode;
+
+/// Errors returned while publishing leases in the DNS, so that
+/// callers can tell a refused update from a transport failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DdnsError {
+    /// The name cannot be encoded (empty or too long label)
+    InvalidName(String),
+    /// The TSIG secret is not valid base64
+    InvalidKey,
+    /// The DNS message could not be parsed
+    Malformed,
+    /// The DNS server could not be reached
+    Io(String),
+    /// The DNS server refused the update
+    Rcode(Rcode),
+    /// The name belongs to another client (DHCID mismatch)
+    Conflict(String),
+    /// The response is not properly signed
+    Tsig(String)
+}
+
+impl fmt::Display for DdnsError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DdnsError::InvalidName(name) => write!(f, "Invalid domain name {}", name),
+            DdnsError::InvalidKey => write!(f, "Invalid TSIG key"),
+            DdnsError::Malformed => write!(f, "Malformed DNS message"),
+            DdnsError::Io(err) => write!(f, "DNS server unreachable: {}", err),
+            DdnsError::Rcode(rcode) => write!(f, "Update refused: {}", rcode),
+            DdnsError::Conflict(name) => write!(f, "{} is used by another client", name),
+            DdnsError::Tsig(err) => write!(f, "TSIG error: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for DdnsError {}
+
+impl From<io::Error> for DdnsError {
+    fn from(err : io::Error) -> Self {
+        DdnsError::Io(err.to_string())
+    }
+}
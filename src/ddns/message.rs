@@ -0,0 +1,484 @@
+//! DNS UPDATE messages (RFC 2136), encoded without
+//! name compression and parsed with it.
+
+use std::fmt;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::error::DdnsError;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_DHCID: u16 = 49;
+pub const TYPE_TSIG: u16 = 250;
+pub const TYPE_ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+const OPCODE_UPDATE: u16 = 5;
+const HEADER_SIZE: usize = 12;
+const MAX_LABEL_SIZE: usize = 63;
+const MAX_NAME_SIZE: usize = 255;
+// Compression pointers followed before giving up on a name
+const MAX_POINTERS: usize = 16;
+
+/// Response codes of a DNS UPDATE (RFC 2136 section 2.2)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rcode {
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+    /// Some name that ought not to exist does exist
+    YxDomain,
+    /// Some RRset that ought not to exist does exist
+    YxRrset,
+    /// Some RRset that ought to exist does not exist
+    NxRrset,
+    /// The server is not authoritative, or the request is not properly signed
+    NotAuth,
+    NotZone,
+    Other(u8)
+}
+
+impl Rcode {
+
+    pub fn from_code(
+        code: u8
+    ) -> Self {
+        match code {
+            0 => Rcode::NoError,
+            1 => Rcode::FormErr,
+            2 => Rcode::ServFail,
+            3 => Rcode::NxDomain,
+            4 => Rcode::NotImp,
+            5 => Rcode::Refused,
+            6 => Rcode::YxDomain,
+            7 => Rcode::YxRrset,
+            8 => Rcode::NxRrset,
+            9 => Rcode::NotAuth,
+            10 => Rcode::NotZone,
+            code => Rcode::Other(code)
+        }
+    }
+
+    pub fn code(
+        &self
+    ) -> u8 {
+        match self {
+            Rcode::NoError => 0,
+            Rcode::FormErr => 1,
+            Rcode::ServFail => 2,
+            Rcode::NxDomain => 3,
+            Rcode::NotImp => 4,
+            Rcode::Refused => 5,
+            Rcode::YxDomain => 6,
+            Rcode::YxRrset => 7,
+            Rcode::NxRrset => 8,
+            Rcode::NotAuth => 9,
+            Rcode::NotZone => 10,
+            Rcode::Other(code) => *code
+        }
+    }
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rcode::NoError => write!(f, "NOERROR"),
+            Rcode::FormErr => write!(f, "FORMERR"),
+            Rcode::ServFail => write!(f, "SERVFAIL"),
+            Rcode::NxDomain => write!(f, "NXDOMAIN"),
+            Rcode::NotImp => write!(f, "NOTIMP"),
+            Rcode::Refused => write!(f, "REFUSED"),
+            Rcode::YxDomain => write!(f, "YXDOMAIN"),
+            Rcode::YxRrset => write!(f, "YXRRSET"),
+            Rcode::NxRrset => write!(f, "NXRRSET"),
+            Rcode::NotAuth => write!(f, "NOTAUTH"),
+            Rcode::NotZone => write!(f, "NOTZONE"),
+            Rcode::Other(code) => write!(f, "RCODE{}", code)
+        }
+    }
+}
+
+/// Returns the wire format of a domain name, a trailing
+/// dot being optional.
+///
+/// # Examples:
+///
+/// ```
+/// assert!(encode_name("host.example.com").unwrap() == b"\x04host\x07example\x03com\x00");
+/// assert!(encode_name("").unwrap() == [0]);
+/// ```
+pub fn encode_name(
+    name: &str
+) -> Result<Vec<u8>, DdnsError> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let mut buffer = Vec::new();
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() | (label.len() > MAX_LABEL_SIZE) {
+                return Err(DdnsError::InvalidName(name.to_string()));
+            };
+            buffer.push(label.len() as u8);
+            buffer.extend_from_slice(label.as_bytes());
+        }
+    };
+    buffer.push(0);
+    if buffer.len() > MAX_NAME_SIZE {
+        return Err(DdnsError::InvalidName(name.to_string()));
+    };
+    Ok(buffer)
+}
+
+/// Reads the domain name starting at `pos`, following
+/// compression pointers, and returns it along with the
+/// position of what follows it.
+pub(crate) fn read_name(
+    bytes: &[u8],
+    mut pos: usize
+) -> Result<(String, usize), DdnsError> {
+    let mut labels = Vec::new();
+    let mut next = None;
+    let mut pointers = 0;
+    loop {
+        let len = *bytes.get(pos).ok_or(DdnsError::Malformed)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => {
+                pos += 1;
+                break;
+            },
+            0x00 => {
+                let label = bytes.get(pos + 1..pos + 1 + len).ok_or(DdnsError::Malformed)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            },
+            0xc0 => {
+                let pointer = bytes.get(pos..pos + 2).ok_or(DdnsError::Malformed)?;
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(DdnsError::Malformed);
+                };
+                next.get_or_insert(pos + 2);
+                pos = (BigEndian::read_u16(pointer) & 0x3fff) as usize;
+            },
+            _ => return Err(DdnsError::Malformed)
+        }
+    }
+    Ok((labels.join("."), next.unwrap_or(pos)))
+}
+
+/// A resource record of a DNS UPDATE message.
+///
+/// RFC 2136 gives a meaning to each class, type and rdata
+/// combination in the prerequisite and update sections : the
+/// constructors below build the ones used by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+impl Record {
+
+    pub fn new(
+        name: &str,
+        rtype: u16,
+        class: u16,
+        ttl: u32,
+        rdata: Vec<u8>
+    ) -> Self {
+        Self { name: name.to_string(), rtype, class, ttl, rdata }
+    }
+
+    /// Prerequisite : no record of any type uses the name
+    pub fn name_not_in_use(
+        name: &str
+    ) -> Self {
+        Self::new(name, TYPE_ANY, CLASS_NONE, 0, Vec::new())
+    }
+
+    /// Prerequisite : the RRset of the name is made of
+    /// the given record
+    pub fn rrset_exists(
+        name: &str,
+        rtype: u16,
+        rdata: Vec<u8>
+    ) -> Self {
+        Self::new(name, rtype, CLASS_IN, 0, rdata)
+    }
+
+    /// Prerequisite : the name has no RRset of the given type
+    pub fn rrset_not_exists(
+        name: &str,
+        rtype: u16
+    ) -> Self {
+        Self::new(name, rtype, CLASS_NONE, 0, Vec::new())
+    }
+
+    /// Update : adds the record to its RRset
+    pub fn add(
+        name: &str,
+        rtype: u16,
+        ttl: u32,
+        rdata: Vec<u8>
+    ) -> Self {
+        Self::new(name, rtype, CLASS_IN, ttl, rdata)
+    }
+
+    /// Update : deletes the whole RRset of the given type
+    pub fn delete_rrset(
+        name: &str,
+        rtype: u16
+    ) -> Self {
+        Self::new(name, rtype, CLASS_ANY, 0, Vec::new())
+    }
+
+    /// Update : deletes the given record from its RRset
+    pub fn delete(
+        name: &str,
+        rtype: u16,
+        rdata: Vec<u8>
+    ) -> Self {
+        Self::new(name, rtype, CLASS_NONE, 0, rdata)
+    }
+
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>
+    ) -> Result<(), DdnsError> {
+        buffer.extend_from_slice(&encode_name(&self.name)?);
+        buffer.extend_from_slice(&self.rtype.to_be_bytes());
+        buffer.extend_from_slice(&self.class.to_be_bytes());
+        buffer.extend_from_slice(&self.ttl.to_be_bytes());
+        buffer.extend_from_slice(&(self.rdata.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&self.rdata);
+        Ok(())
+    }
+}
+
+// Sequential reader over the sections of a message
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DdnsError> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or(DdnsError::Malformed)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DdnsError> {
+        Ok(BigEndian::read_u16(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DdnsError> {
+        Ok(BigEndian::read_u32(self.take(4)?))
+    }
+
+    fn name(&mut self) -> Result<String, DdnsError> {
+        let (name, next) = read_name(self.bytes, self.pos)?;
+        self.pos = next;
+        Ok(name)
+    }
+
+    fn record(&mut self) -> Result<Record, DdnsError> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let rdata = self.take(len)?.to_vec();
+        Ok(Record { name, rtype, class, ttl, rdata })
+    }
+
+    fn records(&mut self, count: u16) -> Result<Vec<Record>, DdnsError> {
+        (0..count).map(|_| self.record()).collect()
+    }
+}
+
+/// A DNS UPDATE message (RFC 2136 section 2), made of the zone
+/// to update, prerequisites the zone must meet, the updates to
+/// apply, and additional records such as the TSIG signature.
+///
+/// # Examples:
+///
+/// ```
+/// let mut message = Message::update(0x1234, "example.com");
+/// message.prerequisites.push(Record::name_not_in_use("host.example.com"));
+/// message.updates.push(Record::add("host.example.com", TYPE_A, 300, vec![192, 168, 0, 5]));
+/// let bytes = message.to_bytes().unwrap();
+/// assert!(Message::parse(&bytes).unwrap() == message);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub response: bool,
+    pub rcode: Rcode,
+    pub zone: String,
+    pub prerequisites: Vec<Record>,
+    pub updates: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+impl Message {
+
+    /// Creates an empty update request of the given zone
+    pub fn update(
+        id: u16,
+        zone: &str
+    ) -> Self {
+        Self {
+            id,
+            response: false,
+            rcode: Rcode::NoError,
+            zone: zone.to_string(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    /// Creates the response to this request
+    pub fn response(
+        &self,
+        rcode: Rcode
+    ) -> Self {
+        Self { response: true, rcode, ..Self::update(self.id, &self.zone) }
+    }
+
+    pub fn to_bytes(
+        &self
+    ) -> Result<Vec<u8>, DdnsError> {
+        let flags = ((self.response as u16) << 15) | (OPCODE_UPDATE << 11) | (self.rcode.code() as u16 & 0x0f);
+        let mut buffer = Vec::with_capacity(HEADER_SIZE);
+        buffer.extend_from_slice(&self.id.to_be_bytes());
+        buffer.extend_from_slice(&flags.to_be_bytes());
+        for count in [1, self.prerequisites.len(), self.updates.len(), self.additional.len()] {
+            buffer.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+
+        buffer.extend_from_slice(&encode_name(&self.zone)?);
+        buffer.extend_from_slice(&TYPE_SOA.to_be_bytes());
+        buffer.extend_from_slice(&CLASS_IN.to_be_bytes());
+        for record in self.prerequisites.iter().chain(&self.updates).chain(&self.additional) {
+            record.write(&mut buffer)?;
+        }
+        Ok(buffer)
+    }
+
+    pub fn parse(
+        bytes: &[u8]
+    ) -> Result<Self, DdnsError> {
+        Ok(Self::parse_with_offset(bytes)?.0)
+    }
+
+    /// Parses a message, and returns the position of its
+    /// TSIG record if it is signed.
+    pub(crate) fn parse_with_offset(
+        bytes: &[u8]
+    ) -> Result<(Self, Option<usize>), DdnsError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let zone_count = reader.u16()?;
+        let prerequisite_count = reader.u16()?;
+        let update_count = reader.u16()?;
+        let additional_count = reader.u16()?;
+
+        // Some servers leave the zone section of their errors empty
+        let mut zone = String::new();
+        for i in 0..zone_count {
+            let name = reader.name()?;
+            reader.take(4)?;
+            if i == 0 {
+                zone = name;
+            };
+        }
+        let prerequisites = reader.records(prerequisite_count)?;
+        let updates = reader.records(update_count)?;
+
+        // TSIG is always the last record of a message
+        let mut additional = Vec::new();
+        let mut last_offset = None;
+        for _ in 0..additional_count {
+            last_offset = Some(reader.pos);
+            additional.push(reader.record()?);
+        }
+        let tsig_offset = last_offset.filter(|_| {
+            additional.last().map_or(false, |record| record.rtype == TYPE_TSIG)
+        });
+
+        let message = Self {
+            id,
+            response: (flags & 0x8000) != 0,
+            rcode: Rcode::from_code((flags & 0x0f) as u8),
+            zone,
+            prerequisites,
+            updates,
+            additional,
+        };
+        Ok((message, tsig_offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_name() {
+        assert!(encode_name("Host.example.com.").unwrap() == b"\x04Host\x07example\x03com\x00");
+        assert!(encode_name(".").unwrap() == [0]);
+        assert!(encode_name("host..example.com").is_err());
+        assert!(encode_name(&format!("{}.example.com", "a".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let mut message = Message::update(0xbeef, "example.com");
+        message.prerequisites.push(Record::name_not_in_use("host.example.com"));
+        message.updates.push(Record::add("host.example.com", TYPE_A, 300, vec![192, 168, 0, 5]));
+        message.updates.push(Record::delete_rrset("5.0.168.192.in-addr.arpa", TYPE_PTR));
+        let bytes = message.to_bytes().unwrap();
+
+        // Header : update opcode, one zone, one prerequisite, two updates
+        assert!(bytes[..12] == [0xbe, 0xef, 0x28, 0x00, 0, 1, 0, 1, 0, 2, 0, 0]);
+        assert!(Message::parse(&bytes).unwrap() == message);
+
+        let response = message.response(Rcode::YxDomain);
+        let parsed = Message::parse(&response.to_bytes().unwrap()).unwrap();
+        assert!(parsed.response);
+        assert!(parsed.rcode == Rcode::YxDomain);
+        assert!(parsed.zone == "example.com");
+    }
+
+    #[test]
+    fn test_parse_compressed_names() {
+        let mut bytes = Message::update(1, "example.com").to_bytes().unwrap();
+        bytes[7] = 1;
+        // host.<pointer to the zone name>, A, IN
+        bytes.extend_from_slice(b"\x04host\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\xc0\xa8\x00\x05");
+        let message = Message::parse(&bytes).unwrap();
+        assert!(message.prerequisites[0].name == "host.example.com");
+        assert!(message.prerequisites[0].rdata == [192, 168, 0, 5]);
+
+        // Pointer loops are rejected
+        let mut looping = bytes.clone();
+        looping[34..36].copy_from_slice(&[0xc0, 0x22]);
+        assert!(Message::parse(&looping).is_err());
+        assert!(Message::parse(&bytes[..20]).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod message;
+pub mod tsig;
+pub mod dhcid;
+pub mod updater;
+pub mod error;
@@ -0,0 +1,258 @@
+//! Transaction signatures (RFC 8945) of DNS messages,
+//! using HMAC-SHA256.
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use byteorder::{BigEndian, ByteOrder};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::{error::DdnsError, message::{Message, Record, encode_name, read_name, CLASS_ANY, TYPE_TSIG}};
+
+pub const HMAC_SHA256: &str = "hmac-sha256";
+
+// Accepted clock skew (in seconds) between the signer and the verifier
+const DEFAULT_FUDGE: u16 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// RDATA of a TSIG record (RFC 8945 section 4.2)
+struct TsigRdata {
+    algorithm: String,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl TsigRdata {
+
+    fn to_bytes(&self) -> Result<Vec<u8>, DdnsError> {
+        let mut buffer = encode_name(&self.algorithm)?;
+        buffer.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        buffer.extend_from_slice(&self.fudge.to_be_bytes());
+        buffer.extend_from_slice(&(self.mac.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&self.mac);
+        buffer.extend_from_slice(&self.original_id.to_be_bytes());
+        buffer.extend_from_slice(&self.error.to_be_bytes());
+        buffer.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&self.other);
+        Ok(buffer)
+    }
+
+    fn parse(rdata: &[u8]) -> Result<Self, DdnsError> {
+        let (algorithm, pos) = read_name(rdata, 0)?;
+        let fixed = rdata.get(pos..pos + 10).ok_or(DdnsError::Malformed)?;
+        let time_signed = BigEndian::read_u48(&fixed[..6]);
+        let fudge = BigEndian::read_u16(&fixed[6..8]);
+        let mac_size = BigEndian::read_u16(&fixed[8..10]) as usize;
+        let pos = pos + 10;
+        let mac = rdata.get(pos..pos + mac_size).ok_or(DdnsError::Malformed)?.to_vec();
+        let pos = pos + mac_size;
+        let fixed = rdata.get(pos..pos + 6).ok_or(DdnsError::Malformed)?;
+        let original_id = BigEndian::read_u16(&fixed[..2]);
+        let error = BigEndian::read_u16(&fixed[2..4]);
+        let other_size = BigEndian::read_u16(&fixed[4..6]) as usize;
+        let other = rdata.get(pos + 6..pos + 6 + other_size).ok_or(DdnsError::Malformed)?.to_vec();
+        Ok(Self { algorithm, time_signed, fudge, mac, original_id, error, other })
+    }
+
+    // TSIG variables covered by the MAC (RFC 8945 section 4.3.3)
+    fn variables(&self, key_name: &str) -> Result<Vec<u8>, DdnsError> {
+        let mut buffer = encode_name(&key_name.to_ascii_lowercase())?;
+        buffer.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        buffer.extend_from_slice(&0u32.to_be_bytes());
+        buffer.extend_from_slice(&encode_name(&self.algorithm.to_ascii_lowercase())?);
+        buffer.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        buffer.extend_from_slice(&self.fudge.to_be_bytes());
+        buffer.extend_from_slice(&self.error.to_be_bytes());
+        buffer.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&self.other);
+        Ok(buffer)
+    }
+}
+
+/// A `TsigKey` is a secret shared with the DNS server, signing
+/// the update messages so that the server only accepts updates
+/// from this DHCP server.
+///
+/// Responses are signed with the same key, chained to the
+/// MAC of the request they answer.
+///
+/// # Examples:
+///
+/// ```
+/// let key = TsigKey::from_base64("dhcp-key", "c2VjcmV0LWtleS1mb3ItdGVzdHM=").unwrap();
+/// let request_mac = key.sign(&mut request, now, None).unwrap();
+/// key.verify(&response_bytes, Some(&request_mac), now).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsigKey {
+    name: String,
+    secret: Vec<u8>,
+    fudge: u16,
+}
+
+impl TsigKey {
+
+    pub fn new(
+        name: &str,
+        secret: Vec<u8>
+    ) -> Self {
+        Self { name: name.to_string(), secret, fudge: DEFAULT_FUDGE }
+    }
+
+    /// Creates a `TsigKey` from its base64 secret, as
+    /// written in the DNS server configuration.
+    pub fn from_base64(
+        name: &str,
+        secret: &str
+    ) -> Result<Self, DdnsError> {
+        let secret = STANDARD.decode(secret).map_err(|_| DdnsError::InvalidKey)?;
+        Ok(Self::new(name, secret))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac(
+        &self,
+        request_mac: Option<&[u8]>,
+        message: &[u8],
+        variables: &[u8]
+    ) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any size");
+        if let Some(request_mac) = request_mac {
+            mac.update(&(request_mac.len() as u16).to_be_bytes());
+            mac.update(request_mac);
+        };
+        mac.update(message);
+        mac.update(variables);
+        mac
+    }
+
+    /// Signs the message at the given time (in seconds since
+    /// the epoch) by appending its TSIG record, and returns
+    /// the MAC. Responses also cover the MAC of their request.
+    pub fn sign(
+        &self,
+        message: &mut Message,
+        time_signed: u64,
+        request_mac: Option<&[u8]>
+    ) -> Result<Vec<u8>, DdnsError> {
+        let bytes = message.to_bytes()?;
+        let mut rdata = TsigRdata {
+            algorithm: HMAC_SHA256.to_string(),
+            time_signed,
+            fudge: self.fudge,
+            mac: Vec::new(),
+            original_id: message.id,
+            error: 0,
+            other: Vec::new(),
+        };
+        let mac = self.mac(request_mac, &bytes, &rdata.variables(&self.name)?)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        rdata.mac = mac.clone();
+        message.additional.push(Record::new(&self.name, TYPE_TSIG, CLASS_ANY, 0, rdata.to_bytes()?));
+        Ok(mac)
+    }
+
+    /// Verifies the signature of a raw message received at the
+    /// given time, and returns its MAC.
+    ///
+    /// Returns an error if the message is not signed with this
+    /// key, was tampered with, or was signed too long ago.
+    pub fn verify(
+        &self,
+        bytes: &[u8],
+        request_mac: Option<&[u8]>,
+        now: u64
+    ) -> Result<Vec<u8>, DdnsError> {
+        let (message, offset) = Message::parse_with_offset(bytes)?;
+        let offset = offset.ok_or(DdnsError::Tsig(String::from("unsigned message")))?;
+        let record = message.additional.last().ok_or(DdnsError::Malformed)?;
+        if !record.name.eq_ignore_ascii_case(&self.name) {
+            return Err(DdnsError::Tsig(format!("unknown key {}", record.name)));
+        };
+        let rdata = TsigRdata::parse(&record.rdata)?;
+        if !rdata.algorithm.eq_ignore_ascii_case(HMAC_SHA256) {
+            return Err(DdnsError::Tsig(format!("unsupported algorithm {}", rdata.algorithm)));
+        };
+        if rdata.error != 0 {
+            return Err(DdnsError::Tsig(format!("error {}", rdata.error)));
+        };
+
+        // The MAC covers the message as it was before being signed
+        let mut unsigned = bytes[..offset].to_vec();
+        let additional_count = BigEndian::read_u16(&unsigned[10..12]) - 1;
+        BigEndian::write_u16(&mut unsigned[10..12], additional_count);
+        BigEndian::write_u16(&mut unsigned[..2], rdata.original_id);
+        self.mac(request_mac, &unsigned, &rdata.variables(&self.name)?)
+            .verify_slice(&rdata.mac)
+            .map_err(|_| DdnsError::Tsig(String::from("bad signature")))?;
+
+        if now.abs_diff(rdata.time_signed) > rdata.fudge as u64 {
+            return Err(DdnsError::Tsig(String::from("bad time")));
+        };
+        Ok(rdata.mac)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ddns::message::TYPE_A;
+
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn request() -> Message {
+        let mut message = Message::update(42, "example.com");
+        message.updates.push(Record::add("host.example.com", TYPE_A, 300, vec![192, 168, 0, 5]));
+        message
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = TsigKey::from_base64("dhcp-key", "c2VjcmV0LWtleS1mb3ItdGVzdHM=").unwrap();
+        let mut message = request();
+        let mac = key.sign(&mut message, NOW, None).unwrap();
+        assert!(mac.len() == 32);
+        assert!(message.additional.len() == 1);
+
+        let bytes = message.to_bytes().unwrap();
+        assert!(key.verify(&bytes, None, NOW + 10).unwrap() == mac);
+
+        // Responses are chained to the request
+        let mut response = message.response(crate::ddns::message::Rcode::NoError);
+        key.sign(&mut response, NOW, Some(&mac)).unwrap();
+        let bytes = response.to_bytes().unwrap();
+        assert!(key.verify(&bytes, Some(&mac), NOW).is_ok());
+        assert!(key.verify(&bytes, None, NOW).is_err());
+    }
+
+    #[test]
+    fn test_rejected_signatures() {
+        let key = TsigKey::new("dhcp-key", b"secret".to_vec());
+        let mut message = request();
+        key.sign(&mut message, NOW, None).unwrap();
+        let bytes = message.to_bytes().unwrap();
+
+        assert!(TsigKey::new("dhcp-key", b"other".to_vec()).verify(&bytes, None, NOW).is_err());
+        assert!(TsigKey::new("other-key", b"secret".to_vec()).verify(&bytes, None, NOW).is_err());
+        assert!(key.verify(&bytes, None, NOW + 301) == Err(DdnsError::Tsig(String::from("bad time"))));
+        assert!(key.verify(&request().to_bytes().unwrap(), None, NOW).is_err());
+
+        // Tampered address
+        let mut tampered = bytes.clone();
+        let address = tampered.windows(4).position(|window| window == [192, 168, 0, 5]).unwrap();
+        tampered[address + 3] = 6;
+        assert!(key.verify(&tampered, None, NOW) == Err(DdnsError::Tsig(String::from("bad signature"))));
+        assert!(TsigKey::from_base64("dhcp-key", "not base64 !").is_err());
+    }
+}
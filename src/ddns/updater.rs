@@ -0,0 +1,648 @@
+use std::{collections::HashMap, net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket}, sync::{Mutex, mpsc::{self, Receiver, Sender}}, thread, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use log::warn;
+use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
+
+use crate::packet::client_fqdn::FqdnFlags;
+
+use super::{dhcid::DhcidIdentifier, error::DdnsError, message::{encode_name, Message, Rcode, Record, TYPE_A, TYPE_AAAA, TYPE_DHCID, TYPE_PTR}, tsig::TsigKey};
+
+const MAX_RESPONSE_SIZE: usize = 4096;
+
+fn _default_ttl() -> u32 {
+    300
+}
+
+fn _default_timeout() -> u64 {
+    1000
+}
+
+/// Key signing the updates, as declared on the DNS server.
+///
+/// Only HMAC-SHA256 keys are supported.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TsigKeyCfg {
+    pub name: String,
+    /// Base64 encoded secret
+    pub secret: String,
+}
+
+/// `DdnsSettings` describes the DNS server publishing the
+/// names of the clients, and the zones it is authoritative for.
+///
+/// # Examples:
+///
+/// ```yaml
+/// ddns:
+///   server: 127.0.0.1:53
+///   forward_zone: "example.com"
+///   reverse_zone: "168.192.in-addr.arpa"
+///   ttl: 300
+///   key:
+///     name: "dhcp-key"
+///     secret: "c2VjcmV0LWtleS1mb3ItdGVzdHM="
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DdnsSettings {
+    pub server: SocketAddr,
+    /// Zone of the client names (A records)
+    pub forward_zone: String,
+    /// Zone of the reverse names (PTR records), no
+    /// reverse updates are sent when unset
+    pub reverse_zone: Option<String>,
+    #[serde(default = "_default_ttl")]
+    pub ttl: u32,
+    /// Time (in milliseconds) to wait for the DNS server
+    #[serde(default = "_default_timeout")]
+    pub timeout: u64,
+    pub key: Option<TsigKeyCfg>,
+    /// Update the client names even when clients
+    /// ask to update them themselves
+    #[serde(default)]
+    pub override_client_update: bool,
+}
+
+impl DdnsSettings {
+
+    /// Returns whether the server updates the A record of a
+    /// client, given the flags of its FQDN option, to answer
+    /// it with [`crate::packet::client_fqdn::ClientFqdn::reply`].
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// assert!(settings.updates_forward(None));
+    /// assert!(!settings.updates_forward(Some(FqdnFlags::default())));
+    /// ```
+    pub fn updates_forward(
+        &self,
+        client_flags: Option<FqdnFlags>
+    ) -> bool {
+        match client_flags {
+            Some(flags) if !self.override_client_update => flags.server_update & !flags.no_update,
+            _ => true
+        }
+    }
+
+    /// Returns the name of a client in the forward zone,
+    /// see [`DdnsUpdater::fqdn`].
+    pub fn fqdn(
+        &self,
+        hostname: &str
+    ) -> String {
+        let hostname = hostname.trim_end_matches('.');
+        let zone = self.forward_zone.trim_end_matches('.');
+        let lowercase = hostname.to_ascii_lowercase();
+        let zone_lowercase = zone.to_ascii_lowercase();
+        if (lowercase == zone_lowercase) | lowercase.ends_with(&format!(".{}", zone_lowercase)) {
+            return hostname.to_string();
+        };
+        format!("{}.{}", hostname, zone)
+    }
+}
+
+/// A lease whose name must be published.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DdnsLease {
+    pub address: Ipv4Addr,
+    /// Hostname of the client, qualified in the
+    /// forward zone unless it already belongs to it
+    pub hostname: String,
+    pub identifier: DhcidIdentifier,
+    /// Flags of the client FQDN option, if the client sent one
+    pub client_flags: Option<FqdnFlags>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DdnsEvent {
+    /// A lease was commited or renewed
+    Publish(DdnsLease),
+    /// A lease was released or expired
+    Withdraw(Ipv4Addr),
+}
+
+/// `DdnsQueue` hands lease events over to a [`DdnsUpdater`]
+/// running on its own thread, so that DNS round trips never
+/// delay lease handling.
+#[derive(Clone, Debug)]
+pub struct DdnsQueue {
+    sender: Sender<DdnsEvent>,
+}
+
+impl DdnsQueue {
+
+    /// Creates a queue along with the receiving end
+    /// of its events.
+    pub fn new() -> (Self, Receiver<DdnsEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self { sender }, receiver)
+    }
+
+    pub fn publish(&self, lease: DdnsLease) {
+        // Events are dropped once the updater is gone
+        let _ = self.sender.send(DdnsEvent::Publish(lease));
+    }
+
+    pub fn withdraw(&self, address: Ipv4Addr) {
+        let _ = self.sender.send(DdnsEvent::Withdraw(address));
+    }
+}
+
+// Records published for a lease, needed to withdraw them
+#[derive(Clone, Debug)]
+struct Published {
+    fqdn: String,
+    dhcid: Vec<u8>,
+    forward: bool,
+    reverse: bool,
+}
+
+/// `DdnsUpdater` publishes the names of the clients in the
+/// DNS with UPDATE messages (RFC 2136), signed with TSIG.
+///
+/// Names are bound to their client with a DHCID record, so that
+/// a client never takes over the name of another one (RFC 4703).
+///
+/// # Examples:
+///
+/// ```
+/// let updater = DdnsUpdater::new(cfg.ddns().unwrap().clone()).unwrap();
+/// let queue = updater.spawn();
+/// transaction_manager.set_ddns(queue, cfg.ddns().unwrap().clone());
+/// ```
+pub struct DdnsUpdater {
+    settings: DdnsSettings,
+    key: Option<TsigKey>,
+    published: Mutex<HashMap<Ipv4Addr, Published>>,
+}
+
+impl DdnsUpdater {
+
+    pub fn new(
+        settings: DdnsSettings
+    ) -> Result<Self, DdnsError> {
+        let key = settings.key
+            .as_ref()
+            .map(|key| TsigKey::from_base64(&key.name, &key.secret))
+            .transpose()?;
+        Ok(Self { settings, key, published: Mutex::new(HashMap::new()) })
+    }
+
+    pub fn settings(&self) -> &DdnsSettings {
+        &self.settings
+    }
+
+    /// Runs the updater on its own thread, handling the
+    /// events sent to the returned [`DdnsQueue`].
+    pub fn spawn(
+        self
+    ) -> DdnsQueue {
+        let (queue, events) = DdnsQueue::new();
+        thread::spawn(move || {
+            for event in events.iter() {
+                let result = match &event {
+                    DdnsEvent::Publish(lease) => self.publish(lease),
+                    DdnsEvent::Withdraw(address) => self.withdraw(*address)
+                };
+                if let Err(e) = result {
+                    warn!("Dynamic DNS update failed : {}", e);
+                };
+            }
+        });
+        queue
+    }
+
+    /// Returns the name of a client in the forward zone.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// assert!(updater.fqdn("host1") == "host1.example.com");
+    /// assert!(updater.fqdn("host1.example.com.") == "host1.example.com");
+    /// ```
+    pub fn fqdn(
+        &self,
+        hostname: &str
+    ) -> String {
+        self.settings.fqdn(hostname)
+    }
+
+    /// Returns the reverse name of an address,
+    /// in the in-addr.arpa domain.
+    pub fn reverse_name(
+        address: Ipv4Addr
+    ) -> String {
+        let [a, b, c, d] = address.octets();
+        format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+    }
+
+    /// Returns whether the forward and reverse names must be
+    /// updated, given the flags of the client (RFC 4702 section 3).
+    fn scope(
+        &self,
+        client_flags: Option<FqdnFlags>
+    ) -> (bool, bool) {
+        let reverse = self.settings.reverse_zone.is_some();
+        match client_flags {
+            Some(flags) if !self.settings.override_client_update => {
+                (self.settings.updates_forward(client_flags), reverse & !flags.no_update)
+            },
+            _ => (true, reverse)
+        }
+    }
+
+    /// Publishes the name of a lease : its A and DHCID records
+    /// in the forward zone, and its PTR record in the reverse zone.
+    ///
+    /// Publishing again the same name is a no-op, so that
+    /// renewals cost no DNS round trip. Returns an error if the
+    /// name belongs to another client, in which case the reverse
+    /// zone is not updated either.
+    pub fn publish(
+        &self,
+        lease: &DdnsLease
+    ) -> Result<(), DdnsError> {
+        if lease.hostname.is_empty() {
+            return Ok(());
+        };
+        let fqdn = self.fqdn(&lease.hostname);
+        let (forward, reverse) = self.scope(lease.client_flags);
+
+        let previous = self.published.lock().unwrap().get(&lease.address).cloned();
+        if let Some(previous) = previous {
+            if previous.fqdn.eq_ignore_ascii_case(&fqdn) & (previous.forward == forward) & (previous.reverse == reverse) {
+                return Ok(());
+            };
+            self.withdraw(lease.address)?;
+        };
+
+        let mut published = Published {
+            dhcid: lease.identifier.rdata(&fqdn)?,
+            fqdn,
+            forward: false,
+            reverse: false,
+        };
+        let result = self.add_records(lease.address, &mut published, forward, reverse);
+        if published.forward | published.reverse {
+            self.published.lock().unwrap().insert(lease.address, published);
+        };
+        result
+    }
+
+    fn add_records(
+        &self,
+        address: Ipv4Addr,
+        published: &mut Published,
+        forward: bool,
+        reverse: bool
+    ) -> Result<(), DdnsError> {
+        if forward {
+            self.add_forward(&published.fqdn, address, &published.dhcid)?;
+            published.forward = true;
+        };
+        if let Some(zone) = self.settings.reverse_zone.as_ref().filter(|_| reverse) {
+            let name = Self::reverse_name(address);
+            let mut message = Message::update(rand::random(), zone);
+            message.updates.push(Record::delete_rrset(&name, TYPE_PTR));
+            message.updates.push(Record::add(&name, TYPE_PTR, self.settings.ttl, encode_name(&published.fqdn)?));
+            self.expect_success(message)?;
+            published.reverse = true;
+        };
+        Ok(())
+    }
+
+    fn add_forward(
+        &self,
+        fqdn: &str,
+        address: Ipv4Addr,
+        dhcid: &[u8]
+    ) -> Result<(), DdnsError> {
+        let zone = &self.settings.forward_zone;
+        let ttl = self.settings.ttl;
+
+        // The name is not used : claim it
+        let mut message = Message::update(rand::random(), zone);
+        message.prerequisites.push(Record::name_not_in_use(fqdn));
+        message.updates.push(Record::add(fqdn, TYPE_A, ttl, address.octets().to_vec()));
+        message.updates.push(Record::add(fqdn, TYPE_DHCID, ttl, dhcid.to_vec()));
+        match self.send(message)? {
+            Rcode::NoError => return Ok(()),
+            Rcode::YxDomain => (),
+            rcode => return Err(DdnsError::Rcode(rcode))
+        };
+
+        // The name is used : only take it over if it belongs to the client
+        let mut message = Message::update(rand::random(), zone);
+        message.prerequisites.push(Record::rrset_exists(fqdn, TYPE_DHCID, dhcid.to_vec()));
+        message.updates.push(Record::delete_rrset(fqdn, TYPE_A));
+        message.updates.push(Record::add(fqdn, TYPE_A, ttl, address.octets().to_vec()));
+        match self.send(message)? {
+            Rcode::NoError => Ok(()),
+            Rcode::NxRrset => Err(DdnsError::Conflict(fqdn.to_string())),
+            rcode => Err(DdnsError::Rcode(rcode))
+        }
+    }
+
+    /// Withdraws the records published for an address.
+    ///
+    /// The A record is only deleted if the name still belongs
+    /// to the client, and its DHCID once no address is left.
+    pub fn withdraw(
+        &self,
+        address: Ipv4Addr
+    ) -> Result<(), DdnsError> {
+        let published = self.published.lock().unwrap().remove(&address);
+        let published = match published {
+            Some(published) => published,
+            None => return Ok(())
+        };
+
+        if published.forward {
+            let zone = &self.settings.forward_zone;
+            let fqdn = &published.fqdn;
+            let mut message = Message::update(rand::random(), zone);
+            message.prerequisites.push(Record::rrset_exists(fqdn, TYPE_DHCID, published.dhcid.clone()));
+            message.updates.push(Record::delete(fqdn, TYPE_A, address.octets().to_vec()));
+            match self.send(message)? {
+                Rcode::NoError => {
+                    let mut message = Message::update(rand::random(), zone);
+                    message.prerequisites.push(Record::rrset_not_exists(fqdn, TYPE_A));
+                    message.prerequisites.push(Record::rrset_not_exists(fqdn, TYPE_AAAA));
+                    message.prerequisites.push(Record::rrset_exists(fqdn, TYPE_DHCID, published.dhcid.clone()));
+                    message.updates.push(Record::delete_rrset(fqdn, TYPE_DHCID));
+                    match self.send(message)? {
+                        // Other addresses still use the name
+                        Rcode::NoError | Rcode::YxRrset | Rcode::NxRrset => (),
+                        rcode => return Err(DdnsError::Rcode(rcode))
+                    };
+                },
+                // The name was taken over by another client
+                Rcode::NxRrset => (),
+                rcode => return Err(DdnsError::Rcode(rcode))
+            };
+        };
+
+        if let Some(zone) = self.settings.reverse_zone.as_ref().filter(|_| published.reverse) {
+            let mut message = Message::update(rand::random(), zone);
+            message.updates.push(Record::delete_rrset(&Self::reverse_name(address), TYPE_PTR));
+            self.expect_success(message)?;
+        };
+        Ok(())
+    }
+
+    fn expect_success(
+        &self,
+        message: Message
+    ) -> Result<(), DdnsError> {
+        match self.send(message)? {
+            Rcode::NoError => Ok(()),
+            rcode => Err(DdnsError::Rcode(rcode))
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs())
+    }
+
+    /// Sends a message to the DNS server, and returns
+    /// the response code.
+    fn send(
+        &self,
+        mut message: Message
+    ) -> Result<Rcode, DdnsError> {
+        let request_mac = match &self.key {
+            Some(key) => Some(key.sign(&mut message, Self::now(), None)?),
+            None => None
+        };
+
+        let local: SocketAddr = match self.settings.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(Duration::from_millis(self.settings.timeout)))?;
+        socket.connect(self.settings.server)?;
+        socket.send(&message.to_bytes()?)?;
+
+        let mut buffer = [0u8; MAX_RESPONSE_SIZE];
+        loop {
+            let len = socket.recv(&mut buffer)?;
+            let response = Message::parse(&buffer[..len])?;
+            // Late answers to previous requests are skipped
+            if !response.response | (response.id != message.id) {
+                continue;
+            };
+            if let Some(key) = &self.key {
+                match key.verify(&buffer[..len], request_mac.as_deref(), Self::now()) {
+                    Ok(_) => (),
+                    // Badly signed requests cannot be answered with a signature
+                    Err(_) if response.rcode == Rcode::NotAuth => (),
+                    Err(e) => return Err(e)
+                };
+            };
+            return Ok(response.rcode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::ddns::message::{CLASS_ANY, CLASS_IN, CLASS_NONE, TYPE_ANY};
+
+    use super::*;
+
+    const SECRET: &str = "c2VjcmV0LWtleS1mb3ItdGVzdHM=";
+
+    type Zone = Arc<Mutex<HashMap<(String, u16), Vec<Vec<u8>>>>>;
+
+    // Applies an update to the zone, following RFC 2136 section 3
+    fn apply(
+        zone: &mut HashMap<(String, u16), Vec<Vec<u8>>>,
+        request: &Message
+    ) -> Rcode {
+        let key = |record: &Record| (record.name.to_ascii_lowercase(), record.rtype);
+        for prerequisite in request.prerequisites.iter() {
+            let name_used = zone.keys().any(|(name, _)| *name == prerequisite.name.to_ascii_lowercase());
+            let rrset = zone.get(&key(prerequisite));
+            let failure = match (prerequisite.class, prerequisite.rtype) {
+                (CLASS_NONE, TYPE_ANY) if name_used => Some(Rcode::YxDomain),
+                (CLASS_NONE, _) if rrset.is_some() => Some(Rcode::YxRrset),
+                (CLASS_IN, _) if rrset.map_or(true, |rrset| !rrset.contains(&prerequisite.rdata)) => Some(Rcode::NxRrset),
+                _ => None
+            };
+            if let Some(rcode) = failure {
+                return rcode;
+            };
+        }
+        for update in request.updates.iter() {
+            match update.class {
+                CLASS_IN => {
+                    let rrset = zone.entry(key(update)).or_default();
+                    if !rrset.contains(&update.rdata) {
+                        rrset.push(update.rdata.clone());
+                    };
+                },
+                CLASS_ANY => {
+                    zone.remove(&key(update));
+                },
+                CLASS_NONE => {
+                    if let Some(rrset) = zone.get_mut(&key(update)) {
+                        rrset.retain(|rdata| *rdata != update.rdata);
+                        if rrset.is_empty() {
+                            zone.remove(&key(update));
+                        };
+                    };
+                },
+                _ => ()
+            };
+        }
+        Rcode::NoError
+    }
+
+    // In-process DNS server, authoritative for every zone
+    fn stub_server(key: TsigKey) -> (SocketAddr, Zone) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        let zone: Zone = Arc::default();
+        let records = zone.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; MAX_RESPONSE_SIZE];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buffer).unwrap();
+                let request = Message::parse(&buffer[..len]).unwrap();
+                let response = match key.verify(&buffer[..len], None, DdnsUpdater::now()) {
+                    Ok(request_mac) => {
+                        let mut response = request.response(apply(&mut records.lock().unwrap(), &request));
+                        key.sign(&mut response, DdnsUpdater::now(), Some(&request_mac)).unwrap();
+                        response
+                    },
+                    Err(_) => request.response(Rcode::NotAuth)
+                };
+                socket.send_to(&response.to_bytes().unwrap(), peer).unwrap();
+            }
+        });
+        (server, zone)
+    }
+
+    fn updater(server: SocketAddr) -> DdnsUpdater {
+        DdnsUpdater::new(DdnsSettings {
+            server,
+            forward_zone: String::from("example.com"),
+            reverse_zone: Some(String::from("168.192.in-addr.arpa")),
+            ttl: 300,
+            timeout: 1000,
+            key: Some(TsigKeyCfg { name: String::from("dhcp-key"), secret: String::from(SECRET) }),
+            override_client_update: false,
+        }).unwrap()
+    }
+
+    fn lease(address: Ipv4Addr, hostname: &str, mac: u8) -> DdnsLease {
+        DdnsLease {
+            address,
+            hostname: hostname.to_string(),
+            identifier: DhcidIdentifier::HwAddr { htype: 1, chaddr: vec![mac; 6] },
+            client_flags: None,
+        }
+    }
+
+    fn rrset(zone: &Zone, name: &str, rtype: u16) -> Option<Vec<Vec<u8>>> {
+        zone.lock().unwrap().get(&(name.to_string(), rtype)).cloned()
+    }
+
+    #[test]
+    fn test_publish_and_withdraw() {
+        let (server, zone) = stub_server(TsigKey::from_base64("dhcp-key", SECRET).unwrap());
+        let updater = updater(server);
+        let address = Ipv4Addr::new(192, 168, 0, 5);
+        updater.publish(&lease(address, "host1", 1)).unwrap();
+
+        assert!(rrset(&zone, "host1.example.com", TYPE_A) == Some(vec![vec![192, 168, 0, 5]]));
+        assert!(rrset(&zone, "host1.example.com", TYPE_DHCID).unwrap()[0][..3] == [0, 0, 1]);
+        assert!(rrset(&zone, "5.0.168.192.in-addr.arpa", TYPE_PTR) == Some(vec![encode_name("host1.example.com").unwrap()]));
+
+        // Renewals publish nothing new
+        updater.publish(&lease(address, "host1", 1)).unwrap();
+        assert!(zone.lock().unwrap().len() == 3);
+
+        updater.withdraw(address).unwrap();
+        assert!(zone.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_name_conflict() {
+        let (server, zone) = stub_server(TsigKey::from_base64("dhcp-key", SECRET).unwrap());
+        let updater = updater(server);
+        updater.publish(&lease(Ipv4Addr::new(192, 168, 0, 5), "host1", 1)).unwrap();
+
+        // Another client cannot take the name over
+        let result = updater.publish(&lease(Ipv4Addr::new(192, 168, 0, 6), "host1", 2));
+        assert!(result == Err(DdnsError::Conflict(String::from("host1.example.com"))));
+        assert!(rrset(&zone, "host1.example.com", TYPE_A) == Some(vec![vec![192, 168, 0, 5]]));
+        assert!(rrset(&zone, "6.0.168.192.in-addr.arpa", TYPE_PTR).is_none());
+        updater.withdraw(Ipv4Addr::new(192, 168, 0, 6)).unwrap();
+        assert!(rrset(&zone, "host1.example.com", TYPE_A).is_some());
+
+        // The same client moving to another address keeps it
+        updater.publish(&lease(Ipv4Addr::new(192, 168, 0, 7), "host1", 1)).unwrap();
+        assert!(rrset(&zone, "host1.example.com", TYPE_A) == Some(vec![vec![192, 168, 0, 7]]));
+    }
+
+    #[test]
+    fn test_client_flags() {
+        let (server, zone) = stub_server(TsigKey::from_base64("dhcp-key", SECRET).unwrap());
+        let updater = updater(server);
+
+        // The client updates its A record itself
+        let mut client_update = lease(Ipv4Addr::new(192, 168, 0, 5), "host1", 1);
+        client_update.client_flags = Some(FqdnFlags::default());
+        assert!(!updater.settings().updates_forward(client_update.client_flags));
+        updater.publish(&client_update).unwrap();
+        assert!(rrset(&zone, "host1.example.com", TYPE_A).is_none());
+        assert!(rrset(&zone, "5.0.168.192.in-addr.arpa", TYPE_PTR).is_some());
+
+        // No update at all
+        let mut no_update = lease(Ipv4Addr::new(192, 168, 0, 6), "host2", 2);
+        no_update.client_flags = Some(FqdnFlags { no_update: true, ..Default::default() });
+        updater.publish(&no_update).unwrap();
+        assert!(rrset(&zone, "6.0.168.192.in-addr.arpa", TYPE_PTR).is_none());
+
+        // Unless the server overrides the client
+        let mut settings = updater.settings().clone();
+        settings.override_client_update = true;
+        DdnsUpdater::new(settings).unwrap().publish(&no_update).unwrap();
+        assert!(rrset(&zone, "host2.example.com", TYPE_A).is_some());
+    }
+
+    #[test]
+    fn test_bad_key() {
+        let (server, zone) = stub_server(TsigKey::new("dhcp-key", b"another secret".to_vec()));
+        let updater = updater(server);
+        let result = updater.publish(&lease(Ipv4Addr::new(192, 168, 0, 5), "host1", 1));
+        assert!(result == Err(DdnsError::Rcode(Rcode::NotAuth)));
+        assert!(zone.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_queue() {
+        let (server, zone) = stub_server(TsigKey::from_base64("dhcp-key", SECRET).unwrap());
+        let queue = updater(server).spawn();
+        queue.publish(lease(Ipv4Addr::new(192, 168, 0, 5), "host1.example.com.", 1));
+        queue.withdraw(Ipv4Addr::new(192, 168, 0, 9));
+
+        for _ in 0..50 {
+            if rrset(&zone, "5.0.168.192.in-addr.arpa", TYPE_PTR).is_some() {
+                break;
+            };
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(rrset(&zone, "host1.example.com", TYPE_A) == Some(vec![vec![192, 168, 0, 5]]));
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use crate::{allocators::allocator::{Allocator, AllocationDraft}, packet::dhcp_packet::DhcpMessage};
+
+use super::peer::FailoverPeer;
+
+/// `FailoverAllocator` wraps the [`Allocator`] of a server
+/// running with a failover partner.
+///
+/// Clients served by the partner are left to it, and the
+/// lease times drafted by the wrapped allocator are shortened
+/// so that leases never outlive what the partner knows of
+/// them by more than the MCLT.
+///
+/// # Examples:
+///
+/// ```
+/// let chain = AllocatorChain::with_static_and_dynamic(static_allocator, dynamic_allocator);
+/// let allocator = FailoverAllocator::new(Arc::new(chain), peer.clone());
+/// let draft = allocator.allocate(dhcp_msg);
+/// ```
+pub struct FailoverAllocator {
+    allocator: Arc<dyn Allocator>,
+    peer: Arc<FailoverPeer>,
+}
+
+impl Allocator for FailoverAllocator {
+
+    /// Allocates an address with the wrapped allocator,
+    /// if the client is served by this server.
+    fn allocate(
+        &self,
+        msg: DhcpMessage
+    ) -> Option<AllocationDraft> {
+        let served = match &msg {
+            DhcpMessage::DhcpDiscover(packet)
+            | DhcpMessage::DhcpRequest(packet)
+            | DhcpMessage::BootpRequest(packet) => self.peer.serves(packet),
+            _ => true
+        };
+        if !served {
+            return None;
+        };
+        let mut draft = self.allocator.allocate(msg)?;
+        self.peer.limit_lease_time(draft.ip_addr(), draft.options_mut());
+        Some(draft)
+    }
+
+    fn seal_allocation(
+        &self,
+        draft: AllocationDraft
+    ) -> Result<(), ()> {
+        self.allocator.seal_allocation(draft)
+    }
+}
+
+impl FailoverAllocator {
+
+    pub fn new(
+        allocator: Arc<dyn Allocator>,
+        peer: Arc<FailoverPeer>
+    ) -> Self {
+        Self { allocator, peer }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{Ipv4Addr, TcpListener}, sync::Mutex};
+
+    use fp_core::core::packet::PacketType;
+
+    use crate::{allocators::dynamic_alloc::dynamic_allocator::DynamicAllocator, failover::{load_balance::{FailoverRole, LoadBalancer}, peer::FailoverSettings}, leases::ip_subnet::Ipv4Subnet, packet::dhcp_packet::DhcpV4Packet};
+
+    use super::*;
+
+    fn discover(client_id: u8) -> DhcpV4Packet {
+        let mut raw = vec![0u8; 236];
+        raw[0] = 1;
+        raw.extend_from_slice(&[99, 130, 83, 99, 0x35, 0x01, 0x01, 0xff]);
+        let mut packet = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+        packet.giaddr = Ipv4Addr::new(192, 168, 0, 254);
+        packet.options.set_client_identifier(Some(vec![0x01, 0, 0, 0, 0, 0, client_id]));
+        packet.options.set_lease_time(Some(86400));
+        packet
+    }
+
+    #[test]
+    fn test_failover_allocation() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let settings = FailoverSettings {
+            role: FailoverRole::Primary,
+            address,
+            partner: address,
+            mclt: 1800,
+            split: 128,
+            heartbeat: 1000,
+            partner_timeout: 3000,
+            auto_partner_down: None,
+        };
+        let peer = Arc::new(FailoverPeer::new(settings));
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let dynamic_allocator = DynamicAllocator::new();
+        dynamic_allocator.register_subnet(subnet.clone()).unwrap();
+        peer.register_subnet(subnet.clone()).unwrap();
+        let allocator = FailoverAllocator::new(Arc::new(dynamic_allocator), peer.clone());
+
+        let balancer = LoadBalancer::new(FailoverRole::Primary, 128);
+        let (served, unserved): (Vec<DhcpV4Packet>, Vec<DhcpV4Packet>) = (0..16).map(discover).partition(|packet| balancer.serves(packet));
+        assert!(!served.is_empty() & !unserved.is_empty());
+
+        for packet in served {
+            let draft = allocator.allocate(DhcpMessage::DhcpDiscover(packet)).unwrap();
+            // Only addresses of the primary are handed out, for the MCLT
+            assert!(peer.owns(draft.ip_addr()));
+            assert!(draft.options().lease_time() == Some(1800));
+            assert!(allocator.seal_allocation(draft).is_ok());
+        }
+        for packet in unserved.iter() {
+            assert!(allocator.allocate(DhcpMessage::DhcpDiscover(packet.clone())).is_none());
+        }
+
+        // Every client is served once the partner is down
+        peer.set_partner_down();
+        assert!(allocator.allocate(DhcpMessage::DhcpDiscover(unserved[0].clone())).is_some());
+    }
+}
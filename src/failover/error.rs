@@ -0,0 +1,31 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned while talking to the failover partner.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailoverError {
+    /// The partner could not be reached
+    Io(String),
+    /// The failover message could not be parsed
+    Malformed,
+    /// No connection to the partner is established
+    NotConnected
+}
+
+impl fmt::Display for FailoverError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailoverError::Io(err) => write!(f, "Failover partner unreachable: {}", err),
+            FailoverError::Malformed => write!(f, "Malformed failover message"),
+            FailoverError::NotConnected => write!(f, "Not connected to the failover partner")
+        }
+    }
+}
+
+impl std::error::Error for FailoverError {}
+
+impl From<io::Error> for FailoverError {
+    fn from(err : io::Error) -> Self {
+        FailoverError::Io(err.to_string())
+    }
+}
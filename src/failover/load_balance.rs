@@ -0,0 +1,149 @@
+//! Implements the load balancing algorithm of RFC 3074
+//! ( <https://www.rfc-editor.org/rfc/rfc3074> ), sharing
+//! the clients between failover partners.
+
+use serde::{Serialize, Deserialize};
+
+use crate::packet::dhcp_packet::DhcpV4Packet;
+
+/// Number of hash buckets (RFC 3074 section 5)
+pub const BUCKETS: u16 = 256;
+
+// Pearson hash permutation of RFC 3074 section 6
+const PERMUTATION: [u8; 256] = [
+    251, 175, 119, 215, 81, 14, 79, 191, 103, 49, 181, 143, 186, 157, 0, 232,
+    31, 32, 55, 60, 152, 58, 17, 237, 174, 70, 160, 144, 220, 90, 57, 223,
+    59, 3, 18, 140, 111, 166, 203, 196, 134, 243, 124, 95, 222, 179, 197, 65,
+    180, 48, 36, 15, 107, 46, 233, 130, 165, 30, 123, 161, 209, 23, 97, 16,
+    40, 91, 219, 61, 100, 10, 210, 109, 250, 127, 22, 138, 29, 108, 244, 67,
+    207, 9, 178, 204, 74, 98, 126, 249, 167, 116, 34, 77, 193, 200, 121, 5,
+    20, 113, 71, 35, 128, 13, 182, 94, 25, 226, 227, 199, 75, 27, 41, 245,
+    230, 224, 43, 225, 177, 26, 155, 150, 212, 142, 218, 115, 241, 73, 88, 105,
+    39, 114, 62, 255, 192, 201, 145, 214, 168, 158, 221, 148, 154, 122, 12, 84,
+    82, 163, 44, 139, 228, 236, 205, 242, 217, 11, 187, 146, 159, 64, 86, 239,
+    195, 42, 106, 198, 118, 112, 184, 172, 87, 2, 173, 117, 176, 229, 247, 253,
+    137, 185, 99, 164, 102, 147, 45, 66, 231, 52, 141, 211, 194, 206, 246, 238,
+    56, 110, 78, 248, 63, 240, 189, 93, 92, 51, 53, 183, 19, 171, 72, 50,
+    33, 104, 101, 69, 8, 252, 83, 120, 76, 135, 85, 54, 202, 125, 188, 213,
+    96, 235, 136, 208, 162, 129, 190, 132, 156, 38, 47, 1, 7, 254, 24, 4,
+    216, 131, 89, 21, 28, 133, 37, 153, 149, 80, 170, 68, 6, 169, 234, 151,
+];
+
+/// Returns the hash bucket of a key, as
+/// computed by RFC 3074 section 6.
+///
+/// # Examples:
+///
+/// ```
+/// assert!(bucket(&[]) == 0);
+/// ```
+pub fn bucket(
+    key: &[u8]
+) -> u8 {
+    key.iter()
+        .rev()
+        .fold(key.len() as u8, |hash, byte| PERMUTATION[(hash ^ byte) as usize])
+}
+
+/// Role of a server among the failover partners.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailoverRole {
+    Primary,
+    Secondary,
+}
+
+/// `LoadBalancer` tells whether a client is served by this
+/// server or its partner : the primary serves the clients whose
+/// hash bucket is below the split, the secondary the other ones.
+///
+/// Clients are identified by their client identifier, or their
+/// hardware address when they send none (RFC 3074 section 4).
+///
+/// # Examples:
+///
+/// ```
+/// let balancer = LoadBalancer::new(FailoverRole::Primary, 128);
+/// if balancer.serves(&packet) {
+///     // Answer the client
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadBalancer {
+    role: FailoverRole,
+    split: u16,
+}
+
+impl LoadBalancer {
+
+    /// Creates the `LoadBalancer` of a server, the primary
+    /// serving `split` buckets out of [`BUCKETS`].
+    pub fn new(
+        role: FailoverRole,
+        split: u16
+    ) -> Self {
+        Self { role, split: split.min(BUCKETS) }
+    }
+
+    /// Returns the key hashed for the client of a packet.
+    pub fn client_key(
+        packet: &DhcpV4Packet
+    ) -> &[u8] {
+        match packet.options.client_identifier() {
+            Some(client_id) => client_id.as_slice(),
+            None => &packet.chadd.raw[..(packet.hlen as usize).min(packet.chadd.raw.len())]
+        }
+    }
+
+    /// Returns true if a key falls in the buckets of this server
+    pub fn serves_key(
+        &self,
+        key: &[u8]
+    ) -> bool {
+        let primary = (bucket(key) as u16) < self.split;
+        match self.role {
+            FailoverRole::Primary => primary,
+            FailoverRole::Secondary => !primary
+        }
+    }
+
+    /// Returns true if the client of a packet is
+    /// served by this server.
+    pub fn serves(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> bool {
+        self.serves_key(Self::client_key(packet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutation() {
+        let mut values = PERMUTATION.to_vec();
+        values.sort();
+        assert!(values == (0..=255).collect::<Vec<u8>>());
+        assert!(bucket(&[]) == 0);
+        assert!(bucket(&[1, 2, 3, 4, 5, 6]) == 179);
+    }
+
+    #[test]
+    fn test_split_between_partners() {
+        let primary = LoadBalancer::new(FailoverRole::Primary, 128);
+        let secondary = LoadBalancer::new(FailoverRole::Secondary, 128);
+        let keys: Vec<[u8; 4]> = (0u32..1000).map(u32::to_be_bytes).collect();
+        // Every client is served by exactly one partner
+        assert!(keys.iter().all(|key| primary.serves_key(key) != secondary.serves_key(key)));
+        let served = keys.iter().filter(|key| primary.serves_key(key.as_slice())).count();
+        assert!((400..600).contains(&served));
+
+        // The whole load goes to the primary
+        let primary = LoadBalancer::new(FailoverRole::Primary, 300);
+        assert!(keys.iter().all(|key| primary.serves_key(key)));
+        let secondary = LoadBalancer::new(FailoverRole::Secondary, 256);
+        assert!(!keys.iter().any(|key| secondary.serves_key(key)));
+    }
+}
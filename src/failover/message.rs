@@ -0,0 +1,281 @@
+//! Messages exchanged by failover partners, each one
+//! sent as a type code followed by its fields in network
+//! order, and framed like bulk leasequery messages.
+
+use std::net::Ipv4Addr;
+
+use byteorder::{BigEndian, ByteOrder};
+use chrono::{DateTime, TimeZone, Utc};
+
+use super::error::FailoverError;
+
+const HEARTBEAT: u8 = 1;
+const LEASE_UPDATE: u8 = 2;
+const LEASE_ACK: u8 = 3;
+
+/// States of a failover server, loosely following
+/// the DHCP failover protocol draft.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailoverState {
+    /// The partner was never heard of
+    Startup,
+    /// Both servers are up and share the clients
+    Normal,
+    /// The partner went silent : it may be down, or
+    /// still serving clients the server cannot reach
+    CommunicationsInterrupted,
+    /// The partner is known to be down : the server
+    /// serves every client, and takes its addresses
+    /// over once they are safe to reuse
+    PartnerDown,
+    /// The partner took over while the server was thought
+    /// down : the server only renews leases until the partner
+    /// sent every lease it commited meanwhile
+    Recover,
+}
+
+impl FailoverState {
+
+    fn code(
+        &self
+    ) -> u8 {
+        match self {
+            FailoverState::Startup => 0,
+            FailoverState::Normal => 1,
+            FailoverState::CommunicationsInterrupted => 2,
+            FailoverState::PartnerDown => 3,
+            FailoverState::Recover => 4
+        }
+    }
+
+    fn from_code(
+        code: u8
+    ) -> Result<Self, FailoverError> {
+        match code {
+            0 => Ok(FailoverState::Startup),
+            1 => Ok(FailoverState::Normal),
+            2 => Ok(FailoverState::CommunicationsInterrupted),
+            3 => Ok(FailoverState::PartnerDown),
+            4 => Ok(FailoverState::Recover),
+            _ => Err(FailoverError::Malformed)
+        }
+    }
+}
+
+/// A lease commited, renewed or released by one of the partners.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaseUpdate {
+    pub address: Ipv4Addr,
+    pub hostname: String,
+    pub expiration: DateTime<Utc>,
+    /// Latest expiration the sender may grant without telling
+    /// its partner first : the expiration plus the MCLT
+    pub potential_expiration: DateTime<Utc>,
+    pub released: bool,
+    /// Client of the lease, so that the partner can
+    /// renew it and answer leasequeries about it
+    pub htype: u8,
+    pub chaddr: Vec<u8>,
+    pub client_id: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailoverMessage {
+    /// Sent periodically, telling the state of the sender
+    Heartbeat {
+        state: FailoverState,
+        time: DateTime<Utc>,
+    },
+    LeaseUpdate(LeaseUpdate),
+    /// Acknowledges a [`LeaseUpdate`], so that the sender may
+    /// extend the lease up to its potential expiration
+    LeaseAck {
+        address: Ipv4Addr,
+        potential_expiration: DateTime<Utc>,
+    },
+}
+
+fn _push_time(buffer: &mut Vec<u8>, time: &DateTime<Utc>) {
+    buffer.extend_from_slice(&time.timestamp().to_be_bytes());
+}
+
+fn _push_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    let bytes = &bytes[..bytes.len().min(u16::MAX as usize)];
+    buffer.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FailoverError> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or(FailoverError::Malformed)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, FailoverError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn address(&mut self) -> Result<Ipv4Addr, FailoverError> {
+        Ok(Ipv4Addr::from(BigEndian::read_u32(self.take(4)?)))
+    }
+
+    fn time(&mut self) -> Result<DateTime<Utc>, FailoverError> {
+        Utc.timestamp_opt(BigEndian::read_i64(self.take(8)?), 0)
+            .single()
+            .ok_or(FailoverError::Malformed)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, FailoverError> {
+        let len = BigEndian::read_u16(self.take(2)?) as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, FailoverError> {
+        String::from_utf8(self.bytes()?).map_err(|_| FailoverError::Malformed)
+    }
+}
+
+impl FailoverMessage {
+
+    /// Returns the wire format of the message.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let message = FailoverMessage::Heartbeat { state: FailoverState::Normal, time: Utc::now() };
+    /// let bytes = message.to_bytes();
+    /// assert!(FailoverMessage::try_from(bytes.as_slice()).is_ok());
+    /// ```
+    pub fn to_bytes(
+        &self
+    ) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            FailoverMessage::Heartbeat { state, time } => {
+                buffer.push(HEARTBEAT);
+                buffer.push(state.code());
+                _push_time(&mut buffer, time);
+            },
+            FailoverMessage::LeaseUpdate(update) => {
+                buffer.push(LEASE_UPDATE);
+                buffer.extend_from_slice(&update.address.octets());
+                buffer.push(update.released as u8);
+                _push_time(&mut buffer, &update.expiration);
+                _push_time(&mut buffer, &update.potential_expiration);
+                _push_bytes(&mut buffer, update.hostname.as_bytes());
+                buffer.push(update.htype);
+                _push_bytes(&mut buffer, &update.chaddr);
+                // Client identifiers are preceded by a presence flag
+                buffer.push(update.client_id.is_some() as u8);
+                if let Some(client_id) = &update.client_id {
+                    _push_bytes(&mut buffer, client_id);
+                };
+            },
+            FailoverMessage::LeaseAck { address, potential_expiration } => {
+                buffer.push(LEASE_ACK);
+                buffer.extend_from_slice(&address.octets());
+                _push_time(&mut buffer, potential_expiration);
+            }
+        };
+        buffer
+    }
+}
+
+impl TryFrom<&[u8]> for FailoverMessage {
+    type Error = FailoverError;
+
+    fn try_from(
+        value: &[u8]
+    ) -> Result<Self, FailoverError> {
+        let mut reader = Reader { bytes: value, pos: 0 };
+        let message = match reader.u8()? {
+            HEARTBEAT => FailoverMessage::Heartbeat {
+                state: FailoverState::from_code(reader.u8()?)?,
+                time: reader.time()?,
+            },
+            LEASE_UPDATE => {
+                let address = reader.address()?;
+                let released = reader.u8()? != 0;
+                let expiration = reader.time()?;
+                let potential_expiration = reader.time()?;
+                let hostname = reader.string()?;
+                let htype = reader.u8()?;
+                let chaddr = reader.bytes()?;
+                let client_id = match reader.u8()? {
+                    0 => None,
+                    _ => Some(reader.bytes()?)
+                };
+                FailoverMessage::LeaseUpdate(LeaseUpdate { address, hostname, expiration, potential_expiration, released, htype, chaddr, client_id })
+            },
+            LEASE_ACK => FailoverMessage::LeaseAck {
+                address: reader.address()?,
+                potential_expiration: reader.time()?,
+            },
+            _ => return Err(FailoverError::Malformed)
+        };
+        match reader.pos == value.len() {
+            true => Ok(message),
+            false => Err(FailoverError::Malformed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let now = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+        let messages = [
+            FailoverMessage::Heartbeat { state: FailoverState::CommunicationsInterrupted, time: now },
+            FailoverMessage::LeaseUpdate(LeaseUpdate {
+                address: Ipv4Addr::new(192, 168, 0, 10),
+                hostname: String::from("host.example.com"),
+                expiration: now + Duration::hours(1),
+                potential_expiration: now + Duration::hours(2),
+                released: false,
+                htype: 1,
+                chaddr: vec![0xf8, 0x4d, 0x89, 0x82, 0x44, 0x2a],
+                client_id: Some(vec![0x01, 0xf8, 0x4d, 0x89, 0x82, 0x44, 0x2a]),
+            }),
+            FailoverMessage::LeaseUpdate(LeaseUpdate {
+                address: Ipv4Addr::new(192, 168, 0, 11),
+                hostname: String::new(),
+                expiration: now,
+                potential_expiration: now,
+                released: true,
+                htype: 0,
+                chaddr: Vec::new(),
+                client_id: None,
+            }),
+            FailoverMessage::LeaseAck { address: Ipv4Addr::new(192, 168, 0, 10), potential_expiration: now },
+        ];
+        for message in messages.iter() {
+            assert!(FailoverMessage::try_from(message.to_bytes().as_slice()).unwrap() == *message);
+        }
+    }
+
+    #[test]
+    fn test_malformed_messages() {
+        assert!(FailoverMessage::try_from([].as_slice()) == Err(FailoverError::Malformed));
+        assert!(FailoverMessage::try_from([9, 0].as_slice()) == Err(FailoverError::Malformed));
+        // Unknown state
+        let mut heartbeat = FailoverMessage::Heartbeat { state: FailoverState::Normal, time: Utc::now() }.to_bytes();
+        heartbeat[1] = 7;
+        assert!(FailoverMessage::try_from(heartbeat.as_slice()).is_err());
+        // Truncated and trailing bytes
+        let ack = FailoverMessage::LeaseAck { address: Ipv4Addr::new(192, 168, 0, 10), potential_expiration: Utc::now() }.to_bytes();
+        assert!(FailoverMessage::try_from(&ack[..ack.len() - 1]).is_err());
+        assert!(FailoverMessage::try_from([ack.as_slice(), &[0]].concat().as_slice()).is_err());
+    }
+}
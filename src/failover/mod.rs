@@ -0,0 +1,5 @@
+pub mod error;
+pub mod message;
+pub mod load_balance;
+pub mod peer;
+pub mod allocator;
@@ -0,0 +1,1090 @@
+//! Implements `FailoverPeer`, keeping the leases of two
+//! servers in sync so that either one can serve every
+//! client when its partner goes down.
+
+use std::{collections::{HashMap, HashSet}, io, net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, Shutdown}, sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, Ordering}}, thread, time};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{info, trace, warn};
+use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
+
+use crate::{allocators::{subnet_map::{CidrSubnet, SubnetV4Map}, subnet_registry::SubnetRegistry}, clock::clock::{Clock, SystemClock}, data::data::LeaseData, leasequery::{binding::LeaseBinding, bulk::{read_message, write_message}}, leases::{ip_subnet::Ipv4Subnet, lease_time::{LeaseTimes, INFINITE_LEASE_TIME}}, packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet}};
+
+use super::{error::FailoverError, load_balance::{FailoverRole, LoadBalancer, BUCKETS}, message::{FailoverMessage, FailoverState, LeaseUpdate}};
+
+fn _default_address() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 647))
+}
+
+fn _default_mclt() -> u32 {
+    3600
+}
+
+fn _default_split() -> u16 {
+    BUCKETS / 2
+}
+
+fn _default_heartbeat() -> u64 {
+    1000
+}
+
+fn _default_partner_timeout() -> u64 {
+    3000
+}
+
+/// `FailoverSettings` describes a server and its partner.
+///
+/// # Examples:
+///
+/// ```yaml
+/// failover:
+///   role: primary
+///   address: 0.0.0.0:647
+///   partner: 192.168.0.2:647
+///   mclt: 3600
+///   split: 128
+///   heartbeat: 1000
+///   partner_timeout: 3000
+///   auto_partner_down: 600000
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FailoverSettings {
+    pub role: FailoverRole,
+    /// Address the partner connects to
+    #[serde(default = "_default_address")]
+    pub address: SocketAddr,
+    pub partner: SocketAddr,
+    /// Maximum Client Lead Time (in seconds) : how much longer
+    /// than its partner knows a server may extend a lease
+    #[serde(default = "_default_mclt")]
+    pub mclt: u32,
+    /// Hash buckets served by the primary, out of 256
+    #[serde(default = "_default_split")]
+    pub split: u16,
+    /// Time (in milliseconds) between two heartbeats
+    #[serde(default = "_default_heartbeat")]
+    pub heartbeat: u64,
+    /// Time (in milliseconds) without hearing from the partner
+    /// after which communications are considered interrupted
+    #[serde(default = "_default_partner_timeout")]
+    pub partner_timeout: u64,
+    /// Time (in milliseconds) after which an unreachable partner
+    /// is assumed down, never when unset
+    pub auto_partner_down: Option<u64>,
+}
+
+impl FailoverSettings {
+
+    pub fn mclt(&self) -> Duration {
+        Duration::seconds(self.mclt as i64)
+    }
+}
+
+// A lease of the server or its partner
+#[derive(Clone, Debug)]
+struct ReplicatedLease {
+    lease: LeaseData,
+    // Leases of the partner are held until their potential expiration
+    partner: bool,
+    // Latest potential expiration acknowledged by the partner, or
+    // announced by it for its own leases
+    potential_expiration: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PeerState {
+    state: FailoverState,
+    partner_state: Option<FailoverState>,
+    last_heard: Option<DateTime<Utc>>,
+    // Time the current state was entered
+    since: DateTime<Utc>,
+}
+
+/// `FailoverPeer` shares the leases of a server with its
+/// partner over TCP, and decides which clients it serves.
+///
+/// In the `Normal` state, clients are shared according to the
+/// [`LoadBalancer`], and so are the free addresses of the pools :
+/// the addresses of the partner are held in the registered subnets,
+/// so that allocators never hand them out. Every commited or released
+/// lease is sent to the partner, which holds its address in turn, and
+/// hands it over to its transaction manager so that its client can
+/// renew it there.
+///
+/// When the partner goes silent, the server keeps serving its own
+/// clients. Once the partner is known to be down, it serves every
+/// client, and takes the addresses of the partner over : free ones
+/// right away, leased ones once they are safe to reuse. When the
+/// partner is back, it recovers, and the pool is only split again
+/// once it acknowledged every lease commited meanwhile.
+///
+/// Leases never outlive what the partner knows of them by more than
+/// the MCLT, so that a partner taking over never hands out an address
+/// still used by a client.
+///
+/// # Examples:
+///
+/// ```
+/// let peer = Arc::new(FailoverPeer::new(cfg.failover().unwrap().clone()));
+/// peer.register_subnet(subnet.clone()).unwrap();
+/// peer.listen().unwrap();
+/// transaction_manager.set_failover(peer.clone());
+/// let allocator = FailoverAllocator::new(Arc::new(chain), peer);
+/// ```
+pub struct FailoverPeer {
+    settings: FailoverSettings,
+    balancer: LoadBalancer,
+    state: Mutex<PeerState>,
+    leases: Mutex<HashMap<Ipv4Addr, ReplicatedLease>>,
+    // Updates sent but not acknowledged yet, sent
+    // again whenever the partner is reconnected
+    pending: Mutex<HashMap<Ipv4Addr, LeaseUpdate>>,
+    // Latest updates of the partner leases, not yet
+    // taken by the transaction manager
+    received: Mutex<HashMap<Ipv4Addr, LeaseUpdate>>,
+    // Free addresses of the partner, held in the subnets
+    withheld: Mutex<HashSet<Ipv4Addr>>,
+    subnet_map: RwLock<SubnetV4Map>,
+    connection: Mutex<Option<TcpStream>>,
+    running: AtomicBool,
+    clock: Arc<dyn Clock>,
+}
+
+impl FailoverPeer {
+
+    pub fn new(
+        settings: FailoverSettings
+    ) -> Self {
+        Self::with_clock(settings, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        settings: FailoverSettings,
+        clock: Arc<dyn Clock>
+    ) -> Self {
+        let state = PeerState { state: FailoverState::Startup, partner_state: None, last_heard: None, since: clock.now() };
+        Self {
+            balancer: LoadBalancer::new(settings.role, settings.split),
+            settings,
+            state: Mutex::new(state),
+            leases: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            received: Mutex::new(HashMap::new()),
+            withheld: Mutex::new(HashSet::new()),
+            subnet_map: RwLock::new(SubnetV4Map::new()),
+            connection: Mutex::new(None),
+            running: AtomicBool::new(true),
+            clock,
+        }
+    }
+
+    pub fn settings(&self) -> &FailoverSettings {
+        &self.settings
+    }
+
+    pub fn state(&self) -> FailoverState {
+        self.state.lock().unwrap().state
+    }
+
+    /// Returns the state last announced by the partner
+    pub fn partner_state(&self) -> Option<FailoverState> {
+        self.state.lock().unwrap().partner_state
+    }
+
+    /// Registers an [`Ipv4Subnet`] shared with the allocators,
+    /// holding the free addresses of the partner.
+    ///
+    /// Returns the already registered [`CidrSubnet`]
+    /// overlapping the given subnet, if any.
+    pub fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .insert_subnet(subnet.clone())?;
+        if self.state() != FailoverState::PartnerDown {
+            self.withhold_partner_addresses(&subnet);
+        };
+        Ok(())
+    }
+
+    /// Returns true if the free address belongs to this server
+    pub fn owns(
+        &self,
+        address: Ipv4Addr
+    ) -> bool {
+        self.balancer.serves_key(&address.octets())
+    }
+
+    /// Returns true if the client of a packet is served by this
+    /// server : renewing clients are served by any server, other
+    /// ones by the server of their hash bucket until the partner
+    /// is down, and by none while the server recovers.
+    pub fn serves(
+        &self,
+        packet: &DhcpV4Packet
+    ) -> bool {
+        let state = self.state();
+        (state == FailoverState::PartnerDown)
+            | !packet.ciaddr.is_unspecified()
+            | ((state != FailoverState::Recover) & self.balancer.serves(packet))
+    }
+
+    /// Returns the longest lease time (in seconds) that may be
+    /// granted for an address : up to the potential expiration
+    /// acknowledged by the partner, or the MCLT from now.
+    pub fn max_lease_time(
+        &self,
+        address: Ipv4Addr
+    ) -> u32 {
+        let now = self.clock.now();
+        let acknowledged = self.leases
+            .lock()
+            .unwrap()
+            .get(&address)
+            .filter(|lease| !lease.partner)
+            .and_then(|lease| lease.potential_expiration);
+        let limit = match acknowledged {
+            Some(potential_expiration) => potential_expiration.max(now + self.settings.mclt()),
+            None => now + self.settings.mclt()
+        };
+        (limit - now).num_seconds().clamp(0, u32::MAX as i64) as u32
+    }
+
+    /// Shortens the lease time of [`DhcpOptions`] drafted for
+    /// an address to [`FailoverPeer::max_lease_time`], along with
+    /// its renewal and rebinding times.
+    ///
+    /// Infinite leases are left as is.
+    pub fn limit_lease_time(
+        &self,
+        address: Ipv4Addr,
+        options: &mut DhcpOptions
+    ) {
+        let lease_time = match options.lease_time() {
+            Some(lease_time) if lease_time != INFINITE_LEASE_TIME => lease_time,
+            _ => return
+        };
+        let max_lease_time = self.max_lease_time(address);
+        if lease_time > max_lease_time {
+            LeaseTimes::new(max_lease_time, 0, max_lease_time).apply(Some(max_lease_time), options);
+        };
+    }
+
+    /// Returns a lease of the partner, known from its updates
+    pub fn partner_lease(
+        &self,
+        address: &Ipv4Addr
+    ) -> Option<LeaseData> {
+        self.leases
+            .lock()
+            .unwrap()
+            .get(address)
+            .filter(|lease| lease.partner)
+            .map(|lease| lease.lease.clone())
+    }
+
+    /// Returns the number of updates the partner
+    /// did not acknowledge yet
+    pub fn pending_updates(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Takes the commited, renewed and released leases of the
+    /// partner received since the last call, the latest update
+    /// of each address only. Leases of the partner given up once
+    /// it is down come as released ones.
+    pub fn take_partner_updates(&self) -> Vec<LeaseUpdate> {
+        self.received.lock().unwrap().drain().map(|(_, update)| update).collect()
+    }
+
+    /// Sends a commited or renewed lease to the partner, along
+    /// with the client of its [`LeaseBinding`].
+    ///
+    /// Updates are sent again until the partner
+    /// acknowledges them.
+    pub fn publish(
+        &self,
+        lease: &LeaseData,
+        binding: &LeaseBinding
+    ) {
+        {
+            let mut leases = self.leases.lock().unwrap();
+            let potential_expiration = leases.get(&lease.address())
+                .filter(|replicated| !replicated.partner)
+                .and_then(|replicated| replicated.potential_expiration);
+            leases.insert(lease.address(), ReplicatedLease { lease: lease.clone(), partner: false, potential_expiration });
+        }
+        self.send_update(LeaseUpdate {
+            address: lease.address(),
+            hostname: lease.hostname().to_string(),
+            expiration: lease.expiration(),
+            potential_expiration: lease.expiration() + self.settings.mclt(),
+            released: false,
+            htype: binding.htype,
+            chaddr: binding.chaddr.clone(),
+            client_id: binding.client_id.clone(),
+        });
+    }
+
+    /// Tells the partner that a lease was released
+    pub fn withdraw(
+        &self,
+        address: Ipv4Addr
+    ) {
+        self.leases.lock().unwrap().remove(&address);
+        let now = self.clock.now();
+        self.send_update(Self::released(address, now));
+    }
+
+    fn released(
+        address: Ipv4Addr,
+        now: DateTime<Utc>
+    ) -> LeaseUpdate {
+        LeaseUpdate {
+            address,
+            hostname: String::new(),
+            expiration: now,
+            potential_expiration: now,
+            released: true,
+            htype: 0,
+            chaddr: Vec::new(),
+            client_id: None,
+        }
+    }
+
+    fn send_update(
+        &self,
+        update: LeaseUpdate
+    ) {
+        self.pending.lock().unwrap().insert(update.address, update.clone());
+        if let Err(e) = self.send(&FailoverMessage::LeaseUpdate(update)) {
+            trace!("Lease update delayed : {}", e);
+        };
+    }
+
+    fn send(
+        &self,
+        message: &FailoverMessage
+    ) -> Result<(), FailoverError> {
+        let mut connection = self.connection.lock().unwrap();
+        let stream = connection.as_mut().ok_or(FailoverError::NotConnected)?;
+        if let Err(e) = write_message(stream, &message.to_bytes()) {
+            *connection = None;
+            return Err(e.into());
+        };
+        Ok(())
+    }
+
+    /// Assumes the partner is down, as told by an operator
+    /// who made sure it does not serve clients anymore.
+    pub fn set_partner_down(&self) {
+        self.transition(FailoverState::PartnerDown);
+    }
+
+    fn transition(
+        &self,
+        next: FailoverState
+    ) {
+        let previous = {
+            let mut state = self.state.lock().unwrap();
+            if state.state == next {
+                return;
+            };
+            let previous = state.state;
+            state.state = next;
+            state.since = self.clock.now();
+            previous
+        };
+        info!("Failover state changed from {:?} to {:?}", previous, next);
+        match (previous, next) {
+            (_, FailoverState::PartnerDown) => self.release_withheld_addresses(),
+            (FailoverState::PartnerDown, _) => {
+                for subnet in self.subnets() {
+                    self.withhold_partner_addresses(&subnet);
+                }
+            },
+            _ => ()
+        };
+    }
+
+    fn subnets(&self) -> Vec<Arc<Mutex<Ipv4Subnet>>> {
+        self.subnet_map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(_, subnet)| subnet.clone())
+            .collect()
+    }
+
+    fn subnet(
+        &self,
+        address: Ipv4Addr
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        self.subnet_map
+            .read()
+            .unwrap()
+            .get_matching_subnet(address)
+    }
+
+    // Holds the free pool addresses owned by the partner
+    fn withhold_partner_addresses(
+        &self,
+        subnet: &Arc<Mutex<Ipv4Subnet>>
+    ) {
+        let mut subnet = subnet.lock().unwrap();
+        let mut withheld = self.withheld.lock().unwrap();
+        let (start, end) = (u32::from(subnet.pool_start()), u32::from(subnet.pool_end()));
+        for address in (start..=end).map(Ipv4Addr::from) {
+            if !self.owns(address) && subnet.take(address).is_ok() {
+                withheld.insert(address);
+            };
+        }
+    }
+
+    // Hands the free addresses of the partner over to the allocators
+    fn release_withheld_addresses(&self) {
+        let withheld: Vec<Ipv4Addr> = self.withheld.lock().unwrap().drain().collect();
+        for address in withheld {
+            if let Some(subnet) = self.subnet(address) {
+                let _ = subnet.lock().unwrap().free(address);
+            };
+        }
+    }
+
+    fn apply_partner_update(
+        &self,
+        update: &LeaseUpdate
+    ) {
+        self.received.lock().unwrap().insert(update.address, update.clone());
+        let subnet = self.subnet(update.address);
+        if update.released {
+            let held = self.leases.lock().unwrap().remove(&update.address).is_some();
+            let subnet = match subnet {
+                Some(subnet) if held => subnet,
+                _ => return
+            };
+            let mut subnet = subnet.lock().unwrap();
+            // Released addresses of the partner stay withheld
+            if !self.owns(update.address) & (self.state() != FailoverState::PartnerDown) {
+                self.withheld.lock().unwrap().insert(update.address);
+            } else {
+                let _ = subnet.free(update.address);
+            };
+            return;
+        };
+
+        let lease = LeaseData::new(update.address, update.hostname.clone(), update.expiration);
+        self.leases.lock().unwrap().insert(update.address, ReplicatedLease {
+            lease,
+            partner: true,
+            potential_expiration: Some(update.potential_expiration),
+        });
+        if !self.withheld.lock().unwrap().remove(&update.address) {
+            if let Some(subnet) = subnet {
+                let _ = subnet.lock().unwrap().take(update.address);
+            };
+        };
+    }
+
+    fn acknowledge(
+        &self,
+        address: Ipv4Addr,
+        potential_expiration: DateTime<Utc>
+    ) {
+        let mut pending = self.pending.lock().unwrap();
+        // Only the latest update of an address counts
+        if pending.get(&address).is_some_and(|update| update.potential_expiration == potential_expiration) {
+            pending.remove(&address);
+        };
+        drop(pending);
+        if let Some(lease) = self.leases.lock().unwrap().get_mut(&address).filter(|lease| !lease.partner) {
+            lease.potential_expiration = Some(potential_expiration);
+        };
+    }
+
+    // Moves to the state following a message of the partner :
+    //
+    // - a server which took over stays down until the partner
+    //   acknowledged every lease it commited meanwhile, so that
+    //   the pool is not split again before the partner knows them ;
+    // - a server the partner took over from recovers until the
+    //   partner is done, serving no new client.
+    fn heard_from_partner(
+        &self,
+        partner_state: Option<FailoverState>
+    ) {
+        let (state, partner_state) = {
+            let mut state = self.state.lock().unwrap();
+            state.last_heard = Some(self.clock.now());
+            if partner_state.is_some() {
+                state.partner_state = partner_state;
+            };
+            (state.state, state.partner_state)
+        };
+        let partner_down = partner_state == Some(FailoverState::PartnerDown);
+        let next = match state {
+            FailoverState::PartnerDown if self.pending_updates() > 0 => return,
+            FailoverState::PartnerDown => FailoverState::Normal,
+            _ if partner_down => FailoverState::Recover,
+            _ => FailoverState::Normal
+        };
+        if state != next {
+            self.transition(next);
+        };
+    }
+
+    // Handles a message sent by the partner, returning the reply to send back
+    fn handle(
+        &self,
+        message: FailoverMessage
+    ) -> Option<FailoverMessage> {
+        match message {
+            FailoverMessage::Heartbeat { state, .. } => {
+                self.heard_from_partner(Some(state));
+                None
+            },
+            FailoverMessage::LeaseUpdate(update) => {
+                self.heard_from_partner(None);
+                self.apply_partner_update(&update);
+                Some(FailoverMessage::LeaseAck { address: update.address, potential_expiration: update.potential_expiration })
+            },
+            FailoverMessage::LeaseAck { address, potential_expiration } => {
+                self.acknowledge(address, potential_expiration);
+                self.heard_from_partner(None);
+                None
+            }
+        }
+    }
+
+    /// Checks that the partner was heard of recently enough,
+    /// moving to the next state otherwise :
+    ///
+    /// - a silent partner interrupts communications ;
+    /// - communications interrupted for too long mean the partner
+    ///   is down, if `auto_partner_down` is set ;
+    /// - a partner down for long enough gives its leases up,
+    ///   once they expired and the MCLT has elapsed.
+    ///
+    /// It is called on every heartbeat.
+    pub fn check_partner(&self) {
+        let now = self.clock.now();
+        let state = *self.state.lock().unwrap();
+        let silence = now - state.last_heard.unwrap_or(state.since).max(state.since);
+        match state.state {
+            FailoverState::Startup | FailoverState::Normal => {
+                if silence > Duration::milliseconds(self.settings.partner_timeout as i64) {
+                    warn!("Failover partner {} stopped answering", self.settings.partner);
+                    self.transition(FailoverState::CommunicationsInterrupted);
+                };
+            },
+            FailoverState::CommunicationsInterrupted => {
+                let auto_partner_down = self.settings.auto_partner_down.map(|delay| Duration::milliseconds(delay as i64));
+                if auto_partner_down.is_some_and(|delay| now - state.since >= delay) {
+                    warn!("Failover partner {} assumed down", self.settings.partner);
+                    self.transition(FailoverState::PartnerDown);
+                };
+            },
+            FailoverState::PartnerDown => self.reclaim_partner_leases(now, state.since),
+            // The partner may serve every client : waiting for it,
+            // unless an operator tells it is down
+            FailoverState::Recover => ()
+        };
+    }
+
+    // Frees the leases of a down partner once no client may use them anymore
+    fn reclaim_partner_leases(
+        &self,
+        now: DateTime<Utc>,
+        partner_down_since: DateTime<Utc>
+    ) {
+        let reclaimed: Vec<Ipv4Addr> = {
+            let mut leases = self.leases.lock().unwrap();
+            let safe = |lease: &ReplicatedLease| {
+                let potential_expiration = lease.potential_expiration.unwrap_or(lease.lease.expiration());
+                now >= potential_expiration.max(partner_down_since + self.settings.mclt())
+            };
+            let reclaimed: Vec<Ipv4Addr> = leases.iter()
+                .filter(|(_, lease)| lease.partner && safe(lease))
+                .map(|(address, _)| *address)
+                .collect();
+            for address in reclaimed.iter() {
+                leases.remove(address);
+            }
+            reclaimed
+        };
+        for address in reclaimed {
+            info!("Reclaiming address {} of the failover partner", address);
+            self.received.lock().unwrap().insert(address, Self::released(address, now));
+            if let Some(subnet) = self.subnet(address) {
+                let _ = subnet.lock().unwrap().free(address);
+            };
+        }
+    }
+
+    /// Listens for the partner on the address of
+    /// the settings, see [`FailoverPeer::start`].
+    pub fn listen(
+        self: &Arc<Self>
+    ) -> io::Result<()> {
+        let listener = TcpListener::bind(self.settings.address)?;
+        self.start(listener);
+        Ok(())
+    }
+
+    /// Starts talking to the partner : its connections are
+    /// accepted on the given listener, while heartbeats and
+    /// lease updates are sent over a connection to its address.
+    pub fn start(
+        self: &Arc<Self>,
+        listener: TcpListener
+    ) {
+        let peer = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !peer.running.load(Ordering::SeqCst) {
+                    break;
+                };
+                match stream {
+                    Ok(stream) => {
+                        let peer = peer.clone();
+                        thread::spawn(move || peer.serve_partner(stream));
+                    },
+                    Err(e) => warn!("Failover connection failed : {}", e)
+                };
+            }
+        });
+
+        let peer = self.clone();
+        thread::spawn(move || {
+            let heartbeat = time::Duration::from_millis(peer.settings.heartbeat);
+            while peer.running.load(Ordering::SeqCst) {
+                peer.connect_partner();
+                let state = peer.state();
+                let _ = peer.send(&FailoverMessage::Heartbeat { state, time: peer.clock.now() });
+                peer.check_partner();
+                thread::sleep(heartbeat);
+            }
+        });
+    }
+
+    /// Stops talking to the partner
+    pub fn shutdown(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(stream) = self.connection.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        };
+        // Wakes the listener up
+        let _ = TcpStream::connect(self.settings.address);
+    }
+
+    // Connects to the partner if needed, then sends it
+    // every update it did not acknowledge
+    fn connect_partner(
+        self: &Arc<Self>
+    ) {
+        if self.connection.lock().unwrap().is_some() {
+            return;
+        };
+        let timeout = time::Duration::from_millis(self.settings.partner_timeout);
+        let stream = match TcpStream::connect_timeout(&self.settings.partner, timeout) {
+            Ok(stream) => stream,
+            Err(e) => {
+                trace!("Failover partner {} unreachable : {}", self.settings.partner, e);
+                return;
+            }
+        };
+        let reader = match stream.set_write_timeout(Some(timeout)).and_then(|_| stream.try_clone()) {
+            Ok(reader) => reader,
+            Err(_) => return
+        };
+        *self.connection.lock().unwrap() = Some(stream);
+
+        // Acknowledgements come back on the same connection
+        let peer = self.clone();
+        thread::spawn(move || peer.serve_partner(reader));
+
+        let pending: Vec<LeaseUpdate> = self.pending.lock().unwrap().values().cloned().collect();
+        for update in pending {
+            if self.send(&FailoverMessage::LeaseUpdate(update)).is_err() {
+                break;
+            };
+        }
+    }
+
+    // Handles the messages of a connection until it is closed,
+    // dropping connections coming from anywhere but the partner
+    fn serve_partner(
+        &self,
+        mut stream: TcpStream
+    ) {
+        match stream.peer_addr() {
+            Ok(address) if address.ip() == self.settings.partner.ip() => (),
+            Ok(address) => {
+                warn!("Failover connection from {} refused : not the partner", address);
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            },
+            Err(_) => return
+        };
+        let _ = stream.set_read_timeout(Some(time::Duration::from_millis(self.settings.heartbeat)));
+        while self.running.load(Ordering::SeqCst) {
+            let message = match read_message(&mut stream) {
+                Ok(Some(message)) => message,
+                Ok(None) => return,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                Err(_) => return
+            };
+            let message = match FailoverMessage::try_from(message.as_slice()) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Failover connection closed : {}", e);
+                    return;
+                }
+            };
+            if !self.running.load(Ordering::SeqCst) {
+                return;
+            };
+            if let Some(reply) = self.handle(message) {
+                if write_message(&mut stream, &reply.to_bytes()).is_err() {
+                    return;
+                };
+            };
+        }
+    }
+}
+
+impl SubnetRegistry for FailoverPeer {
+
+    fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        FailoverPeer::register_subnet(self, subnet)
+    }
+
+    /// Unregisters a subnet, handing the addresses
+    /// withheld for the partner back to it.
+    fn unregister_subnet(
+        &self,
+        cidr: CidrSubnet
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        let subnet = self.subnet_map
+            .write()
+            .unwrap()
+            .remove_subnet(cidr)?;
+        {
+            let mut subnet = subnet.lock().unwrap();
+            self.withheld.lock().unwrap().retain(|address| match cidr.contains(*address) {
+                true => {
+                    let _ = subnet.free(*address);
+                    false
+                },
+                false => true
+            });
+        }
+        Some(subnet)
+    }
+
+    /// Withholds the partner addresses added to the pool,
+    /// and hands back the ones left out of it.
+    fn subnet_reconfigured(
+        &self,
+        subnet: &Arc<Mutex<Ipv4Subnet>>
+    ) {
+        if self.state() != FailoverState::PartnerDown {
+            self.withhold_partner_addresses(subnet);
+        };
+        let mut subnet = subnet.lock().unwrap();
+        self.withheld.lock().unwrap().retain(|address| match subnet.contains(*address) && !subnet.in_pool(*address) {
+            true => {
+                let _ = subnet.free(*address);
+                false
+            },
+            false => true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::clock::MockClock;
+
+    use super::*;
+
+    fn settings(role: FailoverRole, address: SocketAddr, partner: SocketAddr) -> FailoverSettings {
+        FailoverSettings {
+            role,
+            address,
+            partner,
+            mclt: 3600,
+            split: 128,
+            heartbeat: 20,
+            partner_timeout: 200,
+            auto_partner_down: Some(1000),
+        }
+    }
+
+    type Partner = (Arc<FailoverPeer>, Arc<Mutex<Ipv4Subnet>>);
+
+    // Two partners talking over loopback, along with their subnets
+    fn partners(clock: MockClock) -> (Partner, Partner) {
+        let primary_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let secondary_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (primary_address, secondary_address) = (primary_listener.local_addr().unwrap(), secondary_listener.local_addr().unwrap());
+
+        let primary = Arc::new(FailoverPeer::with_clock(settings(FailoverRole::Primary, primary_address, secondary_address), Arc::new(clock)));
+        let secondary = Arc::new(FailoverPeer::new(settings(FailoverRole::Secondary, secondary_address, primary_address)));
+        let primary_subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        let secondary_subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        primary.register_subnet(primary_subnet.clone()).unwrap();
+        secondary.register_subnet(secondary_subnet.clone()).unwrap();
+        primary.start(primary_listener);
+        secondary.start(secondary_listener);
+        ((primary, primary_subnet), (secondary, secondary_subnet))
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        for _ in 0..250 {
+            if condition() {
+                return;
+            };
+            thread::sleep(time::Duration::from_millis(20));
+        }
+        panic!("Condition not met in time");
+    }
+
+    fn binding(lease: &LeaseData) -> LeaseBinding {
+        LeaseBinding {
+            address: lease.address(),
+            htype: 1,
+            chaddr: vec![0xf8, 0x4d, 0x89, 0x82, 0x44, 0x2a],
+            client_id: None,
+            relay_agent_info: None,
+            last_transaction: Utc::now(),
+            expiration: lease.expiration(),
+        }
+    }
+
+    fn owned_address(peer: &FailoverPeer) -> Ipv4Addr {
+        (1..255).map(|host| Ipv4Addr::new(192, 168, 0, host)).find(|address| peer.owns(*address)).unwrap()
+    }
+
+    #[test]
+    fn test_pool_split() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer = FailoverPeer::new(settings(FailoverRole::Primary, address, address));
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        peer.register_subnet(subnet.clone()).unwrap();
+        let subnet = subnet.lock().unwrap();
+        let addresses: Vec<Ipv4Addr> = (1..255).map(|host| Ipv4Addr::new(192, 168, 0, host)).collect();
+        assert!(addresses.iter().all(|address| subnet.is_free(*address) == peer.owns(*address)));
+        assert!(addresses.iter().any(|address| peer.owns(*address)));
+        assert!(addresses.iter().any(|address| !peer.owns(*address)));
+    }
+
+    #[test]
+    fn test_reconfigured_pool() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer = FailoverPeer::new(settings(FailoverRole::Primary, address, address));
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_pool(Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(192, 168, 0, 99)).unwrap();
+        let subnet = Arc::new(Mutex::new(subnet));
+        peer.register_subnet(subnet.clone()).unwrap();
+
+        // Pool moved up : partner addresses follow it
+        {
+            let mut subnet = subnet.lock().unwrap();
+            let mut reconfigured = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+            reconfigured.set_pool(Ipv4Addr::new(192, 168, 0, 50), Ipv4Addr::new(192, 168, 0, 254)).unwrap();
+            reconfigured.absorb(&subnet);
+            *subnet = reconfigured;
+        }
+        peer.subnet_reconfigured(&subnet);
+        let addresses: Vec<Ipv4Addr> = (1..255).map(|host| Ipv4Addr::new(192, 168, 0, host)).collect();
+        {
+            let subnet = subnet.lock().unwrap();
+            assert!(addresses.iter().all(|address| subnet.is_free(*address) == (peer.owns(*address) | !subnet.in_pool(*address))));
+        }
+
+        // Partner addresses are handed back with their subnet
+        assert!(peer.unregister_subnet(CidrSubnet::new(u32::from(Ipv4Addr::new(192, 168, 0, 0)), 24)).is_some());
+        assert!(addresses.iter().all(|address| subnet.lock().unwrap().is_free(*address)));
+        assert!(peer.withheld.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lease_replication() {
+        let clock = MockClock::new(Utc::now());
+        let ((primary, primary_subnet), (secondary, secondary_subnet)) = partners(clock.clone());
+        wait_for(|| (primary.state() == FailoverState::Normal) & (secondary.state() == FailoverState::Normal));
+        wait_for(|| primary.partner_state() == Some(FailoverState::Normal));
+
+        let address = owned_address(&primary);
+        primary_subnet.lock().unwrap().take(address).unwrap();
+        // Unacknowledged leases last no longer than the MCLT
+        assert!(primary.max_lease_time(address) == 3600);
+        let lease = LeaseData::new(address, String::from("host"), clock.now() + Duration::hours(8));
+        primary.publish(&lease, &binding(&lease));
+
+        wait_for(|| secondary.partner_lease(&address).is_some());
+        assert!(secondary.partner_lease(&address).unwrap().hostname() == "host");
+        // Handed over to the transaction manager along with its client
+        let updates = secondary.take_partner_updates();
+        assert!(updates.len() == 1);
+        assert!(!updates[0].released & (updates[0].htype == 1));
+        assert!(updates[0].chaddr == binding(&lease).chaddr);
+        assert!(secondary.take_partner_updates().is_empty());
+        assert!(!secondary_subnet.lock().unwrap().is_free(address));
+        wait_for(|| primary.pending_updates() == 0);
+        assert!(primary.max_lease_time(address) == 9 * 3600);
+
+        let mut options = DhcpOptions::new();
+        options.set_lease_time(Some(86400));
+        primary.limit_lease_time(Ipv4Addr::new(192, 168, 0, 250), &mut options);
+        assert!(options.lease_time() == Some(3600));
+        assert!(options.renewal_time() == Some(1800));
+
+        primary.withdraw(address);
+        wait_for(|| secondary.partner_lease(&address).is_none());
+        let updates = secondary.take_partner_updates();
+        assert!((updates.len() == 1) & updates[0].released);
+        // Still withheld, as it belongs to the primary
+        assert!(!secondary_subnet.lock().unwrap().is_free(address));
+
+        primary.shutdown();
+        secondary.shutdown();
+    }
+
+    #[test]
+    fn test_partner_down_takeover() {
+        let clock = MockClock::new(Utc::now());
+        let ((primary, primary_subnet), (secondary, _)) = partners(clock.clone());
+        wait_for(|| (primary.state() == FailoverState::Normal) & (secondary.state() == FailoverState::Normal));
+
+        let leased = owned_address(&secondary);
+        let lease = LeaseData::new(leased, String::from("host"), clock.now() + Duration::hours(1));
+        secondary.publish(&lease, &binding(&lease));
+        wait_for(|| primary.partner_lease(&leased).is_some());
+        assert!(primary.take_partner_updates().len() == 1);
+        let free = (1..255)
+            .map(|host| Ipv4Addr::new(192, 168, 0, host))
+            .find(|address| !primary.owns(*address) & (*address != leased))
+            .unwrap();
+        assert!(!primary_subnet.lock().unwrap().is_free(free));
+
+        secondary.shutdown();
+        thread::sleep(time::Duration::from_millis(100));
+        clock.advance(Duration::milliseconds(300));
+        primary.check_partner();
+        assert!(primary.state() == FailoverState::CommunicationsInterrupted);
+
+        clock.advance(Duration::milliseconds(1000));
+        primary.check_partner();
+        assert!(primary.state() == FailoverState::PartnerDown);
+        // Free addresses of the partner are taken over right away,
+        // leased ones once they expired and the MCLT elapsed
+        assert!(primary_subnet.lock().unwrap().is_free(free));
+        assert!(!primary_subnet.lock().unwrap().is_free(leased));
+        clock.advance(Duration::hours(1));
+        primary.check_partner();
+        assert!(!primary_subnet.lock().unwrap().is_free(leased));
+        clock.advance(Duration::hours(1));
+        primary.check_partner();
+        assert!(primary_subnet.lock().unwrap().is_free(leased));
+        assert!(primary.partner_lease(&leased).is_none());
+        // The transaction manager drops it as well
+        let updates = primary.take_partner_updates();
+        assert!((updates.len() == 1) & updates[0].released);
+
+        primary.shutdown();
+    }
+
+    #[test]
+    fn test_partner_recovery() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer = FailoverPeer::new(settings(FailoverRole::Primary, address, address));
+        let subnet = Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)));
+        peer.register_subnet(subnet.clone()).unwrap();
+        peer.set_partner_down();
+
+        // An address of the partner leased while it was down
+        let mut partner_addresses = (1..255).map(|host| Ipv4Addr::new(192, 168, 0, host)).filter(|address| !peer.owns(*address));
+        let (leased, free) = (partner_addresses.next().unwrap(), partner_addresses.next().unwrap());
+        subnet.lock().unwrap().take(leased).unwrap();
+        let lease = LeaseData::new(leased, String::from("host"), Utc::now() + Duration::hours(1));
+        peer.publish(&lease, &binding(&lease));
+        assert!(peer.pending_updates() == 1);
+
+        // The partner is back, but does not know the lease yet
+        assert!(peer.handle(FailoverMessage::Heartbeat { state: FailoverState::Recover, time: Utc::now() }).is_none());
+        assert!(peer.state() == FailoverState::PartnerDown);
+        assert!(subnet.lock().unwrap().is_free(free));
+
+        peer.handle(FailoverMessage::LeaseAck { address: leased, potential_expiration: lease.expiration() + Duration::hours(1) });
+        assert!(peer.pending_updates() == 0);
+        assert!(peer.state() == FailoverState::Normal);
+        assert!(!subnet.lock().unwrap().is_free(free));
+    }
+
+    #[test]
+    fn test_recover() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer = FailoverPeer::new(settings(FailoverRole::Secondary, address, address));
+        let mut raw = vec![0u8; 236];
+        raw[0] = 1;
+        raw.extend_from_slice(&[99, 130, 83, 99, 0x35, 0x01, 0x01, 0xff]);
+        let discover = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+        let mut renewal = discover.clone();
+        renewal.ciaddr = Ipv4Addr::new(192, 168, 0, 10);
+
+        // The partner took over : no new client is served until it is done
+        peer.handle(FailoverMessage::Heartbeat { state: FailoverState::PartnerDown, time: Utc::now() });
+        assert!(peer.state() == FailoverState::Recover);
+        assert!(!peer.serves(&discover));
+        assert!(peer.serves(&renewal));
+        peer.check_partner();
+        assert!(peer.state() == FailoverState::Recover);
+
+        peer.handle(FailoverMessage::Heartbeat { state: FailoverState::Normal, time: Utc::now() });
+        assert!(peer.state() == FailoverState::Normal);
+        assert!(peer.serves(&discover) == peer.balancer.serves(&discover));
+    }
+
+    #[test]
+    fn test_foreign_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer = Arc::new(FailoverPeer::new(settings(FailoverRole::Primary, address, "127.0.0.2:647".parse().unwrap())));
+        peer.start(listener);
+
+        let lease = LeaseData::new(Ipv4Addr::new(192, 168, 0, 10), String::from("host"), Utc::now() + Duration::hours(1));
+        let update = FailoverMessage::LeaseUpdate(LeaseUpdate {
+            address: lease.address(),
+            hostname: lease.hostname().to_string(),
+            expiration: lease.expiration(),
+            potential_expiration: lease.expiration(),
+            released: false,
+            htype: 1,
+            chaddr: binding(&lease).chaddr,
+            client_id: None,
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        let _ = write_message(&mut stream, &update.to_bytes());
+        // Closed without any acknowledgement
+        assert!(read_message(&mut stream).unwrap_or(None).is_none());
+        assert!(peer.partner_lease(&lease.address()).is_none());
+        assert!(peer.take_partner_updates().is_empty());
+
+        peer.shutdown();
+    }
+}
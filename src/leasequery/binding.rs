@@ -0,0 +1,206 @@
+use std::{collections::HashMap, net::Ipv4Addr};
+
+use chrono::{DateTime, Utc};
+
+use crate::packet::{dhcp_packet::DhcpV4Packet, relay_agent_info::RelayAgentInfo};
+
+/// `LeaseBinding` ties a commited lease to its client, and
+/// to the relay agent the client went through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaseBinding {
+    pub address: Ipv4Addr,
+    pub htype: u8,
+    pub chaddr: Vec<u8>,
+    pub client_id: Option<Vec<u8>>,
+    pub relay_agent_info: Option<RelayAgentInfo>,
+    /// Last time the client talked to the server
+    pub last_transaction: DateTime<Utc>,
+    pub expiration: DateTime<Utc>,
+}
+
+impl LeaseBinding {
+
+    /// Creates the binding of the lease given by a DHCPACK, which
+    /// echoes the client identifier (RFC 6842) and the relay agent
+    /// information (RFC 3046) of the request it answers.
+    pub fn from_packet(
+        packet: &DhcpV4Packet,
+        address: Ipv4Addr,
+        last_transaction: DateTime<Utc>,
+        expiration: DateTime<Utc>
+    ) -> Self {
+        let hlen = (packet.hlen as usize).min(packet.chadd.raw.len());
+        Self {
+            address,
+            htype: packet.htype,
+            chaddr: packet.chadd.raw[..hlen].to_vec(),
+            client_id: packet.options.client_identifier().cloned(),
+            relay_agent_info: packet.options.relay_agent_info().cloned(),
+            last_transaction,
+            expiration,
+        }
+    }
+
+    pub fn remote_id(
+        &self
+    ) -> Option<&Vec<u8>> {
+        self.relay_agent_info.as_ref()?.remote_id()
+    }
+
+    pub fn relay_id(
+        &self
+    ) -> Option<&Vec<u8>> {
+        self.relay_agent_info.as_ref()?.relay_id()
+    }
+
+    /// Returns true if the lease did not expire before `now`
+    pub fn is_active(
+        &self,
+        now: DateTime<Utc>
+    ) -> bool {
+        self.expiration > now
+    }
+}
+
+/// `BindingTable` holds the [`LeaseBinding`] of every
+/// commited lease, indexed by address.
+///
+/// # Examples:
+///
+/// ```
+/// let mut table = BindingTable::new();
+/// table.insert(LeaseBinding::from_packet(&ack, ack.yiaddr, now, now + Duration::hours(8)));
+/// assert!(table.by_hw_addr(1, &[1, 2, 3, 4, 5, 6]).len() == 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BindingTable {
+    bindings: HashMap<Ipv4Addr, LeaseBinding>,
+}
+
+impl BindingTable {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a binding, replacing the one
+    /// previously registered for its address.
+    pub fn insert(
+        &mut self,
+        binding: LeaseBinding
+    ) {
+        self.bindings.insert(binding.address, binding);
+    }
+
+    pub fn remove(
+        &mut self,
+        address: &Ipv4Addr
+    ) -> Option<LeaseBinding> {
+        self.bindings.remove(address)
+    }
+
+    pub fn get(
+        &self,
+        address: &Ipv4Addr
+    ) -> Option<&LeaseBinding> {
+        self.bindings.get(address)
+    }
+
+    // Bindings matching the predicate, most recently used first
+    fn select(
+        &self,
+        predicate: impl Fn(&LeaseBinding) -> bool
+    ) -> Vec<&LeaseBinding> {
+        let mut bindings: Vec<&LeaseBinding> = self.bindings
+            .values()
+            .filter(|binding| predicate(binding))
+            .collect();
+        bindings.sort_by(|a, b| b.last_transaction.cmp(&a.last_transaction).then(a.address.cmp(&b.address)));
+        bindings
+    }
+
+    pub fn by_hw_addr(
+        &self,
+        htype: u8,
+        chaddr: &[u8]
+    ) -> Vec<&LeaseBinding> {
+        self.select(|binding| (binding.htype == htype) & (binding.chaddr == chaddr))
+    }
+
+    pub fn by_client_id(
+        &self,
+        client_id: &[u8]
+    ) -> Vec<&LeaseBinding> {
+        self.select(|binding| binding.client_id.as_deref() == Some(client_id))
+    }
+
+    pub fn by_remote_id(
+        &self,
+        remote_id: &[u8]
+    ) -> Vec<&LeaseBinding> {
+        self.select(|binding| binding.remote_id().map(Vec::as_slice) == Some(remote_id))
+    }
+
+    pub fn by_relay_id(
+        &self,
+        relay_id: &[u8]
+    ) -> Vec<&LeaseBinding> {
+        self.select(|binding| binding.relay_id().map(Vec::as_slice) == Some(relay_id))
+    }
+
+    pub fn len(
+        &self
+    ) -> usize {
+        self.bindings.len()
+    }
+
+    pub fn is_empty(
+        &self
+    ) -> bool {
+        self.bindings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn binding(address: Ipv4Addr, mac: u8, minutes_ago: i64) -> LeaseBinding {
+        let now = Utc::now();
+        let mut relay_agent_info = RelayAgentInfo::new(None, Some(vec![0xca, 0xfe]));
+        relay_agent_info.set_relay_id(Some(vec![0x00, 0x01]));
+        LeaseBinding {
+            address,
+            htype: 1,
+            chaddr: vec![0, 0, 0, 0, 0, mac],
+            client_id: Some(vec![1, 0, 0, 0, 0, 0, mac]),
+            relay_agent_info: Some(relay_agent_info),
+            last_transaction: now - Duration::minutes(minutes_ago),
+            expiration: now + Duration::hours(8),
+        }
+    }
+
+    #[test]
+    fn test_lookups() {
+        let mut table = BindingTable::new();
+        table.insert(binding(Ipv4Addr::new(192, 168, 0, 5), 1, 10));
+        table.insert(binding(Ipv4Addr::new(192, 168, 0, 6), 1, 0));
+        table.insert(binding(Ipv4Addr::new(192, 168, 0, 7), 2, 5));
+        assert!(table.len() == 3);
+
+        // Most recently used first
+        let addresses: Vec<Ipv4Addr> = table.by_hw_addr(1, &[0, 0, 0, 0, 0, 1]).iter().map(|binding| binding.address).collect();
+        assert!(addresses == vec![Ipv4Addr::new(192, 168, 0, 6), Ipv4Addr::new(192, 168, 0, 5)]);
+        assert!(table.by_hw_addr(6, &[0, 0, 0, 0, 0, 1]).is_empty());
+        assert!(table.by_client_id(&[1, 0, 0, 0, 0, 0, 2]).len() == 1);
+        assert!(table.by_remote_id(&[0xca, 0xfe]).len() == 3);
+        assert!(table.by_relay_id(&[0x00, 0x01]).len() == 3);
+        assert!(table.by_relay_id(&[0x00, 0x02]).is_empty());
+
+        assert!(table.remove(&Ipv4Addr::new(192, 168, 0, 7)).is_some());
+        assert!(table.get(&Ipv4Addr::new(192, 168, 0, 7)).is_none());
+        assert!(table.by_client_id(&[1, 0, 0, 0, 0, 0, 2]).is_empty());
+    }
+}
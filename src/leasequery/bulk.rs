@@ -0,0 +1,226 @@
+//! Bulk leasequery (RFC 6926) over TCP : each DHCP message
+//! is preceded by its size, on two bytes in network order.
+
+use std::{io::{self, Read, Write}, net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream}, sync::Arc, thread::{self, JoinHandle}};
+
+use fp_core::core::packet::PacketType;
+use log::{trace, warn};
+
+use crate::packet::dhcp_packet::DhcpV4Packet;
+
+use super::responder::LeaseQueryResponder;
+
+// Fixed fields and magic cookie of a DHCP message
+const MIN_MESSAGE_SIZE: usize = 240;
+
+/// Reads the next message of a connection.
+///
+/// Returns `None` once the peer closed the connection.
+pub fn read_message(
+    stream: &mut impl Read
+) -> io::Result<Option<Vec<u8>>> {
+    let mut size = [0u8; 2];
+    match stream.read_exact(&mut size) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    let mut message = vec![0u8; u16::from_be_bytes(size) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Writes a message, preceded by its size.
+pub fn write_message(
+    stream: &mut impl Write,
+    message: &[u8]
+) -> io::Result<()> {
+    let size = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+    stream.write_all(&size.to_be_bytes())?;
+    stream.write_all(message)
+}
+
+/// `BulkLeaseQueryServer` answers the DHCPBULKLEASEQUERY
+/// messages sent by relay agents over TCP connections, with
+/// a [`LeaseQueryResponder`].
+///
+/// A connection carries any number of queries, answered in
+/// order, and is closed by the requestor once it is done.
+///
+/// # Examples:
+///
+/// ```
+/// let server = BulkLeaseQueryServer::bind(responder.clone()).unwrap();
+/// server.spawn();
+/// ```
+pub struct BulkLeaseQueryServer {
+    listener: TcpListener,
+    responder: Arc<LeaseQueryResponder>,
+}
+
+impl BulkLeaseQueryServer {
+
+    /// Listens on the bulk leasequery address
+    /// of the settings of the responder.
+    pub fn bind(
+        responder: Arc<LeaseQueryResponder>
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(responder.settings().bulk_address)?;
+        Ok(Self { listener, responder })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections on its own thread, each
+    /// connection being served on a thread of its own.
+    pub fn spawn(
+        self
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Bulk leasequery connection failed : {}", e);
+                        continue;
+                    }
+                };
+                let responder = self.responder.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(&responder, stream) {
+                        trace!("Bulk leasequery connection closed : {}", e);
+                    }
+                });
+            }
+        })
+    }
+}
+
+// Answers the queries of a connection until the requestor closes
+// it, or stays idle for too long
+fn serve(
+    responder: &LeaseQueryResponder,
+    mut stream: TcpStream
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(responder.settings().timeout()))?;
+    let requestor = match stream.peer_addr()?.ip() {
+        IpAddr::V4(address) => address,
+        IpAddr::V6(address) => address.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED)
+    };
+
+    while let Some(message) = read_message(&mut stream)? {
+        if message.len() < MIN_MESSAGE_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated DHCP message"));
+        };
+        let query = DhcpV4Packet::from_raw_bytes(&message);
+        for reply in responder.answer_bulk(&query, requestor) {
+            write_message(&mut stream, &reply.to_bytes())?;
+        }
+        // Rejected requestors are told why before being disconnected
+        if !responder.is_allowed(requestor) {
+            return Ok(());
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Mutex};
+
+    use chrono::{Duration, Utc};
+
+    use crate::{leasequery::{binding::{BindingTable, LeaseBinding}, responder::{LeaseQuerySettings, DHCPBULKLEASEQUERY, DHCPLEASEACTIVE, DHCPLEASEQUERYDONE}}, packet::relay_agent_info::RelayAgentInfo};
+
+    use super::*;
+
+    fn bulk_query(xid: u32, relay_id: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0u8; 236];
+        raw[4..8].copy_from_slice(&xid.to_le_bytes());
+        raw.extend_from_slice(&[99, 130, 83, 99, 0x35, 0x01, DHCPBULKLEASEQUERY, 0xff]);
+        let mut query = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+        let mut relay_agent_info = RelayAgentInfo::new(None, None);
+        relay_agent_info.set_relay_id(Some(relay_id.to_vec()));
+        query.options.set_relay_agent_info(Some(relay_agent_info));
+        query.to_bytes()
+    }
+
+    fn server(allowed: Vec<Ipv4Addr>) -> SocketAddr {
+        let mut table = BindingTable::new();
+        for host in 5..8 {
+            let mut relay_agent_info = RelayAgentInfo::new(None, None);
+            relay_agent_info.set_relay_id(Some(vec![0x00, 0x01]));
+            table.insert(LeaseBinding {
+                address: Ipv4Addr::new(192, 168, 0, host),
+                htype: 1,
+                chaddr: vec![0, 0, 0, 0, 0, host],
+                client_id: None,
+                relay_agent_info: Some(relay_agent_info),
+                last_transaction: Utc::now(),
+                expiration: Utc::now() + Duration::hours(1),
+            });
+        }
+        let settings = LeaseQuerySettings { bulk_address: "127.0.0.1:0".parse().unwrap(), allowed, timeout: 1000 };
+        let responder = LeaseQueryResponder::new(Arc::new(Mutex::new(table)), Ipv4Addr::new(192, 168, 0, 1), settings);
+        let server = BulkLeaseQueryServer::bind(Arc::new(responder)).unwrap();
+        let address = server.local_addr().unwrap();
+        server.spawn();
+        address
+    }
+
+    // Reads replies until DHCPLEASEQUERYDONE
+    fn replies(stream: &mut TcpStream) -> Vec<DhcpV4Packet> {
+        let mut replies = Vec::new();
+        while let Some(message) = read_message(stream).unwrap() {
+            let reply = DhcpV4Packet::from_raw_bytes(&message);
+            let done = reply.options.message_type() == Some(DHCPLEASEQUERYDONE);
+            replies.push(reply);
+            if done {
+                break;
+            };
+        }
+        replies
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &[1, 2, 3]).unwrap();
+        assert!(buffer == [0, 3, 1, 2, 3]);
+        let mut cursor = Cursor::new(buffer);
+        assert!(read_message(&mut cursor).unwrap() == Some(vec![1, 2, 3]));
+        assert!(read_message(&mut cursor).unwrap().is_none());
+        assert!(read_message(&mut Cursor::new(vec![0, 3, 1])).is_err());
+    }
+
+    #[test]
+    fn test_bulk_leasequery() {
+        let mut stream = TcpStream::connect(server(Vec::new())).unwrap();
+
+        write_message(&mut stream, &bulk_query(1, &[0x00, 0x01])).unwrap();
+        let replies = replies(&mut stream);
+        assert!(replies.len() == 4);
+        assert!(replies[..3].iter().all(|reply| reply.options.message_type() == Some(DHCPLEASEACTIVE)));
+        assert!(replies.iter().all(|reply| reply.xid == 1));
+
+        // The connection carries several queries
+        write_message(&mut stream, &bulk_query(2, &[0x00, 0x02])).unwrap();
+        let replies = self::replies(&mut stream);
+        assert!(replies.len() == 1);
+        assert!(replies[0].xid == 2);
+    }
+
+    #[test]
+    fn test_requestor_not_allowed() {
+        let mut stream = TcpStream::connect(server(vec![Ipv4Addr::new(192, 168, 0, 254)])).unwrap();
+        write_message(&mut stream, &bulk_query(1, &[0x00, 0x01])).unwrap();
+        let replies = replies(&mut stream);
+        assert!(replies.len() == 1);
+        assert!(replies[0].options.status_code().unwrap().0 == 4);
+        // And disconnected
+        assert!(read_message(&mut stream).unwrap().is_none());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod binding;
+pub mod responder;
+pub mod bulk;
@@ -0,0 +1,526 @@
+use std::{net::{Ipv4Addr, SocketAddr}, sync::{Arc, Mutex, RwLock}, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    allocators::{subnet_map::{CidrSubnet, SubnetV4Map}, subnet_registry::SubnetRegistry},
+    clock::clock::{Clock, SystemClock},
+    leases::ip_subnet::Ipv4Subnet,
+    netutils::hw_addr::HardwareAddress,
+    packet::{dhcp_options::DhcpOptions, dhcp_packet::DhcpV4Packet}
+};
+
+use super::binding::{BindingTable, LeaseBinding};
+
+// Message types of leasequery (RFC 4388) and bulk leasequery (RFC 6926)
+pub const DHCPLEASEQUERY: u8 = 10;
+pub const DHCPLEASEUNASSIGNED: u8 = 11;
+pub const DHCPLEASEUNKNOWN: u8 = 12;
+pub const DHCPLEASEACTIVE: u8 = 13;
+pub const DHCPBULKLEASEQUERY: u8 = 14;
+pub const DHCPLEASEQUERYDONE: u8 = 15;
+
+// Status codes of bulk leasequery (RFC 6926 section 6.2.2)
+pub const STATUS_SUCCESS: u8 = 0;
+pub const STATUS_MALFORMED_QUERY: u8 = 3;
+pub const STATUS_NOT_ALLOWED: u8 = 4;
+
+fn _default_bulk_address() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 67))
+}
+
+fn _default_timeout() -> u64 {
+    30000
+}
+
+/// `LeaseQuerySettings` describes who may query the
+/// leases, and where bulk leasequery is served.
+///
+/// # Examples:
+///
+/// ```yaml
+/// leasequery:
+///   bulk_address: 0.0.0.0:67
+///   allowed:
+///     - 192.168.0.254
+///   timeout: 30000
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LeaseQuerySettings {
+    /// Address of the bulk leasequery (TCP) server
+    #[serde(default = "_default_bulk_address")]
+    pub bulk_address: SocketAddr,
+    /// Relay agents allowed to query the leases, any when empty
+    #[serde(default)]
+    pub allowed: Vec<Ipv4Addr>,
+    /// Time (in milliseconds) after which idle
+    /// bulk leasequery connections are closed
+    #[serde(default = "_default_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for LeaseQuerySettings {
+    fn default() -> Self {
+        Self { bulk_address: _default_bulk_address(), allowed: Vec::new(), timeout: _default_timeout() }
+    }
+}
+
+impl LeaseQuerySettings {
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+}
+
+/// What a leasequery looks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LeaseQuery {
+    /// The client bound to an address (ciaddr)
+    Address(Ipv4Addr),
+    /// The client with a given client identifier (option 61)
+    ClientId(Vec<u8>),
+    /// The client with a given hardware address (chaddr)
+    HwAddr { htype: u8, chaddr: Vec<u8> },
+    /// The clients relayed by a relay agent (option 82, bulk only)
+    RelayId(Vec<u8>),
+    /// The clients with a given remote id (option 82, bulk only)
+    RemoteId(Vec<u8>),
+}
+
+impl LeaseQuery {
+
+    /// Returns the query carried by a leasequery message, looking
+    /// in turn at ciaddr, the client identifier, chaddr, and the
+    /// relay agent information.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// query.ciaddr = Ipv4Addr::new(192, 168, 0, 5);
+    /// assert!(LeaseQuery::from_packet(&query) == Some(LeaseQuery::Address(Ipv4Addr::new(192, 168, 0, 5))));
+    /// ```
+    pub fn from_packet(
+        packet: &DhcpV4Packet
+    ) -> Option<Self> {
+        if !packet.ciaddr.is_unspecified() {
+            return Some(LeaseQuery::Address(packet.ciaddr));
+        };
+        if let Some(client_id) = packet.options.client_identifier().filter(|id| !id.is_empty()) {
+            return Some(LeaseQuery::ClientId(client_id.clone()));
+        };
+        let hlen = (packet.hlen as usize).min(packet.chadd.raw.len());
+        let chaddr = &packet.chadd.raw[..hlen];
+        if (packet.htype != 0) & chaddr.iter().any(|byte| *byte != 0) {
+            return Some(LeaseQuery::HwAddr { htype: packet.htype, chaddr: chaddr.to_vec() });
+        };
+        let relay_agent_info = packet.options.relay_agent_info()?;
+        relay_agent_info.relay_id()
+            .map(|relay_id| LeaseQuery::RelayId(relay_id.clone()))
+            .or_else(|| relay_agent_info.remote_id().map(|remote_id| LeaseQuery::RemoteId(remote_id.clone())))
+    }
+}
+
+/// `LeaseQueryResponder` answers the relay agents querying
+/// the leases of their clients, with DHCPLEASEQUERY messages
+/// (RFC 4388) or DHCPBULKLEASEQUERY ones (RFC 6926).
+///
+/// Leases are looked up in the [`BindingTable`] filled by the
+/// [`crate::transactions::manager::TransactionManager`]. Unbound
+/// addresses are told apart from unknown ones with the subnets
+/// registered in the responder.
+///
+/// # Examples:
+///
+/// ```
+/// let responder = LeaseQueryResponder::new(manager.bindings(), server_identifier, settings);
+/// responder.register_subnet(subnet).unwrap();
+/// let reply = responder.answer(&query).unwrap();
+/// assert!(reply.options.message_type() == Some(DHCPLEASEACTIVE));
+/// ```
+pub struct LeaseQueryResponder {
+    bindings: Arc<Mutex<BindingTable>>,
+    subnet_map: RwLock<SubnetV4Map>,
+    server_identifier: Ipv4Addr,
+    settings: LeaseQuerySettings,
+    clock: Arc<dyn Clock>,
+}
+
+impl LeaseQueryResponder {
+
+    pub fn new(
+        bindings: Arc<Mutex<BindingTable>>,
+        server_identifier: Ipv4Addr,
+        settings: LeaseQuerySettings
+    ) -> Self {
+        Self::with_clock(bindings, server_identifier, settings, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        bindings: Arc<Mutex<BindingTable>>,
+        server_identifier: Ipv4Addr,
+        settings: LeaseQuerySettings,
+        clock: Arc<dyn Clock>
+    ) -> Self {
+        Self { bindings, subnet_map: RwLock::new(SubnetV4Map::new()), server_identifier, settings, clock }
+    }
+
+    /// Registers an [`Ipv4Subnet`] whose unbound addresses
+    /// are answered with DHCPLEASEUNASSIGNED.
+    ///
+    /// Returns the already registered [`CidrSubnet`]
+    /// overlapping the given subnet, if any.
+    pub fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .insert_subnet(subnet)
+    }
+
+    pub fn settings(&self) -> &LeaseQuerySettings {
+        &self.settings
+    }
+
+    /// Returns true if the given relay agent may query the leases
+    pub fn is_allowed(
+        &self,
+        requestor: Ipv4Addr
+    ) -> bool {
+        self.settings.allowed.is_empty() | self.settings.allowed.contains(&requestor)
+    }
+
+    // Active bindings matching the query, most recently used first
+    fn find(
+        &self,
+        query: &LeaseQuery,
+        now: DateTime<Utc>
+    ) -> Vec<LeaseBinding> {
+        let bindings = self.bindings.lock().unwrap();
+        let found: Vec<&LeaseBinding> = match query {
+            LeaseQuery::Address(address) => bindings.get(address).into_iter().collect(),
+            LeaseQuery::ClientId(client_id) => bindings.by_client_id(client_id),
+            LeaseQuery::HwAddr { htype, chaddr } => bindings.by_hw_addr(*htype, chaddr),
+            LeaseQuery::RelayId(relay_id) => bindings.by_relay_id(relay_id),
+            LeaseQuery::RemoteId(remote_id) => bindings.by_remote_id(remote_id),
+        };
+        found.into_iter()
+            .filter(|binding| binding.is_active(now))
+            .cloned()
+            .collect()
+    }
+
+    fn reply(
+        &self,
+        query: &DhcpV4Packet,
+        message_type: u8
+    ) -> DhcpV4Packet {
+        let mut reply = query.clone();
+        reply.op = 2;
+        reply.hops = 0;
+        reply.secs = Duration::ZERO;
+        reply.yiaddr = Ipv4Addr::UNSPECIFIED;
+        reply.siaddr = Ipv4Addr::UNSPECIFIED;
+        reply.options = DhcpOptions::new();
+        reply.options.set_message_type(Some(message_type));
+        reply.options.set_server_identifier(Some(self.server_identifier));
+        reply
+    }
+
+    // DHCPLEASEACTIVE describing a binding (RFC 4388 section 6.4.2)
+    fn active(
+        &self,
+        query: &DhcpV4Packet,
+        binding: &LeaseBinding,
+        now: DateTime<Utc>
+    ) -> DhcpV4Packet {
+        let mut reply = self.reply(query, DHCPLEASEACTIVE);
+        reply.ciaddr = binding.address;
+        reply.htype = binding.htype;
+        reply.hlen = binding.chaddr.len() as u8;
+        reply.chadd = HardwareAddress::from_slice(&binding.chaddr).unwrap_or(query.chadd);
+        let remaining = (binding.expiration - now).num_seconds().clamp(0, u32::MAX as i64);
+        reply.options.set_lease_time(Some(remaining as u32));
+        let elapsed = (now - binding.last_transaction).num_seconds().clamp(0, u32::MAX as i64);
+        reply.options.set_client_last_transaction_time(Some(elapsed as u32));
+        if let Some(client_id) = &binding.client_id {
+            reply.options.set_client_identifier(Some(client_id.clone()));
+        };
+        let requested = query.options.parameter_request().is_some_and(|codes| codes.contains(&82));
+        if let Some(relay_agent_info) = binding.relay_agent_info.clone().filter(|_| requested) {
+            reply.options.set_relay_agent_info(Some(relay_agent_info));
+        };
+        reply
+    }
+
+    // Reply to a query for an address nobody is bound to
+    fn unbound(
+        &self,
+        query: &DhcpV4Packet,
+        address: Ipv4Addr
+    ) -> DhcpV4Packet {
+        match self.subnet_map.read().unwrap().get_matching_subnet(address) {
+            Some(_) => {
+                let mut reply = self.reply(query, DHCPLEASEUNASSIGNED);
+                reply.ciaddr = address;
+                reply
+            },
+            None => self.reply(query, DHCPLEASEUNKNOWN)
+        }
+    }
+
+    /// Answers a DHCPLEASEQUERY (RFC 4388) with DHCPLEASEACTIVE,
+    /// DHCPLEASEUNASSIGNED or DHCPLEASEUNKNOWN.
+    ///
+    /// Queries by client identifier or hardware address are answered
+    /// with the most recently used binding of the client, the others
+    /// being listed in the associated-ip option.
+    ///
+    /// Returns `None` if the query must be dropped : it comes from
+    /// a relay agent that is not allowed, or queries nothing.
+    pub fn answer(
+        &self,
+        query: &DhcpV4Packet
+    ) -> Option<DhcpV4Packet> {
+        if (query.options.message_type() != Some(DHCPLEASEQUERY)) | !self.is_allowed(query.giaddr) {
+            return None;
+        };
+        let lease_query = LeaseQuery::from_packet(query)?;
+        let now = self.clock.now();
+        let bindings = self.find(&lease_query, now);
+        match lease_query {
+            LeaseQuery::Address(address) => match bindings.first() {
+                Some(binding) => Some(self.active(query, binding, now)),
+                None => Some(self.unbound(query, address))
+            },
+            LeaseQuery::ClientId(_) | LeaseQuery::HwAddr { .. } => match bindings.first() {
+                Some(binding) => {
+                    let mut reply = self.active(query, binding, now);
+                    if bindings.len() > 1 {
+                        reply.options.set_associated_ip(Some(bindings.iter().map(|binding| binding.address).collect()));
+                    };
+                    Some(reply)
+                },
+                None => Some(self.reply(query, DHCPLEASEUNKNOWN))
+            },
+            // Only supported by bulk leasequery
+            LeaseQuery::RelayId(_) | LeaseQuery::RemoteId(_) => None
+        }
+    }
+
+    fn done(
+        &self,
+        query: &DhcpV4Packet,
+        status: u8,
+        message: &str
+    ) -> DhcpV4Packet {
+        let mut done = self.reply(query, DHCPLEASEQUERYDONE);
+        done.options.set_status_code(Some((status, message.to_string())));
+        done
+    }
+
+    /// Answers a DHCPBULKLEASEQUERY (RFC 6926) received from
+    /// the given requestor : one DHCPLEASEACTIVE per matching
+    /// binding, then a DHCPLEASEQUERYDONE.
+    ///
+    /// A query for an unbound address is answered like a
+    /// DHCPLEASEQUERY. Rejected queries are only answered with
+    /// a DHCPLEASEQUERYDONE carrying the reason.
+    pub fn answer_bulk(
+        &self,
+        query: &DhcpV4Packet,
+        requestor: Ipv4Addr
+    ) -> Vec<DhcpV4Packet> {
+        if !self.is_allowed(requestor) {
+            return vec![self.done(query, STATUS_NOT_ALLOWED, "requestor not allowed")];
+        };
+        let lease_query = match query.options.message_type() {
+            Some(DHCPBULKLEASEQUERY) => LeaseQuery::from_packet(query),
+            _ => None
+        };
+        let lease_query = match lease_query {
+            Some(lease_query) => lease_query,
+            None => return vec![self.done(query, STATUS_MALFORMED_QUERY, "no query")]
+        };
+
+        let now = self.clock.now();
+        let bindings = self.find(&lease_query, now);
+        let mut replies: Vec<DhcpV4Packet> = bindings.iter()
+            .map(|binding| self.active(query, binding, now))
+            .collect();
+        if let (LeaseQuery::Address(address), true) = (&lease_query, replies.is_empty()) {
+            replies.push(self.unbound(query, *address));
+        };
+        replies.push(self.done(query, STATUS_SUCCESS, ""));
+        // Times of the replies are relative to the clock of the server
+        for reply in replies.iter_mut() {
+            reply.options.set_base_time(Some(now.timestamp().clamp(0, u32::MAX as i64) as u32));
+        }
+        replies
+    }
+}
+
+impl SubnetRegistry for LeaseQueryResponder {
+
+    fn register_subnet(
+        &self,
+        subnet: Arc<Mutex<Ipv4Subnet>>
+    ) -> Result<(), CidrSubnet> {
+        LeaseQueryResponder::register_subnet(self, subnet)
+    }
+
+    fn unregister_subnet(
+        &self,
+        subnet: CidrSubnet
+    ) -> Option<Arc<Mutex<Ipv4Subnet>>> {
+        self.subnet_map
+            .write()
+            .unwrap()
+            .remove_subnet(subnet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use fp_core::core::packet::PacketType;
+
+    use crate::{clock::clock::MockClock, packet::relay_agent_info::RelayAgentInfo};
+
+    use super::*;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+    const RELAY: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 254);
+
+    fn query(message_type: u8) -> DhcpV4Packet {
+        let mut raw = vec![0u8; 236];
+        raw.extend_from_slice(&[99, 130, 83, 99, 0x35, 0x01, message_type, 0xff]);
+        let mut query = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+        query.giaddr = RELAY;
+        query
+    }
+
+    fn responder(clock: MockClock) -> LeaseQueryResponder {
+        let mut table = BindingTable::new();
+        let now = clock.now();
+        for (host, mac, minutes_ago) in [(5, 1, 10), (6, 1, 0), (7, 2, 5)] {
+            table.insert(LeaseBinding {
+                address: Ipv4Addr::new(192, 168, 0, host),
+                htype: 1,
+                chaddr: vec![0, 0, 0, 0, 0, mac],
+                client_id: Some(vec![1, 0, 0, 0, 0, 0, mac]),
+                relay_agent_info: Some(RelayAgentInfo::new(None, Some(vec![mac]))),
+                last_transaction: now - Duration::minutes(minutes_ago),
+                expiration: now + Duration::hours(1),
+            });
+        }
+        let responder = LeaseQueryResponder::with_clock(Arc::new(Mutex::new(table)), SERVER, LeaseQuerySettings::default(), Arc::new(clock));
+        responder.register_subnet(Arc::new(Mutex::new(Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24)))).unwrap();
+        responder
+    }
+
+    #[test]
+    fn test_query_by_address() {
+        let clock = MockClock::new(Utc::now());
+        let responder = responder(clock.clone());
+        let mut query = query(DHCPLEASEQUERY);
+        query.ciaddr = Ipv4Addr::new(192, 168, 0, 7);
+        query.options.add_parameter_request(82);
+
+        let reply = responder.answer(&query).unwrap();
+        assert!(reply.op == 2);
+        assert!(reply.options.message_type() == Some(DHCPLEASEACTIVE));
+        assert!(reply.options.server_identifier() == Some(SERVER));
+        assert!(reply.chadd.raw[..6] == [0, 0, 0, 0, 0, 2]);
+        assert!(reply.options.lease_time() == Some(3600));
+        assert!(reply.options.client_last_transaction_time() == Some(300));
+        assert!(reply.options.relay_agent_info().unwrap().remote_id().unwrap() == &[2]);
+
+        // Address in a subnet of the server, but not leased
+        query.ciaddr = Ipv4Addr::new(192, 168, 0, 8);
+        let reply = responder.answer(&query).unwrap();
+        assert!(reply.options.message_type() == Some(DHCPLEASEUNASSIGNED));
+        assert!(reply.ciaddr == Ipv4Addr::new(192, 168, 0, 8));
+
+        // Address unknown to the server
+        query.ciaddr = Ipv4Addr::new(10, 0, 0, 1);
+        assert!(responder.answer(&query).unwrap().options.message_type() == Some(DHCPLEASEUNKNOWN));
+
+        // Expired lease
+        clock.advance(Duration::hours(2));
+        query.ciaddr = Ipv4Addr::new(192, 168, 0, 7);
+        assert!(responder.answer(&query).unwrap().options.message_type() == Some(DHCPLEASEUNASSIGNED));
+    }
+
+    #[test]
+    fn test_query_by_client() {
+        let responder = responder(MockClock::new(Utc::now()));
+        let mut query = query(DHCPLEASEQUERY);
+        query.htype = 1;
+        query.hlen = 6;
+        query.chadd = HardwareAddress::from_slice(&[0, 0, 0, 0, 0, 1]).unwrap();
+
+        // Most recently used binding, along with every bound address
+        let reply = responder.answer(&query).unwrap();
+        assert!(reply.options.message_type() == Some(DHCPLEASEACTIVE));
+        assert!(reply.ciaddr == Ipv4Addr::new(192, 168, 0, 6));
+        assert!(reply.options.associated_ip().unwrap() == &vec![Ipv4Addr::new(192, 168, 0, 6), Ipv4Addr::new(192, 168, 0, 5)]);
+        assert!(reply.options.relay_agent_info().is_none());
+
+        // Client identifiers take precedence over chaddr
+        query.options.set_client_identifier(Some(vec![1, 0, 0, 0, 0, 0, 2]));
+        let reply = responder.answer(&query).unwrap();
+        assert!(reply.ciaddr == Ipv4Addr::new(192, 168, 0, 7));
+        assert!(reply.options.associated_ip().is_none());
+
+        query.options.set_client_identifier(Some(vec![1, 0, 0, 0, 0, 0, 3]));
+        assert!(responder.answer(&query).unwrap().options.message_type() == Some(DHCPLEASEUNKNOWN));
+    }
+
+    #[test]
+    fn test_rejected_queries() {
+        let clock = MockClock::new(Utc::now());
+        // Nothing to look for
+        assert!(responder(clock.clone()).answer(&query(DHCPLEASEQUERY)).is_none());
+        // Not a leasequery
+        let mut discover = query(1);
+        discover.ciaddr = Ipv4Addr::new(192, 168, 0, 5);
+        assert!(responder(clock.clone()).answer(&discover).is_none());
+
+        let mut responder = responder(clock);
+        responder.settings.allowed = vec![Ipv4Addr::new(192, 168, 0, 253)];
+        let mut query = query(DHCPLEASEQUERY);
+        query.ciaddr = Ipv4Addr::new(192, 168, 0, 5);
+        assert!(responder.answer(&query).is_none());
+
+        let replies = responder.answer_bulk(&query, RELAY);
+        assert!(replies.len() == 1);
+        assert!(replies[0].options.status_code() == Some((STATUS_NOT_ALLOWED, "requestor not allowed")));
+    }
+
+    #[test]
+    fn test_bulk_query() {
+        let responder = responder(MockClock::new(Utc::now()));
+        let mut query = query(DHCPBULKLEASEQUERY);
+        query.options.set_relay_agent_info(Some(RelayAgentInfo::new(None, Some(vec![1]))));
+
+        let replies = responder.answer_bulk(&query, RELAY);
+        let message_types: Vec<Option<u8>> = replies.iter().map(|reply| reply.options.message_type()).collect();
+        assert!(message_types == vec![Some(DHCPLEASEACTIVE), Some(DHCPLEASEACTIVE), Some(DHCPLEASEQUERYDONE)]);
+        assert!(replies.iter().all(|reply| reply.xid == query.xid && reply.options.base_time().is_some()));
+        assert!(replies[2].options.status_code() == Some((STATUS_SUCCESS, "")));
+
+        // No binding
+        query.options.set_relay_agent_info(Some(RelayAgentInfo::new(None, Some(vec![3]))));
+        assert!(responder.answer_bulk(&query, RELAY).len() == 1);
+
+        // Bulk leasequery needs its own message type
+        let mut query = self::query(DHCPLEASEQUERY);
+        query.ciaddr = Ipv4Addr::new(192, 168, 0, 5);
+        let replies = responder.answer_bulk(&query, RELAY);
+        assert!(replies[0].options.status_code() == Some((STATUS_MALFORMED_QUERY, "no query")));
+    }
+}
@@ -0,0 +1,263 @@
+use std::{collections::HashMap, net::Ipv4Addr};
+
+use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
+
+use crate::packet::dhcp_packet::DhcpV4Packet;
+
+const SNAME_SIZE: usize = 64;
+const FILE_SIZE: usize = 128;
+
+// User class sent by iPXE once it is running
+const IPXE_USER_CLASS: &[u8] = b"iPXE";
+
+/// Client system architectures (option 93), as
+/// registered by IANA for RFC 4578.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientArch {
+    Bios,
+    EfiIa32,
+    EfiX64,
+    EfiArm32,
+    EfiArm64,
+}
+
+impl ClientArch {
+
+    /// Returns the `ClientArch` of an architecture type,
+    /// if known.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// assert!(ClientArch::from_code(7) == Some(ClientArch::EfiX64));
+    /// assert!(ClientArch::from_code(0x1f).is_none());
+    /// ```
+    pub fn from_code(
+        code: u16
+    ) -> Option<Self> {
+        match code {
+            0 => Some(ClientArch::Bios),
+            6 => Some(ClientArch::EfiIa32),
+            // EFI BC and EFI x86-64 are both x64 UEFI clients,
+            // 16 being their HTTP boot variant
+            7 | 9 | 16 => Some(ClientArch::EfiX64),
+            10 | 18 => Some(ClientArch::EfiArm32),
+            11 | 19 => Some(ClientArch::EfiArm64),
+            _ => None
+        }
+    }
+}
+
+/// `BootSettings` describes how network booting
+/// clients (PXE) find their boot loader.
+///
+/// The boot file is chosen by client architecture (option 93),
+/// so that BIOS and UEFI clients get different loaders. Clients
+/// already running iPXE (user class "iPXE") are given the
+/// `ipxe_filename` instead, to break chainloading loops.
+///
+/// # Examples:
+///
+/// ```yaml
+/// boot:
+///   next_server: 192.168.0.10
+///   filename: "pxelinux.0"
+///   arch_filenames:
+///     efi_x64: "grub/shimx64.efi"
+///     efi_arm64: "grub/grubaa64.efi"
+///   ipxe_filename: "http://boot.example.com/boot.ipxe"
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootSettings {
+    /// TFTP server address (siaddr)
+    pub next_server: Option<Ipv4Addr>,
+    /// TFTP server name (sname, or option 66)
+    pub server_name: Option<String>,
+    /// Boot file of clients without a more specific one
+    /// (file, or option 67)
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub arch_filenames: HashMap<ClientArch, String>,
+    /// Boot file of clients running iPXE
+    pub ipxe_filename: Option<String>,
+    /// Always send options 66 and 67, rather than only
+    /// when they are requested or overflow the header fields
+    pub use_options: bool,
+}
+
+impl BootSettings {
+
+    /// Returns true if the client is running iPXE.
+    pub fn is_ipxe(
+        request: &DhcpV4Packet
+    ) -> bool {
+        request.options
+            .user_classes()
+            .iter()
+            .any(|user_class| user_class == IPXE_USER_CLASS)
+    }
+
+    /// Returns the boot file the client should load.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let boot = BootSettings { filename: Some(String::from("pxelinux.0")), ..Default::default() };
+    /// assert!(boot.filename_for(&request) == Some("pxelinux.0"));
+    /// ```
+    pub fn filename_for(
+        &self,
+        request: &DhcpV4Packet
+    ) -> Option<&str> {
+        if let Some(ipxe_filename) = self.ipxe_filename.as_ref().filter(|_| Self::is_ipxe(request)) {
+            return Some(ipxe_filename.as_str());
+        };
+        request.options
+            .client_arch()
+            .into_iter()
+            .flatten()
+            .filter_map(|code| ClientArch::from_code(*code))
+            .find_map(|arch| self.arch_filenames.get(&arch))
+            .or(self.filename.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Fills the boot settings of the reply to the given request.
+    ///
+    /// The server name and boot file are written in the `sname`
+    /// and `file` header fields. They are also sent as options 66
+    /// and 67 when `use_options` is set, when the client requested
+    /// them (option 55), or when they do not fit in the header.
+    pub fn apply(
+        &self,
+        request: &DhcpV4Packet,
+        reply: &mut DhcpV4Packet
+    ) {
+        if let Some(next_server) = self.next_server {
+            reply.siaddr = next_server;
+        };
+
+        let requested = |code: u8| {
+            request.options
+                .parameter_request()
+                .map_or(false, |codes| codes.contains(&code))
+        };
+
+        if let Some(server_name) = &self.server_name {
+            // Header fields are NUL terminated
+            let fits = server_name.len() < SNAME_SIZE;
+            if fits {
+                reply.sname = [0; SNAME_SIZE];
+                reply.sname[..server_name.len()].copy_from_slice(server_name.as_bytes());
+            };
+            if self.use_options | requested(66) | !fits {
+                reply.options.set_tftp_server_name(Some(server_name.clone()));
+            };
+        };
+
+        if let Some(filename) = self.filename_for(request) {
+            let fits = filename.len() < FILE_SIZE;
+            if fits {
+                reply.file = [0; FILE_SIZE];
+                reply.file[..filename.len()].copy_from_slice(filename.as_bytes());
+            };
+            if self.use_options | requested(67) | !fits {
+                reply.options.set_bootfile_name(Some(filename.to_string()));
+            };
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fp_core::core::packet::PacketType;
+
+    use super::*;
+
+    fn request(arch: Option<u16>) -> DhcpV4Packet {
+        let mut buf = vec![0u8; 240];
+        buf[1] = 1;
+        buf[2] = 6;
+        let mut packet = DhcpV4Packet::from_raw_bytes(buf.as_slice());
+        packet.options.set_client_arch(arch.map(|arch| vec![arch]));
+        packet
+    }
+
+    fn settings() -> BootSettings {
+        BootSettings {
+            next_server: Some(Ipv4Addr::new(192, 168, 0, 10)),
+            server_name: Some(String::from("tftp.example.com")),
+            filename: Some(String::from("pxelinux.0")),
+            arch_filenames: HashMap::from([
+                (ClientArch::EfiX64, String::from("grub/shimx64.efi")),
+                (ClientArch::EfiArm64, String::from("grub/grubaa64.efi")),
+            ]),
+            ipxe_filename: Some(String::from("http://boot.example.com/boot.ipxe")),
+            use_options: false,
+        }
+    }
+
+    #[test]
+    fn test_filename_by_arch() {
+        let boot = settings();
+        assert!(boot.filename_for(&request(None)) == Some("pxelinux.0"));
+        assert!(boot.filename_for(&request(Some(0))) == Some("pxelinux.0"));
+        assert!(boot.filename_for(&request(Some(7))) == Some("grub/shimx64.efi"));
+        assert!(boot.filename_for(&request(Some(9))) == Some("grub/shimx64.efi"));
+        assert!(boot.filename_for(&request(Some(11))) == Some("grub/grubaa64.efi"));
+        // Unknown or unconfigured architectures fall back to the default file
+        assert!(boot.filename_for(&request(Some(6))) == Some("pxelinux.0"));
+        assert!(boot.filename_for(&request(Some(0x1f))) == Some("pxelinux.0"));
+    }
+
+    #[test]
+    fn test_ipxe_chainloading() {
+        let boot = settings();
+        let mut ipxe = request(Some(7));
+        ipxe.options.set_user_class(Some(b"iPXE".to_vec()));
+        assert!(BootSettings::is_ipxe(&ipxe));
+        assert!(boot.filename_for(&ipxe) == Some("http://boot.example.com/boot.ipxe"));
+
+        // Without an iPXE script, iPXE clients get their usual loader
+        let boot = BootSettings { ipxe_filename: None, ..settings() };
+        assert!(boot.filename_for(&ipxe) == Some("grub/shimx64.efi"));
+    }
+
+    #[test]
+    fn test_apply() {
+        let boot = settings();
+        let request = request(Some(7));
+        let mut reply = request.clone();
+        boot.apply(&request, &mut reply);
+
+        assert!(reply.siaddr == Ipv4Addr::new(192, 168, 0, 10));
+        assert!(reply.sname.starts_with(b"tftp.example.com\0"));
+        assert!(reply.file.starts_with(b"grub/shimx64.efi\0"));
+        assert!(reply.options.tftp_server_name().is_none());
+        assert!(reply.options.bootfile_name().is_none());
+
+        // Requested options are sent as well
+        let mut request = request;
+        request.options.add_parameter_request(66);
+        request.options.add_parameter_request(67);
+        boot.apply(&request, &mut reply);
+        assert!(reply.options.tftp_server_name().unwrap() == "tftp.example.com");
+        assert!(reply.options.bootfile_name().unwrap() == "grub/shimx64.efi");
+    }
+
+    #[test]
+    fn test_long_filename() {
+        let filename = format!("http://boot.example.com/{}.efi", "a".repeat(128));
+        let boot = BootSettings { filename: Some(filename.clone()), ..Default::default() };
+        let request = request(None);
+        let mut reply = request.clone();
+        boot.apply(&request, &mut reply);
+
+        assert!(reply.file == [0; 128]);
+        assert!(reply.options.bootfile_name() == Some(&filename));
+    }
+}
@@ -0,0 +1,53 @@
+use std::net::Ipv4Addr;
+
+use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
+
+/// `BootpSettings` enables BOOTP (RFC 951) clients
+/// on a subnet.
+///
+/// BOOTP clients are always answered from their static
+/// reservations. When a `dynamic_start` - `dynamic_end`
+/// range is set, unknown BOOTP clients are also given an
+/// address of that range (the "dynamic-bootp" range).
+///
+/// BOOTP clients never renew nor release their address :
+/// their leases are infinite.
+///
+/// # Examples:
+///
+/// ```yaml
+/// bootp:
+///   dynamic_start: 192.168.0.200
+///   dynamic_end: 192.168.0.219
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootpSettings {
+    pub dynamic_start: Option<Ipv4Addr>,
+    pub dynamic_end: Option<Ipv4Addr>,
+}
+
+impl BootpSettings {
+
+    /// Settings answering reserved BOOTP clients only
+    pub fn static_only() -> Self {
+        Self::default()
+    }
+
+    /// Settings answering every BOOTP client, unknown
+    /// clients being given an address of the given range
+    pub fn with_dynamic_range(
+        dynamic_start: Ipv4Addr,
+        dynamic_end: Ipv4Addr
+    ) -> Self {
+        Self { dynamic_start: Some(dynamic_start), dynamic_end: Some(dynamic_end) }
+    }
+
+    /// Returns the dynamic-bootp range, if both
+    /// of its bounds are set.
+    pub fn dynamic_range(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        self.dynamic_start.zip(self.dynamic_end)
+    }
+}
@@ -0,0 +1,190 @@
+//! Implements `HostnamePolicy`, turning the names sent
+//! by clients into valid hostnames before they are stored
+//! in leases and published in the DNS.
+
+use std::net::Ipv4Addr;
+
+use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
+
+use crate::packet::dhcp_options::DhcpOptions;
+
+const MAX_LABEL_SIZE: usize = 63;
+
+fn _is_valid(c: char) -> bool {
+    c.is_ascii_alphanumeric() | (c == '-')
+}
+
+/// `HostnamePolicy` describes how the names sent by clients
+/// (options 12 and 81) are sanitized : hostnames only hold
+/// letters, digits and hyphens (RFC 1123), which clients
+/// often ignore.
+///
+/// # Examples:
+///
+/// ```yaml
+/// hostname_policy:
+///   replacement: "-"
+///   lowercase: true
+///   domain_suffix: "example.com"
+///   generated_prefix: "dhcp-"
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostnamePolicy {
+    /// Replaces each run of invalid characters,
+    /// which are dropped when empty
+    pub replacement: String,
+    pub lowercase: bool,
+    /// Appended to the names made of a single label
+    pub domain_suffix: Option<String>,
+    /// Prefix of the names generated from the address of
+    /// the clients sending none, which stay unnamed when unset
+    pub generated_prefix: Option<String>,
+}
+
+impl Default for HostnamePolicy {
+    fn default() -> Self {
+        Self { replacement: String::from("-"), lowercase: true, domain_suffix: None, generated_prefix: None }
+    }
+}
+
+impl HostnamePolicy {
+
+    /// Returns the name requested by a client : the name of
+    /// its client FQDN option if any, its hostname otherwise.
+    pub fn requested_name(
+        options: &DhcpOptions
+    ) -> Option<&str> {
+        options.client_fqdn()
+            .map(|fqdn| fqdn.domain_name())
+            .filter(|name| !name.is_empty())
+            .or_else(|| options.hostname().map(String::as_str))
+    }
+
+    /// Returns the sanitized name of the client of the given
+    /// options, to be stored in the lease of the given address.
+    pub fn hostname(
+        &self,
+        options: &DhcpOptions,
+        address: Ipv4Addr
+    ) -> String {
+        self.apply(Self::requested_name(options).unwrap_or_default(), address)
+    }
+
+    fn sanitize_label(
+        &self,
+        label: &str
+    ) -> String {
+        let replacement: String = self.replacement.chars().filter(|c| _is_valid(*c)).collect();
+        let mut sanitized = String::new();
+        for c in label.chars() {
+            if _is_valid(c) {
+                sanitized.push(c);
+            } else if !sanitized.ends_with(replacement.as_str()) {
+                sanitized.push_str(&replacement);
+            };
+        }
+        // Labels neither start nor end with a hyphen
+        let mut sanitized = sanitized.trim_matches('-').to_string();
+        sanitized.truncate(MAX_LABEL_SIZE);
+        sanitized.trim_end_matches('-').to_string()
+    }
+
+    /// Sanitizes a name sent by the client of the given address.
+    ///
+    /// Applying the policy to a name it returned
+    /// leaves it unchanged. An empty string means that
+    /// the client has no name.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let policy = HostnamePolicy::default();
+    /// assert!(policy.apply("MBP de Sacha\0", address) == "mbp-de-sacha");
+    /// ```
+    pub fn apply(
+        &self,
+        hostname: &str,
+        address: Ipv4Addr
+    ) -> String {
+        let hostname = hostname.trim_matches(|c: char| (c == '\0') | c.is_whitespace());
+        let mut labels: Vec<String> = hostname.split('.')
+            .map(|label| self.sanitize_label(label))
+            .filter(|label| !label.is_empty())
+            .collect();
+
+        if labels.is_empty() {
+            let prefix = match &self.generated_prefix {
+                Some(prefix) => prefix,
+                None => return String::new()
+            };
+            let generated = format!("{}{}", prefix, address.to_string().replace('.', "-"));
+            labels.push(self.sanitize_label(&generated));
+        };
+        if let Some(suffix) = self.domain_suffix.as_ref().filter(|_| labels.len() == 1) {
+            labels.extend(suffix.split('.').filter(|label| !label.is_empty()).map(String::from));
+        };
+
+        let hostname = labels.join(".");
+        match self.lowercase {
+            true => hostname.to_ascii_lowercase(),
+            false => hostname
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::client_fqdn::{ClientFqdn, FqdnFlags};
+
+    use super::*;
+
+    const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 5);
+
+    #[test]
+    fn test_sanitize() {
+        let policy = HostnamePolicy::default();
+        assert!(policy.apply("MBP de Sacha", ADDRESS) == "mbp-de-sacha");
+        assert!(policy.apply("Sacha’s MacBook Pro\0\0", ADDRESS) == "sacha-s-macbook-pro");
+        assert!(policy.apply("_printer_.Office..LAN.", ADDRESS) == "printer.office.lan");
+        assert!(policy.apply(&"a".repeat(70), ADDRESS).len() == 63);
+        assert!(policy.apply("  \0", ADDRESS).is_empty());
+        assert!(policy.apply("été", ADDRESS) == "t");
+
+        let policy = HostnamePolicy { replacement: String::new(), lowercase: false, ..Default::default() };
+        assert!(policy.apply("MBP de Sacha", ADDRESS) == "MBPdeSacha");
+    }
+
+    #[test]
+    fn test_suffix_and_generated_names() {
+        let policy = HostnamePolicy {
+            domain_suffix: Some(String::from("Example.com.")),
+            generated_prefix: Some(String::from("dhcp-")),
+            ..Default::default()
+        };
+        assert!(policy.apply("host", ADDRESS) == "host.example.com");
+        assert!(policy.apply("host.corp.net", ADDRESS) == "host.corp.net");
+        assert!(policy.apply("", ADDRESS) == "dhcp-192-168-0-5.example.com");
+        assert!(policy.apply("???", ADDRESS) == "dhcp-192-168-0-5.example.com");
+        // Sanitized names are left as is
+        assert!(policy.apply(&policy.apply("My PC", ADDRESS), ADDRESS) == "my-pc.example.com");
+    }
+
+    #[test]
+    fn test_requested_name() {
+        let policy = HostnamePolicy::default();
+        let mut options = DhcpOptions::new();
+        assert!(policy.hostname(&options, ADDRESS).is_empty());
+
+        options.set_hostname(Some(String::from("Host 12")));
+        assert!(policy.hostname(&options, ADDRESS) == "host-12");
+
+        // The client FQDN option takes precedence
+        options.set_client_fqdn(Some(ClientFqdn::new(FqdnFlags::default(), "laptop.example.com.")));
+        assert!(policy.hostname(&options, ADDRESS) == "laptop.example.com");
+        options.set_client_fqdn(Some(ClientFqdn::new(FqdnFlags::default(), "")));
+        assert!(policy.hostname(&options, ADDRESS) == "host-12");
+    }
+}
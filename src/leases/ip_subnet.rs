@@ -1,9 +1,15 @@
-use std::{net::Ipv4Addr, collections::HashMap};
+use std::{net::Ipv4Addr, collections::{HashMap, HashSet}};
 
 use serde::{Serialize, Deserialize};
 
 use crate::packet::dhcp_options::DhcpOptions;
 
+use super::{lease_time::LeaseTimes, selection::SelectionKind, boot::BootSettings, bootp::BootpSettings};
+
+// Whether two inclusive address ranges share an address
+fn _overlaps(a: (Ipv4Addr, Ipv4Addr), b: (Ipv4Addr, Ipv4Addr)) -> bool {
+    (u32::from(a.0) <= u32::from(b.1)) & (u32::from(b.0) <= u32::from(a.1))
+}
 
 /// `Ipv4Subnet` provides an abstraction layer over 
 /// IP v4 subnets, to help manage such subnets.
@@ -11,14 +17,45 @@ use crate::packet::dhcp_options::DhcpOptions;
 pub struct Ipv4Subnet {
 
     network_addr: Ipv4Addr,
+    // Offset of the first never allocated address
     #[serde(skip)]
     alloc_ptr: u32,
+    // Released addresses, from the oldest to the latest release
     #[serde(skip)]
     released: Vec<Ipv4Addr>,
     #[serde(skip)]
+    leased: HashSet<Ipv4Addr>,
+    // Addresses found in use by another host
+    #[serde(skip)]
+    declined: HashSet<Ipv4Addr>,
+    #[serde(skip)]
     force_allocated: HashMap<Ipv4Addr, usize>,
     prefix: u8,
+    // Bounds of the dynamic pool, the whole subnet when unset
+    #[serde(default)]
+    pool_start: Option<Ipv4Addr>,
+    #[serde(default)]
+    pool_end: Option<Ipv4Addr>,
+    // Only reserved addresses are handed out
+    #[serde(skip)]
+    only_static: bool,
+    #[serde(default)]
+    selection: SelectionKind,
+    // Client classes allowed to allocate from the dynamic pool
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    client_classes: Vec<String>,
     options: DhcpOptions,
+    // Network boot settings of the clients of the subnet
+    #[serde(default)]
+    boot: Option<BootSettings>,
+    // BOOTP clients are ignored unless set
+    #[serde(default)]
+    bootp: Option<BootpSettings>,
+    // DISCOVER with rapid commit (option 80) are directly acknowledged
+    #[serde(default)]
+    rapid_commit: bool,
+    #[serde(flatten)]
+    lease_times: LeaseTimes,
 
 }
 
@@ -34,7 +71,7 @@ impl Ipv4Subnet {
     /// ```
 
     pub fn new(network_addr: Ipv4Addr, prefix: u8) -> Self {
-        Self { network_addr, alloc_ptr: 1, released: Vec::new(), force_allocated: HashMap::new(), prefix, options: DhcpOptions::new()}
+        Self { network_addr, alloc_ptr: 1, released: Vec::new(), leased: HashSet::new(), declined: HashSet::new(), force_allocated: HashMap::new(), prefix, pool_start: None, pool_end: None, only_static: false, selection: SelectionKind::default(), client_classes: Vec::new(), options: DhcpOptions::new(), boot: None, bootp: None, rapid_commit: false, lease_times: LeaseTimes::default()}
     }
 
     /// Returns the network address corresponding to the
@@ -96,7 +133,56 @@ impl Ipv4Subnet {
     /// ```
 
     pub fn allocated_count(&self) -> u32 {
-        self.alloc_ptr - 1 - self.released.len() as u32
+        self.leased.len() as u32
+    }
+
+    /// Returns the first address of the dynamic pool,
+    /// the first host address of the subnet by default.
+    pub fn pool_start(&self) -> Ipv4Addr {
+        self.pool_start
+            .unwrap_or(Ipv4Addr::from(u32::from(self.network_addr) + 1))
+    }
+
+    /// Returns the last address of the dynamic pool,
+    /// the last host address of the subnet by default.
+    pub fn pool_end(&self) -> Ipv4Addr {
+        self.pool_end
+            .unwrap_or(Ipv4Addr::from(u32::from(self.broadcast()) - 1))
+    }
+
+    /// Restricts the dynamic pool of this `Ipv4Subnet` to
+    /// the given range. Addresses of the subnet outside of
+    /// the pool are only handed out through static allocations.
+    ///
+    /// Returns an error if the range is empty, does not
+    /// belong to this `Ipv4Subnet`, includes its network
+    /// or broadcast address, or overlaps its dynamic-bootp
+    /// range.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+    /// assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 100));
+    /// ```
+    pub fn set_pool(&mut self, pool_start: Ipv4Addr, pool_end: Ipv4Addr) -> Result<(), ()> {
+        if !self.contains(pool_start) | !self.contains(pool_end) { return Err(()); };
+        if u32::from(pool_start) > u32::from(pool_end) { return Err(()); };
+        if (pool_start == self.network_addr) | (pool_end == self.broadcast()) { return Err(()); };
+        if let Some((start, end)) = self.bootp.and_then(|bootp| bootp.dynamic_range()) {
+            if _overlaps((pool_start, pool_end), (start, end)) { return Err(()); };
+        };
+
+        self.pool_start = Some(pool_start);
+        self.pool_end = Some(pool_end);
+        Ok(())
+    }
+
+    /// Check if a given [`Ipv4Addr`] belongs to the
+    /// dynamic pool of this `Ipv4Subnet`.
+    pub fn in_pool(&self, ip: Ipv4Addr) -> bool {
+        (u32::from(self.pool_start()) <= u32::from(ip)) && (u32::from(self.pool_end()) >= u32::from(ip))
     }
 
     /// Check if a given [`Ipv4Addr`] belongs to 
@@ -129,8 +215,7 @@ impl Ipv4Subnet {
     /// ```
 
     pub fn is_free(&self, ip: Ipv4Addr) -> bool {
-        let cnt_from_nw = u32::from(ip) - u32::from(self.network_addr);
-        self.contains(ip) & ((cnt_from_nw >= self.alloc_ptr) | (self.released.contains(&ip))) & !self.force_allocated.contains_key(&ip)
+        self.contains(ip) && !self.force_allocated.contains_key(&ip) && !self.leased.contains(&ip) && !self.declined.contains(&ip)
     }
 
     /// De-allocate a given [`Ipv4Addr`].
@@ -148,11 +233,51 @@ impl Ipv4Subnet {
     /// ```
 
     pub fn free(&mut self, ip: Ipv4Addr) -> Result<(), ()> {
-        if !self.contains(ip) { return Err(()); };
-        if self.is_free(ip) {
+        if !self.leased.remove(&ip) {
             return Err(());
         }; 
 
+        // Addresses of the dynamic-bootp range lie out of the pool
+        if self.in_pool(ip) {
+            self.released.push(ip);
+        };
+        Ok(())
+    }
+
+    /// Marks an allocated [`Ipv4Addr`] as declined, because
+    /// it was found in use by another host. Declined addresses
+    /// are not handed out until [`Ipv4Subnet::free_declined`]
+    /// is called.
+    ///
+    /// Returns an error if the address was not allocated.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// let ip = subnet.allocate().unwrap();
+    /// subnet.decline(ip).unwrap();
+    /// assert!(subnet.is_declined(ip));
+    /// ```
+    pub fn decline(&mut self, ip: Ipv4Addr) -> Result<(), ()> {
+        if !self.leased.remove(&ip) {
+            return Err(());
+        };
+        self.declined.insert(ip);
+        Ok(())
+    }
+
+    pub fn is_declined(&self, ip: Ipv4Addr) -> bool {
+        self.declined.contains(&ip)
+    }
+
+    /// Makes a declined [`Ipv4Addr`] available again.
+    ///
+    /// Returns an error if the address was not declined.
+    pub fn free_declined(&mut self, ip: Ipv4Addr) -> Result<(), ()> {
+        if !self.declined.remove(&ip) {
+            return Err(());
+        };
         self.released.push(ip);
         Ok(())
     }
@@ -175,14 +300,21 @@ impl Ipv4Subnet {
         if !self.force_allocated.contains_key(&ip) { return Err(()); };
         self.force_allocated.remove(&ip);
 
+        // Pool addresses below the allocation pointer are
+        // only found again through the released ones
+        let offset = u32::from(ip) - u32::from(self.network_addr);
+        if self.in_pool(ip) & (offset < self.alloc_ptr) {
+            self.released.push(ip);
+        };
         Ok(())
     }
 
-    /// Allocate an [`Ipv4Addr`] in that `Ipv4Subnet`.
+    /// Allocate an [`Ipv4Addr`] in that `Ipv4Subnet`, as
+    /// chosen by its [`SelectionKind`] (see [`Ipv4Subnet::allocate_for`]).
     ///
-    /// If any allocated IPs that were previously allocated
-    /// have been freed, they are chosen first. Otherwise,
-    /// the next never-allocated IP is returned.
+    /// Only addresses of the dynamic pool are returned.
+    /// Statically allocated IPs, as well as the network
+    /// and broadcast addresses, are never returned.
     ///
     /// Returns an error if there are no more IP addresses
     /// available.
@@ -196,18 +328,83 @@ impl Ipv4Subnet {
     /// ```
 
     pub fn allocate(&mut self) -> Result<Ipv4Addr, ()> {
-        if (self.alloc_ptr > self.count()) & self.released.is_empty() {
-            return Err(());
-        };
+        self.allocate_for(&[])
+    }
+
+    /// Allocate an [`Ipv4Addr`] in that `Ipv4Subnet` for the
+    /// client with the given identifier, as chosen by the
+    /// [`SelectionStrategy`](super::selection::SelectionStrategy)
+    /// of the subnet.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// subnet.set_selection(SelectionKind::ClientHash);
+    /// let ip = subnet.allocate_for(b"client").unwrap();
+    /// ```
+    pub fn allocate_for(&mut self, client_id: &[u8]) -> Result<Ipv4Addr, ()> {
+        let ip = self.selection
+            .strategy()
+            .select(self, client_id)
+            .ok_or(())?;
+        self.take(ip)?;
+        Ok(ip)
+    }
 
-        if !self.released.is_empty() {
-            return Ok(self.released.pop().unwrap());
+    /// Allocate the given [`Ipv4Addr`] of the dynamic pool.
+    ///
+    /// Returns an error if it is already allocated, or
+    /// does not belong to the dynamic pool.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// assert!(subnet.take(Ipv4Addr::new(192, 168, 0, 17)).is_ok());
+    /// assert!(subnet.take(Ipv4Addr::new(192, 168, 0, 17)).is_err());
+    /// ```
+    pub fn take(&mut self, ip: Ipv4Addr) -> Result<(), ()> {
+        if !self.in_pool(ip) | !self.is_free(ip) { return Err(()); };
+
+        self.leased.insert(ip);
+        self.released.retain(|released| *released != ip);
+
+        let pool_end = u32::from(self.pool_end()) - u32::from(self.network_addr);
+        self.alloc_ptr = self.alloc_ptr.max(u32::from(self.pool_start()) - u32::from(self.network_addr));
+        while (self.alloc_ptr <= pool_end) && !self.is_free(Ipv4Addr::from(u32::from(self.network_addr) + self.alloc_ptr)) {
+            self.alloc_ptr += 1;
         };
+        Ok(())
+    }
 
-        let will_allocate = u32::from(self.network_addr) + self.alloc_ptr;
-        self.alloc_ptr += 1;
+    /// Returns the released addresses that may be allocated
+    /// again, from the oldest to the latest release.
+    pub fn released(&self) -> &[Ipv4Addr] {
+        &self.released
+    }
 
-        Ok(Ipv4Addr::from(will_allocate))
+    /// Returns the first and last never allocated addresses
+    /// of the dynamic pool, if any.
+    ///
+    /// Addresses in between may have been allocated out of
+    /// order, use [`Ipv4Subnet::is_free`] to check them.
+    pub fn fresh_bounds(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        let first = (u32::from(self.network_addr) + self.alloc_ptr).max(u32::from(self.pool_start()));
+        let last = u32::from(self.pool_end());
+        match first <= last {
+            true => Some((Ipv4Addr::from(first), Ipv4Addr::from(last))),
+            false => None
+        }
+    }
+
+    /// Returns the lowest never allocated free address
+    /// of the dynamic pool, if any.
+    pub fn next_fresh(&self) -> Option<Ipv4Addr> {
+        let (first, last) = self.fresh_bounds()?;
+        (u32::from(first)..=u32::from(last))
+            .map(Ipv4Addr::from)
+            .find(|ip| self.is_free(*ip))
     }
 
     /// Performs a static allocation on the given [`Ipv4Addr`].
@@ -227,10 +424,74 @@ impl Ipv4Subnet {
         if !self.is_free(ip) { return Err(()); };
 
         self.force_allocated.insert(ip, 1);
+        self.released.retain(|released| *released != ip);
 
         Ok(())
     }
 
+    /// Returns the leased addresses of this `Ipv4Subnet`,
+    /// in ascending order.
+    pub fn leased(&self) -> Vec<Ipv4Addr> {
+        let mut leased: Vec<Ipv4Addr> = self.leased.iter().copied().collect();
+        leased.sort();
+        leased
+    }
+
+    /// Takes over the allocation state of the previous version
+    /// of this `Ipv4Subnet`, when the configuration is reloaded.
+    /// Both subnets may have a different prefix or pool.
+    ///
+    /// Leased, declined and statically allocated addresses that
+    /// still belong to this subnet stay so, and released addresses
+    /// still in the pool can be allocated again. Addresses newly
+    /// added to the pool are handed out like never allocated ones.
+    ///
+    /// Returns the leased addresses that are now out of both the
+    /// pool and the dynamic-bootp range. They stay leased if they
+    /// still belong to this subnet, until their client is gone.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut previous = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// previous.take(Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+    /// assert!(subnet.absorb(&previous) == vec![Ipv4Addr::new(192, 168, 0, 10)]);
+    /// ```
+    pub fn absorb(&mut self, previous: &Ipv4Subnet) -> Vec<Ipv4Addr> {
+        let mut out_of_range = Vec::new();
+        for ip in previous.leased() {
+            if !self.in_pool(ip) & !self.in_bootp_range(ip) {
+                out_of_range.push(ip);
+            };
+            if self.contains(ip) {
+                self.leased.insert(ip);
+            };
+        }
+        let declined: Vec<Ipv4Addr> = previous.declined.iter().copied().filter(|ip| self.contains(*ip)).collect();
+        self.declined.extend(declined);
+        let force_allocated: Vec<(Ipv4Addr, usize)> = previous.force_allocated.iter()
+            .map(|(ip, count)| (*ip, *count))
+            .filter(|(ip, _)| self.contains(*ip))
+            .collect();
+        self.force_allocated.extend(force_allocated);
+
+        // The allocation pointer keeps its address, pool addresses
+        // below it that were out of the previous pool are released
+        let network = u32::from(self.network_addr);
+        let fresh = u32::from(previous.network_addr) + previous.alloc_ptr;
+        self.alloc_ptr = fresh.saturating_sub(network);
+        let below = u32::from(self.pool_start())..fresh.min(u32::from(self.pool_end()) + 1);
+        let mut released: Vec<Ipv4Addr> = below
+            .map(Ipv4Addr::from)
+            .filter(|ip| !(previous.contains(*ip) && previous.in_pool(*ip)) && self.is_free(*ip))
+            .collect();
+        released.extend(previous.released.iter().filter(|ip| self.in_pool(**ip) && self.is_free(**ip)));
+        self.released = released;
+        out_of_range
+    }
+
     pub fn prefix(&self) -> u8 {
         self.prefix
     }
@@ -238,13 +499,165 @@ impl Ipv4Subnet {
     pub fn options(&self) -> &DhcpOptions {
         &self.options
     }
+
+    /// Returns the [`BootSettings`] sent to network
+    /// booting clients of this `Ipv4Subnet`, if any.
+    pub fn boot(&self) -> Option<&BootSettings> {
+        self.boot.as_ref()
+    }
+
+    pub fn set_boot(&mut self, boot: Option<BootSettings>) {
+        self.boot = boot;
+    }
+
+    pub fn bootp(&self) -> Option<&BootpSettings> {
+        self.bootp.as_ref()
+    }
+
+    /// Enables BOOTP clients on this `Ipv4Subnet`, or
+    /// disables them when given [`None`].
+    ///
+    /// Returns an error if the dynamic-bootp range is empty,
+    /// does not belong to this `Ipv4Subnet`, includes its
+    /// network or broadcast address, or overlaps its dynamic
+    /// pool, which spans the whole subnet unless restricted.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+    /// let bootp = BootpSettings::with_dynamic_range(Ipv4Addr::new(192, 168, 0, 200), Ipv4Addr::new(192, 168, 0, 219));
+    /// subnet.set_bootp(Some(bootp)).unwrap();
+    /// assert!(subnet.allocate_bootp().unwrap() == Ipv4Addr::new(192, 168, 0, 200));
+    /// ```
+    pub fn set_bootp(&mut self, bootp: Option<BootpSettings>) -> Result<(), ()> {
+        if let Some((start, end)) = bootp.as_ref().and_then(BootpSettings::dynamic_range) {
+            if !self.contains(start) | !self.contains(end) { return Err(()); };
+            if u32::from(start) > u32::from(end) { return Err(()); };
+            if (start == self.network_addr) | (end == self.broadcast()) { return Err(()); };
+            if _overlaps((start, end), (self.pool_start(), self.pool_end())) { return Err(()); };
+        };
+        self.bootp = bootp;
+        Ok(())
+    }
+
+    /// Check if a given [`Ipv4Addr`] belongs to the
+    /// dynamic-bootp range of this `Ipv4Subnet`.
+    pub fn in_bootp_range(&self, ip: Ipv4Addr) -> bool {
+        self.bootp
+            .and_then(|bootp| bootp.dynamic_range())
+            .map_or(false, |(start, end)| (u32::from(start) <= u32::from(ip)) && (u32::from(end) >= u32::from(ip)))
+    }
+
+    /// Allocate the lowest free [`Ipv4Addr`] of the
+    /// dynamic-bootp range, for an unknown BOOTP client.
+    ///
+    /// Returns an error if BOOTP has no dynamic range
+    /// on this `Ipv4Subnet`, or if the range is exhausted.
+    pub fn allocate_bootp(&mut self) -> Result<Ipv4Addr, ()> {
+        let (start, end) = self.bootp
+            .and_then(|bootp| bootp.dynamic_range())
+            .ok_or(())?;
+        let ip = (u32::from(start)..=u32::from(end))
+            .map(Ipv4Addr::from)
+            .find(|ip| self.is_free(*ip))
+            .ok_or(())?;
+
+        self.leased.insert(ip);
+        self.released.retain(|released| *released != ip);
+        Ok(ip)
+    }
+
+    /// Returns the [`LeaseTimes`] policy that applies
+    /// to leases granted in this `Ipv4Subnet`.
+    pub fn lease_times(&self) -> &LeaseTimes {
+        &self.lease_times
+    }
+
+    pub fn set_lease_times(&mut self, lease_times: LeaseTimes) {
+        self.lease_times = lease_times;
+    }
+
+    /// Returns true if addresses of this `Ipv4Subnet` are
+    /// only handed out through static allocations.
+    pub fn only_static(&self) -> bool {
+        self.only_static
+    }
+
+    pub fn set_only_static(&mut self, only_static: bool) {
+        self.only_static = only_static;
+    }
+
+    pub fn rapid_commit(&self) -> bool {
+        self.rapid_commit
+    }
+
+    pub fn set_rapid_commit(&mut self, rapid_commit: bool) {
+        self.rapid_commit = rapid_commit;
+    }
+
+    /// Returns true if the DHCPDISCOVER carrying the given
+    /// options must be answered by a DHCPACK (RFC 4039) : the
+    /// client asked for it, and rapid commit is enabled on
+    /// this `Ipv4Subnet`.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// subnet.set_rapid_commit(true);
+    /// let mut options = DhcpOptions::new();
+    /// options.set_message_type(Some(1));
+    /// options.set_rapid_commit(true);
+    /// assert!(subnet.accepts_rapid_commit(&options));
+    /// ```
+    pub fn accepts_rapid_commit(&self, request_options: &DhcpOptions) -> bool {
+        self.rapid_commit
+            & request_options.rapid_commit()
+            & (request_options.message_type() == Some(1))
+    }
+
+    pub fn selection(&self) -> SelectionKind {
+        self.selection
+    }
+
+    pub fn set_selection(&mut self, selection: SelectionKind) {
+        self.selection = selection;
+    }
+
+    /// Restricts the dynamic pool of this `Ipv4Subnet` to the
+    /// members of the given client classes. An empty list
+    /// lets every client in.
+    pub fn set_client_classes(&mut self, client_classes: Vec<String>) {
+        self.client_classes = client_classes;
+    }
+
+    pub fn client_classes(&self) -> &[String] {
+        &self.client_classes
+    }
+
+    /// Returns true if a client member of the given classes
+    /// may be allocated an address from the dynamic pool.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// subnet.set_client_classes(vec![String::from("voip")]);
+    /// assert!(subnet.allows(&[String::from("voip")]));
+    /// assert!(!subnet.allows(&[]));
+    /// ```
+    pub fn allows(&self, classes: &[String]) -> bool {
+        self.client_classes.is_empty() || self.client_classes.iter().any(|class| classes.contains(class))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::net::Ipv4Addr;
 
-    use super::Ipv4Subnet;
+    use super::{Ipv4Subnet, BootpSettings, DhcpOptions};
 
 
     #[test]
@@ -314,6 +727,25 @@ mod tests {
         assert!(last == Ipv4Addr::new(192, 168, 0, 1));
     }
 
+    #[test]
+    fn test_allocation_skips_static() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24); 
+
+        subnet.force_allocate(Ipv4Addr::new(192, 168, 0, 2)).unwrap();
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 1));
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 3));
+    }
+
+    #[test]
+    fn test_allocation_exhaustion() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 29); 
+
+        let allocated: Vec<Ipv4Addr> = (0..6).map(|_| subnet.allocate().unwrap()).collect();
+        assert!(!allocated.contains(&subnet.network()));
+        assert!(!allocated.contains(&subnet.broadcast()));
+        assert!(subnet.allocate().is_err());
+    }
+
     #[test]
     fn test_static_allocation() {
         let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24); 
@@ -323,4 +755,149 @@ mod tests {
         assert!(!subnet.force_allocate(Ipv4Addr::new(192, 168, 0, 5)).is_ok());
     }
 
+    #[test]
+    fn test_pool_allocation() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        assert!(subnet.set_pool(Ipv4Addr::new(192, 168, 0, 10), Ipv4Addr::new(192, 168, 0, 11)).is_ok());
+
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 10));
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 11));
+        assert!(subnet.allocate().is_err());
+        assert!(subnet.allocated_count() == 2);
+
+        // Out of pool addresses can still be statically allocated
+        assert!(subnet.is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        assert!(subnet.force_allocate(Ipv4Addr::new(192, 168, 0, 3)).is_ok());
+        assert!(!subnet.is_free(Ipv4Addr::new(192, 168, 0, 3)));
+    }
+
+    #[test]
+    fn test_invalid_pool() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        assert!(subnet.set_pool(Ipv4Addr::new(192, 168, 0, 11), Ipv4Addr::new(192, 168, 0, 10)).is_err());
+        assert!(subnet.set_pool(Ipv4Addr::new(192, 168, 0, 10), Ipv4Addr::new(192, 168, 1, 10)).is_err());
+        assert!(subnet.pool_start() == Ipv4Addr::new(192, 168, 0, 1));
+        assert!(subnet.pool_end() == Ipv4Addr::new(192, 168, 0, 254));
+    }
+
+    #[test]
+    fn test_declined_address() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let ip = subnet.allocate().unwrap();
+        assert!(subnet.decline(Ipv4Addr::new(192, 168, 0, 2)).is_err());
+        subnet.decline(ip).unwrap();
+
+        assert!(!subnet.is_free(ip));
+        assert!(subnet.free(ip).is_err());
+        assert!(subnet.allocate().unwrap() != ip);
+
+        subnet.free_declined(ip).unwrap();
+        assert!(subnet.is_free(ip));
+        assert!(subnet.allocate().unwrap() == ip);
+    }
+
+    #[test]
+    fn test_bootp_allocation() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+        assert!(subnet.allocate_bootp().is_err());
+
+        // Reserved BOOTP clients only
+        subnet.set_bootp(Some(BootpSettings::static_only())).unwrap();
+        assert!(subnet.allocate_bootp().is_err());
+
+        let bootp = BootpSettings::with_dynamic_range(Ipv4Addr::new(192, 168, 0, 200), Ipv4Addr::new(192, 168, 0, 201));
+        subnet.set_bootp(Some(bootp)).unwrap();
+        subnet.force_allocate(Ipv4Addr::new(192, 168, 0, 200)).unwrap();
+        assert!(subnet.allocate_bootp().unwrap() == Ipv4Addr::new(192, 168, 0, 201));
+        assert!(subnet.allocate_bootp().is_err());
+        assert!(subnet.in_bootp_range(Ipv4Addr::new(192, 168, 0, 201)));
+        assert!(!subnet.in_bootp_range(Ipv4Addr::new(192, 168, 0, 199)));
+
+        // Freed BOOTP addresses are not handed out to DHCP clients
+        subnet.free(Ipv4Addr::new(192, 168, 0, 201)).unwrap();
+        assert!(subnet.released().is_empty());
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 100));
+    }
+
+    #[test]
+    fn test_rapid_commit() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let mut discover = DhcpOptions::new();
+        discover.set_message_type(Some(1));
+        discover.set_rapid_commit(true);
+        assert!(!subnet.accepts_rapid_commit(&discover));
+
+        subnet.set_rapid_commit(true);
+        assert!(subnet.accepts_rapid_commit(&discover));
+
+        // Only DISCOVER can be rapidly committed
+        let mut request = discover.clone();
+        request.set_message_type(Some(3));
+        assert!(!subnet.accepts_rapid_commit(&request));
+        discover.set_rapid_commit(false);
+        assert!(!subnet.accepts_rapid_commit(&discover));
+    }
+
+    #[test]
+    fn test_invalid_bootp_range() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let range = |start: u8, end: u8| Some(BootpSettings::with_dynamic_range(Ipv4Addr::new(192, 168, 0, start), Ipv4Addr::new(192, 168, 0, end)));
+        assert!(subnet.set_bootp(range(20, 10)).is_err());
+        assert!(subnet.set_bootp(range(0, 10)).is_err());
+        assert!(subnet.set_bootp(range(250, 255)).is_err());
+        assert!(subnet.bootp().is_none());
+
+        // The default pool spans the whole subnet
+        assert!(subnet.set_bootp(range(10, 20)).is_err());
+        subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199)).unwrap();
+        assert!(subnet.set_bootp(range(190, 210)).is_err());
+        assert!(subnet.set_bootp(range(90, 100)).is_err());
+        assert!(subnet.bootp().is_none());
+        assert!(subnet.set_bootp(range(10, 20)).is_ok());
+        assert!(subnet.set_bootp(range(200, 210)).is_ok());
+
+        // Nor can the pool be extended over the dynamic-bootp range
+        assert!(subnet.set_pool(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 200)).is_err());
+        assert!(subnet.pool_end() == Ipv4Addr::new(192, 168, 0, 199));
+    }
+
+    #[test]
+    fn test_absorb_reconfigured_subnet() {
+        let mut previous = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        previous.set_pool(Ipv4Addr::new(192, 168, 0, 10), Ipv4Addr::new(192, 168, 0, 20)).unwrap();
+        for _ in 0..4 {
+            previous.allocate().unwrap();
+        }
+        previous.free(Ipv4Addr::new(192, 168, 0, 11)).unwrap();
+        previous.decline(Ipv4Addr::new(192, 168, 0, 12)).unwrap();
+        previous.force_allocate(Ipv4Addr::new(192, 168, 0, 3)).unwrap();
+
+        // Smaller pool, starting at the third allocated address
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_pool(Ipv4Addr::new(192, 168, 0, 13), Ipv4Addr::new(192, 168, 0, 30)).unwrap();
+        assert!(subnet.absorb(&previous) == vec![Ipv4Addr::new(192, 168, 0, 10)]);
+        assert!(!subnet.is_free(Ipv4Addr::new(192, 168, 0, 10)));
+        assert!(subnet.is_declined(Ipv4Addr::new(192, 168, 0, 12)));
+        assert!(!subnet.is_free(Ipv4Addr::new(192, 168, 0, 3)));
+        assert!(subnet.released().is_empty());
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 14));
+
+        // Growing subnet, addresses below the previous network are new
+        let mut previous = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 1, 0), 24);
+        previous.take(Ipv4Addr::new(192, 168, 1, 5)).unwrap();
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 23);
+        assert!(subnet.absorb(&previous).is_empty());
+        assert!(!subnet.is_free(Ipv4Addr::new(192, 168, 1, 5)));
+        assert!(subnet.released().len() == 256);
+        assert!(subnet.released()[0] == Ipv4Addr::new(192, 168, 0, 1));
+        assert!(subnet.next_fresh() == Some(Ipv4Addr::new(192, 168, 1, 1)));
+
+        // Shrinking subnet, out of range leases are dropped
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 1, 0), 30);
+        assert!(subnet.absorb(&previous) == vec![Ipv4Addr::new(192, 168, 1, 5)]);
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 1, 8), 29);
+        assert!(subnet.absorb(&previous) == vec![Ipv4Addr::new(192, 168, 1, 5)]);
+        assert!(subnet.leased().is_empty());
+    }
 }
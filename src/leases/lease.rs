@@ -1,8 +1,8 @@
-use std::net::Ipv4Addr;
+use std::{net::Ipv4Addr, sync::Arc};
 
-use chrono::{Duration, Utc, DateTime};
+use chrono::{Duration, DateTime, Utc};
 
-use crate::netutils::hw_addr::HardwareAddress;
+use crate::{netutils::hw_addr::HardwareAddress, clock::clock::{Clock, SystemClock}};
 
 use super::ip_subnet::Ipv4Subnet;
 
@@ -14,7 +14,8 @@ pub struct LeaseV4<'a> {
     t_end: DateTime<Utc>,
     hw_addr: HardwareAddress,
     cid: HardwareAddress, 
-    hostname: String
+    hostname: String,
+    clock: Arc<dyn Clock>,
 
 }
 
@@ -50,12 +51,45 @@ impl<'a> LeaseV4<'a> {
         cid: HardwareAddress,
         hostname: String
     ) -> Result<Self, ()> {
+        Self::with_clock(addr, subnet, duration, hw_addr, cid, hostname, Arc::new(SystemClock))
+    }
+
+    /// Create a new `LeaseV4` whose times are
+    /// computed from the given [`Clock`].
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let clock = MockClock::new(Utc::now());
+    /// let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// let lease = LeaseV4::with_clock(
+    ///     Ipv4Addr::new(192, 168, 0, 3),
+    ///     &subnet,
+    ///     Duration::hours(8),
+    ///     HardwareAddress::broadcast(),
+    ///     HardwareAddress::broadcast(),
+    ///     String::from("test_lease"),
+    ///     Arc::new(clock.clone()),
+    /// );
+    /// clock.advance(Duration::hours(9));
+    /// assert!(lease.remaining() == Duration::zero());
+    /// ```
+
+    pub fn with_clock(
+        addr: Ipv4Addr,
+        subnet: &'a Ipv4Subnet,
+        duration: Duration,
+        hw_addr: HardwareAddress,
+        cid: HardwareAddress,
+        hostname: String,
+        clock: Arc<dyn Clock>
+    ) -> Result<Self, ()> {
 
         if !subnet.contains(addr) { return Err(()); };
 
-        let t_begin = Utc::now();
+        let t_begin = clock.now();
         let t_end = t_begin + duration;
-        Ok(Self { addr, subnet, t_begin, t_end, hw_addr, cid, hostname })
+        Ok(Self { addr, subnet, t_begin, t_end, hw_addr, cid, hostname, clock })
     }
 
     /// Returns the remaining [`Duration`] on the `LeaseV4`
@@ -80,8 +114,9 @@ impl<'a> LeaseV4<'a> {
     pub fn remaining(
         &self
     ) -> Duration {
-        if self.t_end - Utc::now() < Duration::zero() { return Duration::zero(); };
-        self.t_end - Utc::now()
+        let now = self.clock.now();
+        if self.t_end - now < Duration::zero() { return Duration::zero(); };
+        self.t_end - now
     }
 
     /// Extends the remaining [`Duration`] on this `LeaseV4`
@@ -108,12 +143,48 @@ impl<'a> LeaseV4<'a> {
         &mut self,
         time_to_add: Duration
     ) -> Result<(), ()> {
-        if Utc::now() > self.t_end { return Err(()); };
+        if self.clock.now() > self.t_end { return Err(()); };
         self.t_end += time_to_add;
 
         Ok(())
     }
 
+    /// Renews this `LeaseV4` so that it ends after the
+    /// given [`Duration`], starting from now.
+    ///
+    /// Unlike [`LeaseV4::extend`], the remaining time is
+    /// not kept : a renewed lease always lasts for the
+    /// lease time granted in the last DHCPACK.
+    ///
+    /// Returns an error if the lease already expired.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+    /// let mut lease = LeaseV4::new(
+    ///     Ipv4Addr::new(192, 168, 0, 3),
+    ///     subnet,
+    ///     Duration::hours(8),
+    ///     HardwareAddress::broadcast(),
+    ///     HardwareAddress::broadcast(),
+    ///     String::from("test_lease"),
+    /// );
+    /// lease.renew(Duration::hours(2))
+    /// assert!(lease.remaining() <= Duration::hours(2));
+    /// ```
+
+    pub fn renew(
+        &mut self,
+        duration: Duration
+    ) -> Result<(), ()> {
+        let now = self.clock.now();
+        if now > self.t_end { return Err(()); };
+        self.t_end = now + duration;
+
+        Ok(())
+    }
+
     pub fn hostname(
         &self
     ) -> &str {
@@ -150,6 +221,10 @@ impl<'a> LeaseV4<'a> {
         self.subnet
     }
 
+    pub fn begin(&self) -> DateTime<Utc>{
+        self.t_begin
+    }
+
     pub fn end(&self) -> DateTime<Utc>{
         self.t_end
     }
@@ -158,6 +233,8 @@ impl<'a> LeaseV4<'a> {
 #[cfg(test)]
 mod tests {
 
+    use crate::clock::clock::MockClock;
+
     use super::*;
 
     #[test]
@@ -193,4 +270,46 @@ mod tests {
         assert!(lease.remaining() > Duration::hours(9));
     }
 
+    #[test]
+    fn test_lease_renew() {
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let mut lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 3),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+        ).unwrap();
+
+        lease.renew(Duration::hours(2)).unwrap();
+        assert!(lease.remaining() <= Duration::hours(2));
+        assert!(lease.remaining() > Duration::hours(1));
+    }
+
+    #[test]
+    fn test_lease_expiry() {
+        let clock = MockClock::new(Utc::now());
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let mut lease = LeaseV4::with_clock(
+            Ipv4Addr::new(192, 168, 0, 3),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+            Arc::new(clock.clone()),
+        ).unwrap();
+
+        assert!(lease.remaining() == Duration::hours(8));
+        clock.advance(Duration::hours(7));
+        assert!(lease.remaining() == Duration::hours(1));
+        lease.renew(Duration::hours(2)).unwrap();
+        assert!(lease.remaining() == Duration::hours(2));
+        clock.advance(Duration::hours(3));
+        assert!(lease.remaining() == Duration::zero());
+        assert!(lease.extend(Duration::hours(2)).is_err());
+        assert!(lease.renew(Duration::hours(2)).is_err());
+    }
+
 }
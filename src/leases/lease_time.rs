@@ -0,0 +1,169 @@
+use serde::{Serialize, Deserialize};
+
+use crate::packet::dhcp_options::DhcpOptions;
+
+const DEFAULT_LEASE_TIME: u32 = 86400;
+const MIN_LEASE_TIME: u32 = 300;
+const MAX_LEASE_TIME: u32 = 604800;
+/// Lease time meaning "infinity" (RFC 2131), granted
+/// to clients that never renew such as BOOTP ones
+pub const INFINITE_LEASE_TIME: u32 = u32::MAX;
+
+/// `LeaseTimes` holds the lease duration policy of
+/// a given subnet, expressed in seconds.
+///
+/// The lease time requested by a client (option 51)
+/// is clamped between `min_lease_time` and `max_lease_time`,
+/// and `default_lease_time` is used when the client
+/// did not request anything.
+///
+/// Renewal (T1) and rebinding (T2) times are derived
+/// from the resulting lease time, as recommended by
+/// RFC 2131 : T1 = 0.5 * L and T2 = 0.875 * L.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LeaseTimes {
+    pub default_lease_time: u32,
+    pub min_lease_time: u32,
+    pub max_lease_time: u32,
+}
+
+impl Default for LeaseTimes {
+    fn default() -> Self {
+        Self {
+            default_lease_time: DEFAULT_LEASE_TIME,
+            min_lease_time: MIN_LEASE_TIME,
+            max_lease_time: MAX_LEASE_TIME,
+        }
+    }
+}
+
+impl LeaseTimes {
+
+    pub fn new(
+        default_lease_time: u32,
+        min_lease_time: u32,
+        max_lease_time: u32
+    ) -> Self {
+        Self { default_lease_time, min_lease_time, max_lease_time }
+    }
+
+    /// Returns the lease time that should be granted
+    /// to a client that requested `requested` seconds.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let times = LeaseTimes::new(3600, 600, 7200);
+    /// assert!(times.lease_time(None) == 3600);
+    /// assert!(times.lease_time(Some(60)) == 600);
+    /// assert!(times.lease_time(Some(86400)) == 7200);
+    /// ```
+    pub fn lease_time(
+        &self,
+        requested: Option<u32>
+    ) -> u32 {
+        let lease_time = requested.unwrap_or(self.default_lease_time);
+        lease_time
+            .min(self.max_lease_time)
+            .max(self.min_lease_time)
+    }
+
+    /// Returns the default renewal time (T1) for
+    /// a given lease time.
+    pub fn renewal_time(
+        lease_time: u32
+    ) -> u32 {
+        lease_time / 2
+    }
+
+    /// Returns the default rebinding time (T2) for
+    /// a given lease time.
+    pub fn rebinding_time(
+        lease_time: u32
+    ) -> u32 {
+        ((lease_time as u64 * 7) / 8) as u32
+    }
+
+    /// Fills the lease time, renewal time and rebinding
+    /// time of the given [`DhcpOptions`], according to the
+    /// lease time requested by the client.
+    ///
+    /// Renewal and rebinding times that are already set in
+    /// the options (usually coming from the subnet configuration)
+    /// are kept, as long as they are consistent with
+    /// the granted lease time (T1 < T2 < L).
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let times = LeaseTimes::new(3600, 600, 7200);
+    /// let mut options = DhcpOptions::new();
+    /// times.apply(None, &mut options);
+    /// assert!(options.lease_time() == Some(3600));
+    /// assert!(options.renewal_time() == Some(1800));
+    /// assert!(options.rebinding_time() == Some(3150));
+    /// ```
+    pub fn apply(
+        &self,
+        requested: Option<u32>,
+        options: &mut DhcpOptions
+    ) {
+        let lease_time = self.lease_time(requested);
+
+        let renewal_time = options.renewal_time()
+            .filter(|t1| *t1 < lease_time)
+            .unwrap_or(Self::renewal_time(lease_time));
+
+        let rebinding_time = options.rebinding_time()
+            .filter(|t2| (*t2 > renewal_time) & (*t2 < lease_time))
+            .unwrap_or(Self::rebinding_time(lease_time).max(renewal_time));
+
+        options.set_lease_time(Some(lease_time));
+        options.set_renewal_time(Some(renewal_time));
+        options.set_rebinding_time(Some(rebinding_time));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_time_clamp() {
+        let times = LeaseTimes::new(3600, 600, 7200);
+        assert!(times.lease_time(None) == 3600);
+        assert!(times.lease_time(Some(60)) == 600);
+        assert!(times.lease_time(Some(5000)) == 5000);
+        assert!(times.lease_time(Some(86400)) == 7200);
+    }
+
+    #[test]
+    fn test_default_timers() {
+        let times = LeaseTimes::new(3600, 600, 7200);
+        let mut options = DhcpOptions::new();
+        times.apply(Some(4000), &mut options);
+        assert!(options.lease_time() == Some(4000));
+        assert!(options.renewal_time() == Some(2000));
+        assert!(options.rebinding_time() == Some(3500));
+    }
+
+    #[test]
+    fn test_overridden_timers() {
+        let times = LeaseTimes::new(3600, 600, 7200);
+        let mut options = DhcpOptions::new();
+        options.set_renewal_time(Some(1000));
+        options.set_rebinding_time(Some(2000));
+        times.apply(None, &mut options);
+        assert!(options.renewal_time() == Some(1000));
+        assert!(options.rebinding_time() == Some(2000));
+
+        // Overrides that do not fit in the granted lease are dropped
+        let mut options = DhcpOptions::new();
+        options.set_renewal_time(Some(5000));
+        times.apply(Some(600), &mut options);
+        assert!(options.renewal_time() == Some(300));
+        assert!(options.rebinding_time() == Some(525));
+    }
+
+}
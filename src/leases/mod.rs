@@ -1,2 +1,7 @@
 pub mod ip_subnet;
 pub mod lease;
+pub mod lease_time;
+pub mod selection;
+pub mod boot;
+pub mod bootp;
+pub mod hostname;
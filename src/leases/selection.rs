@@ -0,0 +1,251 @@
+//! Strategies choosing which free address of an
+//! [`Ipv4Subnet`] pool is handed out next.
+
+use std::net::Ipv4Addr;
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use super::ip_subnet::Ipv4Subnet;
+
+// Random picks landing on an address taken out of order
+// are retried a few times before falling back to a sequential pick
+const MAX_RANDOM_ATTEMPTS: usize = 16;
+
+/// A `SelectionStrategy` picks a free address in the
+/// dynamic pool of an [`Ipv4Subnet`], for a given client.
+///
+/// Strategies only choose : the address is taken
+/// from the subnet by [`Ipv4Subnet::allocate_for`].
+pub trait SelectionStrategy: Send + Sync {
+    fn select(&self, subnet: &Ipv4Subnet, client_id: &[u8]) -> Option<Ipv4Addr>;
+}
+
+/// Reuses the most recently released address first,
+/// otherwise walks the pool in ascending order.
+pub struct SequentialSelection;
+
+/// Picks a uniformly random free address of the pool.
+pub struct RandomSelection;
+
+/// Hands out never allocated addresses first, then the
+/// address released the longest time ago, maximising the
+/// time before an address is reused.
+pub struct LeastRecentlyUsedSelection;
+
+/// Derives the address from a hash of the client identifier,
+/// so that a client is always offered the same address as long
+/// as it is free. Collisions are resolved by probing the next
+/// addresses of the pool.
+pub struct ClientHashSelection;
+
+impl SelectionStrategy for SequentialSelection {
+    fn select(&self, subnet: &Ipv4Subnet, _client_id: &[u8]) -> Option<Ipv4Addr> {
+        subnet.released()
+            .iter()
+            .rev()
+            .find(|ip| subnet.is_free(**ip))
+            .copied()
+            .or_else(|| subnet.next_fresh())
+    }
+}
+
+impl SelectionStrategy for RandomSelection {
+    fn select(&self, subnet: &Ipv4Subnet, client_id: &[u8]) -> Option<Ipv4Addr> {
+        let released = subnet.released();
+        let (fresh_start, fresh_count) = match subnet.fresh_bounds() {
+            Some((start, end)) => (u32::from(start), u32::from(end) - u32::from(start) + 1),
+            None => (0, 0)
+        };
+        let total = released.len() as u32 + fresh_count;
+        if total == 0 {
+            return None;
+        };
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..MAX_RANDOM_ATTEMPTS {
+            let pick = rng.gen_range(0..total);
+            let ip = match (pick as usize) < released.len() {
+                true => released[pick as usize],
+                false => Ipv4Addr::from(fresh_start + pick - released.len() as u32)
+            };
+            if subnet.is_free(ip) {
+                return Some(ip);
+            };
+        }
+        SequentialSelection.select(subnet, client_id)
+    }
+}
+
+impl SelectionStrategy for LeastRecentlyUsedSelection {
+    fn select(&self, subnet: &Ipv4Subnet, _client_id: &[u8]) -> Option<Ipv4Addr> {
+        subnet.next_fresh().or_else(|| {
+            subnet.released()
+                .iter()
+                .find(|ip| subnet.is_free(**ip))
+                .copied()
+        })
+    }
+}
+
+/// 32 bits FNV-1a, stable across builds and platforms
+fn _fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+impl SelectionStrategy for ClientHashSelection {
+    fn select(&self, subnet: &Ipv4Subnet, client_id: &[u8]) -> Option<Ipv4Addr> {
+        let start = u32::from(subnet.pool_start());
+        let size = u32::from(subnet.pool_end()).checked_sub(start)? + 1;
+        let offset = _fnv1a(client_id) % size;
+
+        (0..size)
+            .map(|i| Ipv4Addr::from(start + (offset + i) % size))
+            .find(|ip| subnet.is_free(*ip))
+    }
+}
+
+/// Selection strategy of a subnet, as written in
+/// the subnets configuration file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionKind {
+    #[default]
+    Sequential,
+    Random,
+    LeastRecentlyUsed,
+    ClientHash,
+}
+
+impl SelectionKind {
+
+    /// Returns the [`SelectionStrategy`] implementing this kind.
+    pub fn strategy(&self) -> &'static dyn SelectionStrategy {
+        match self {
+            SelectionKind::Sequential => &SequentialSelection,
+            SelectionKind::Random => &RandomSelection,
+            SelectionKind::LeastRecentlyUsed => &LeastRecentlyUsedSelection,
+            SelectionKind::ClientHash => &ClientHashSelection,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn subnet_with(selection: SelectionKind) -> Ipv4Subnet {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        subnet.set_selection(selection);
+        subnet
+    }
+
+    // Share of the addresses falling in each quarter of a /24
+    fn quarters(ips: &[Ipv4Addr]) -> [usize; 4] {
+        let mut quarters = [0; 4];
+        for ip in ips {
+            quarters[(ip.octets()[3] / 64) as usize] += 1;
+        }
+        quarters
+    }
+
+    #[test]
+    fn test_sequential_selection() {
+        let mut subnet = subnet_with(SelectionKind::Sequential);
+        let allocated: Vec<Ipv4Addr> = (0..4).map(|_| subnet.allocate().unwrap()).collect();
+        assert!(allocated == (1..=4).map(|i| Ipv4Addr::new(192, 168, 0, i)).collect::<Vec<Ipv4Addr>>());
+
+        // Most recently released address is reused first
+        subnet.free(Ipv4Addr::new(192, 168, 0, 1)).unwrap();
+        subnet.free(Ipv4Addr::new(192, 168, 0, 3)).unwrap();
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 3));
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 1));
+        assert!(subnet.allocate().unwrap() == Ipv4Addr::new(192, 168, 0, 5));
+    }
+
+    #[test]
+    fn test_random_selection() {
+        let mut subnet = subnet_with(SelectionKind::Random);
+        let allocated: Vec<Ipv4Addr> = (0..254).map(|_| subnet.allocate().unwrap()).collect();
+
+        // Whole pool is eventually handed out, without duplicates
+        let unique: HashSet<Ipv4Addr> = allocated.iter().copied().collect();
+        assert!(unique.len() == 254);
+        assert!(subnet.allocate().is_err());
+
+        // First picks are spread over the pool rather than sequential :
+        // about 25 per quarter, bounds lying over 5 standard deviations away
+        let first_picks = quarters(&allocated[..100]);
+        assert!(first_picks.iter().all(|count| (5..=50).contains(count)));
+        assert!(allocated[..100].windows(2).filter(|pair| u32::from(pair[1]) == u32::from(pair[0]) + 1).count() < 20);
+    }
+
+    #[test]
+    fn test_random_selection_distribution() {
+        let mut subnet = subnet_with(SelectionKind::Random);
+        let picks: Vec<Ipv4Addr> = (0..4000).map(|_| {
+            let ip = subnet.allocate().unwrap();
+            subnet.free(ip).unwrap();
+            ip
+        }).collect();
+
+        for count in quarters(&picks) {
+            assert!((800..=1200).contains(&count));
+        }
+    }
+
+    #[test]
+    fn test_lru_selection() {
+        let mut subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 29);
+        subnet.set_selection(SelectionKind::LeastRecentlyUsed);
+        let first = subnet.allocate().unwrap();
+        subnet.free(first).unwrap();
+
+        // Never allocated addresses come before released ones
+        let allocated: Vec<Ipv4Addr> = (0..5).map(|_| subnet.allocate().unwrap()).collect();
+        assert!(!allocated.contains(&first));
+        assert!(subnet.allocate().unwrap() == first);
+
+        // Then the address released the longest time ago
+        for i in [4, 2, 6] {
+            subnet.free(Ipv4Addr::new(192, 168, 0, i)).unwrap();
+        }
+        let reused: Vec<Ipv4Addr> = (0..3).map(|_| subnet.allocate().unwrap()).collect();
+        assert!(reused == vec![Ipv4Addr::new(192, 168, 0, 4), Ipv4Addr::new(192, 168, 0, 2), Ipv4Addr::new(192, 168, 0, 6)]);
+    }
+
+    #[test]
+    fn test_client_hash_selection() {
+        let mut subnet = subnet_with(SelectionKind::ClientHash);
+        let mut other = subnet_with(SelectionKind::ClientHash);
+
+        // Same client, same address
+        let ip = subnet.allocate_for(b"client-1").unwrap();
+        assert!(other.allocate_for(b"client-1").unwrap() == ip);
+        subnet.free(ip).unwrap();
+        assert!(subnet.allocate_for(b"client-1").unwrap() == ip);
+
+        // Collisions are resolved to another free address
+        let mut colliding = subnet_with(SelectionKind::ClientHash);
+        colliding.force_allocate(ip).unwrap();
+        let moved = colliding.allocate_for(b"client-1").unwrap();
+        assert!(moved != ip);
+        assert!(colliding.pool_start() <= moved && moved <= colliding.pool_end());
+    }
+
+    #[test]
+    fn test_client_hash_distribution() {
+        let subnet = subnet_with(SelectionKind::ClientHash);
+        let picks: Vec<Ipv4Addr> = (0..4000u32)
+            .map(|i| ClientHashSelection.select(&subnet, &i.to_be_bytes()).unwrap())
+            .collect();
+
+        for count in quarters(&picks) {
+            assert!((800..=1200).contains(&count));
+        }
+    }
+}
@@ -9,6 +9,11 @@ mod allocators;
 mod transactions;
 mod data;
 mod cfg;
+mod clock;
+mod classes;
+mod ddns;
+mod leasequery;
+mod failover;
 
 
 fn main() {
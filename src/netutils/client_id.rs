@@ -0,0 +1,133 @@
+use super::hw_addr::HardwareAddress;
+
+const DUID_TYPE: u8 = 255;
+
+/// Client identifier (option 61) of a DHCP client,
+/// decoded according to its type byte.
+///
+/// - type 255 holds an IAID followed by a DUID (RFC 4361)
+/// - types 1 to 254 hold a hardware address of the given
+/// hardware type, usually the MAC of the client (RFC 2132)
+/// - anything else is kept as opaque bytes
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ClientId {
+    Duid { iaid: u32, duid: Vec<u8> },
+    HwAddr { htype: u8, addr: Vec<u8> },
+    Opaque(Vec<u8>),
+}
+
+impl ClientId {
+
+    /// Decodes the raw content of a client identifier option.
+    /// Returns `None` if the identifier is empty. Identifiers
+    /// shorter than the two bytes required by RFC 2132 are
+    /// still kept, as opaque bytes.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let cid = ClientId::parse(&[0x01, 0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08]).unwrap();
+    /// assert!(cid == ClientId::HwAddr { htype: 1, addr: vec![0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08] });
+    /// ```
+    pub fn parse(
+        raw: &[u8]
+    ) -> Option<Self> {
+        let (id_type, content) = raw.split_first()?;
+        if content.is_empty() {
+            return Some(ClientId::Opaque(raw.to_vec()));
+        };
+
+        match *id_type {
+            // A DUID is at least made of its 2 bytes type and 1 byte of data
+            DUID_TYPE if content.len() >= 7 => {
+                let iaid = u32::from_be_bytes(content[..4].try_into().ok()?);
+                Some(ClientId::Duid { iaid, duid: content[4..].to_vec() })
+            },
+            htype @ 1..=254 if content.len() <= 16 => {
+                Some(ClientId::HwAddr { htype, addr: content.to_vec() })
+            },
+            _ => Some(ClientId::Opaque(raw.to_vec()))
+        }
+    }
+
+    /// Returns the hardware address embedded in a type-prefixed
+    /// client identifier, if any.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let cid = ClientId::parse(&[0x01, 0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08]).unwrap();
+    /// assert!(cid.hardware_address().unwrap().raw[..6] == [0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08]);
+    /// ```
+    pub fn hardware_address(
+        &self
+    ) -> Option<HardwareAddress> {
+        match self {
+            ClientId::HwAddr { addr, .. } => HardwareAddress::from_slice(addr),
+            _ => None
+        }
+    }
+
+    /// Encodes the client identifier back to the
+    /// content of option 61.
+    pub fn to_bytes(
+        &self
+    ) -> Vec<u8> {
+        match self {
+            ClientId::Duid { iaid, duid } => {
+                let mut bytes = vec![DUID_TYPE];
+                bytes.extend_from_slice(&iaid.to_be_bytes());
+                bytes.extend_from_slice(duid);
+                bytes
+            },
+            ClientId::HwAddr { htype, addr } => {
+                let mut bytes = vec![*htype];
+                bytes.extend_from_slice(addr);
+                bytes
+            },
+            ClientId::Opaque(bytes) => bytes.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mac() {
+        let raw = [0x01, 0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08];
+        let cid = ClientId::parse(&raw).unwrap();
+        assert!(cid == ClientId::HwAddr { htype: 1, addr: raw[1..].to_vec() });
+        assert!(cid.hardware_address().unwrap() == HardwareAddress::from_slice(&raw[1..]).unwrap());
+        assert!(cid.to_bytes() == raw);
+    }
+
+    #[test]
+    fn test_parse_duid() {
+        // IAID 0x0c0d0e0f, DUID-LL of an ethernet interface
+        let raw = [0xff, 0x0c, 0x0d, 0x0e, 0x0f, 0x00, 0x03, 0x00, 0x01, 0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08];
+        let cid = ClientId::parse(&raw).unwrap();
+        assert!(cid == ClientId::Duid { iaid: 0x0c0d0e0f, duid: raw[5..].to_vec() });
+        assert!(cid.hardware_address().is_none());
+        assert!(cid.to_bytes() == raw);
+    }
+
+    #[test]
+    fn test_parse_opaque() {
+        // Type 0 identifiers are not hardware addresses
+        let raw = b"\x00my-client".to_vec();
+        let cid = ClientId::parse(&raw).unwrap();
+        assert!(cid == ClientId::Opaque(raw.clone()));
+        assert!(cid.hardware_address().is_none());
+        assert!(cid.to_bytes() == raw);
+
+        // Truncated DUID, too long hardware address, single byte
+        let truncated = [0xff, 0x0c, 0x0d];
+        assert!(ClientId::parse(&truncated).unwrap() == ClientId::Opaque(truncated.to_vec()));
+        let long = [0x01; 18];
+        assert!(ClientId::parse(&long).unwrap() == ClientId::Opaque(long.to_vec()));
+        assert!(ClientId::parse(&[0x01]).unwrap() == ClientId::Opaque(vec![0x01]));
+        assert!(ClientId::parse(&[]).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! Detection of addresses already in use on the network,
+//! before they get offered to a client.
+
+use std::{future::Future, io, net::{IpAddr, Ipv4Addr}, pin::Pin, time::{Duration, Instant}};
+
+use log::warn;
+use pnet::{packet::{Packet, icmp::{IcmpPacket, IcmpTypes, echo_reply::EchoReplyPacket, echo_request::MutableEchoRequestPacket}, ip::IpNextHeaderProtocols}, transport::{transport_channel, icmp_packet_iter, TransportChannelType::Layer4, TransportProtocol::Ipv4}};
+
+const ECHO_REQUEST_SIZE: usize = 16;
+const ECHO_IDENTIFIER: u16 = 0x6468;
+const ECHO_SEQUENCE: u16 = 1;
+
+/// A `ConflictProbe` checks whether an address is already
+/// used by a host of the network, usually one that was
+/// statically configured.
+///
+/// Probing is asynchronous, so that a slow or silent network
+/// never blocks the server. Tests use a stub implementation
+/// instead of the network.
+pub trait ConflictProbe: Send + Sync {
+    /// Returns true if a host answered on the given address
+    fn probe(&self, ip: Ipv4Addr) -> Pin<Box<dyn Future<Output = bool> + Send + '_>>;
+}
+
+/// `IcmpProbe` sends an ICMP echo request to the probed
+/// address, and reports a conflict if a reply comes back
+/// before the timeout.
+///
+/// Raw sockets require elevated privileges : if the probe
+/// cannot be sent, the address is considered free.
+///
+/// # Examples:
+///
+/// ```
+/// let probe = IcmpProbe::new(Duration::from_millis(500));
+/// let in_use = probe.probe(Ipv4Addr::new(192, 168, 0, 3)).await;
+/// ```
+pub struct IcmpProbe {
+    timeout: Duration,
+}
+
+impl IcmpProbe {
+
+    pub fn new(
+        timeout: Duration
+    ) -> Self {
+        Self { timeout }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn ping(
+        ip: Ipv4Addr,
+        timeout: Duration
+    ) -> Result<bool, io::Error> {
+        let (mut tx, mut rx) = transport_channel(
+            1024,
+            Layer4(Ipv4(IpNextHeaderProtocols::Icmp))
+        )?;
+
+        let mut buffer = [0u8; ECHO_REQUEST_SIZE];
+        let mut echo_request = MutableEchoRequestPacket::new(&mut buffer)
+            .ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        echo_request.set_icmp_type(IcmpTypes::EchoRequest);
+        echo_request.set_identifier(ECHO_IDENTIFIER);
+        echo_request.set_sequence_number(ECHO_SEQUENCE);
+        let checksum = pnet::packet::icmp::checksum(
+            &IcmpPacket::new(echo_request.packet())
+                .ok_or(io::Error::from(io::ErrorKind::InvalidData))?
+        );
+        echo_request.set_checksum(checksum);
+        tx.send_to(echo_request, IpAddr::V4(ip))?;
+
+        // Replies from other hosts, or to other requests, are skipped
+        let deadline = Instant::now() + timeout;
+        let mut replies = icmp_packet_iter(&mut rx);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            };
+            match replies.next_with_timeout(remaining)? {
+                Some((reply, addr)) => {
+                    if (addr == IpAddr::V4(ip)) & Self::answers_request(reply.packet()) {
+                        return Ok(true);
+                    };
+                }
+                None => return Ok(false)
+            }
+        }
+    }
+
+    /// Returns true if the given ICMP packet is an echo
+    /// reply to the request sent by [`IcmpProbe::ping`].
+    fn answers_request(
+        reply: &[u8]
+    ) -> bool {
+        match EchoReplyPacket::new(reply) {
+            Some(reply) => {
+                (reply.get_icmp_type() == IcmpTypes::EchoReply)
+                    & (reply.get_identifier() == ECHO_IDENTIFIER)
+                    & (reply.get_sequence_number() == ECHO_SEQUENCE)
+            }
+            None => false
+        }
+    }
+}
+
+impl ConflictProbe for IcmpProbe {
+    fn probe(&self, ip: Ipv4Addr) -> Pin<Box<dyn Future<Output = bool> + Send + '_>> {
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let result = tokio::task::spawn_blocking(move || Self::ping(ip, timeout)).await;
+            match result {
+                Ok(Ok(in_use)) => in_use,
+                Ok(Err(e)) => {
+                    warn!("Could not probe {} for conflicts : {}", ip, e);
+                    false
+                }
+                Err(e) => {
+                    warn!("Could not probe {} for conflicts : {}", ip, e);
+                    false
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use pnet::packet::icmp::echo_reply::MutableEchoReplyPacket;
+
+    use super::*;
+
+    fn echo_reply(identifier: u16, sequence: u16) -> [u8; ECHO_REQUEST_SIZE] {
+        let mut buffer = [0u8; ECHO_REQUEST_SIZE];
+        let mut reply = MutableEchoReplyPacket::new(&mut buffer).unwrap();
+        reply.set_icmp_type(IcmpTypes::EchoReply);
+        reply.set_identifier(identifier);
+        reply.set_sequence_number(sequence);
+        buffer
+    }
+
+    #[test]
+    fn test_answers_request() {
+        assert!(IcmpProbe::answers_request(&echo_reply(ECHO_IDENTIFIER, ECHO_SEQUENCE)));
+
+        // Replies to other pings of the same host are skipped
+        assert!(!IcmpProbe::answers_request(&echo_reply(0x1234, ECHO_SEQUENCE)));
+        assert!(!IcmpProbe::answers_request(&echo_reply(ECHO_IDENTIFIER, 2)));
+
+        let mut request = echo_reply(ECHO_IDENTIFIER, ECHO_SEQUENCE);
+        request[0] = IcmpTypes::EchoRequest.0;
+        assert!(!IcmpProbe::answers_request(&request));
+        assert!(!IcmpProbe::answers_request(&[]));
+    }
+}
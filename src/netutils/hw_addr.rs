@@ -31,4 +31,15 @@ impl HardwareAddress {
         Self { is_mac_address, raw: (raw) }
 
     }
+
+    /// Builds a `HardwareAddress` from a slice of at most
+    /// 16 bytes, padded with zeros.
+    pub fn from_slice(bytes : &[u8]) -> Option<Self> {
+        if bytes.len() > 16 {
+            return None;
+        }
+        let mut raw = [0u8; 16];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        Some(Self::new(raw))
+    }
 }
@@ -1 +1,3 @@
 pub mod hw_addr;
+pub mod client_id;
+pub mod conflict_probe;
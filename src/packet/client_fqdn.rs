@@ -0,0 +1,284 @@
+//! Implements `ClientFqdn`, the content of the Client
+//! Fully Qualified Domain Name option (81), defined
+//! in RFC 4702 ( <https://www.rfc-editor.org/rfc/rfc4702> )
+
+use serde::{Serialize, Deserialize};
+
+const FLAG_S: u8 = 0x01;
+const FLAG_O: u8 = 0x02;
+const FLAG_E: u8 = 0x04;
+const FLAG_N: u8 = 0x08;
+
+// RCODE1 and RCODE2 are deprecated : servers set them
+// to 255, clients to 0 (RFC 4702 section 2.2)
+const SERVER_RCODE: u8 = 255;
+const MAX_LABEL_SIZE: usize = 63;
+
+/// Update flags of the client FQDN option.
+///
+/// # Examples:
+///
+/// ```
+/// let flags = FqdnFlags::from(0x05);
+/// assert!(flags.server_update & flags.encoded);
+/// assert!(u8::from(flags) == 0x05);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FqdnFlags {
+    /// S : the server should update the A record
+    pub server_update: bool,
+    /// O : the server overrode the preference of the client
+    pub override_client: bool,
+    /// E : the name is in canonical wire format
+    pub encoded: bool,
+    /// N : the server should not update any record
+    pub no_update: bool,
+}
+
+impl From<u8> for FqdnFlags {
+    fn from(
+        value: u8
+    ) -> Self {
+        Self {
+            server_update: value & FLAG_S != 0,
+            override_client: value & FLAG_O != 0,
+            encoded: value & FLAG_E != 0,
+            no_update: value & FLAG_N != 0,
+        }
+    }
+}
+
+impl From<FqdnFlags> for u8 {
+    fn from(flags: FqdnFlags) -> Self {
+        [(flags.server_update, FLAG_S), (flags.override_client, FLAG_O), (flags.encoded, FLAG_E), (flags.no_update, FLAG_N)]
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |value, (_, flag)| value | flag)
+    }
+}
+
+/// `ClientFqdn` holds the name of a client along with
+/// who should publish it in the DNS.
+///
+/// The name is either fully qualified, or partial (a
+/// single label in practice) to be completed by the server.
+/// It is kept without its trailing dot, and encoded back
+/// in the format the client used.
+///
+/// # Examples:
+///
+/// ```
+/// let fqdn = ClientFqdn::try_from([0x05, 0x00, 0x00, 0x04, b'h', b'o', b's', b't'].as_slice()).unwrap();
+/// assert!(fqdn.domain_name() == "host");
+/// assert!(!fqdn.is_fully_qualified());
+/// assert!(fqdn.flags().server_update);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct ClientFqdn {
+    flags: FqdnFlags,
+    rcode1: u8,
+    rcode2: u8,
+    domain_name: String,
+    fully_qualified: bool,
+}
+
+// Decodes a name in canonical wire format, which has no
+// terminating root label when the name is partial
+fn _decode_labels(bytes: &[u8]) -> Result<(String, bool), ()> {
+    let mut labels = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let len = bytes[pos] as usize;
+        if len == 0 {
+            return Ok((labels.join("."), true));
+        };
+        // Compression is not allowed in this option
+        if (len > MAX_LABEL_SIZE) | (pos + 1 + len > bytes.len()) {
+            return Err(());
+        };
+        labels.push(String::from_utf8_lossy(&bytes[pos + 1..pos + 1 + len]).into_owned());
+        pos += 1 + len;
+    }
+    Ok((labels.join("."), false))
+}
+
+impl TryFrom<&[u8]> for ClientFqdn {
+    type Error = ();
+
+    fn try_from(
+        value: &[u8]
+    ) -> Result<Self, ()> {
+        if value.len() < 3 {
+            return Err(());
+        };
+        let flags = FqdnFlags::from(value[0]);
+        let (domain_name, fully_qualified) = match flags.encoded {
+            true => _decode_labels(&value[3..])?,
+            // Deprecated ASCII encoding, which may carry a trailing NUL
+            false => {
+                let name = String::from_utf8_lossy(&value[3..]);
+                let name = name.trim_end_matches('\0');
+                (name.trim_end_matches('.').to_string(), name.ends_with('.'))
+            }
+        };
+        Ok(Self { flags, rcode1: value[1], rcode2: value[2], domain_name, fully_qualified })
+    }
+}
+
+impl From<&ClientFqdn> for Vec<u8> {
+    fn from(fqdn: &ClientFqdn) -> Self {
+        let mut buf = vec![u8::from(fqdn.flags), fqdn.rcode1, fqdn.rcode2];
+        if fqdn.domain_name.is_empty() {
+            return buf;
+        };
+        match fqdn.flags.encoded {
+            true => {
+                for label in fqdn.domain_name.split('.').filter(|label| !label.is_empty()) {
+                    let label = &label.as_bytes()[..label.len().min(MAX_LABEL_SIZE)];
+                    buf.push(label.len() as u8);
+                    buf.extend_from_slice(label);
+                }
+                if fqdn.fully_qualified {
+                    buf.push(0);
+                };
+            },
+            false => {
+                buf.extend_from_slice(fqdn.domain_name.as_bytes());
+                if fqdn.fully_qualified {
+                    buf.push(b'.');
+                };
+            }
+        };
+        buf
+    }
+}
+
+impl ClientFqdn {
+
+    /// Creates a `ClientFqdn` sent by a client, the name
+    /// being fully qualified when it ends with a dot.
+    pub fn new(
+        flags: FqdnFlags,
+        domain_name: &str
+    ) -> Self {
+        Self {
+            flags,
+            rcode1: 0,
+            rcode2: 0,
+            domain_name: domain_name.trim_end_matches('.').to_string(),
+            fully_qualified: domain_name.ends_with('.'),
+        }
+    }
+
+    /// Returns the option answering this one, with the fully
+    /// qualified name given to the client and whether the
+    /// server updates its A record (RFC 4702 section 3.2).
+    ///
+    /// The name is encoded like the client did.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let request = ClientFqdn::new(FqdnFlags { encoded: true, ..Default::default() }, "host");
+    /// let reply = request.reply("host.example.com", true);
+    /// assert!(reply.flags().server_update & reply.flags().override_client);
+    /// assert!(reply.is_fully_qualified());
+    /// ```
+    pub fn reply(
+        &self,
+        domain_name: &str,
+        server_update: bool
+    ) -> Self {
+        let flags = FqdnFlags {
+            server_update,
+            override_client: server_update != self.flags.server_update,
+            encoded: self.flags.encoded,
+            no_update: !server_update & self.flags.no_update,
+        };
+        Self {
+            flags,
+            rcode1: SERVER_RCODE,
+            rcode2: SERVER_RCODE,
+            domain_name: domain_name.trim_end_matches('.').to_string(),
+            fully_qualified: true,
+        }
+    }
+
+    pub fn flags(
+        &self
+    ) -> FqdnFlags {
+        self.flags
+    }
+
+    pub fn domain_name(
+        &self
+    ) -> &str {
+        &self.domain_name
+    }
+
+    pub fn is_fully_qualified(
+        &self
+    ) -> bool {
+        self.fully_qualified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_fqdn_roundtrip() {
+        let bytes = [
+            0x05, 0x00, 0x00,
+            0x04, b'h', b'o', b's', b't',
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+            0x03, b'c', b'o', b'm', 0x00
+        ];
+        let fqdn = ClientFqdn::try_from(bytes.as_slice()).unwrap();
+        assert!(fqdn.domain_name() == "host.example.com");
+        assert!(fqdn.is_fully_qualified());
+        assert!(fqdn.flags() == FqdnFlags { server_update: true, encoded: true, ..Default::default() });
+        assert!(Vec::from(&fqdn) == bytes.to_vec());
+
+        // Partial names have no root label
+        let fqdn = ClientFqdn::try_from(&bytes[..8]).unwrap();
+        assert!(fqdn.domain_name() == "host");
+        assert!(!fqdn.is_fully_qualified());
+        assert!(Vec::from(&fqdn) == bytes[..8].to_vec());
+    }
+
+    #[test]
+    fn test_ascii_client_fqdn() {
+        let fqdn = ClientFqdn::try_from([0x00, 0x00, 0x00, b'h', b'o', b's', b't', b'.', 0x00].as_slice()).unwrap();
+        assert!(fqdn.domain_name() == "host");
+        assert!(fqdn.is_fully_qualified());
+        assert!(!fqdn.flags().encoded);
+        assert!(Vec::from(&fqdn) == [0x00, 0x00, 0x00, b'h', b'o', b's', b't', b'.']);
+    }
+
+    #[test]
+    fn test_malformed_client_fqdn() {
+        assert!(ClientFqdn::try_from([0x04, 0x00].as_slice()).is_err());
+        // Truncated label
+        assert!(ClientFqdn::try_from([0x04, 0x00, 0x00, 0x05, b'h', b'o'].as_slice()).is_err());
+        // Compression pointer
+        assert!(ClientFqdn::try_from([0x04, 0x00, 0x00, 0xc0, 0x0c].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_reply_flags() {
+        // The client updates its A record itself
+        let request = ClientFqdn::new(FqdnFlags { encoded: true, ..Default::default() }, "host");
+        let reply = request.reply("host.example.com.", false);
+        assert!(u8::from(reply.flags()) == FLAG_E);
+        assert!(Vec::from(&reply)[..3] == [FLAG_E, 255, 255]);
+        assert!(reply.domain_name() == "host.example.com");
+
+        // The server overrides a client asking for no update
+        let request = ClientFqdn::new(FqdnFlags { no_update: true, ..Default::default() }, "host");
+        let reply = request.reply("host.example.com", true);
+        assert!(reply.flags() == FqdnFlags { server_update: true, override_client: true, ..Default::default() });
+        assert!(request.reply("host.example.com", false).flags().no_update);
+    }
+}
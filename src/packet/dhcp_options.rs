@@ -12,6 +12,11 @@ use log::trace;
 use serde::{Serialize, Deserialize};
 use serde_with::skip_serializing_none;
 
+use super::{client_fqdn::ClientFqdn, relay_agent_info::RelayAgentInfo};
+
+// DHCP extensions (RFC 2132 section 9), meaningless to BOOTP clients
+const DHCP_ONLY_OPTIONS: [u8; 12] = [50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61];
+
 /// `DhcpOptions` is used as an abstraction
 /// of the available set of options used by DHCP requests,
 /// defined in RFC 2132 ( <https://www.rfc-editor.org/rfc/rfc2132> )
@@ -88,6 +93,21 @@ pub struct DhcpOptions {
     client_identifier: Option<Vec<u8>>,
     interface_mtu: Option<u16>,
     ntp_servers: Option<Vec<Ipv4Addr>>,
+    vendor_class: Option<String>,
+    tftp_server_name: Option<String>,
+    bootfile_name: Option<String>,
+    user_class: Option<Vec<u8>>,
+    // Rapid commit (option 80) carries no data, only its presence matters
+    #[serde(skip)]
+    rapid_commit: Option<()>,
+    client_fqdn: Option<ClientFqdn>,
+    relay_agent_info: Option<RelayAgentInfo>,
+    client_last_transaction_time: Option<u32>,
+    associated_ip: Option<Vec<Ipv4Addr>>,
+    client_arch: Option<Vec<u16>>,
+    client_ndi: Option<[u8; 3]>,
+    status_code: Option<(u8, String)>,
+    base_time: Option<u32>,
     wpad: Option<String>
     
 
@@ -203,7 +223,8 @@ impl From<&[u8]> for DhcpOptions {
                 53 => {
                     let dhcp_code: u8 = data.first().unwrap().to_owned();
                     data.remove(0);
-                    if dhcp_code > 9 {
+                    // Up to DHCPLEASEQUERYDONE (RFC 6926)
+                    if dhcp_code > 15 {
                         trace!("Invalid DHCP Message type");
                         break;
                     }
@@ -227,10 +248,86 @@ impl From<&[u8]> for DhcpOptions {
                     let rebinding_time = data.drain(..len).as_slice().to_owned();
                     options.set_rebinding_time(Some(BigEndian::read_u32(rebinding_time.as_slice())));
                 }
+                60 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    options.set_vendor_class(
+                        _parse_string_type(&raw_bytes)
+                    );
+                }
                 61 => {
                     let client_id: Vec<u8> = data.drain(..len).collect();
                     options.set_client_identifier(Some(client_id));
                 }
+                66 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    options.set_tftp_server_name(
+                        _parse_string_type(&raw_bytes)
+                    );
+                }
+                67 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    options.set_bootfile_name(
+                        _parse_string_type(&raw_bytes)
+                    );
+                }
+                77 => {
+                    let user_class: Vec<u8> = data.drain(..len).collect();
+                    options.set_user_class(Some(user_class));
+                }
+                80 => {
+                    data.drain(..len);
+                    options.set_rapid_commit(true);
+                }
+                81 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    if let Ok(client_fqdn) = ClientFqdn::try_from(raw_bytes.as_slice()) {
+                        options.set_client_fqdn(Some(client_fqdn));
+                    };
+                }
+                82 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    options.set_relay_agent_info(
+                        Some(RelayAgentInfo::from(raw_bytes.as_slice()))
+                    );
+                }
+                91 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    if raw_bytes.len() == 4 {
+                        options.set_client_last_transaction_time(Some(BigEndian::read_u32(&raw_bytes)));
+                    };
+                }
+                92 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    options.set_associated_ip(Some(
+                        raw_bytes.chunks_exact(4).map(|addr| Ipv4Addr::from(BigEndian::read_u32(addr))).collect()
+                    ));
+                }
+                93 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    if raw_bytes.len() % 2 == 0 {
+                        options.set_client_arch(Some(
+                            raw_bytes.chunks_exact(2).map(BigEndian::read_u16).collect()
+                        ));
+                    };
+                }
+                94 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    if let Ok(client_ndi) = raw_bytes.try_into() {
+                        options.set_client_ndi(Some(client_ndi));
+                    };
+                }
+                151 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    if let Some((code, message)) = raw_bytes.split_first() {
+                        options.set_status_code(Some((*code, String::from_utf8_lossy(message).into_owned())));
+                    };
+                }
+                152 => {
+                    let raw_bytes: Vec<u8> = data.drain(..len).collect();
+                    if raw_bytes.len() == 4 {
+                        options.set_base_time(Some(BigEndian::read_u32(&raw_bytes)));
+                    };
+                }
                 252 => {
                     let raw_bytes: Vec<u8> = data.drain(..len).collect();
                     options.set_wpad(
@@ -335,7 +432,7 @@ fn _append_option(option_code: u8, options: &DhcpOptions, buffer: &mut Vec<u8>)
             buffer.extend_from_slice(&bytes);
         }
         42 => {
-            let mut bytes = _format_ipv4_list(options.time_server().unwrap());
+            let mut bytes = _format_ipv4_list(options.ntp_servers().unwrap());
             buffer.push(bytes.len() as u8);
             buffer.append(&mut bytes);
         }
@@ -373,11 +470,77 @@ fn _append_option(option_code: u8, options: &DhcpOptions, buffer: &mut Vec<u8>)
             buffer.push(4);
             buffer.extend_from_slice(&bytes);
         }
+        60 => {
+            let bytes = _format_string(options.vendor_class().unwrap());
+            buffer.push(bytes.len() as u8);
+            buffer.extend_from_slice(bytes);
+        }
         61 => {
             let bytes = options.client_identifier().unwrap();
             buffer.push(bytes.len() as u8);
             buffer.extend(bytes.iter());
         }
+        66 => {
+            let bytes = _format_string(options.tftp_server_name().unwrap());
+            buffer.push(bytes.len() as u8);
+            buffer.extend_from_slice(bytes);
+        }
+        67 => {
+            let bytes = _format_string(options.bootfile_name().unwrap());
+            buffer.push(bytes.len() as u8);
+            buffer.extend_from_slice(bytes);
+        }
+        77 => {
+            let bytes = options.user_class().unwrap();
+            buffer.push(bytes.len() as u8);
+            buffer.extend(bytes.iter());
+        }
+        80 => {
+            buffer.push(0);
+        }
+        81 => {
+            let mut bytes = Vec::from(options.client_fqdn().unwrap());
+            buffer.push(bytes.len() as u8);
+            buffer.append(&mut bytes);
+        }
+        82 => {
+            let mut bytes = Vec::from(options.relay_agent_info().unwrap());
+            buffer.push(bytes.len() as u8);
+            buffer.append(&mut bytes);
+        }
+        91 => {
+            let bytes = u32::to_be_bytes(options.client_last_transaction_time().unwrap());
+            buffer.push(4);
+            buffer.extend_from_slice(&bytes);
+        }
+        92 => {
+            let mut bytes = _format_ipv4_list(options.associated_ip().unwrap());
+            buffer.push(bytes.len() as u8);
+            buffer.append(&mut bytes);
+        }
+        93 => {
+            let bytes: Vec<u8> = options.client_arch().unwrap()
+                .iter()
+                .flat_map(|arch| arch.to_be_bytes())
+                .collect();
+            buffer.push(bytes.len() as u8);
+            buffer.extend_from_slice(&bytes);
+        }
+        94 => {
+            buffer.push(3);
+            buffer.extend_from_slice(&options.client_ndi().unwrap());
+        }
+        151 => {
+            let (code, message) = options.status_code().unwrap();
+            buffer.push(1 + message.len() as u8);
+            buffer.push(code);
+            buffer.extend_from_slice(message.as_bytes());
+        }
+        152 => {
+            let bytes = u32::to_be_bytes(options.base_time().unwrap());
+            buffer.push(4);
+            buffer.extend_from_slice(&bytes);
+        }
         252 => {
             let bytes = _format_string(options.wpad().unwrap());
             buffer.push(bytes.len() as u8);
@@ -412,6 +575,19 @@ impl DhcpOptions {
             client_identifier: None,
             interface_mtu: None,
             ntp_servers: None,
+            vendor_class: None,
+            tftp_server_name: None,
+            bootfile_name: None,
+            user_class: None,
+            rapid_commit: None,
+            client_fqdn: None,
+            relay_agent_info: None,
+            client_last_transaction_time: None,
+            associated_ip: None,
+            client_arch: None,
+            client_ndi: None,
+            status_code: None,
+            base_time: None,
             wpad: None,
         } 
     }    
@@ -701,6 +877,410 @@ impl DhcpOptions {
         self.defined_options.insert(42);
         self.ntp_servers = ntp_servers;
     }
+
+    pub fn vendor_class(
+        &self
+    ) -> Option<&String> {
+        self.vendor_class.as_ref()
+    }
+
+    pub fn set_vendor_class(
+        &mut self,
+        vendor_class: Option<String>
+    ) {
+        self.defined_options.insert(60);
+        self.vendor_class = vendor_class;
+    }
+
+    pub fn tftp_server_name(
+        &self
+    ) -> Option<&String> {
+        self.tftp_server_name.as_ref()
+    }
+
+    pub fn set_tftp_server_name(
+        &mut self,
+        tftp_server_name: Option<String>
+    ) {
+        self.defined_options.insert(66);
+        self.tftp_server_name = tftp_server_name;
+    }
+
+    pub fn bootfile_name(
+        &self
+    ) -> Option<&String> {
+        self.bootfile_name.as_ref()
+    }
+
+    pub fn set_bootfile_name(
+        &mut self,
+        bootfile_name: Option<String>
+    ) {
+        self.defined_options.insert(67);
+        self.bootfile_name = bootfile_name;
+    }
+
+    /// Client system architecture types (option 93, RFC 4578),
+    /// in order of preference.
+    pub fn client_arch(
+        &self
+    ) -> Option<&Vec<u16>> {
+        self.client_arch.as_ref()
+    }
+
+    pub fn set_client_arch(
+        &mut self,
+        client_arch: Option<Vec<u16>>
+    ) {
+        self.defined_options.insert(93);
+        self.client_arch = client_arch;
+    }
+
+    /// Client network interface identifier (option 94, RFC 4578) :
+    /// interface type, major and minor revisions.
+    pub fn client_ndi(
+        &self
+    ) -> Option<[u8; 3]> {
+        self.client_ndi
+    }
+
+    pub fn set_client_ndi(
+        &mut self,
+        client_ndi: Option<[u8; 3]>
+    ) {
+        self.defined_options.insert(94);
+        self.client_ndi = client_ndi;
+    }
+
+    pub fn user_class(
+        &self
+    ) -> Option<&Vec<u8>> {
+        self.user_class.as_ref()
+    }
+
+    pub fn set_user_class(
+        &mut self,
+        user_class: Option<Vec<u8>>
+    ) {
+        self.defined_options.insert(77);
+        self.user_class = user_class;
+    }
+
+    /// Returns true if the rapid commit option (option 80,
+    /// RFC 4039) is set.
+    pub fn rapid_commit(
+        &self
+    ) -> bool {
+        self.rapid_commit.is_some()
+    }
+
+    /// Sets or clears the rapid commit option. A client sets
+    /// it in its DHCPDISCOVER to ask for a two-message exchange,
+    /// the server in the DHCPACK answering it.
+    pub fn set_rapid_commit(
+        &mut self,
+        rapid_commit: bool
+    ) {
+        match rapid_commit {
+            true => {
+                self.defined_options.insert(80);
+                self.rapid_commit = Some(());
+            },
+            false => {
+                self.defined_options.remove(&80);
+                self.rapid_commit = None;
+            }
+        };
+    }
+
+    /// Returns the user classes (option 77) of the client.
+    ///
+    /// The option is a list of length prefixed classes (RFC 3004),
+    /// but many clients send a single class as plain text instead.
+    /// Both forms are returned.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut options = DhcpOptions::new();
+    /// options.set_user_class(Some(b"iPXE".to_vec()));
+    /// assert!(options.user_classes() == vec![b"iPXE".to_vec()]);
+    /// ```
+    pub fn user_classes(
+        &self
+    ) -> Vec<Vec<u8>> {
+        let raw = match &self.user_class {
+            Some(raw) => raw,
+            None => return Vec::new()
+        };
+
+        let mut classes = Vec::new();
+        let mut data = raw.as_slice();
+        while let Some((len, rest)) = data.split_first() {
+            if (*len == 0) | (rest.len() < *len as usize) {
+                return vec![raw.clone()];
+            };
+            classes.push(rest[..*len as usize].to_vec());
+            data = &rest[*len as usize..];
+        }
+        classes.push(raw.clone());
+        classes
+    }
+
+    /// Overrides the options of `self` with every option
+    /// set in `other`, keeping the others untouched.
+    ///
+    /// Used to layer options from the least to the most
+    /// specific scope (global, subnet, class, reservation).
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut options = DhcpOptions::new();
+    /// options.set_hostname(Some(String::from("Subnet")));
+    /// let mut class_options = DhcpOptions::new();
+    /// class_options.set_domain_name(Some(String::from("Class")));
+    /// options.overlay(&class_options);
+    /// assert!(options.hostname().unwrap() == "Subnet");
+    /// assert!(options.domain_name().unwrap() == "Class");
+    /// ```
+    pub fn overlay(
+        &mut self,
+        other: &DhcpOptions
+    ) {
+        macro_rules! overlay_fields {
+            ($($field:ident => $code:expr),* $(,)?) => {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field.clone();
+                        self.defined_options.insert($code);
+                    };
+                )*
+            };
+        }
+
+        overlay_fields!(
+            subnet_mask => 1,
+            router_option => 3,
+            time_server => 4,
+            name_server => 5,
+            log_server => 7,
+            hostname => 12,
+            domain_name => 15,
+            interface_mtu => 26,
+            broadcast_addr => 28,
+            ntp_servers => 42,
+            requested_ip => 50,
+            lease_time => 51,
+            message_type => 53,
+            server_identifier => 54,
+            parameter_request => 55,
+            renewal_time => 58,
+            rebinding_time => 59,
+            vendor_class => 60,
+            client_identifier => 61,
+            tftp_server_name => 66,
+            bootfile_name => 67,
+            user_class => 77,
+            rapid_commit => 80,
+            client_fqdn => 81,
+            relay_agent_info => 82,
+            client_last_transaction_time => 91,
+            associated_ip => 92,
+            client_arch => 93,
+            client_ndi => 94,
+            status_code => 151,
+            base_time => 152,
+            wpad => 252,
+        );
+    }
+
+    /// Returns the wire encoding of the value of the given
+    /// option, without its code and length.
+    ///
+    /// Returns [`None`] if the option is not set, or
+    /// is not supported.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut options = DhcpOptions::new();
+    /// options.set_vendor_class(Some(String::from("PXEClient")));
+    /// assert!(options.option_bytes(60).unwrap() == b"PXEClient");
+    /// assert!(options.option_bytes(12).is_none());
+    /// ```
+    pub fn option_bytes(
+        &self,
+        code: u8
+    ) -> Option<Vec<u8>> {
+        let is_set = match code {
+            1 => self.subnet_mask.is_some(),
+            4 => self.time_server.is_some(),
+            5 => self.name_server.is_some(),
+            12 => self.hostname.is_some(),
+            15 => self.domain_name.is_some(),
+            26 => self.interface_mtu.is_some(),
+            28 => self.broadcast_addr.is_some(),
+            42 => self.ntp_servers.is_some(),
+            50 => self.requested_ip.is_some(),
+            51 => self.lease_time.is_some(),
+            53 => self.message_type.is_some(),
+            54 => self.server_identifier.is_some(),
+            55 => self.parameter_request.is_some(),
+            58 => self.renewal_time.is_some(),
+            59 => self.rebinding_time.is_some(),
+            60 => self.vendor_class.is_some(),
+            61 => self.client_identifier.is_some(),
+            66 => self.tftp_server_name.is_some(),
+            67 => self.bootfile_name.is_some(),
+            77 => self.user_class.is_some(),
+            80 => self.rapid_commit.is_some(),
+            81 => self.client_fqdn.is_some(),
+            82 => self.relay_agent_info.is_some(),
+            91 => self.client_last_transaction_time.is_some(),
+            92 => self.associated_ip.is_some(),
+            93 => self.client_arch.is_some(),
+            94 => self.client_ndi.is_some(),
+            151 => self.status_code.is_some(),
+            152 => self.base_time.is_some(),
+            252 => self.wpad.is_some(),
+            _ => false
+        };
+        if !is_set {
+            return None;
+        };
+
+        // Skip the option code and length
+        let mut buffer = Vec::new();
+        _append_option(code, self, &mut buffer);
+        Some(buffer.split_off(2))
+    }
+
+    /// Returns a copy of these options without the
+    /// options that only make sense to DHCP clients
+    /// (RFC 2132 section 9), to answer BOOTP clients.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut options = DhcpOptions::new();
+    /// options.set_lease_time(Some(3600));
+    /// options.set_domain_name(Some(String::from("example.com")));
+    /// let bootp_options = options.to_bootp();
+    /// assert!(bootp_options.lease_time().is_none());
+    /// assert!(bootp_options.domain_name().is_some());
+    /// ```
+    pub fn to_bootp(
+        &self
+    ) -> DhcpOptions {
+        let mut options = self.clone();
+        options.requested_ip = None;
+        options.lease_time = None;
+        options.message_type = None;
+        options.server_identifier = None;
+        options.parameter_request = None;
+        options.renewal_time = None;
+        options.rebinding_time = None;
+        options.vendor_class = None;
+        options.client_identifier = None;
+
+        options.defined_options.retain(|code| !DHCP_ONLY_OPTIONS.contains(code));
+        if let Some(output_order) = options.output_order.as_mut() {
+            output_order.retain(|code| !DHCP_ONLY_OPTIONS.contains(code));
+        };
+        options
+    }
+
+    pub fn client_fqdn(
+        &self
+    ) -> Option<&ClientFqdn> {
+        self.client_fqdn.as_ref()
+    }
+
+    pub fn set_client_fqdn(
+        &mut self,
+        client_fqdn: Option<ClientFqdn>
+    ) {
+        self.defined_options.insert(81);
+        self.client_fqdn = client_fqdn;
+    }
+
+    pub fn relay_agent_info(
+        &self
+    ) -> Option<&RelayAgentInfo> {
+        self.relay_agent_info.as_ref()
+    }
+
+    pub fn set_relay_agent_info(
+        &mut self,
+        relay_agent_info: Option<RelayAgentInfo>
+    ) {
+        self.defined_options.insert(82);
+        self.relay_agent_info = relay_agent_info;
+    }
+
+    /// Seconds elapsed since the client last talked to
+    /// the server, in leasequery replies (RFC 4388)
+    pub fn client_last_transaction_time(
+        &self
+    ) -> Option<u32> {
+        self.client_last_transaction_time
+    }
+
+    pub fn set_client_last_transaction_time(
+        &mut self,
+        client_last_transaction_time: Option<u32>
+    ) {
+        self.defined_options.insert(91);
+        self.client_last_transaction_time = client_last_transaction_time;
+    }
+
+    /// Every address bound to the client, in
+    /// leasequery replies (RFC 4388)
+    pub fn associated_ip(
+        &self
+    ) -> Option<&Vec<Ipv4Addr>> {
+        self.associated_ip.as_ref()
+    }
+
+    pub fn set_associated_ip(
+        &mut self,
+        associated_ip: Option<Vec<Ipv4Addr>>
+    ) {
+        self.defined_options.insert(92);
+        self.associated_ip = associated_ip;
+    }
+
+    /// Status of a bulk leasequery and its message (RFC 6926)
+    pub fn status_code(
+        &self
+    ) -> Option<(u8, &str)> {
+        self.status_code.as_ref().map(|(code, message)| (*code, message.as_str()))
+    }
+
+    pub fn set_status_code(
+        &mut self,
+        status_code: Option<(u8, String)>
+    ) {
+        self.defined_options.insert(151);
+        self.status_code = status_code;
+    }
+
+    /// Current time of the server, in seconds since the epoch,
+    /// to which bulk leasequery times are relative (RFC 6926)
+    pub fn base_time(
+        &self
+    ) -> Option<u32> {
+        self.base_time
+    }
+
+    pub fn set_base_time(
+        &mut self,
+        base_time: Option<u32>
+    ) {
+        self.defined_options.insert(152);
+        self.base_time = base_time;
+    }
 }
 
 #[cfg(test)]
@@ -732,4 +1312,130 @@ mod tests {
         assert!(DhcpOptions::from(bytes.as_slice()) == DhcpOptions::from(OPTION_BYTES.as_slice()));
     }
 
+    #[test]
+    fn identification_options() {
+        let bytes = [
+            0x3c, 0x04, b'P', b'X', b'E', b'C',
+            0x52, 0x08, 0x01, 0x02, b'G', b'i', 0x02, 0x02, 0xca, 0xfe,
+            0xff
+        ];
+        let options = DhcpOptions::from(bytes.as_slice());
+        assert!(options.vendor_class().unwrap() == "PXEC");
+        let relay_agent_info = options.relay_agent_info().unwrap();
+        assert!(relay_agent_info.circuit_id().unwrap() == b"Gi");
+        assert!(relay_agent_info.remote_id().unwrap() == &[0xca, 0xfe]);
+        assert!(DhcpOptions::from(Vec::from(options.clone()).as_slice()) == options);
+    }
+
+    #[test]
+    fn boot_options() {
+        let bytes = [
+            0x5d, 0x04, 0x00, 0x07, 0x00, 0x00,
+            0x5e, 0x03, 0x01, 0x03, 0x10,
+            0x42, 0x04, b't', b'f', b't', b'p',
+            0x43, 0x0a, b'p', b'x', b'e', b'l', b'i', b'n', b'u', b'x', b'.', b'0',
+            0xff
+        ];
+        let options = DhcpOptions::from(bytes.as_slice());
+        assert!(options.client_arch().unwrap() == &vec![7, 0]);
+        assert!(options.client_ndi() == Some([1, 3, 16]));
+        assert!(options.tftp_server_name().unwrap() == "tftp");
+        assert!(options.bootfile_name().unwrap() == "pxelinux.0");
+        assert!(DhcpOptions::from(Vec::from(options.clone()).as_slice()) == options);
+
+        // Truncated architecture types and interface identifier are dropped
+        let bytes = [
+            0x5d, 0x03, 0x00, 0x07, 0x00,
+            0x5e, 0x02, 0x01, 0x03,
+            0x42, 0x04, b't', b'f', b't', b'p',
+            0xff
+        ];
+        let options = DhcpOptions::from(bytes.as_slice());
+        assert!(options.client_arch().is_none());
+        assert!(options.client_ndi().is_none());
+        assert!(options.tftp_server_name().unwrap() == "tftp");
+        assert!(DhcpOptions::from(Vec::from(options.clone()).as_slice()) == options);
+    }
+
+    #[test]
+    fn bootp_options() {
+        let mut options = DhcpOptions::from(OPTION_BYTES.as_slice());
+        options.set_domain_name(Some(String::from("example.com")));
+        let bootp_options = options.to_bootp();
+
+        assert!(bootp_options.message_type().is_none());
+        assert!(bootp_options.server_identifier().is_none());
+        assert!(bootp_options.lease_time().is_none());
+        assert!(bootp_options.subnet_mask() == options.subnet_mask());
+        assert!(bootp_options.domain_name().unwrap() == "example.com");
+        assert!(!bootp_options.defined_options.contains(&53));
+    }
+
+    #[test]
+    fn rapid_commit_option() {
+        let options = DhcpOptions::from([0x35, 0x01, 0x01, 0x50, 0x00, 0xff].as_slice());
+        assert!(options.message_type() == Some(1));
+        assert!(options.rapid_commit());
+        assert!(options.option_bytes(80).unwrap().is_empty());
+
+        let mut options = DhcpOptions::new();
+        options.set_rapid_commit(true);
+        assert!(Vec::from(options.clone()) == [0x50, 0x00, 0xff]);
+        options.set_rapid_commit(false);
+        assert!(!options.rapid_commit());
+        assert!(Vec::from(options) == [0xff]);
+    }
+
+    #[test]
+    fn client_fqdn_option() {
+        let bytes = [
+            0x0c, 0x04, b'h', b'o', b's', b't',
+            0x51, 0x08, 0x05, 0x00, 0x00, 0x04, b'h', b'o', b's', b't',
+            0xff
+        ];
+        let options = DhcpOptions::from(bytes.as_slice());
+        let client_fqdn = options.client_fqdn().unwrap();
+        assert!(client_fqdn.domain_name() == "host");
+        assert!(client_fqdn.flags().server_update);
+        assert!(options.option_bytes(81).unwrap() == bytes[8..16]);
+        assert!(DhcpOptions::from(Vec::from(options.clone()).as_slice()) == options);
+
+        // Malformed names are dropped
+        let options = DhcpOptions::from([0x51, 0x02, 0x05, 0x00, 0xff].as_slice());
+        assert!(options.client_fqdn().is_none());
+        assert!(Vec::from(options) == [0xff]);
+    }
+
+    #[test]
+    fn leasequery_options() {
+        let bytes = [
+            0x35, 0x01, 0x0d,
+            0x5b, 0x04, 0x00, 0x00, 0x00, 0x3c,
+            0x5c, 0x08, 0xc0, 0xa8, 0x00, 0x05, 0xc0, 0xa8, 0x00, 0x06,
+            0x97, 0x03, 0x00, b'o', b'k',
+            0x98, 0x04, 0x65, 0x53, 0xf1, 0x00,
+            0xff
+        ];
+        let options = DhcpOptions::from(bytes.as_slice());
+        assert!(options.message_type() == Some(13));
+        assert!(options.client_last_transaction_time() == Some(60));
+        assert!(options.associated_ip().unwrap() == &vec![Ipv4Addr::new(192, 168, 0, 5), Ipv4Addr::new(192, 168, 0, 6)]);
+        assert!(options.status_code() == Some((0, "ok")));
+        assert!(options.base_time() == Some(0x6553f100));
+        assert!(DhcpOptions::from(Vec::from(options.clone()).as_slice()) == options);
+
+        // DHCPLEASEQUERYDONE is the last known message type
+        assert!(DhcpOptions::from([0x35, 0x01, 0x0f, 0xff].as_slice()).message_type() == Some(15));
+        assert!(DhcpOptions::from([0x35, 0x01, 0x10, 0xff].as_slice()).message_type().is_none());
+    }
+
+    #[test]
+    fn raw_option_bytes() {
+        let options = DhcpOptions::from(OPTION_BYTES.as_slice());
+        assert!(options.option_bytes(53).unwrap() == [5]);
+        assert!(options.option_bytes(54).unwrap() == [0xc0, 0xa8, 0x00, 0xfe]);
+        assert!(options.option_bytes(60).is_none());
+        assert!(options.option_bytes(200).is_none());
+    }
+
 }
@@ -7,6 +7,8 @@ use crate::netutils::hw_addr::HardwareAddress;
 
 use super::dhcp_options::DhcpOptions;
 
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
 #[derive(Clone, Debug)]
 pub struct DhcpV4Packet {
     pub op: u8,
@@ -23,7 +25,14 @@ pub struct DhcpV4Packet {
     pub chadd : HardwareAddress,
     pub sname : [u8; 64],
     pub file : [u8; 128],
-    pub options : DhcpOptions
+    pub options : DhcpOptions,
+    /// Name of the network interface the packet was received
+    /// on, filled by the listener of that interface with
+    /// [`DhcpV4Packet::from_interface`]. Not part of the wire format.
+    pub interface : Option<String>,
+    /// Names of the client classes the packet matched, filled
+    /// by the classifier. Not part of the wire format.
+    pub classes : Vec<String>
 }
 
 #[derive(Clone)]
@@ -33,6 +42,61 @@ pub enum DhcpMessage {
     DhcpDecline(DhcpV4Packet),
     DhcpRelease(DhcpV4Packet),
     DhcpInform(DhcpV4Packet),
+    /// BOOTREQUEST of a BOOTP (RFC 951) client
+    BootpRequest(DhcpV4Packet),
+}
+
+impl DhcpV4Packet {
+
+    /// Decodes a packet received on the given network interface.
+    ///
+    /// Listeners must build their packets this way, for every
+    /// interface of [`crate::cfg::main_cfg::DhcpCfg::interfaces`], so
+    /// that clients that are not relayed get an address in the subnet
+    /// of their interface.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let packet = DhcpV4Packet::from_interface(raw.as_slice(), "eth0");
+    /// assert!(packet.interface.as_deref() == Some("eth0"));
+    /// ```
+    pub fn from_interface(raw: &[u8], interface: &str) -> Self {
+        let mut packet = Self::from_raw_bytes(raw);
+        packet.interface = Some(interface.to_string());
+        packet
+    }
+
+    /// Returns true if the packet is a BOOTREQUEST of a
+    /// BOOTP client, i.e. it carries no DHCP message type.
+    pub fn is_bootp(&self) -> bool {
+        (self.op == 1) & self.options.message_type().is_none()
+    }
+
+    /// Returns the wire format of the packet, the exact
+    /// reverse of [`PacketType::from_raw_bytes`].
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let packet = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+    /// assert!(DhcpV4Packet::from_raw_bytes(&packet.to_bytes()).xid == packet.xid);
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = vec![self.op, self.htype, self.hlen, self.hops];
+        raw.extend_from_slice(&self.xid.to_le_bytes());
+        raw.extend_from_slice(&(self.secs.as_secs() as u16).to_le_bytes());
+        raw.extend_from_slice(&self.flags);
+        for addr in [self.ciaddr, self.yiaddr, self.siaddr, self.giaddr] {
+            raw.extend_from_slice(&addr.octets());
+        }
+        raw.extend_from_slice(&self.chadd.raw);
+        raw.extend_from_slice(&self.sname);
+        raw.extend_from_slice(&self.file);
+        raw.extend_from_slice(&MAGIC_COOKIE);
+        raw.append(&mut Vec::from(self.options.clone()));
+        raw
+    }
 }
 
 impl PacketType for DhcpV4Packet {
@@ -75,7 +139,28 @@ impl PacketType for DhcpV4Packet {
         let file: [u8; 128] = raw.drain(0..128).as_slice().to_vec().try_into().unwrap();
         let _magic_cookie = raw.drain(0..4).as_slice().to_vec();
         let options = DhcpOptions::from(raw.as_slice()); 
-        Self { op, htype, hlen, hops, xid, secs, flags, ciaddr, yiaddr, siaddr, giaddr, chadd, sname, file, options }
+        Self { op, htype, hlen, hops, xid, secs, flags, ciaddr, yiaddr, siaddr, giaddr, chadd, sname, file, options, interface: None, classes: Vec::new() }
+
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let mut raw = vec![0u8; 236];
+        raw[..4].copy_from_slice(&[1, 1, 6, 0]);
+        raw[4..8].copy_from_slice(&[0x39, 0x03, 0xf3, 0x26]);
+        raw[12..16].copy_from_slice(&[192, 168, 0, 5]);
+        raw[28..34].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        raw.extend_from_slice(&MAGIC_COOKIE);
+        raw.extend_from_slice(&[0x35, 0x01, 0x0a, 0xff]);
 
+        let packet = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+        assert!(packet.options.message_type() == Some(10));
+        assert!(packet.ciaddr == Ipv4Addr::new(192, 168, 0, 5));
+        assert!(packet.to_bytes() == raw);
     }
 }
@@ -1,2 +1,4 @@
 pub mod dhcp_packet;
 pub mod dhcp_options;
+pub mod relay_agent_info;
+pub mod client_fqdn;
@@ -0,0 +1,154 @@
+//! Implements `RelayAgentInfo`, the content of the
+//! Relay Agent Information option (82) inserted by
+//! relay agents, defined in RFC 3046
+//! ( <https://www.rfc-editor.org/rfc/rfc3046> )
+//! and extended by the Relay-ID sub-option of RFC 6925
+
+use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
+
+const CIRCUIT_ID: u8 = 1;
+const REMOTE_ID: u8 = 2;
+const RELAY_ID: u8 = 12;
+
+/// `RelayAgentInfo` holds the sub-options of the
+/// Relay Agent Information option.
+///
+/// Only the Agent Circuit ID (1), Agent Remote ID (2) and
+/// Relay-ID (12) sub-options are interpreted, other sub-options are
+/// kept as is so that they can be echoed back to the relay.
+///
+/// # Examples:
+///
+/// ```
+/// let info = RelayAgentInfo::from([0x01, 0x03, b'e', b't', b'1'].as_slice());
+/// assert!(info.circuit_id().unwrap() == b"et1");
+/// assert!(info.remote_id().is_none());
+/// ```
+#[skip_serializing_none]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct RelayAgentInfo {
+    circuit_id: Option<Vec<u8>>,
+    remote_id: Option<Vec<u8>>,
+    relay_id: Option<Vec<u8>>,
+    #[serde(skip)]
+    other_suboptions: Vec<(u8, Vec<u8>)>
+}
+
+impl From<&[u8]> for RelayAgentInfo {
+    fn from(
+        value: &[u8]
+    ) -> Self {
+        let mut info = RelayAgentInfo::default();
+        let mut data = value;
+
+        while data.len() >= 2 {
+            let code = data[0];
+            let len = data[1] as usize;
+            if data.len() < 2 + len {
+                break;
+            }
+            let content = data[2..2 + len].to_vec();
+            match code {
+                CIRCUIT_ID => info.circuit_id = Some(content),
+                REMOTE_ID => info.remote_id = Some(content),
+                RELAY_ID => info.relay_id = Some(content),
+                _ => info.other_suboptions.push((code, content)),
+            }
+            data = &data[2 + len..];
+        }
+        info
+    }
+}
+
+impl From<&RelayAgentInfo> for Vec<u8> {
+    fn from(info: &RelayAgentInfo) -> Self {
+        let mut buf = Vec::new();
+        let suboptions = info.circuit_id.iter().map(|id| (CIRCUIT_ID, id))
+            .chain(info.remote_id.iter().map(|id| (REMOTE_ID, id)))
+            .chain(info.relay_id.iter().map(|id| (RELAY_ID, id)))
+            .chain(info.other_suboptions.iter().map(|(code, content)| (*code, content)));
+
+        for (code, content) in suboptions {
+            buf.push(code);
+            buf.push(content.len() as u8);
+            buf.extend_from_slice(content);
+        }
+        buf
+    }
+}
+
+impl RelayAgentInfo {
+
+    pub fn new(
+        circuit_id: Option<Vec<u8>>,
+        remote_id: Option<Vec<u8>>
+    ) -> Self {
+        Self { circuit_id, remote_id, relay_id: None, other_suboptions: Vec::new() }
+    }
+
+    pub fn circuit_id(
+        &self
+    ) -> Option<&Vec<u8>> {
+        self.circuit_id.as_ref()
+    }
+
+    pub fn remote_id(
+        &self
+    ) -> Option<&Vec<u8>> {
+        self.remote_id.as_ref()
+    }
+
+    /// Identifier of the relay agent itself, used
+    /// by bulk leasequery (RFC 6926) to find the
+    /// leases it relayed
+    pub fn relay_id(
+        &self
+    ) -> Option<&Vec<u8>> {
+        self.relay_id.as_ref()
+    }
+
+    pub fn set_relay_id(
+        &mut self,
+        relay_id: Option<Vec<u8>>
+    ) {
+        self.relay_id = relay_id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_agent_info_roundtrip() {
+        let bytes = [
+            0x01, 0x04, b'G', b'i', b'0', b'1',
+            0x02, 0x02, 0xca, 0xfe,
+            0x09, 0x01, 0x00
+        ];
+        let info = RelayAgentInfo::from(bytes.as_slice());
+        assert!(info.circuit_id().unwrap() == b"Gi01");
+        assert!(info.remote_id().unwrap() == &[0xca, 0xfe]);
+        assert!(Vec::from(&info) == bytes.to_vec());
+    }
+
+    #[test]
+    fn test_relay_id() {
+        let bytes = [0x02, 0x01, 0x07, 0x0c, 0x03, 0x00, 0x01, 0x02];
+        let info = RelayAgentInfo::from(bytes.as_slice());
+        assert!(info.relay_id().unwrap() == &[0x00, 0x01, 0x02]);
+        assert!(Vec::from(&info) == bytes.to_vec());
+
+        let mut info = RelayAgentInfo::new(None, None);
+        info.set_relay_id(Some(vec![0x00, 0x01]));
+        assert!(Vec::from(&info) == [0x0c, 0x02, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_truncated_relay_agent_info() {
+        let info = RelayAgentInfo::from([0x01, 0x02, b'G', b'i', 0x02, 0x08, 0xca].as_slice());
+        assert!(info.circuit_id().unwrap() == b"Gi");
+        assert!(info.remote_id().is_none());
+    }
+}
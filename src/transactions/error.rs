@@ -0,0 +1,53 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use super::transaction::TransactionState;
+
+/// Errors returned by the [`TransactionManager`](super::manager::TransactionManager),
+/// so that callers and hooks can decide what to do depending on what went wrong.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionError {
+    /// No transaction matches the packet
+    NotFound,
+    /// A transaction with the same key already exists
+    AlreadyExists,
+    /// A lease is already bound to the transaction
+    LeaseAlreadyBound,
+    /// No lease is bound to the transaction
+    NoLease,
+    /// The transaction is not allowed to switch between those states
+    IllegalTransition { from: TransactionState, to: TransactionState },
+    /// No commited lease for the given address
+    UnknownLease(Ipv4Addr),
+    /// The packet carries no usable server identifier
+    InvalidServerIdentifier,
+    /// A DHCPACK was sent without lease time
+    MissingLeaseTime,
+    /// The underlying storage failed
+    Storage(String)
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::NotFound => write!(f, "No such transaction"),
+            TransactionError::AlreadyExists => write!(f, "Transaction already exists"),
+            TransactionError::LeaseAlreadyBound => write!(f, "Lease already bound to this transaction"),
+            TransactionError::NoLease => write!(f, "No lease bound to this transaction"),
+            TransactionError::IllegalTransition { from, to } => write!(f, "Illegal transition from {} to {}", from, to),
+            TransactionError::UnknownLease(address) => write!(f, "No lease for address {}", address),
+            TransactionError::InvalidServerIdentifier => write!(f, "Unvalid Server Identifier"),
+            TransactionError::MissingLeaseTime => write!(f, "ACK without lease time"),
+            TransactionError::Storage(err) => write!(f, "Storage error: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+// RuntimeStorage reports its errors as Strings
+impl From<String> for TransactionError {
+    fn from(err : String) -> Self {
+        TransactionError::Storage(err)
+    }
+}
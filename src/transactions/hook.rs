@@ -2,7 +2,9 @@ use std::sync::{Arc, Mutex};
 
 use fp_core::{hooks::{self, hook_registry::{Hook, HookRegistry}, typemap::TypeMap, flags::HookFlag}, core::{packet::PacketContext, errors::HookError}};
 
-use crate::{packet::dhcp_packet::DhcpV4Packet, transactions::manager::TransactionManager};
+use log::warn;
+
+use crate::{packet::dhcp_packet::DhcpV4Packet, transactions::{manager::TransactionManager, error::TransactionError}};
 
 fn init_transaction_manager_hook(mut registry : HookRegistry<DhcpV4Packet, DhcpV4Packet>) -> HookRegistry<DhcpV4Packet, DhcpV4Packet>{
     let input_handler = Box::new(|type_map : Arc<Mutex<TypeMap>>, context : &mut PacketContext<DhcpV4Packet, DhcpV4Packet>|{
@@ -13,7 +15,17 @@ fn init_transaction_manager_hook(mut registry : HookRegistry<DhcpV4Packet, DhcpV
         let mut transaction_handler = transaction_manager.lock().unwrap();
         let result = transaction_handler.handle_input(input);
         match result {
-            Err(e) => Ok(-1),
+            // Retransmitted packet, answer with the reply we already sent
+            Ok(Some(reply)) => {
+                context.set_output(reply);
+                Ok(0)
+            },
+            // Packet does not belong to any transaction, let other hooks deal with it
+            Err(TransactionError::NotFound) => Ok(0),
+            Err(e) => {
+                warn!("Transaction input rejected : {}", e);
+                Ok(-1)
+            },
             _ => Ok(0)
         }
     });
@@ -21,12 +33,19 @@ fn init_transaction_manager_hook(mut registry : HookRegistry<DhcpV4Packet, DhcpV
     let output_handler = Box::new(|type_map : Arc<Mutex<TypeMap>>, context : &mut PacketContext<DhcpV4Packet, DhcpV4Packet>|{
         let mut input_manager = type_map.lock().unwrap();
         let transaction_manager = input_manager.get_mut::<Arc<Mutex<TransactionManager>>>().unwrap();
-        let output = context.get_output();
+        let mut output = context.get_output().clone();
         let transaction_manager = transaction_manager.clone();
         let mut transaction_handler = transaction_manager.lock().unwrap();
-        let result = transaction_handler.handle_output(output);
+        // Answers the client FQDN option before the DNS gets updated
+        transaction_handler.set_client_fqdn_reply(context.get_input(), &mut output);
+        let result = transaction_handler.handle_output(&output);
+        context.set_output(output);
         match result {
-            Err(e) => Ok(-1),
+            Err(TransactionError::NotFound) => Ok(0),
+            Err(e) => {
+                warn!("Transaction output rejected : {}", e);
+                Ok(-1)
+            },
             _ => Ok(0)
         }
     });
@@ -1,14 +1,21 @@
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
 use itertools::Itertools;
+use crate::clock::clock::{Clock, SystemClock};
 use crate::extract;
 use fp_core::utils::data::{Storable, RuntimeStorage, DataPool};
 use crate::data::data::{Data, LeaseData};
-use crate::leases::lease::LeaseV4;
-use crate::packet::dhcp_packet::DhcpV4Packet;
+use crate::ddns::{dhcid::DhcidIdentifier, updater::{DdnsLease, DdnsQueue, DdnsSettings}};
+use crate::leases::{hostname::HostnamePolicy, lease::LeaseV4};
+use crate::leasequery::binding::{BindingTable, LeaseBinding};
+use crate::failover::{message::LeaseUpdate, peer::FailoverPeer};
+use crate::packet::{client_fqdn::ClientFqdn, dhcp_packet::DhcpV4Packet};
+use crate::cfg::main_cfg::TransactionCfg;
 
-use super::transaction::{Transaction, TransactionState};
+use super::error::TransactionError;
+use super::transaction::{Transaction, TransactionKey, TransactionState};
 
 const ADDRESS : Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
 const PENDING_LEASE_POOL_NAME : &str = "PendingLeases";
@@ -31,6 +38,17 @@ const TRANSACTION_POOL_NAME : &str = "Transactions";
 /// have the same lifecycle. Thus committing or aborting a transaction will have the
 /// same effet on the lease.
 ///
+/// Transitions between states are checked against the table defined by [`TransactionState::can_transition_to`],
+/// and every method reports failures through a [`TransactionError`].
+///
+/// Transactions are identified by a [`TransactionKey`] made of the client identifier, the
+/// xid and the relay agent address, so that two clients picking the same xid do not collide.
+///
+/// Clients retransmit their DHCPDISCOVER and DHCPREQUEST with the same xid until they
+/// get an answer. The last DHCPOFFER and DHCPACK sent for each transaction are therefore kept
+/// during the offer timeout, so that a retransmission gets the very same answer instead
+/// of starting a new transaction.
+///
 /// To initiate a [`TransactionManager`], you will need a [`RuntimeStorage`] enclosed
 /// in an Arc Mutex.
 ///
@@ -60,9 +78,25 @@ const TRANSACTION_POOL_NAME : &str = "Transactions";
 pub struct TransactionManager {
     // Index registers every Transaction and their address in RuntimeStorage
     // Could maybe improve in the future by making it not a Arc Mutex
-    index : Arc<Mutex<HashMap<u32, u16>>>,
+    index : Arc<Mutex<HashMap<TransactionKey, u16>>>,
+    // Registers every commited lease and its address in RuntimeStorage
+    leases : Arc<Mutex<HashMap<Ipv4Addr, u16>>>,
     // Shared storage
-    storage : Arc<Mutex<RuntimeStorage<Data>>>
+    storage : Arc<Mutex<RuntimeStorage<Data>>>,
+    // Source of time for transactions and leases
+    clock : Arc<dyn Clock>,
+    // Time during which an offered lease is held for the client
+    offer_timeout : Duration,
+    // Last DHCPOFFER or DHCPACK sent for each transaction, with the time it was sent
+    replies : Arc<Mutex<HashMap<TransactionKey, (DhcpV4Packet, DateTime<Utc>)>>>,
+    // Dynamic DNS updates of commited and released leases, if enabled
+    ddns : Option<(DdnsQueue, DdnsSettings)>,
+    // Sanitization of the hostnames stored in leases
+    hostname_policy : HostnamePolicy,
+    // Clients and relay agents of the commited leases, answered to leasequeries
+    bindings : Arc<Mutex<BindingTable>>,
+    // Partner the commited and released leases are sent to, if any
+    failover : Option<Arc<FailoverPeer>>
 }
 
 
@@ -75,40 +109,42 @@ impl TransactionManager{
         // Create DataPool
         let pending_lease_pool = DataPool::new(PENDING_LEASE_POOL_NAME.to_string(), "(type VARCHAR(255), id BIGINT, name VARCHAR(255), address VARCHAR(255), expiration VARCHAR(255))".to_string());
         let lease_pool = DataPool::new(LEASE_POOL_NAME.to_string(), "(type VARCHAR(255), id BIGINT, name VARCHAR(255), address VARCHAR(255), expiration VARCHAR(255))".to_string());
-        let transaction_pool = DataPool::new(TRANSACTION_POOL_NAME.to_string(), "(type VARCHAR(255), id BIGINT, identifier BIGINT, time VARCHAR(255), lease_address BIGINT, state VARCHAR(255))".to_string());
+        let transaction_pool = DataPool::new(TRANSACTION_POOL_NAME.to_string(), "(type VARCHAR(255), id BIGINT, identifier BIGINT, time VARCHAR(255), lease_address BIGINT, state VARCHAR(255), client VARCHAR(255), relay VARCHAR(255))".to_string());
         // Add DataPool
         storage.add_pool(pending_lease_pool);
         storage.add_pool(lease_pool);
         storage.add_pool(transaction_pool);
     }
 
-    ///Initiate a [`Transaction`] with a given identifier (usually the xid of your packet)
-    pub fn initiate_transaction(&self, transaction_id : u32) -> Result<(), String>{
+    ///Initiate a [`Transaction`] with a given key (usually built from your packet)
+    pub fn initiate_transaction(&self, key : &TransactionKey) -> Result<(), TransactionError>{
         // Lock index
         let index = self.index.clone();
         let mut index = index.lock().unwrap();
-        match index.get(&transaction_id) {
+        match index.get(key) {
             // Check if there is already a transaction with same identifier
-            Some(_) => Err("Transaction already exists".to_string()),
+            Some(_) => Err(TransactionError::AlreadyExists),
             None => {
                 let mut storage = self.storage.lock().unwrap();
                 // If not
                 // Init new transaction
-                let mut transaction = Transaction::init_new(transaction_id, chrono::Utc::now());
+                let mut transaction = Transaction::init_new(key, self.clock.now());
                 // Set pending state, which is default state
-                transaction.set_state(TransactionState::Pending("PENDING".to_string()));
+                transaction.set_state(TransactionState::Pending)?;
                 // Store Transaction and register its address in Storage in index
                 let address = storage.store(Data::Transaction(transaction.clone()), TRANSACTION_POOL_NAME.to_string())?;
-                index.insert(transaction_id, address);
+                index.insert(key.clone(), address);
                Ok(())
             }
         }
     }
 
     /// Aborts a [`Transaction`]
-    pub fn abort(&mut self, transaction_id : u32) -> Result<u16, String>{
-        // Aborting just deletes the transaction, but keep two method to be clearer and to allow changes if needed
-        self.delete_transaction(transaction_id)?;
+    pub fn abort(&mut self, key : &TransactionKey) -> Result<u16, TransactionError>{
+        // Aborted transactions are not kept, we only check that the transaction can be aborted
+        // before deleting it
+        self.get_transaction(key)?.set_state(TransactionState::Aborted)?;
+        self.delete_transaction(key)?;
         Ok(0)
     }
 
@@ -116,30 +152,110 @@ impl TransactionManager{
     /// Commiting a [`Transaction`] includes :
     /// - Moving bound [`LeaseV4`] from pending [`DataPool`] to running [`DataPool`]
     /// - Closing [`Transaction`]
-    pub fn commit (&mut self, transaction_id : u32) -> Result<(), String> {
+    pub fn commit (&mut self, key : &TransactionKey) -> Result<(), TransactionError> {
+        // Only a requested transaction, or a bound one using rapid commit, can be commited
+        self.get_transaction(key)?.set_state(TransactionState::Committed)?;
         // We get the lease because we'll move it from PendingLeases to Leases
-        let lease = self.get_transaction_lease(transaction_id)?;
+        let lease = self.get_transaction_lease(key)?;
         // Delete transaction
-        self.delete_transaction(transaction_id)?;
+        self.delete_transaction(key)?;
         let storage = self.storage.clone();
         let mut storage = storage.lock().unwrap();
+        let address = lease.address();
         // We finally move the lease in Leases Pool
-        match storage.store(Data::Lease(lease), LEASE_POOL_NAME.to_string()) {
-            Err(err) => Err(err),
-            _ => Ok(())
+        let lease_address = storage.store(Data::Lease(lease), LEASE_POOL_NAME.to_string())?;
+        // And register it so that it can be renewed later
+        let leases = self.leases.clone();
+        let mut leases = leases.lock().unwrap();
+        leases.insert(address, lease_address);
+        Ok(())
+    }
+
+    /// Renews a commited [`LeaseData`] given its address, so that it expires
+    /// after the given [`Duration`]. The renewed lease is persisted in the running [`DataPool`].
+    pub fn renew_lease(&mut self, address : Ipv4Addr, duration : Duration) -> Result<u16, TransactionError> {
+        let lease_address = self.get_lease_address(&address).ok_or(TransactionError::UnknownLease(address))?;
+        let storage = self.storage.clone();
+        let mut storage = storage.lock().unwrap();
+        let data = storage.get(lease_address)?;
+        let mut lease = extract!(data, Data::Lease).ok_or(TransactionError::UnknownLease(address))?;
+        lease.renew(self.clock.now(), duration);
+        // Same as transactions, update is done by removing and adding back the lease
+        storage.delete(lease_address, LEASE_POOL_NAME.to_string());
+        let new_address = storage.store(Data::Lease(lease), LEASE_POOL_NAME.to_string())?;
+        let leases = self.leases.clone();
+        let mut leases = leases.lock().unwrap();
+        leases.insert(address, new_address);
+        Ok(new_address)
+    }
+
+    /// Returns the addresses of every commited lease that expired before `now`.
+    ///
+    /// Leases of the failover partner are left out : they are given up by
+    /// the [`FailoverPeer`] once they are safe to reuse.
+    pub fn expired_leases(&self, now : DateTime<Utc>) -> Vec<Ipv4Addr> {
+        let addresses : Vec<Ipv4Addr>;
+        {
+            let leases = self.leases.lock().unwrap();
+            addresses = leases.keys().copied().collect_vec();
         }
+        addresses.into_iter().filter(|address| !self.is_partner_lease(address)).filter(|address| {
+            match self.get_lease(address) {
+                Ok(lease) => lease.expired(now),
+                _ => false
+            }
+        }).collect_vec()
     }
 
-    /// Given a [`LeaseV4`] and an xid, binds xid's transaction to that lease, so that the state of the lease will be
+    /// Drops a commited [`LeaseData`] from the running [`DataPool`] given its address
+    pub fn release_lease(&mut self, address : &Ipv4Addr) -> Result<(), TransactionError> {
+        let leases = self.leases.clone();
+        let mut leases = leases.lock().unwrap();
+        let lease_address = leases.remove(address).ok_or(TransactionError::UnknownLease(*address))?;
+        let storage = self.storage.clone();
+        let mut storage = storage.lock().unwrap();
+        storage.delete(lease_address, LEASE_POOL_NAME.to_string());
+        self.bindings.lock().unwrap().remove(address);
+        if let Some(failover) = &self.failover {
+            failover.withdraw(*address);
+        }
+        if let Some((ddns, _)) = &self.ddns {
+            ddns.withdraw(*address);
+        }
+        Ok(())
+    }
+
+    /// Gets a commited [`LeaseData`] given its address
+    pub fn get_lease(&self, address : &Ipv4Addr) -> Result<LeaseData, TransactionError> {
+        let lease_address = self.get_lease_address(address).ok_or(TransactionError::UnknownLease(*address))?;
+        let storage = self.storage.clone();
+        let storage = storage.lock().unwrap();
+        let data = storage.get(lease_address)?;
+        let lease = extract!(data, Data::Lease).ok_or(TransactionError::UnknownLease(*address))?;
+        Ok(lease)
+    }
+
+    /// Returns the storage address of a commited lease given its address
+    pub fn get_lease_address(&self, address : &Ipv4Addr) -> Option<u16> {
+        let leases = self.leases.clone();
+        let leases = leases.lock().unwrap();
+        leases.get(address).copied()
+    }
+
+    /// Given a [`LeaseV4`] and the packet of a transaction, binds the transaction to that lease, so that the state of the lease will be
     /// autommatically either commited ot aborted depnding on the incoming events.
-    pub fn bind_lease(&mut self, xid : u32, lease : LeaseV4) -> Result<u16, String>{
-        let t = self.get_transaction(xid)?;
+    ///
+    /// The client FQDN option (81) of the packet, if any, takes precedence over the hostname of the lease.
+    pub fn bind_lease(&mut self, packet : &DhcpV4Packet, mut lease : LeaseV4) -> Result<u16, TransactionError>{
+        let key = &TransactionKey::from_packet(packet);
+        let t = self.get_transaction(key)?;
         // If there is no lease already bound
         if t.pending_lease_address == 0 {
-            let xid = t.xid;
             // We change the state to BOUND
-            self.update_transaction_state(xid, TransactionState::Bound("BOUND".to_string()))?;
-            let mut t = self.get_transaction(xid)?;
+            self.update_transaction_state(key, TransactionState::Bound)?;
+            let mut t = self.get_transaction(key)?;
+            let hostname = self.client_hostname(packet, &lease);
+            *lease.hostname_mut() = hostname;
             let storage = self.storage.clone();
             let mut storage = storage.lock().unwrap();
             // We store the lease and get the Storage location
@@ -153,18 +269,18 @@ impl TransactionManager{
             let address = storage.store(Data::Transaction(t), TRANSACTION_POOL_NAME.to_string())?;
             let index = self.index.clone();
             let mut index = index.lock().unwrap();
-            index.remove(&xid);
-            index.insert(xid, address);
+            index.remove(key);
+            index.insert(key.clone(), address);
             Ok(lease_address)
         }else {
-            Err("Lease already bound to this transaction".to_string())
+            Err(TransactionError::LeaseAlreadyBound)
         }
 
     }
 
     /// Deletes a [`Transaction`] from the index and its [`LeaseV4`] from the storage
-    fn delete_transaction(&self, transaction_id : u32) -> Result<(), String>{
-        let t = self.get_transaction(transaction_id)?;
+    fn delete_transaction(&self, key : &TransactionKey) -> Result<(), TransactionError>{
+        let t = self.get_transaction(key)?;
         let transaction_address = t.uid;
         let lease_address = t.pending_lease_address;
 
@@ -174,7 +290,7 @@ impl TransactionManager{
         let index = self.index.clone();
         let mut index = index.lock().unwrap();
         // Remove transaction from index
-        index.remove(&transaction_id);
+        index.remove(key);
         //Drops transaction from storage
         storage.delete(transaction_address, TRANSACTION_POOL_NAME.to_string());
         //Drops lease from storage
@@ -183,25 +299,25 @@ impl TransactionManager{
     }
 
     /// Gets [`Transaction`] from identifier
-    pub fn get_transaction(&self, transaction_id : u32) -> Result<Transaction, String>{
+    pub fn get_transaction(&self, key : &TransactionKey) -> Result<Transaction, TransactionError>{
         // Get transaction location
-        let transaction_address = self.get_transaction_address(&transaction_id).ok_or_else(||"Error".to_string())?;
+        let transaction_address = self.get_transaction_address(key).ok_or(TransactionError::NotFound)?;
         //Lock storage
         let storage = self.storage.clone();
         let storage = storage.lock().unwrap();
         // Get transaction
         let data = storage.get(transaction_address)?;
-        let transaction = extract!(data, Data::Transaction).ok_or_else(|| "Error".to_string())?;
+        let transaction = extract!(data, Data::Transaction).ok_or(TransactionError::NotFound)?;
         Ok(transaction)
     }
 
-    /// Updates the state of a [`Transaction`] given its id
-    fn update_transaction_state(&mut self, transaction_id : u32, state : TransactionState) -> Result<(), String>{
+    /// Updates the state of a [`Transaction`] given its id, if the transition is allowed
+    fn update_transaction_state(&mut self, key : &TransactionKey, state : TransactionState) -> Result<(), TransactionError>{
         // We need to remove and add back to update
-        let address = self.get_transaction_address(&transaction_id).ok_or_else(||"No address for given transaction".to_string())?;
-        let mut t = self.get_transaction(transaction_id)?;
+        let address = self.get_transaction_address(key).ok_or(TransactionError::NotFound)?;
+        let mut t = self.get_transaction(key)?;
         // Update
-        t.set_state(state);
+        t.set_state(state)?;
         let storage = self.storage.clone();
         let mut storage = storage.lock().unwrap();
         // Delete
@@ -210,66 +326,116 @@ impl TransactionManager{
         let new_address = storage.store(Data::Transaction(t), TRANSACTION_POOL_NAME.to_string())?;
         let index = self.index.clone();
         let mut index = index.lock().unwrap();
-        index.remove(&transaction_id);
+        index.remove(key);
         // Update index consequently
-        index.insert(transaction_id, new_address);
+        index.insert(key.clone(), new_address);
         Ok(())
     }
 
-    ///Given an xid, returns the storage address of the bound lease
-    fn get_transaction_lease_address(&self, transaction_id : u32) -> Result<u16, String> {
-        let transaction = self.get_transaction(transaction_id)?;
+    ///Given a transaction key, returns the storage address of the bound lease
+    fn get_transaction_lease_address(&self, key : &TransactionKey) -> Result<u16, TransactionError> {
+        let transaction = self.get_transaction(key)?;
         Ok(transaction.pending_lease_address)
     }
 
     /// Gets [`LeaseV4`] bound to the [`Transaction`] identified by the given id
-    pub fn get_transaction_lease(&self, transaction_id : u32) -> Result<LeaseData, String>{
-        let transaction = self.get_transaction(transaction_id)?;
+    pub fn get_transaction_lease(&self, key : &TransactionKey) -> Result<LeaseData, TransactionError>{
+        let transaction = self.get_transaction(key)?;
+        if transaction.pending_lease_address == 0 {
+            return Err(TransactionError::NoLease);
+        }
         let storage = self.storage.clone();
         let storage = storage.lock().unwrap();
         let data = storage.get(transaction.pending_lease_address)?;
-        let lease = extract!(data, Data::Lease).ok_or_else(||"No lease".to_string())?;
+        let lease = extract!(data, Data::Lease).ok_or(TransactionError::NoLease)?;
         Ok(lease)
     }
 
     /// Returns true if the transaction exists
-    pub fn is_in(&self, xid : u32) -> bool {
+    pub fn is_in(&self, key : &TransactionKey) -> bool {
+        let index = self.index.clone();
+        let index = index.lock().unwrap();
+        index.get(key).is_some()
+    }
+
+    /// Returns the storage address (should be called location...) of a transaction given its key
+    pub fn get_transaction_address(&self, key : &TransactionKey) -> Option<u16>{
         let index = self.index.clone();
         let index = index.lock().unwrap();
-        index.get(&xid).is_some()
+        index.get(key).and_then(|t|Some(*t))
     }
 
-    /// Returns the storage address (should be called location...) of a transaction given its id
-    pub fn get_transaction_address(&self, xid : &u32) -> Option<u16>{
+    /// Returns the key of every transaction using the given xid
+    pub fn find_by_xid(&self, xid : u32) -> Vec<TransactionKey> {
         let index = self.index.clone();
         let index = index.lock().unwrap();
-        index.get(xid).and_then(|t|Some(*t))
+        index.keys().filter(|key| key.xid() == xid).cloned().collect_vec()
+    }
+
+    /// Gets the [`Transaction`] a packet belongs to
+    pub fn get_packet_transaction(&self, packet : &DhcpV4Packet) -> Result<Transaction, TransactionError> {
+        self.get_transaction(&TransactionKey::from_packet(packet))
     }
 
     /// Handle an input packet
-    pub fn handle_input(&mut self, packet : &DhcpV4Packet) -> Result<(), String> {
+    ///
+    /// Returns the reply that must be sent again if the packet is a retransmission
+    /// of an already answered DHCPDISCOVER or DHCPREQUEST
+    pub fn handle_input(&mut self, packet : &DhcpV4Packet) -> Result<Option<DhcpV4Packet>, TransactionError> {
         match packet.options.message_type() {
             Some(1) => self.handle_discover(packet),
             Some(3) => self.handle_request(packet),
-            _ => Ok(())
+            None if packet.is_bootp() => self.handle_bootrequest(packet),
+            _ => Ok(None)
         }
     }
 
-    /// Handle an input packet if the packet is a DHCPREQUEST one
-    fn handle_discover(&mut self, packet : &DhcpV4Packet) -> Result<(),String>{
-        let xid = packet.xid;
-        //Abort if DHCP Discover has an xid that is already bound to a transaction
-        if self.is_in(xid) {
-            return Err("DISCOVER trying to initiate an uncommited transaction, aborting.".to_string())
+    /// Handles an input packet if the packet is a BOOTREQUEST one.
+    ///
+    /// BOOTP is a single request / reply exchange : no transaction is
+    /// created, and a retransmitted request gets the reply sent before.
+    fn handle_bootrequest(&mut self, packet : &DhcpV4Packet) -> Result<Option<DhcpV4Packet>, TransactionError> {
+        Ok(self.get_cached_reply(&TransactionKey::from_packet(packet), None))
+    }
+
+    /// Handle an input packet if the packet is a DHCPDISCOVER one
+    fn handle_discover(&mut self, packet : &DhcpV4Packet) -> Result<Option<DhcpV4Packet>, TransactionError>{
+        let key = TransactionKey::from_packet(packet);
+        // DISCOVER retransmitted by the client
+        if self.is_in(&key) {
+            let t = self.get_transaction(&key)?;
+            return match t.state {
+                // Offer was already sent, send it again
+                TransactionState::Waiting => Ok(self.get_cached_reply(&key, Some(2))),
+                // Offer is still being built or client already requested the lease
+                _ => Ok(None)
+            }
+        }
+        // DISCOVER retransmitted after a rapid commit ACK
+        if packet.options.rapid_commit() {
+            if let Some(ack) = self.get_cached_reply(&key, Some(5)) {
+                return Ok(Some(ack));
+            }
         }
         //Else initiate new transaction
-        self.initiate_transaction(xid)
+        self.initiate_transaction(&key)?;
+        Ok(None)
     }
 
     /// Handles an input packet if the packet is a DHCPREQUEST one
-    fn handle_request(& mut self, packet : &DhcpV4Packet) -> Result<(), String> {
-        let xid = packet.xid;
-        let t = self.get_transaction(xid)?;
+    fn handle_request(& mut self, packet : &DhcpV4Packet) -> Result<Option<DhcpV4Packet>, TransactionError> {
+        let key = TransactionKey::from_packet(packet);
+        // REQUEST retransmitted by the client after our ACK
+        if !self.is_in(&key) {
+            if let Some(ack) = self.get_cached_reply(&key, Some(5)) {
+                return Ok(Some(ack));
+            }
+        }
+        // A client in RENEWING or REBINDING state fills ciaddr and sends no server identifier
+        if packet.options.server_identifier().is_none() && !packet.ciaddr.is_unspecified() {
+            return self.handle_renewal(packet);
+        }
+        let t = self.get_transaction(&key)?;
         match packet.options.server_identifier() {
 
             Some(address) => {
@@ -277,99 +443,369 @@ impl TransactionManager{
                     /// Client responded to our DHCPOFFER by chosing our address as server_identifier
                     if address == ADDRESS {
                         match t.state {
-                            // If the state was WAITING...
-                            TransactionState::Waiting(_) => {
-                                ///... we switch to REQUESTED state
-                                self.update_transaction_state(xid, TransactionState::Requested("REQUESTED".to_string()))?;
-                                return Ok(());
+                            // REQUEST retransmitted before our ACK was sent
+                            TransactionState::Requested => return Ok(None),
+                            // If the state was WAITING, we switch to REQUESTED state. Any other state
+                            // means somebody is doing something nasty or the dhcp is not working
+                            _ => {
+                                self.update_transaction_state(&key, TransactionState::Requested)?;
+                                return Ok(None);
                             }
-                            // Either somebody is doing something nasty or the dhcp is not working
-                            _ => return Err("Trying to request a lease never awaited".to_string())
                         }
                     }
                     // Client chose another server and didn't answer our DHCPOFFER
                     else {
-                        self.abort(xid)?;
-                        return Ok(())
+                        self.abort(&key)?;
+                        return Ok(None)
                     }
                 }
             }
-            _ => return Err("Unvalid Server Identifier".to_string())
+            _ => return Err(TransactionError::InvalidServerIdentifier)
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    /// Handles an input packet if the packet is a DHCPREQUEST sent by a RENEWING client
+    fn handle_renewal(&mut self, packet : &DhcpV4Packet) -> Result<Option<DhcpV4Packet>, TransactionError> {
+        // The lease may have been commited by the failover partner
+        self.sync_failover()?;
+        // The lease will be extended once the DHCPACK is sent, we only
+        // check that we know about it
+        match self.get_lease_address(&packet.ciaddr) {
+            Some(_) => Ok(None),
+            None => Err(TransactionError::UnknownLease(packet.ciaddr))
+        }
+    }
+
+    /// Returns the last reply sent for the given transaction if it has the given message type,
+    /// BOOTREPLY having none
+    fn get_cached_reply(&self, key : &TransactionKey, message_type : Option<u8>) -> Option<DhcpV4Packet> {
+        let replies = self.replies.lock().unwrap();
+        replies.get(key)
+            .filter(|(reply, _)| reply.options.message_type() == message_type)
+            .map(|(reply, _)| reply.clone())
+    }
+
+    /// Keeps a reply so that it can be sent again to a retransmitting client
+    fn cache_reply(&self, packet : &DhcpV4Packet) {
+        let mut replies = self.replies.lock().unwrap();
+        replies.insert(TransactionKey::from_packet(packet), (packet.clone(), self.clock.now()));
     }
 
     /// Handles an output [`DhcpV4Packet`]
-    pub fn handle_output(&mut self, packet : &DhcpV4Packet) -> Result<(), String>{
+    pub fn handle_output(&mut self, packet : &DhcpV4Packet) -> Result<(), TransactionError>{
         match packet.options.message_type() {
             Some(2) => self.handle_offer(packet),
             Some(5) => self.handle_ack(packet),
             Some(6) => self.handle_nack(packet),
+            // BOOTREPLY, kept for retransmitted BOOTREQUEST
+            None if packet.op == 2 => {
+                self.cache_reply(packet);
+                Ok(())
+            }
             _ => Ok(())
         }
     }
 
+    /// Commits the [`LeaseV4`] given to a BOOTP client.
+    ///
+    /// BOOTP clients never renew nor release their address : the lease
+    /// is stored without any transaction, and should be created with an
+    /// infinite duration (see [`crate::leases::lease_time::INFINITE_LEASE_TIME`]).
+    /// The BOOTREQUEST identifies the client in the DNS updates.
+    pub fn commit_bootp_lease(&mut self, packet : &DhcpV4Packet, mut lease : LeaseV4) -> Result<u16, TransactionError> {
+        let address = lease.addr();
+        let hostname = self.client_hostname(packet, &lease);
+        *lease.hostname_mut() = hostname;
+        let lease = LeaseData::from(lease);
+        self.replicate_lease(packet, &lease);
+        self.publish_lease(packet, &lease);
+        let storage = self.storage.clone();
+        let mut storage = storage.lock().unwrap();
+        let lease_address = storage.store(Data::Lease(lease), LEASE_POOL_NAME.to_string())?;
+        let leases = self.leases.clone();
+        let mut leases = leases.lock().unwrap();
+        leases.insert(address, lease_address);
+        Ok(lease_address)
+    }
+
     /// Handles an output packet if the packet is a DHCPACK one
-    fn handle_ack(&mut self, packet : &DhcpV4Packet) -> Result<(), String>{
-        let xid = packet.xid;
-        let t = self.get_transaction(xid)?;
+    fn handle_ack(&mut self, packet : &DhcpV4Packet) -> Result<(), TransactionError>{
+        let key = TransactionKey::from_packet(packet);
+        // No transaction means this ACK answers a renewal
+        if !self.is_in(&key) {
+            let address = if packet.yiaddr.is_unspecified() { packet.ciaddr } else { packet.yiaddr };
+            let lease_time = packet.options.lease_time().ok_or(TransactionError::MissingLeaseTime)?;
+            self.renew_lease(address, Duration::seconds(lease_time as i64))?;
+            let lease = self.get_lease(&address)?;
+            self.record_binding(packet, &lease);
+            self.replicate_lease(packet, &lease);
+            self.publish_lease(packet, &lease);
+            self.cache_reply(packet);
+            return Ok(());
+        }
+        let t = self.get_transaction(&key)?;
         match t.state() {
             // If the transaction was requested and ACK is being sent, transaction must be commited
-            TransactionState::Requested(_e) => self.commit(xid),
+            TransactionState::Requested => {
+                let lease = self.get_transaction_lease(&key)?;
+                self.commit(&key)?;
+                self.record_binding(packet, &lease);
+                self.replicate_lease(packet, &lease);
+                self.publish_lease(packet, &lease);
+                self.cache_reply(packet);
+                Ok(())
+            },
+            // Rapid commit (RFC 4039) : the DISCOVER is directly acknowledged,
+            // the lease is commited without any REQUEST
+            TransactionState::Bound if packet.options.rapid_commit() => {
+                let lease = self.get_transaction_lease(&key)?;
+                self.commit(&key)?;
+                self.record_binding(packet, &lease);
+                self.replicate_lease(packet, &lease);
+                self.publish_lease(packet, &lease);
+                self.cache_reply(packet);
+                Ok(())
+            },
             _ => Ok(()) // Maybe will need something else there
         }
     }
 
+    /// Records the client and relay agent of a DHCPACK
+    /// as the binding of its commited lease
+    fn record_binding(&self, packet : &DhcpV4Packet, lease : &LeaseData) {
+        let binding = LeaseBinding::from_packet(packet, lease.address(), self.clock.now(), lease.expiration());
+        self.bindings.lock().unwrap().insert(binding);
+    }
+
+    /// Sends a commited or renewed lease to the failover partner, if any,
+    /// along with the client of the DHCPACK or BOOTREQUEST
+    fn replicate_lease(&self, packet : &DhcpV4Packet, lease : &LeaseData) {
+        if let Some(failover) = &self.failover {
+            let binding = LeaseBinding::from_packet(packet, lease.address(), self.clock.now(), lease.expiration());
+            failover.publish(lease, &binding);
+        }
+    }
+
+    /// Stores a lease commited, renewed or released by the failover partner,
+    /// along with its binding, so that its client can renew it here and be
+    /// answered to leasequeries.
+    ///
+    /// Unlike [`TransactionManager::release_lease`], dropping a lease of the
+    /// partner is neither sent back to it nor to the DNS.
+    pub fn commit_replicated_lease(&mut self, update : &LeaseUpdate) -> Result<(), TransactionError> {
+        let leases = self.leases.clone();
+        let mut leases = leases.lock().unwrap();
+        let storage = self.storage.clone();
+        let mut storage = storage.lock().unwrap();
+        if let Some(lease_address) = leases.remove(&update.address) {
+            storage.delete(lease_address, LEASE_POOL_NAME.to_string());
+        }
+        let mut bindings = self.bindings.lock().unwrap();
+        if update.released {
+            bindings.remove(&update.address);
+            return Ok(());
+        }
+        let lease = LeaseData::new(update.address, update.hostname.clone(), update.expiration);
+        let lease_address = storage.store(Data::Lease(lease), LEASE_POOL_NAME.to_string())?;
+        leases.insert(update.address, lease_address);
+        bindings.insert(LeaseBinding {
+            address: update.address,
+            htype: update.htype,
+            chaddr: update.chaddr.clone(),
+            client_id: update.client_id.clone(),
+            relay_agent_info: None,
+            last_transaction: self.clock.now(),
+            expiration: update.expiration,
+        });
+        Ok(())
+    }
+
+    /// Commits the leases received from the failover partner, if any
+    fn sync_failover(&mut self) -> Result<(), TransactionError> {
+        let updates = match &self.failover {
+            Some(failover) => failover.take_partner_updates(),
+            None => return Ok(())
+        };
+        for update in updates.iter() {
+            self.commit_replicated_lease(update)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the lease of the address belongs to the failover partner
+    fn is_partner_lease(&self, address : &Ipv4Addr) -> bool {
+        match &self.failover {
+            Some(failover) => failover.partner_lease(address).is_some(),
+            None => false
+        }
+    }
+
+    /// Publishes the name of the client of a DHCPACK or BOOTREQUEST in the DNS,
+    /// if dynamic DNS updates are enabled, following the flags
+    /// of the client FQDN option the server answered with
+    fn publish_lease(&self, packet : &DhcpV4Packet, lease : &LeaseData) {
+        if let Some((ddns, _)) = &self.ddns {
+            ddns.publish(DdnsLease {
+                address: lease.address(),
+                hostname: lease.hostname().to_string(),
+                identifier: DhcidIdentifier::from_packet(packet),
+                client_flags: packet.options.client_fqdn().map(|fqdn| fqdn.flags()),
+            });
+        }
+    }
+
+    /// Returns the sanitized name of the client of a request : the name of its
+    /// client FQDN option if any, the hostname of its lease otherwise, falling
+    /// back to its hostname option for unnamed leases
+    fn client_hostname(&self, packet : &DhcpV4Packet, lease : &LeaseV4) -> String {
+        let requested = match lease.hostname().is_empty() {
+            true => HostnamePolicy::requested_name(&packet.options),
+            false => packet.options.client_fqdn().map(|fqdn| fqdn.domain_name()).filter(|name| !name.is_empty())
+        };
+        self.hostname_policy.apply(requested.unwrap_or(lease.hostname()), lease.addr())
+    }
+
+    /// Returns the client FQDN option (81) answering the one of a request, if any : the
+    /// name of the client, qualified in the forward zone, and whether the server updates
+    /// its A record
+    pub fn client_fqdn_reply(&self, request : &DhcpV4Packet, hostname : &str) -> Option<ClientFqdn> {
+        let fqdn = request.options.client_fqdn()?;
+        match &self.ddns {
+            Some((_, settings)) => Some(fqdn.reply(&settings.fqdn(hostname), settings.updates_forward(Some(fqdn.flags())))),
+            // Without dynamic DNS updates, the client is left to update its records
+            None => Some(fqdn.reply(hostname, false))
+        }
+    }
+
+    /// Sets the client FQDN option (81) of a DHCPACK, answering the one of the request
+    /// with the name of the lease being acknowledged. Must be called before the DHCPACK
+    /// is handled, so that the DNS is updated as the client was told.
+    pub fn set_client_fqdn_reply(&self, request : &DhcpV4Packet, ack : &mut DhcpV4Packet) {
+        if ack.options.message_type() != Some(5) {
+            return;
+        }
+        let key = TransactionKey::from_packet(ack);
+        let address = if ack.yiaddr.is_unspecified() { ack.ciaddr } else { ack.yiaddr };
+        let lease = self.get_transaction_lease(&key).or_else(|_| self.get_lease(&address));
+        if let Ok(lease) = lease {
+            ack.options.set_client_fqdn(self.client_fqdn_reply(request, lease.hostname()));
+        }
+    }
+
     /// Handles an output packet if the packet is a DHCPNACK one
-    fn handle_nack(&mut self, _packet : &DhcpV4Packet) -> Result<(), String>{
+    fn handle_nack(&mut self, _packet : &DhcpV4Packet) -> Result<(), TransactionError>{
         Ok(())
     }
 
     /// Handles an output packet if the packet is a DHCPOFFER one
-    fn handle_offer(&mut self, packet :&DhcpV4Packet) -> Result<(), String>{
-        let xid = packet.xid;
-        let t = self.get_transaction(xid)?;
-        match t.state {
-            TransactionState::Bound(_e) => self.update_transaction_state(xid, TransactionState::Waiting("WAITING".to_string()))?,
-            _ => return Err("Trying to offer but no lease has been bound...".to_string())
-        };
+    fn handle_offer(&mut self, packet :&DhcpV4Packet) -> Result<(), TransactionError>{
+        let key = TransactionKey::from_packet(packet);
+        // Offering is only allowed once a lease has been bound
+        self.update_transaction_state(&key, TransactionState::Waiting)?;
+        self.cache_reply(packet);
 
         Ok(())
     }
 
     /// Creates new [`TransactionManager`] from a shared [`RuntimeStorage`]
     pub fn new(storage : Arc<Mutex<RuntimeStorage<Data>>>) -> Self{
-        Self { index: Arc::new(Mutex::new(HashMap::new())), storage}
+        Self::with_clock(storage, Arc::new(SystemClock))
+    }
+
+    /// Creates new [`TransactionManager`] from a shared [`RuntimeStorage`] and the [`Clock`]
+    /// used to time transactions and leases
+    pub fn with_clock(storage : Arc<Mutex<RuntimeStorage<Data>>>, clock : Arc<dyn Clock>) -> Self{
+        Self {
+            index: Arc::new(Mutex::new(HashMap::new())),
+            leases: Arc::new(Mutex::new(HashMap::new())),
+            storage,
+            clock,
+            offer_timeout: TransactionCfg::default().offer_timeout(),
+            replies: Arc::new(Mutex::new(HashMap::new())),
+            ddns: None,
+            hostname_policy: HostnamePolicy::default(),
+            bindings: Arc::new(Mutex::new(BindingTable::new())),
+            failover: None
+        }
+    }
+
+    /// Sends the lease events to the given [`DdnsQueue`], so that
+    /// the names of the clients are published in the DNS following
+    /// the given [`DdnsSettings`]
+    pub fn set_ddns(&mut self, ddns : DdnsQueue, settings : DdnsSettings) {
+        self.ddns = Some((ddns, settings));
+    }
+
+    /// Sets the [`HostnamePolicy`] applied to the hostnames
+    /// of the leases before they are stored
+    pub fn set_hostname_policy(&mut self, hostname_policy : HostnamePolicy) {
+        self.hostname_policy = hostname_policy;
+    }
+
+    /// Sends the commited and released leases to the
+    /// failover partner of the given [`FailoverPeer`]
+    pub fn set_failover(&mut self, failover : Arc<FailoverPeer>) {
+        self.failover = Some(failover);
+    }
+
+    /// Returns the [`BindingTable`] of the commited leases,
+    /// to be shared with a leasequery responder
+    pub fn bindings(&self) -> Arc<Mutex<BindingTable>> {
+        self.bindings.clone()
+    }
+
+    /// Applies the settings of the `transactions` section
+    /// of the main configuration file
+    pub fn apply_cfg(&mut self, cfg : &TransactionCfg) {
+        self.set_offer_timeout(cfg.offer_timeout());
+    }
+
+    /// Sets the time during which an offered lease is held before the transaction
+    /// is aborted, 30 seconds by default
+    pub fn set_offer_timeout(&mut self, offer_timeout : Duration) {
+        self.offer_timeout = offer_timeout;
+    }
+
+    pub fn offer_timeout(&self) -> Duration {
+        self.offer_timeout
     }
 
     /// Drop [`Transaction`] that have timed out
-    pub fn watchout(&mut self) -> Result<(), String>{
+    pub fn watchout(&mut self) -> Result<(), TransactionError>{
         // Lock index
         let index = self.index.clone();
         let index_list = index.clone();
-        let keys : Vec<u32>;
+        let keys : Vec<TransactionKey>;
         {
             let index_list = index_list.lock().unwrap();
-            // Sus code but didn't know how to do better
-            keys = index_list.keys().collect_vec().into_iter().map(|k| *k).collect_vec();
+            keys = index_list.keys().cloned().collect_vec();
         }
         // Check which transactions are outdated
+        let now = self.clock.now();
+        let timeout = self.offer_timeout;
         let outdated_transactions = keys.into_iter().filter(|key|{
-            let t = *key;
-            match self.get_transaction(t){
+            match self.get_transaction(key){
                 Ok(t) =>{
-                    t.outdated()
+                    t.outdated(now, timeout)
                 },
                 _ => false
             }
         }).collect_vec();
 
         // Abort every transaction that are outdated
-        for transaction_id in outdated_transactions {
-            println!("Aborting {}", transaction_id);
-            self.abort(transaction_id)?;
+        for key in outdated_transactions {
+            println!("Aborting {}", key.xid());
+            self.abort(&key)?;
         };
+
+        // Commit the leases of the failover partner
+        self.sync_failover()?;
+
+        // Forget replies that are too old to be asked again
+        let mut replies = self.replies.lock().unwrap();
+        replies.retain(|_, (_, sent)| (now - *sent) <= timeout);
         Ok(())
     }
 
@@ -389,13 +825,20 @@ mod test {
     use std::sync::{Arc, Mutex};
     use std::thread::sleep;
     use std::time;
-    use crate::extract;
     use crate::leases::ip_subnet::Ipv4Subnet;
     use crate::leases::lease::LeaseV4;
+    use crate::leases::lease_time::INFINITE_LEASE_TIME;
     use crate::netutils::hw_addr::HardwareAddress;
     use crate::packet::dhcp_packet::DhcpV4Packet;
-    use crate::transactions::manager::{TransactionManager, Transaction, TransactionState, ADDRESS};
+    use crate::transactions::error::TransactionError;
+    use crate::transactions::manager::{TransactionManager, Transaction, TransactionKey, TransactionState, ADDRESS};
+    use crate::transactions::reclaimer::LeaseReclaimer;
+    use crate::clock::clock::MockClock;
     use crate::data::data::{Data, LeaseData};
+    use crate::ddns::updater::{DdnsEvent, DdnsQueue, DdnsSettings};
+    use crate::leases::hostname::HostnamePolicy;
+    use crate::packet::client_fqdn::{ClientFqdn, FqdnFlags};
+    use crate::failover::{load_balance::FailoverRole, peer::{FailoverPeer, FailoverSettings}};
 
     const DHCP_REQUEST : [u8; 300] = [
         0x01, 0x01, 0x06, 0x00, 0xaa, 0xed,
@@ -526,6 +969,661 @@ mod test {
         tokio::time::sleep(time::Duration::from_secs(7)).await;
     }
 
+    fn mock_manager(clock : MockClock) -> TransactionManager {
+        let db = DbManager::new(String::from("dhcp"), String::from("frozenpeach"), String::from("poney"), String::from("127.0.0.1:3333"));
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(Arc::new(Mutex::new(db)));
+        let transaction_manager = TransactionManager::with_clock(Arc::new(Mutex::new(storage)), Arc::new(clock));
+        transaction_manager.init();
+        transaction_manager
+    }
+
+    fn ddns_settings() -> DdnsSettings {
+        DdnsSettings {
+            server: "127.0.0.1:53".parse().unwrap(),
+            forward_zone: String::from("example.com"),
+            reverse_zone: None,
+            ttl: 300,
+            timeout: 1000,
+            key: None,
+            override_client_update: false,
+        }
+    }
+
+    #[test]
+    fn test_transaction_timeout(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        manager.handle_input(&packet_discover).unwrap();
+
+        clock.advance(Duration::seconds(30));
+        manager.watchout().unwrap();
+        assert!(manager.is_in(&TransactionKey::from_packet(&packet_discover)));
+
+        clock.advance(Duration::seconds(1));
+        manager.watchout().unwrap();
+        assert!(!manager.is_in(&TransactionKey::from_packet(&packet_discover)));
+    }
+
+    #[test]
+    fn test_configurable_timeout(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        manager.set_offer_timeout(Duration::seconds(10));
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        manager.handle_input(&packet_discover).unwrap();
+
+        clock.advance(Duration::seconds(11));
+        manager.watchout().unwrap();
+        assert!(!manager.is_in(&TransactionKey::from_packet(&packet_discover)));
+    }
+
+    #[test]
+    fn test_apply_cfg(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        assert!(manager.offer_timeout() == Duration::seconds(30));
+
+        let cfg = crate::cfg::main_cfg::load_main_cfg("tests/main.yml").unwrap();
+        manager.apply_cfg(cfg.transaction_cfg());
+        assert!(manager.offer_timeout() == Duration::seconds(10));
+    }
+
+    #[test]
+    fn test_discover_retransmission(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 6),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+        ).unwrap();
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        let packet_offer = DhcpV4Packet::from_raw_bytes(DHCP_OFFER.as_slice());
+        let mut packet_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        packet_request.options.set_server_identifier(Some(ADDRESS));
+        let xid = packet_discover.xid;
+        let key = TransactionKey::from_packet(&packet_discover);
+
+        // PENDING : nothing to send again, transaction is kept
+        assert!(manager.handle_input(&packet_discover).unwrap().is_none());
+        assert!(manager.handle_input(&packet_discover).unwrap().is_none());
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Pending);
+
+        // BOUND : offer is not sent yet
+        manager.bind_lease(&packet_discover, lease).unwrap();
+        assert!(manager.handle_input(&packet_discover).unwrap().is_none());
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Bound);
+
+        // WAITING : same offer is sent again
+        manager.handle_output(&packet_offer).unwrap();
+        let offer = manager.handle_input(&packet_discover).unwrap().unwrap();
+        assert_eq!(offer.xid, xid);
+        assert_eq!(offer.options.message_type(), Some(2));
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Waiting);
+
+        // REQUESTED : client is past the discovery
+        manager.handle_input(&packet_request).unwrap();
+        assert!(manager.handle_input(&packet_discover).unwrap().is_none());
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Requested);
+    }
+
+    #[test]
+    fn test_bootp_retransmission(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let (queue, events) = DdnsQueue::new();
+        manager.set_ddns(queue, ddns_settings());
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 200),
+            &subnet,
+            Duration::seconds(INFINITE_LEASE_TIME as i64),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("bootp_lease"),
+        ).unwrap();
+        let mut bootrequest = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        bootrequest.options.set_message_type(None);
+        let mut bootreply = bootrequest.clone();
+        bootreply.op = 2;
+        let key = TransactionKey::from_packet(&bootrequest);
+        assert!(bootrequest.is_bootp());
+
+        // No transaction is created for BOOTP clients
+        assert!(manager.handle_input(&bootrequest).unwrap().is_none());
+        assert!(!manager.is_in(&key));
+
+        // Lease is committed right away, and published
+        manager.commit_bootp_lease(&bootrequest, lease).unwrap();
+        assert!(manager.get_lease_address(&Ipv4Addr::new(192, 168, 0, 200)).is_some());
+        assert_matches!(
+            events.try_recv(),
+            Ok(DdnsEvent::Publish(lease)) if (lease.hostname == "bootp_lease") & (lease.address == Ipv4Addr::new(192, 168, 0, 200))
+        );
+
+        // Retransmitted request gets the same reply
+        manager.handle_output(&bootreply).unwrap();
+        let reply = manager.handle_input(&bootrequest).unwrap().unwrap();
+        assert_eq!(reply.xid, bootrequest.xid);
+        assert_eq!(reply.op, 2);
+    }
+
+    #[test]
+    fn test_rapid_commit(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 8),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("rapid_lease"),
+        ).unwrap();
+        let mut packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        packet_discover.options.set_rapid_commit(true);
+        let mut packet_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        packet_ack.options.set_rapid_commit(true);
+        let key = TransactionKey::from_packet(&packet_discover);
+
+        assert!(manager.handle_input(&packet_discover).unwrap().is_none());
+        manager.bind_lease(&packet_discover, lease).unwrap();
+
+        // ACK answers the DISCOVER, no REQUEST is needed
+        manager.handle_output(&packet_ack).unwrap();
+        assert!(!manager.is_in(&key));
+        let lease = manager.get_lease(&Ipv4Addr::new(192, 168, 0, 8)).unwrap();
+        assert_eq!(lease.address(), Ipv4Addr::new(192, 168, 0, 8));
+
+        // Retransmitted DISCOVER gets the same ACK
+        let ack = manager.handle_input(&packet_discover).unwrap().unwrap();
+        assert_eq!(ack.options.message_type(), Some(5));
+        assert!(ack.options.rapid_commit());
+    }
+
+    // Binds a lease of 192.168.0.10 named after the given hostname to a rapid commit
+    // DISCOVER, sent with the given client FQDN option. Returns the DISCOVER, along
+    // with the ACK that commits the lease once handled
+    fn rapid_commit_lease(manager : &mut TransactionManager, hostname : &str, client_fqdn : Option<ClientFqdn>) -> (DhcpV4Packet, DhcpV4Packet) {
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 10),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            hostname.to_string(),
+        ).unwrap();
+        let mut packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        packet_discover.options.set_rapid_commit(true);
+        packet_discover.options.set_client_fqdn(client_fqdn);
+        let mut packet_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        packet_ack.options.set_rapid_commit(true);
+
+        manager.handle_input(&packet_discover).unwrap();
+        manager.bind_lease(&packet_discover, lease).unwrap();
+        (packet_discover, packet_ack)
+    }
+
+    #[test]
+    fn test_ddns_events(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let (queue, events) = DdnsQueue::new();
+        manager.set_ddns(queue, ddns_settings());
+        let (_, packet_ack) = rapid_commit_lease(&mut manager, "host1", None);
+        assert!(events.try_recv().is_err());
+
+        // Commited leases are published
+        manager.handle_output(&packet_ack).unwrap();
+        assert_matches!(
+            events.try_recv(),
+            Ok(DdnsEvent::Publish(lease)) if (lease.hostname == "host1") & (lease.address == Ipv4Addr::new(192, 168, 0, 10))
+        );
+
+        // Released leases are withdrawn
+        manager.release_lease(&Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+        assert_matches!(events.try_recv(), Ok(DdnsEvent::Withdraw(address)) if address == Ipv4Addr::new(192, 168, 0, 10));
+    }
+
+    #[test]
+    fn test_hostname_policy(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let (queue, events) = DdnsQueue::new();
+        manager.set_ddns(queue, ddns_settings());
+        manager.set_hostname_policy(HostnamePolicy { domain_suffix: Some(String::from("example.com")), ..Default::default() });
+        let request_fqdn = ClientFqdn::new(FqdnFlags { encoded: true, ..Default::default() }, "My PC");
+
+        // The client FQDN option takes precedence over the hostname of the lease
+        let (packet_discover, mut packet_ack) = rapid_commit_lease(&mut manager, "MBP-de-Sacha\0", Some(request_fqdn.clone()));
+        let key = TransactionKey::from_packet(&packet_discover);
+        assert_eq!(manager.get_transaction_lease(&key).unwrap().hostname(), "my-pc.example.com");
+
+        // The client updates its A record itself
+        manager.set_client_fqdn_reply(&packet_discover, &mut packet_ack);
+        let reply_fqdn = request_fqdn.reply("my-pc.example.com", false);
+        assert_eq!(packet_ack.options.client_fqdn(), Some(&reply_fqdn));
+
+        manager.handle_output(&packet_ack).unwrap();
+        assert_matches!(
+            events.try_recv(),
+            Ok(DdnsEvent::Publish(lease)) if (lease.hostname == "my-pc.example.com") & (lease.client_flags == Some(reply_fqdn.flags()))
+        );
+    }
+
+    #[test]
+    fn test_lease_bindings(){
+        let now = chrono::Utc::now();
+        let clock = MockClock::new(now);
+        let mut manager = mock_manager(clock.clone());
+        let bindings = manager.bindings();
+        let (_, mut packet_ack) = rapid_commit_lease(&mut manager, "binding_lease", None);
+        packet_ack.options.set_client_identifier(Some(vec![0x01, 0xf8, 0x4d, 0x89, 0x82, 0x44, 0x2a]));
+        assert!(bindings.lock().unwrap().is_empty());
+
+        manager.handle_output(&packet_ack).unwrap();
+        {
+            let bindings = bindings.lock().unwrap();
+            let binding = bindings.get(&Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+            assert_eq!(binding.chaddr, packet_ack.chadd.raw[..6].to_vec());
+            assert_eq!(binding.last_transaction, now);
+            assert_eq!(bindings.by_client_id(&[0x01, 0xf8, 0x4d, 0x89, 0x82, 0x44, 0x2a]).len(), 1);
+        }
+
+        manager.release_lease(&Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+        assert!(bindings.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failover_updates(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let partner : std::net::SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let peer = Arc::new(FailoverPeer::new(FailoverSettings {
+            role: FailoverRole::Primary,
+            address: partner,
+            partner,
+            mclt: 3600,
+            split: 128,
+            heartbeat: 1000,
+            partner_timeout: 3000,
+            auto_partner_down: None,
+        }));
+        manager.set_failover(peer.clone());
+        let (_, packet_ack) = rapid_commit_lease(&mut manager, "failover_lease", None);
+        assert_eq!(peer.pending_updates(), 0);
+
+        // The partner is unreachable : updates wait for it
+        manager.handle_output(&packet_ack).unwrap();
+        assert_eq!(peer.pending_updates(), 1);
+        manager.release_lease(&Ipv4Addr::new(192, 168, 0, 10)).unwrap();
+        assert_eq!(peer.pending_updates(), 1);
+    }
+
+    #[test]
+    fn test_partner_lease_renewal(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let settings = |role, address, partner| FailoverSettings {
+            role,
+            address,
+            partner,
+            mclt: 3600,
+            split: 128,
+            heartbeat: 20,
+            partner_timeout: 200,
+            auto_partner_down: None,
+        };
+        let primary_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let secondary_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (primary_address, secondary_address) = (primary_listener.local_addr().unwrap(), secondary_listener.local_addr().unwrap());
+        let primary = Arc::new(FailoverPeer::new(settings(FailoverRole::Primary, primary_address, secondary_address)));
+        let secondary = Arc::new(FailoverPeer::new(settings(FailoverRole::Secondary, secondary_address, primary_address)));
+        primary.start(primary_listener);
+        secondary.start(secondary_listener);
+        let mut primary_manager = mock_manager(clock.clone());
+        let mut secondary_manager = mock_manager(clock.clone());
+        primary_manager.set_failover(primary.clone());
+        secondary_manager.set_failover(secondary.clone());
+
+        // The primary commits a lease, then goes down
+        let address = Ipv4Addr::new(192, 168, 0, 10);
+        let (_, packet_ack) = rapid_commit_lease(&mut primary_manager, "partner_lease", None);
+        primary_manager.handle_output(&packet_ack).unwrap();
+        for _ in 0..250 {
+            if secondary.partner_lease(&address).is_some() {
+                break;
+            }
+            sleep(time::Duration::from_millis(20));
+        }
+        assert!(secondary.partner_lease(&address).is_some());
+        primary.shutdown();
+        secondary.set_partner_down();
+
+        // The secondary renews it for its client
+        let mut renew_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        let mut renew_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        renew_request.ciaddr = address;
+        renew_request.xid = 0x1234;
+        renew_ack.xid = 0x1234;
+        renew_ack.ciaddr = address;
+        renew_ack.yiaddr = address;
+        renew_ack.options.set_lease_time(Some(172800));
+        assert!(secondary_manager.handle_input(&renew_request).unwrap().is_none());
+        secondary_manager.handle_output(&renew_ack).unwrap();
+        let renewed = secondary_manager.get_lease(&address).unwrap();
+        assert_eq!(renewed.hostname(), "partner_lease");
+        assert!(renewed.expiration() > clock.now() + Duration::days(1));
+
+        // And answers leasequeries about its client
+        let bindings = secondary_manager.bindings();
+        let bindings = bindings.lock().unwrap();
+        let binding = bindings.get(&address).unwrap();
+        let primary_bindings = primary_manager.bindings();
+        let primary_bindings = primary_bindings.lock().unwrap();
+        let primary_binding = primary_bindings.get(&address).unwrap();
+        assert_eq!((binding.htype, &binding.chaddr, &binding.client_id), (primary_binding.htype, &primary_binding.chaddr, &primary_binding.client_id));
+        secondary.shutdown();
+    }
+
+    #[test]
+    fn test_ack_without_rapid_commit(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 9),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("lease"),
+        ).unwrap();
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        let packet_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        let key = TransactionKey::from_packet(&packet_discover);
+
+        manager.handle_input(&packet_discover).unwrap();
+        manager.bind_lease(&packet_discover, lease).unwrap();
+
+        // A bound lease is only commited through rapid commit
+        manager.handle_output(&packet_ack).unwrap();
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Bound);
+        assert!(manager.get_lease(&Ipv4Addr::new(192, 168, 0, 9)).is_err());
+    }
+
+    #[test]
+    fn test_request_retransmission(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock.clone());
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 7),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+        ).unwrap();
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        let packet_offer = DhcpV4Packet::from_raw_bytes(DHCP_OFFER.as_slice());
+        let mut packet_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        let packet_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        packet_request.options.set_server_identifier(Some(ADDRESS));
+        let xid = packet_discover.xid;
+        let key = TransactionKey::from_packet(&packet_discover);
+
+        manager.handle_input(&packet_discover).unwrap();
+        manager.bind_lease(&packet_discover, lease.clone()).unwrap();
+        manager.handle_output(&packet_offer).unwrap();
+
+        // REQUESTED : duplicate REQUEST before the ACK is ignored
+        assert!(manager.handle_input(&packet_request).unwrap().is_none());
+        assert!(manager.handle_input(&packet_request).unwrap().is_none());
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Requested);
+
+        // COMMITED : duplicate REQUEST gets the cached ACK, lease is untouched
+        manager.handle_output(&packet_ack).unwrap();
+        let ack = manager.handle_input(&packet_request).unwrap().unwrap();
+        assert_eq!(ack.xid, xid);
+        assert_eq!(ack.options.message_type(), Some(5));
+        assert!(!manager.is_in(&key));
+        assert!(manager.get_lease(&lease.addr()).is_ok());
+
+        // Cached ACK is forgotten after the offer timeout
+        clock.advance(Duration::seconds(31));
+        manager.watchout().unwrap();
+        assert!(manager.handle_input(&packet_request).is_err());
+    }
+
+    #[test]
+    fn test_same_xid_clients(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock);
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 8),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("first_lease"),
+        ).unwrap();
+        let other_lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 9),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("second_lease"),
+        ).unwrap();
+
+        // Second client uses the same xid, but another client identifier
+        let other_client = Some(vec![0x01, 0x54, 0x3a, 0xd6, 0x35, 0x76, 0x08]);
+        let mut packets = vec![];
+        for raw in [DHCP_DISCOVER, DHCP_OFFER, DHCP_REQUEST, DHCP_ACK] {
+            let packet = DhcpV4Packet::from_raw_bytes(raw.as_slice());
+            let mut other_packet = packet.clone();
+            other_packet.options.set_client_identifier(other_client.clone());
+            packets.push((packet, other_packet));
+        }
+        packets[2].0.options.set_server_identifier(Some(ADDRESS));
+        packets[2].1.options.set_server_identifier(Some(ADDRESS));
+        let (discover, other_discover) = packets[0].clone();
+        let key = TransactionKey::from_packet(&discover);
+        let other_key = TransactionKey::from_packet(&other_discover);
+        assert_eq!(key.xid(), other_key.xid());
+        assert_ne!(key, other_key);
+
+        // Both transactions are interleaved
+        manager.handle_input(&discover).unwrap();
+        manager.handle_input(&other_discover).unwrap();
+        assert_eq!(manager.find_by_xid(key.xid()).len(), 2);
+        manager.bind_lease(&discover, lease.clone()).unwrap();
+        manager.bind_lease(&other_discover, other_lease.clone()).unwrap();
+        for (packet, other_packet) in packets.iter().skip(1) {
+            if packet.options.message_type() == Some(3) {
+                manager.handle_input(packet).unwrap();
+                manager.handle_input(other_packet).unwrap();
+            } else {
+                manager.handle_output(packet).unwrap();
+                manager.handle_output(other_packet).unwrap();
+            }
+        }
+
+        assert!(!manager.is_in(&key));
+        assert!(!manager.is_in(&other_key));
+        assert!(manager.get_lease(&lease.addr()).is_ok());
+        assert!(manager.get_lease(&other_lease.addr()).is_ok());
+    }
+
+    #[test]
+    fn test_rejected_transitions(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let mut manager = mock_manager(clock);
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 10),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+        ).unwrap();
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        let packet_offer = DhcpV4Packet::from_raw_bytes(DHCP_OFFER.as_slice());
+        let mut packet_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        packet_request.options.set_server_identifier(Some(ADDRESS));
+        let key = TransactionKey::from_packet(&packet_discover);
+
+        // Nothing is known about this client yet
+        assert_eq!(manager.handle_output(&packet_offer), Err(TransactionError::NotFound));
+        manager.handle_input(&packet_discover).unwrap();
+        assert_eq!(manager.initiate_transaction(&key), Err(TransactionError::AlreadyExists));
+
+        // PENDING : cannot offer or be requested without a bound lease
+        assert_eq!(
+            manager.handle_output(&packet_offer),
+            Err(TransactionError::IllegalTransition { from: TransactionState::Pending, to: TransactionState::Waiting })
+        );
+        assert_eq!(
+            manager.handle_input(&packet_request),
+            Err(TransactionError::IllegalTransition { from: TransactionState::Pending, to: TransactionState::Requested })
+        );
+        assert_eq!(manager.commit(&key), Err(TransactionError::IllegalTransition { from: TransactionState::Pending, to: TransactionState::Committed }));
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Pending);
+
+        // BOUND : lease cannot be bound twice, nor requested before being offered
+        manager.bind_lease(&packet_discover, lease.clone()).unwrap();
+        assert_eq!(manager.bind_lease(&packet_discover, lease), Err(TransactionError::LeaseAlreadyBound));
+        assert_eq!(
+            manager.handle_input(&packet_request),
+            Err(TransactionError::IllegalTransition { from: TransactionState::Bound, to: TransactionState::Requested })
+        );
+        assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Bound);
+
+        // Aborting is always allowed before commit
+        manager.abort(&key).unwrap();
+        assert_eq!(manager.get_transaction(&key).unwrap_err(), TransactionError::NotFound);
+    }
+
+    #[test]
+    fn test_lease_reclaim(){
+        let clock = MockClock::new(chrono::Utc::now());
+        let manager = Arc::new(Mutex::new(mock_manager(clock.clone())));
+        let reclaimer = LeaseReclaimer::new(manager.clone(), Arc::new(clock.clone()));
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::with_clock(
+            Ipv4Addr::new(192, 168, 0, 5),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+            Arc::new(clock.clone()),
+        ).unwrap();
+
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        let packet_offer = DhcpV4Packet::from_raw_bytes(DHCP_OFFER.as_slice());
+        let mut packet_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        let packet_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        packet_request.options.set_server_identifier(Some(ADDRESS));
+        {
+            let mut manager = manager.lock().unwrap();
+            manager.handle_input(&packet_discover).unwrap();
+            manager.bind_lease(&packet_discover, lease.clone()).unwrap();
+            manager.handle_output(&packet_offer).unwrap();
+            manager.handle_input(&packet_request).unwrap();
+            manager.handle_output(&packet_ack).unwrap();
+        }
+
+        clock.advance(Duration::hours(7));
+        assert!(reclaimer.reclaim().unwrap().is_empty());
+        assert!(manager.lock().unwrap().get_lease(&lease.addr()).is_ok());
+
+        clock.advance(Duration::hours(1));
+        assert_eq!(reclaimer.reclaim().unwrap(), vec![lease.addr()]);
+        assert!(manager.lock().unwrap().get_lease(&lease.addr()).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_renewal(){
+        let db = DbManager::new(String::from("dhcp"), String::from("frozenpeach"), String::from("poney"), String::from("127.0.0.1:3333"));
+        let sto_db = Arc::new(Mutex::new(db));
+        let test_db = sto_db.clone();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(sto_db);
+        let storage = Arc::new(Mutex::new(storage));
+        let sync = storage.clone();
+        let transaction_manager = TransactionManager::new(storage);
+        transaction_manager.init();
+        let manager = Arc::new(Mutex::new(transaction_manager));
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(time::Duration::from_millis(100)).await;
+                sync.lock().unwrap().sync();
+            }
+        });
+
+        let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
+        let lease = LeaseV4::new(
+            Ipv4Addr::new(192, 168, 0, 4),
+            &subnet,
+            Duration::hours(8),
+            HardwareAddress::broadcast(),
+            HardwareAddress::broadcast(),
+            String::from("test_lease"),
+        ).unwrap();
+
+        let packet_discover = DhcpV4Packet::from_raw_bytes(DHCP_DISCOVER.as_slice());
+        let packet_offer = DhcpV4Packet::from_raw_bytes(DHCP_OFFER.as_slice());
+        let mut packet_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        let mut packet_ack = DhcpV4Packet::from_raw_bytes(DHCP_ACK.as_slice());
+        packet_request.options.set_server_identifier(Some(ADDRESS));
+        {
+            let mut manager = manager.lock().unwrap();
+            manager.handle_input(&packet_discover).unwrap();
+            manager.bind_lease(&packet_discover, lease.clone()).unwrap();
+            manager.handle_output(&packet_offer).unwrap();
+            manager.handle_input(&packet_request).unwrap();
+            manager.handle_output(&packet_ack).unwrap();
+        }
+
+        // Renewing client unicasts a REQUEST with ciaddr and no server identifier
+        let mut renew_request = DhcpV4Packet::from_raw_bytes(DHCP_REQUEST.as_slice());
+        renew_request.ciaddr = lease.addr();
+        renew_request.xid = 0x1234;
+        packet_ack.xid = 0x1234;
+        packet_ack.ciaddr = lease.addr();
+        packet_ack.yiaddr = lease.addr();
+        packet_ack.options.set_lease_time(Some(172800));
+        {
+            let mut manager = manager.lock().unwrap();
+            manager.handle_input(&renew_request).unwrap();
+            manager.handle_output(&packet_ack).unwrap();
+            let renewed = manager.get_lease(&lease.addr()).unwrap();
+            assert!(renewed.expiration() > chrono::Utc::now() + Duration::days(1));
+        }
+
+        sleep(time::Duration::from_secs(1));
+        let test_db = test_db.lock().unwrap();
+        let l : Vec<LeaseData> = test_db.exec_and_return("SELECT * FROM Leases where address = :address".to_string(), params! {"address" => lease.addr().to_string()}).unwrap();
+        assert_eq!(l.len(), 1);
+        assert!(l.get(0).unwrap().expiration() > chrono::Utc::now() + Duration::days(1));
+
+        // Unknown leases cannot be renewed
+        let mut unknown_request = renew_request.clone();
+        unknown_request.ciaddr = Ipv4Addr::new(192, 168, 0, 200);
+        let mut manager = manager.lock().unwrap();
+        assert!(manager.handle_input(&unknown_request).is_err());
+    }
+
     fn discover_process(manager : Arc<Mutex<TransactionManager>>, test_db : Arc<Mutex<DbManager>>, success : bool){
         let subnet = Ipv4Subnet::new(Ipv4Addr::new(192, 168, 0, 0), 24);
         let lease = LeaseV4::new(
@@ -558,7 +1656,7 @@ mod test {
             let transaction = t.get(0).unwrap();
             assert_eq!(t.len(), 1);
             assert_eq!(transaction.xid, xid);
-            assert_matches!(transaction.state, TransactionState::Pending(_));
+            assert_matches!(transaction.state, TransactionState::Pending);
         }
 
         //Test lease binding
@@ -566,17 +1664,16 @@ mod test {
         {
             {
             let mut manager = manager.lock().unwrap();
-            manager.bind_lease(packet_discover.xid, lease.clone()).unwrap();
+            manager.bind_lease(&packet_discover, lease.clone()).unwrap();
             }
             let xid = packet_discover.xid;
             sleep(time::Duration::from_secs(1));
             let test_db = test_db.lock().unwrap();
             let t : Vec<Transaction> = test_db.exec_and_return("SELECT * FROM Transactions where identifier = :identifier".to_string(), params! {"identifier" => xid}).unwrap();
             let transaction = t.get(0).unwrap();
-            let state = transaction.state.clone();
-            assert_matches!(state, TransactionState::Bound(_));
-            let state = extract!(state, TransactionState::Bound).unwrap();
-            assert_eq!(state, "BOUND".to_string());
+            let state = transaction.state;
+            assert_matches!(state, TransactionState::Bound);
+            assert_eq!(state.as_str(), "BOUND");
 
             let l : Vec<LeaseData> = test_db.exec_and_return("SELECT * FROM PendingLeases where id = :id".to_string(), params! {"id" => transaction.pending_lease_address}).unwrap();
             let l = l.get(0).unwrap();
@@ -599,8 +1696,8 @@ mod test {
             let test_db = test_db.lock().unwrap();
             let t : Vec<Transaction> = test_db.exec_and_return("SELECT * FROM Transactions where identifier = :identifier".to_string(), params! {"identifier" => xid}).unwrap();
             let transaction = t.get(0).unwrap();
-            let state = transaction.state.clone();
-            assert_matches!(state, TransactionState::Waiting(_));
+            let state = transaction.state;
+            assert_matches!(state, TransactionState::Waiting);
 
             let l : Vec<LeaseData> = test_db.exec_and_return("SELECT * FROM PendingLeases where id = :id".to_string(), params! {"id" => transaction.pending_lease_address}).unwrap();
             let l = l.get(0).unwrap();
@@ -626,8 +1723,8 @@ mod test {
                 let test_db = test_db.lock().unwrap();
                 let t : Vec<Transaction> = test_db.exec_and_return("SELECT * FROM Transactions where identifier = :identifier".to_string(), params! {"identifier" => xid}).unwrap();
                 let transaction = t.get(0).unwrap();
-                let state = transaction.state.clone();
-                assert_matches!(state, TransactionState::Requested(_));
+                let state = transaction.state;
+                assert_matches!(state, TransactionState::Requested);
 
                 let l : Vec<LeaseData> = test_db.exec_and_return("SELECT * FROM PendingLeases where id = :id".to_string(), params! {"id" => transaction.pending_lease_address}).unwrap();
                 let l = l.get(0).unwrap();
@@ -671,9 +1768,9 @@ mod test {
             println!("Testing unvalid request handling");
             {
                 {
-                    let xid = packet_discover.xid;
+                    let key = TransactionKey::from_packet(&packet_discover);
                     let mut manager = manager.lock().unwrap();
-                    manager.update_transaction_state(xid, TransactionState::Waiting("WAITING".to_string())).unwrap();
+                    assert_matches!(manager.get_transaction(&key).unwrap().state, TransactionState::Waiting);
                     packet_request.options.set_server_identifier(Some(Ipv4Addr::BROADCAST));
                     manager.handle_input(&packet_request).unwrap();
                 }
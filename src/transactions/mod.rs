@@ -1,4 +1,6 @@
 #[macro_use]
 pub mod manager;
 pub mod transaction;
-pub mod hook;
\ No newline at end of file
+pub mod hook;
+pub mod reclaimer;
+pub mod error;
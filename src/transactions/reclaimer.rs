@@ -0,0 +1,46 @@
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use crate::clock::clock::Clock;
+
+use super::error::TransactionError;
+use super::manager::TransactionManager;
+
+/// [`LeaseReclaimer`] drops the commited leases of a [`TransactionManager`]
+/// once they expired, so that their addresses can be given back to the allocators.
+///
+/// # Examples
+/// ```
+/// let reclaimer = LeaseReclaimer::new(my_manager.clone(), Arc::new(SystemClock));
+///
+/// tokio::spawn(async move {
+/// loop {
+///     tokio::time::sleep(time::Duration::from_secs(1)).await;
+///     for address in reclaimer.reclaim().unwrap() {
+///         // free address in its subnet
+///     }
+/// }
+/// });
+/// ```
+pub struct LeaseReclaimer {
+    manager : Arc<Mutex<TransactionManager>>,
+    clock : Arc<dyn Clock>
+}
+
+impl LeaseReclaimer {
+    /// Creates new [`LeaseReclaimer`] watching the leases of the given [`TransactionManager`]
+    pub fn new(manager : Arc<Mutex<TransactionManager>>, clock : Arc<dyn Clock>) -> Self {
+        Self { manager, clock }
+    }
+
+    /// Drops every expired lease and returns their addresses
+    pub fn reclaim(&self) -> Result<Vec<Ipv4Addr>, TransactionError> {
+        let now = self.clock.now();
+        let mut manager = self.manager.lock().unwrap();
+        let expired = manager.expired_leases(now);
+        for address in expired.iter() {
+            manager.release_lease(address)?;
+        }
+        Ok(expired)
+    }
+}
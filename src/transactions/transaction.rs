@@ -1,14 +1,147 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use fp_core::utils::data::Storable;
+use itertools::Itertools;
 use mysql::{params, prelude::FromRow};
 
-#[derive(Clone, Debug)]
+use crate::packet::dhcp_packet::DhcpV4Packet;
+
+use super::error::TransactionError;
+
+/// States a [`Transaction`] can live in. A transaction goes through
+/// Undefined -> Pending -> Bound -> Waiting -> Requested -> Committed,
+/// and can be Aborted from any state that is not final.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TransactionState {
-    Pending(String),
-    Waiting(String),
-    Requested(String),
-    Undefined(String),
-    Bound(String)
+    Undefined,
+    Pending,
+    Bound,
+    Waiting,
+    Requested,
+    Committed,
+    Aborted
+}
+
+impl TransactionState {
+    /// Returns true if a transaction in this state is allowed to switch to `next`
+    pub fn can_transition_to(&self, next : TransactionState) -> bool {
+        use TransactionState::*;
+        matches!((self, next),
+            (Undefined, Pending)
+            | (Pending, Bound)
+            | (Bound, Waiting)
+            | (Waiting, Requested)
+            | (Requested, Committed)
+            // Rapid commit (RFC 4039) : DISCOVER is answered by an ACK
+            | (Bound, Committed)
+            | (Undefined | Pending | Bound | Waiting | Requested, Aborted)
+        )
+    }
+
+    /// Returns `next` if the transition is allowed, an error otherwise
+    pub fn transition(self, next : TransactionState) -> Result<TransactionState, TransactionError> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(TransactionError::IllegalTransition { from: self, to: next })
+        }
+    }
+
+    /// Returns true if no transition is allowed from this state
+    pub fn is_final(&self) -> bool {
+        matches!(self, TransactionState::Committed | TransactionState::Aborted)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionState::Undefined => "UNDEFINED",
+            TransactionState::Pending => "PENDING",
+            TransactionState::Bound => "BOUND",
+            TransactionState::Waiting => "WAITING",
+            TransactionState::Requested => "REQUESTED",
+            TransactionState::Committed => "COMMITTED",
+            TransactionState::Aborted => "ABORTED"
+        }
+    }
+}
+
+impl FromStr for TransactionState {
+    type Err = TransactionError;
+
+    fn from_str(s : &str) -> Result<Self, Self::Err> {
+        match s {
+            "UNDEFINED" => Ok(TransactionState::Undefined),
+            "PENDING" => Ok(TransactionState::Pending),
+            "BOUND" => Ok(TransactionState::Bound),
+            "WAITING" => Ok(TransactionState::Waiting),
+            "REQUESTED" => Ok(TransactionState::Requested),
+            "COMMITTED" => Ok(TransactionState::Committed),
+            "ABORTED" => Ok(TransactionState::Aborted),
+            _ => Err(TransactionError::Storage(format!("Unknown transaction state {}", s)))
+        }
+    }
+}
+
+impl fmt::Display for TransactionState {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// [`TransactionKey`] identifies a [`Transaction`]. An xid alone is chosen randomly by
+/// the client and may collide with the xid of another client, so transactions are keyed by
+/// the client identifier (or chaddr when the client sent none), the xid and the relay
+/// agent the packet went through.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TransactionKey {
+    client : Vec<u8>,
+    xid : u32,
+    relay : Ipv4Addr
+}
+
+impl TransactionKey {
+    pub fn new(client : Vec<u8>, xid : u32, relay : Ipv4Addr) -> Self {
+        Self { client, xid, relay }
+    }
+
+    /// Builds the key of the transaction a packet belongs to.
+    /// Replies carry the same chaddr and giaddr as the request, and echo the client
+    /// identifier (RFC 6842), so that input and output packets share the same key.
+    pub fn from_packet(packet : &DhcpV4Packet) -> Self {
+        let client = match packet.options.client_identifier() {
+            Some(client_id) if !client_id.is_empty() => client_id.clone(),
+            _ => {
+                let hlen = (packet.hlen as usize).min(packet.chadd.raw.len());
+                packet.chadd.raw[..hlen].to_vec()
+            }
+        };
+        Self::new(client, packet.xid, packet.giaddr)
+    }
+
+    pub fn client(&self) -> &[u8] {
+        &self.client
+    }
+
+    pub fn xid(&self) -> u32 {
+        self.xid
+    }
+
+    pub fn relay(&self) -> Ipv4Addr {
+        self.relay
+    }
+}
+
+fn _format_hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).join("")
+}
+
+fn _parse_hex(hex : &str) -> Vec<u8> {
+    (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok())
+        .collect()
 }
 
 //Make Transactions storable
@@ -18,15 +151,12 @@ impl Storable for Transaction{
     }
 
     fn insert_statement(&self, place : String) -> String {
-        format!("INSERT INTO {} VALUE (:type, :id, :identifier, :time, :lease_address, :state)", place)
+        format!("INSERT INTO {} VALUE (:type, :id, :identifier, :time, :lease_address, :state, :client, :relay)", place)
     }
 
     fn value(&self) -> mysql::params::Params {
-        let state : String;
-        match &self.state {
-            TransactionState::Pending(e)|TransactionState::Requested(e)|TransactionState::Waiting(e)|TransactionState::Undefined(e)|TransactionState::Bound(e) => state = e.to_string()
-        }
-        params! {"type" => "transaction", "id" => self.uid, "identifier" => self.xid, "time" => self.start.to_rfc2822(), "lease_address" => self.pending_lease_address, "state" => state}
+        let state = self.state.as_str();
+        params! {"type" => "transaction", "id" => self.uid, "identifier" => self.xid, "time" => self.start.to_rfc2822(), "lease_address" => self.pending_lease_address, "state" => state, "client" => _format_hex(&self.client), "relay" => self.relay.to_string()}
     }
 
     fn set_uid(&mut self, uid : u16) {
@@ -41,26 +171,37 @@ pub struct Transaction {
     pub pending_lease_address : u16,
     pub uid : u16,
     pub xid : u32,
+    pub client : Vec<u8>,
+    pub relay : Ipv4Addr,
 }
 
 #[allow(dead_code)]
 impl Transaction{
-    pub fn init_new(transaction_id : u32, time : DateTime<Utc>) -> Self {
-        Self { state: TransactionState::Undefined("UNDEFINED".to_string()), start: time, pending_lease_address: 0, uid: 0, xid: transaction_id }
+    pub fn init_new(key : &TransactionKey, time : DateTime<Utc>) -> Self {
+        Self::new(TransactionState::Undefined, time, 0, 0, key)
     }
 
-    pub fn new(state : TransactionState, start : DateTime<Utc>, pending_lease_address : u16, uid : u16, xid : u32) -> Self{
+    pub fn new(state : TransactionState, start : DateTime<Utc>, pending_lease_address : u16, uid : u16, key : &TransactionKey) -> Self{
         Self {
             state,
             start,
             pending_lease_address,
             uid,
-            xid
+            xid: key.xid,
+            client: key.client.clone(),
+            relay: key.relay
         }
     }
 
-    pub fn set_state(&mut self, state : TransactionState) {
-        self.state = state;
+    /// Returns the [`TransactionKey`] identifying this transaction
+    pub fn key(&self) -> TransactionKey {
+        TransactionKey::new(self.client.clone(), self.xid, self.relay)
+    }
+
+    /// Switches the transaction to the given state, if the transition is allowed
+    pub fn set_state(&mut self, state : TransactionState) -> Result<(), TransactionError> {
+        self.state = self.state.transition(state)?;
+        Ok(())
     }
 
     pub fn abort(self) {
@@ -75,12 +216,13 @@ impl Transaction{
         self.pending_lease_address = lease_address;
     }
 
-    pub fn outdated(&self) -> bool{
-        (Utc::now() - self.start) > chrono::Duration::seconds(30)
+    /// Returns true if the transaction started more than `timeout` before `now`
+    pub fn outdated(&self, now : DateTime<Utc>, timeout : chrono::Duration) -> bool{
+        (now - self.start) > timeout
     }
 
     pub fn state(&self) -> TransactionState{
-        self.state.clone()
+        self.state
     }
 }
 
@@ -95,16 +237,11 @@ impl FromRow for Transaction {
         let time: DateTime<Utc> = DateTime::parse_from_rfc2822(&time).unwrap().into();
         let lease_address: u16 = row.get(4).unwrap();
         let state : String = row.get(5).unwrap();
-        let a : TransactionState;
-        match state.as_str() {
-            "UNDEFINED" => a = TransactionState::Undefined("UNDEFINED".to_string()),
-            "PENDING" => a = TransactionState::Pending("PENDING".to_string()),
-            "REQUESTED" => a = TransactionState::Requested("REQUESTED".to_string()),
-            "WAITING" => a = TransactionState::Waiting("WAITING".to_string()),
-            "BOUND" => a = TransactionState::Bound("BOUND".to_string()),
-            _ => a = TransactionState::Undefined("UNDEFINED".to_string()) 
-        }
-        Self::new(a, time, lease_address, uid, identifier)
+        let client : String = row.get(6).unwrap();
+        let relay : String = row.get(7).unwrap();
+        let key = TransactionKey::new(_parse_hex(&client), identifier, Ipv4Addr::from_str(&relay).unwrap_or(Ipv4Addr::UNSPECIFIED));
+        let state = TransactionState::from_str(&state).unwrap_or(TransactionState::Undefined);
+        Self::new(state, time, lease_address, uid, &key)
     }
 
     fn from_row_opt(row: mysql::Row) -> Result<Self, mysql::FromRowError>
@@ -113,3 +250,89 @@ impl FromRow for Transaction {
         Ok(Transaction::from_row(row))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use std::net::Ipv4Addr;
+
+    use std::str::FromStr;
+
+    use crate::transactions::error::TransactionError;
+
+    use super::{Transaction, TransactionKey, TransactionState, _format_hex, _parse_hex};
+
+    #[test]
+    fn test_transaction_outdated() {
+        let start = Utc::now();
+        let key = TransactionKey::new(vec![1, 2, 3], 1, Ipv4Addr::UNSPECIFIED);
+        let transaction = Transaction::new(TransactionState::Pending, start, 0, 0, &key);
+        assert!(!transaction.outdated(start, Duration::seconds(30)));
+        assert!(!transaction.outdated(start + Duration::seconds(30), Duration::seconds(30)));
+        assert!(transaction.outdated(start + Duration::seconds(31), Duration::seconds(30)));
+        assert!(transaction.outdated(start + Duration::seconds(11), Duration::seconds(10)));
+    }
+
+    #[test]
+    fn test_transaction_key() {
+        let key = TransactionKey::new(vec![1, 0xf8, 0x4d], 0xaaed4eea, Ipv4Addr::new(10, 0, 0, 1));
+        let transaction = Transaction::init_new(&key, Utc::now());
+        assert_eq!(transaction.key(), key);
+        assert_eq!(_format_hex(key.client()), "01f84d");
+        assert_eq!(_parse_hex("01f84d"), key.client());
+    }
+
+    #[test]
+    fn test_legal_transitions() {
+        let key = TransactionKey::new(vec![1, 2, 3], 1, Ipv4Addr::UNSPECIFIED);
+        let mut transaction = Transaction::init_new(&key, Utc::now());
+        for state in [TransactionState::Pending, TransactionState::Bound, TransactionState::Waiting, TransactionState::Requested, TransactionState::Committed] {
+            transaction.set_state(state).unwrap();
+            assert_eq!(transaction.state(), state);
+        }
+        assert!(transaction.state().is_final());
+
+        for state in [TransactionState::Undefined, TransactionState::Pending, TransactionState::Bound, TransactionState::Waiting, TransactionState::Requested] {
+            assert_eq!(state.transition(TransactionState::Aborted), Ok(TransactionState::Aborted));
+        }
+
+        // Rapid commit skips the offer
+        assert_eq!(TransactionState::Bound.transition(TransactionState::Committed), Ok(TransactionState::Committed));
+        assert!(TransactionState::Waiting.transition(TransactionState::Committed).is_err());
+    }
+
+    #[test]
+    fn test_illegal_transitions() {
+        let key = TransactionKey::new(vec![1, 2, 3], 1, Ipv4Addr::UNSPECIFIED);
+        let mut transaction = Transaction::init_new(&key, Utc::now());
+        transaction.set_state(TransactionState::Pending).unwrap();
+
+        // Skipping a state is rejected and leaves the transaction untouched
+        assert_eq!(
+            transaction.set_state(TransactionState::Waiting),
+            Err(TransactionError::IllegalTransition { from: TransactionState::Pending, to: TransactionState::Waiting })
+        );
+        assert_eq!(transaction.state(), TransactionState::Pending);
+        assert!(transaction.set_state(TransactionState::Committed).is_err());
+        assert!(transaction.set_state(TransactionState::Pending).is_err());
+
+        // Going backwards is rejected
+        assert!(TransactionState::Requested.transition(TransactionState::Waiting).is_err());
+        assert!(TransactionState::Waiting.transition(TransactionState::Bound).is_err());
+
+        // Nothing can leave a final state
+        for state in [TransactionState::Pending, TransactionState::Aborted, TransactionState::Committed] {
+            assert!(TransactionState::Committed.transition(state).is_err());
+            assert!(TransactionState::Aborted.transition(state).is_err());
+        }
+    }
+
+    #[test]
+    fn test_state_storage_representation() {
+        for state in [TransactionState::Undefined, TransactionState::Pending, TransactionState::Bound, TransactionState::Waiting, TransactionState::Requested, TransactionState::Committed, TransactionState::Aborted] {
+            assert_eq!(TransactionState::from_str(state.as_str()), Ok(state));
+        }
+        assert!(TransactionState::from_str("BOGUS").is_err());
+    }
+}